@@ -0,0 +1,17 @@
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/tuya_protocol.rs"]
+mod tuya_protocol;
+
+use libfuzzer_sys::fuzz_target;
+
+const KEY: [u8; 16] = *b"0123456789abcdef";
+
+fuzz_target!(|data: &[u8]| {
+    let _ = tuya_protocol::decrypt_payload(data, &KEY);
+
+    let encrypted = tuya_protocol::encrypt_payload(data, &KEY);
+    let decrypted = tuya_protocol::decrypt_payload(&encrypted, &KEY).unwrap();
+    assert_eq!(decrypted, data);
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+// hearth is a binary crate, so pull the protocol module in by path.
+// It's pure and self-contained, which is exactly what makes it fuzzable.
+#[allow(dead_code)]
+#[path = "../../src/tuya_protocol.rs"]
+mod tuya_protocol;
+
+use libfuzzer_sys::fuzz_target;
+
+const KEY: [u8; 16] = *b"0123456789abcdef";
+
+fuzz_target!(|data: &[u8]| {
+    // Arbitrary bytes straight off the wire
+    let _ = tuya_protocol::parse_frame(data, &KEY);
+
+    // Same bytes wrapped in a well-formed frame, so the fuzzer spends its
+    // time past the CRC check instead of bouncing off it.
+    let seqno = data.len() as u32;
+    let frame = tuya_protocol::build_frame(seqno, tuya_protocol::CMD_CONTROL, data, &KEY);
+    if let Ok(msg) = tuya_protocol::parse_frame(&frame.bytes, &KEY) {
+        assert_eq!(msg.seqno, seqno);
+        assert_eq!(msg.cmd, tuya_protocol::CMD_CONTROL);
+    }
+});
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{BoolType, DataType, DoubleType, Int32Type, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+
+use crate::history::{self, Reading};
+use crate::store::{self, Store, StoreError};
+
+// -- Monthly Parquet archives --
+//
+// `hearth export-parquet` compacts the store's readings into one Parquet
+// file per month, hearth-YYYY-MM.parquet, for DuckDB or pandas to query
+// without touching the live SQLite file. The store is read a month at a
+// time. Only whole months before the current one are written, and a month
+// whose file is already there is skipped, so it can run from cron. With
+// --prune the archived readings then leave the store; events and commands
+// stay.
+
+const SCHEMA: &str = "
+    message reading {
+        required int64 time (TIMESTAMP(MILLIS, true));
+        optional int32 humidity (INTEGER(32, false));
+        optional int32 target (INTEGER(32, false));
+        optional boolean power;
+        optional int32 fault (INTEGER(32, false));
+        optional double temperature;
+    }
+";
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io { path: String, source: std::io::Error },
+    Parquet(ParquetError),
+    Store(StoreError),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Io { path, source } => write!(f, "Can't write {path}: {source}"),
+            ArchiveError::Parquet(e) => write!(f, "Parquet error: {e}"),
+            ArchiveError::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<ParquetError> for ArchiveError {
+    fn from(e: ParquetError) -> Self {
+        ArchiveError::Parquet(e)
+    }
+}
+
+impl From<StoreError> for ArchiveError {
+    fn from(e: StoreError) -> Self {
+        ArchiveError::Store(e)
+    }
+}
+
+/// The file a month's readings go in.
+pub fn file_name(year: i64, month: i64) -> String {
+    format!("hearth-{year:04}-{month:02}.parquet")
+}
+
+/// The next column of `row_group`, written from `values`, None for nulls.
+fn write_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, File>,
+    values: Vec<Option<T::T>>,
+) -> Result<(), ParquetError> {
+    let Some(mut column) = row_group.next_column()? else {
+        return Err(ParquetError::General("more columns written than the schema has".into()));
+    };
+    let levels: Vec<i16> = values.iter().map(|value| i16::from(value.is_some())).collect();
+    let present: Vec<T::T> = values.into_iter().flatten().collect();
+    column.typed::<T>().write_batch(&present, Some(&levels), None)?;
+    column.close()
+}
+
+/// Write `readings` to `path` as one row group, through a temporary file
+/// so an interrupted run leaves no half-written month behind.
+pub fn write_month(path: &Path, readings: &[Reading]) -> Result<(), ArchiveError> {
+    let io = |source| ArchiveError::Io {
+        path: path.display().to_string(),
+        source,
+    };
+    let partial = path.with_extension("parquet.partial");
+    let file = File::create(&partial).map_err(io)?;
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+    let mut writer = SerializedFileWriter::new(file, schema, properties)?;
+
+    let mut row_group = writer.next_row_group()?;
+    if let Some(mut column) = row_group.next_column()? {
+        let times: Vec<i64> = readings.iter().map(|r| i64::try_from(r.t.saturating_mul(1000)).unwrap_or(i64::MAX)).collect();
+        column.typed::<Int64Type>().write_batch(&times, None, None)?;
+        column.close()?;
+    }
+    // Unsigned columns are stored in int32s, bit for bit
+    write_column::<Int32Type>(&mut row_group, readings.iter().map(|r| r.humidity.map(|v| v as i32)).collect())?;
+    write_column::<Int32Type>(&mut row_group, readings.iter().map(|r| r.target.map(|v| v as i32)).collect())?;
+    write_column::<BoolType>(&mut row_group, readings.iter().map(|r| r.power).collect())?;
+    write_column::<Int32Type>(&mut row_group, readings.iter().map(|r| r.fault.map(|v| v as i32)).collect())?;
+    write_column::<DoubleType>(&mut row_group, readings.iter().map(|r| r.temperature).collect())?;
+    row_group.close()?;
+    writer.close()?;
+
+    std::fs::rename(&partial, path).map_err(io)
+}
+
+/// Archive every whole month of readings before the one `now` is in to
+/// `dir`, skipping months already there. With `prune`, each archived
+/// month's readings are dropped from the store. Returns the files written.
+pub fn export(store: &Store, dir: &Path, now: u64, prune: bool) -> Result<Vec<PathBuf>, ArchiveError> {
+    let Some(first) = store::first_reading(store)? else {
+        return Ok(Vec::new());
+    };
+    std::fs::create_dir_all(dir).map_err(|source| ArchiveError::Io {
+        path: dir.display().to_string(),
+        source,
+    })?;
+
+    let (this_year, this_month) = history::utc_month(now);
+    let end = history::month_start(this_year, this_month);
+    let (mut year, mut month) = history::utc_month(first);
+    let mut written = Vec::new();
+    while history::month_start(year, month) < end {
+        let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        let (from, to) = (history::month_start(year, month), history::month_start(next_year, next_month));
+
+        let path = dir.join(file_name(year, month));
+        if !path.exists() {
+            let readings = store::readings(store, from, to)?;
+            if !readings.is_empty() {
+                write_month(&path, &readings)?;
+                written.push(path.clone());
+            }
+        }
+        if prune && path.exists() {
+            let dropped = store::drop_readings(store, from, to)?;
+            tracing::info!(dropped, file = %path.display(), "Pruned archived readings from the store");
+        }
+        (year, month) = (next_year, next_month);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+
+    #[test]
+    fn whole_months_are_archived_once() {
+        let dir = std::env::temp_dir().join(format!("hearth-archive-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = store::open(dir.join("hearth.db").to_str().unwrap()).unwrap();
+
+        let march = history::month_start(2024, 3);
+        let april = history::month_start(2024, 4);
+        let status = |humidity: u32| -> crate::meaco::DehumidifierStatus {
+            serde_json::from_value(serde_json::json!({"power": true, "target_humidity": 50, "current_humidity": humidity})).unwrap()
+        };
+        store::record_status(&store, march + 60, &status(61)).unwrap();
+        store::record_status(&store, march + 120, &status(58)).unwrap();
+        store::record_status(&store, april + 60, &status(55)).unwrap();
+
+        // April is still running, so only March is written
+        let written = export(&store, &dir, april + 3600, false).unwrap();
+        assert_eq!(written, [dir.join("hearth-2024-03.parquet")]);
+        let reader = SerializedFileReader::new(File::open(&written[0]).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let first = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        let columns: Vec<(&String, &Field)> = first.get_column_iter().collect();
+        assert_eq!(columns[0], (&"time".to_owned(), &Field::TimestampMillis(((march + 60) * 1000) as i64)));
+        assert_eq!(columns[1], (&"humidity".to_owned(), &Field::UInt(61)));
+        assert_eq!(columns[2].1, &Field::UInt(50));
+        assert_eq!(columns[3].1, &Field::Bool(true));
+        // No temperature DP on this model
+        assert_eq!(columns[5].1, &Field::Null);
+
+        // A month already archived is left alone, until --prune clears it
+        // from the store
+        let may = history::month_start(2024, 5);
+        assert_eq!(export(&store, &dir, may, true).unwrap(), [dir.join("hearth-2024-04.parquet")]);
+        assert!(store::readings(&store, 0, may).unwrap().is_empty());
+        assert!(export(&store, &dir, may, true).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
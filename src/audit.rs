@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::store::{self, Store};
+use crate::watchdog;
+
+// -- Command audit log --
+//
+// When the dehumidifier switches off "by itself", this says whether an
+// agent did it: every control tool call, which client made it, the DPS it
+// wrote and what the device said, one JSON line each. The file is only
+// ever appended to. With `[store] path` set, entries also go into the
+// SQLite store, next to the status changes they caused.
+//
+// Writes are collected for the duration of a call rather than passed back
+// up through every tool: `tuya_connection::set_dps` reports each one to
+// whatever audit scope the current task is in, if any.
+
+tokio::task_local! {
+    static WRITES: RefCell<Vec<AuditWrite>>;
+}
+
+/// One DPS write and the device's answer to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditWrite {
+    pub dps: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    /// Unix seconds.
+    pub t: u64,
+    /// The tool called, or "schedule #3" for a scheduled run.
+    pub source: String,
+    /// The MCP client's name, as it introduced itself.
+    pub client: Option<String>,
+    pub arguments: serde_json::Value,
+    pub writes: Vec<AuditWrite>,
+    /// "ok", or the error the caller got.
+    pub outcome: String,
+}
+
+/// Where entries go: the JSON-lines file, the store, both or neither.
+#[derive(Debug, Clone, Default)]
+pub struct AuditSinks {
+    pub path: Option<String>,
+    pub store: Option<Arc<Store>>,
+}
+
+pub fn is_on(sinks: &AuditSinks) -> bool {
+    sinks.path.is_some() || sinks.store.is_some()
+}
+
+/// Run `call`, collecting the DPS writes it makes.
+pub async fn scope<T>(call: impl Future<Output = T>) -> (T, Vec<AuditWrite>) {
+    WRITES
+        .scope(RefCell::new(Vec::new()), async {
+            let result = call.await;
+            (result, WRITES.with(|writes| writes.take()))
+        })
+        .await
+}
+
+/// Note a write for the enclosing scope. Outside one, does nothing.
+pub fn record_write<E: std::fmt::Display>(
+    dps: &serde_json::Value,
+    result: &Result<serde_json::Value, E>,
+) {
+    let write = AuditWrite {
+        dps: dps.clone(),
+        response: result.as_ref().ok().cloned(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    };
+    let _ = WRITES.try_with(|writes| writes.borrow_mut().push(write));
+}
+
+pub fn append(path: &str, entry: &AuditEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(entry).map_err(std::io::Error::other)?;
+    line.push('\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Record an entry stamped now; a failure to write is logged, not fatal.
+pub fn log(
+    sinks: &AuditSinks,
+    source: String,
+    client: Option<String>,
+    arguments: serde_json::Value,
+    writes: Vec<AuditWrite>,
+    outcome: String,
+) {
+    let entry = AuditEntry {
+        t: watchdog::unix_secs(SystemTime::now()),
+        source,
+        client,
+        arguments,
+        writes,
+        outcome,
+    };
+    if let Some(path) = &sinks.path
+        && let Err(e) = append(path, &entry)
+    {
+        tracing::warn!(path, "Can't write audit log: {e}");
+    }
+    if let Some(store) = &sinks.store
+        && let Err(e) = store::record_audit(store, &entry)
+    {
+        tracing::warn!("Can't store audit entry: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_are_collected_per_scope() {
+        let ok: Result<serde_json::Value, String> = Ok(serde_json::json!({"1": false}));
+        let failed: Result<serde_json::Value, String> = Err("timed out".into());
+
+        // Outside a scope nothing is kept
+        record_write(&serde_json::json!({"1": true}), &ok);
+
+        let ((), writes) = scope(async {
+            record_write(&serde_json::json!({"1": false}), &ok);
+            record_write(&serde_json::json!({"2": 50}), &failed);
+        })
+        .await;
+        assert_eq!(writes.len(), 2);
+        assert_eq!(writes[0].response, Some(serde_json::json!({"1": false})));
+        assert_eq!(writes[1].error.as_deref(), Some("timed out"));
+
+        let path = std::env::temp_dir().join(format!("hearth-audit-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let sinks = AuditSinks {
+            path: Some(path.to_owned()),
+            store: None,
+        };
+        log(
+            &sinks,
+            "power".into(),
+            Some("claude".into()),
+            serde_json::json!({"on": false}),
+            writes,
+            "ok".into(),
+        );
+        log(
+            &sinks,
+            "schedule #1".into(),
+            None,
+            serde_json::Value::Null,
+            Vec::new(),
+            "ok".into(),
+        );
+        let contents = std::fs::read_to_string(path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.starts_with(r#"{"t":"#));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use clap::{Args, Parser, Subcommand};
+
+use crate::config::{LogFormat, TransportMode};
+
+// -- Command line --
+//
+// MCP clients launch hearth from wherever they like, so the config file
+// can be named on the command line rather than found in the working
+// directory. Without --config, hearth looks in the usual places (see
+// `config_candidates`), since launchers often start it from `/`. The
+// flags here override their hearth.toml counterparts.
+
+#[derive(Debug, Parser)]
+#[command(version, about = "MCP server for a Meaco dehumidifier on the local network")]
+pub struct Cli {
+    /// The config file. Default: the first of
+    /// $XDG_CONFIG_HOME/hearth/hearth.toml, ~/.config/hearth/hearth.toml,
+    /// /etc/hearth/hearth.toml and ./hearth.toml that exists.
+    #[arg(long, env = "HEARTH_CONFIG")]
+    pub config: Option<String>,
+    /// A level for hearth's own logs (error, warn, info, debug, trace) or
+    /// tracing filter directives, e.g. "hearth=debug,rmcp=info". Overrides
+    /// RUST_LOG.
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// How log lines are written, instead of `[logging] format`.
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+    /// How clients reach hearth, instead of `[transport] mode`.
+    #[arg(long, value_enum)]
+    pub transport: Option<TransportMode>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the recorded readings as CSV and exit. Dates are YYYY-MM-DD,
+    /// UTC; omitted, the whole history is printed.
+    ExportHistory { from: Option<String>, to: Option<String> },
+    /// Write each whole month of the store's readings before this one to
+    /// its own hearth-YYYY-MM.parquet, skipping months already written,
+    /// and exit. Needs [store] path.
+    ExportParquet {
+        /// Where the files go. Default: beside the store.
+        #[arg(long)]
+        dir: Option<String>,
+        /// Drop the archived readings from the store afterwards.
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Store a device's local key in the OS keyring, read from stdin, and
+    /// exit. Then set local_key to the same keyring:<service>/<user>.
+    StoreKey {
+        /// Where to store it, e.g. keyring:hearth/bedroom.
+        reference: String,
+    },
+    /// Fetch the devices and their local keys from a Tuya Cloud project,
+    /// find them on the LAN and write the config (--config, or
+    /// hearth.toml here), then exit.
+    Init(InitArgs),
+    /// Work with the config format.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print a JSON Schema for hearth.toml and exit, for editors to
+    /// validate and complete the config with.
+    Schema,
+}
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    /// The project's data centre: eu, eu-w, us, us-e, cn or in.
+    #[arg(long, default_value = "eu")]
+    pub region: String,
+    /// The project's Access ID. Asked for if not given.
+    #[arg(long, env = "TUYA_ACCESS_ID")]
+    pub access_id: Option<String>,
+    /// The project's Access Secret. Asked for if not given.
+    #[arg(long, env = "TUYA_ACCESS_SECRET", hide_env_values = true)]
+    pub access_secret: Option<String>,
+    /// Keep the local keys in the OS keyring rather than in the file.
+    #[arg(long)]
+    pub keyring: bool,
+    /// Seconds to listen for the devices' LAN broadcasts.
+    #[arg(long, default_value_t = 6)]
+    pub listen_secs: u64,
+    /// Replace an existing config.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Where to look for the config without `--config`, in order. An unset,
+/// empty or relative XDG_CONFIG_HOME is skipped, as the spec says.
+pub fn config_candidates(xdg_config_home: Option<&str>, home: Option<&str>) -> Vec<String> {
+    let mut candidates = Vec::new();
+    if let Some(xdg) = xdg_config_home.filter(|dir| dir.starts_with('/')) {
+        candidates.push(format!("{xdg}/hearth/hearth.toml"));
+    }
+    if let Some(home) = home.filter(|dir| !dir.is_empty()) {
+        candidates.push(format!("{home}/.config/hearth/hearth.toml"));
+    }
+    candidates.push("/etc/hearth/hearth.toml".to_owned());
+    candidates.push("hearth.toml".to_owned());
+    candidates.dedup();
+    candidates
+}
+
+/// The config file to load: `--config`, or the first candidate that
+/// exists. When none does, hearth.toml in the working directory, so the
+/// error names it and environment-only setups still work.
+pub fn config_path(cli: &Cli) -> String {
+    if let Some(path) = &cli.config {
+        return path.clone();
+    }
+    let xdg = std::env::var("XDG_CONFIG_HOME").ok();
+    let home = std::env::var("HOME").ok();
+    config_candidates(xdg.as_deref(), home.as_deref())
+        .into_iter()
+        .find(|path| std::path::Path::new(path).is_file())
+        .unwrap_or_else(|| "hearth.toml".to_owned())
+}
+
+/// The tracing filter `--log-level` asks for: a bare level applies to
+/// hearth, anything else is taken as filter directives.
+pub fn log_filter(level: &str) -> String {
+    match level.parse::<tracing::Level>() {
+        Ok(level) => format!("hearth={}", level.as_str().to_ascii_lowercase()),
+        Err(_) => level.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_and_levels() {
+        let cli = Cli::try_parse_from(["hearth", "--config", "/etc/hearth.toml", "--log-level", "INFO", "--transport", "http"])
+            .unwrap();
+        assert_eq!(config_path(&cli), "/etc/hearth.toml");
+        assert_eq!(cli.transport, Some(TransportMode::Http));
+        assert_eq!(log_filter(cli.log_level.as_deref().unwrap()), "hearth=info");
+        assert_eq!(log_filter("hearth=debug,rmcp=warn"), "hearth=debug,rmcp=warn");
+
+        let export = Cli::try_parse_from(["hearth", "export-history", "2026-01-01"]).unwrap();
+        assert!(matches!(export.command, Some(Command::ExportHistory { from: Some(_), to: None })));
+        let store = Cli::try_parse_from(["hearth", "store-key", "keyring:hearth/bedroom"]).unwrap();
+        assert!(matches!(store.command, Some(Command::StoreKey { reference }) if reference == "keyring:hearth/bedroom"));
+        let schema = Cli::try_parse_from(["hearth", "config", "schema"]).unwrap();
+        assert!(matches!(schema.command, Some(Command::Config { command: ConfigCommand::Schema })));
+        assert!(Cli::try_parse_from(["hearth", "--transport", "carrier-pigeon"]).is_err());
+        let json = Cli::try_parse_from(["hearth", "--log-format", "json"]).unwrap();
+        assert_eq!(json.log_format, Some(LogFormat::Json));
+    }
+
+    #[test]
+    fn config_search_order() {
+        assert_eq!(
+            config_candidates(Some("/home/u/.xdg"), Some("/home/u")),
+            [
+                "/home/u/.xdg/hearth/hearth.toml",
+                "/home/u/.config/hearth/hearth.toml",
+                "/etc/hearth/hearth.toml",
+                "hearth.toml"
+            ]
+        );
+        // A relative XDG_CONFIG_HOME is ignored, and with no home either
+        // only the system-wide and working directory paths are left
+        assert_eq!(config_candidates(Some("xdg"), None), ["/etc/hearth/hearth.toml", "hearth.toml"]);
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// -- Tuya Cloud API --
+//
+// Only `hearth init` talks to the cloud, to fetch the local keys that
+// otherwise take tinytuya's wizard to extract. It needs a cloud project
+// on the Tuya IoT Platform with the user's Smart Life / Tuya app account
+// linked to it; the project's Access ID and Secret sign each request.
+// Once hearth.toml is written, everything stays on the LAN.
+
+/// Data centres by the short names the IoT Platform uses.
+const REGIONS: &[(&str, &str)] = &[
+    ("eu", "https://openapi.tuyaeu.com"),
+    ("eu-w", "https://openapi-weaz.tuyaeu.com"),
+    ("us", "https://openapi.tuyaus.com"),
+    ("us-e", "https://openapi-ueaz.tuyaus.com"),
+    ("cn", "https://openapi.tuyacn.com"),
+    ("in", "https://openapi.tuyain.com"),
+];
+
+/// Devices per page of the device list.
+const PAGE_SIZE: u32 = 50;
+
+pub struct Cloud {
+    http: reqwest::Client,
+    base: String,
+    client_id: String,
+    secret: String,
+    access_token: String,
+}
+
+/// A device on the linked app account, as the cloud lists it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CloudDevice {
+    pub id: String,
+    pub name: String,
+    pub local_key: String,
+    /// Tuya's category code: "cs" for dehumidifiers, "cz" for plugs.
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub product_name: String,
+}
+
+#[derive(Debug)]
+pub enum CloudError {
+    UnknownRegion(String),
+    Http(String),
+    /// The API answered, but with an error.
+    Api { code: i64, msg: String },
+}
+
+impl fmt::Display for CloudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudError::UnknownRegion(region) => {
+                let known: Vec<&str> = REGIONS.iter().map(|(name, _)| *name).collect();
+                write!(f, "Unknown region \"{region}\". Valid choices: {}", known.join(", "))
+            }
+            CloudError::Http(msg) => write!(f, "Tuya Cloud request failed: {msg}"),
+            CloudError::Api { code, msg } => write!(f, "Tuya Cloud error {code}: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for CloudError {}
+
+fn hex_upper(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// HMAC-SHA256 request signature. `url` is the path with its query
+/// parameters in alphabetical order; `access_token` is empty when
+/// requesting one.
+pub fn sign(secret: &str, client_id: &str, access_token: &str, t: &str, method: &str, body: &[u8], url: &str) -> String {
+    let content_hash: String = Sha256::digest(body).iter().map(|b| format!("{b:02x}")).collect();
+    let string_to_sign = format!("{method}\n{content_hash}\n\n{url}");
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{client_id}{access_token}{t}{string_to_sign}").as_bytes());
+    hex_upper(&mac.finalize().into_bytes())
+}
+
+async fn get(cloud: &Cloud, url: &str) -> Result<serde_json::Value, CloudError> {
+    let http = |e: reqwest::Error| CloudError::Http(e.to_string());
+    let t = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .to_string();
+    let signature = sign(&cloud.secret, &cloud.client_id, &cloud.access_token, &t, "GET", b"", url);
+
+    let mut request = cloud
+        .http
+        .get(format!("{}{url}", cloud.base))
+        .header("client_id", &cloud.client_id)
+        .header("sign", signature)
+        .header("t", t)
+        .header("sign_method", "HMAC-SHA256");
+    if !cloud.access_token.is_empty() {
+        request = request.header("access_token", &cloud.access_token);
+    }
+    let body: serde_json::Value = request.send().await.map_err(http)?.json().await.map_err(http)?;
+
+    if body.get("success").and_then(|s| s.as_bool()) != Some(true) {
+        return Err(CloudError::Api {
+            code: body.get("code").and_then(|c| c.as_i64()).unwrap_or_default(),
+            msg: body.get("msg").and_then(|m| m.as_str()).unwrap_or("no message").to_owned(),
+        });
+    }
+    Ok(body.get("result").cloned().unwrap_or_default())
+}
+
+/// Sign in to the cloud project for `region` with its Access ID and
+/// Secret.
+pub async fn connect(region: &str, client_id: &str, secret: &str) -> Result<Cloud, CloudError> {
+    let (_, base) = REGIONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(region.trim()))
+        .ok_or_else(|| CloudError::UnknownRegion(region.to_owned()))?;
+    let mut cloud = Cloud {
+        http: reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(15))
+            .build()
+            .map_err(|e| CloudError::Http(e.to_string()))?,
+        base: (*base).to_owned(),
+        client_id: client_id.trim().to_owned(),
+        secret: secret.trim().to_owned(),
+        access_token: String::new(),
+    };
+
+    let token = get(&cloud, "/v1.0/token?grant_type=1").await?;
+    cloud.access_token = token
+        .get("access_token")
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| CloudError::Http("no access_token in the token response".into()))?
+        .to_owned();
+    Ok(cloud)
+}
+
+/// Every device on the app accounts linked to the project, local keys
+/// included.
+pub async fn devices(cloud: &Cloud) -> Result<Vec<CloudDevice>, CloudError> {
+    let mut devices = Vec::new();
+    let mut last_row_key: Option<String> = None;
+
+    loop {
+        let url = match &last_row_key {
+            Some(key) => format!("/v1.0/iot-01/associated-users/devices?last_row_key={key}&size={PAGE_SIZE}"),
+            None => format!("/v1.0/iot-01/associated-users/devices?size={PAGE_SIZE}"),
+        };
+        let page = get(cloud, &url).await?;
+        let listed: Vec<CloudDevice> = serde_json::from_value(page.get("devices").cloned().unwrap_or_default())
+            .map_err(|e| CloudError::Http(format!("unexpected device list: {e}")))?;
+        devices.extend(listed);
+
+        let more = page.get("has_more").and_then(|m| m.as_bool()).unwrap_or(false);
+        last_row_key = page.get("last_row_key").and_then(|k| k.as_str()).map(str::to_owned);
+        if !more || last_row_key.is_none() {
+            return Ok(devices);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_token_and_business_requests() {
+        // Worked out independently with Python's hmac and hashlib
+        assert_eq!(
+            sign("secret", "client", "", "1588925778000", "GET", b"", "/v1.0/token?grant_type=1"),
+            "977AAB3F39B74596932A7FBB2A4E25BC51B3755905CA67AB0003D2BA119572AD"
+        );
+        assert_eq!(
+            sign("secret", "client", "token", "1588925778000", "GET", b"", "/v1.0/iot-01/associated-users/devices?size=50"),
+            "5A58397996B8D65087D887329CFA88510B0958E7E11725155108DEFC83D92D5C"
+        );
+    }
+}
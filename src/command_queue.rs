@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::config::RateLimitConfig;
+
+// -- Outbound command queue --
+//
+// Some Wi-Fi modules fall over when CONTROL frames arrive faster than about
+// two a second. Every CONTROL frame takes a token from a bucket that refills
+// at the configured rate; callers queue in FIFO order on the bucket lock.
+// Heartbeats are housekeeping, so they step aside while commands wait.
+
+/// How often a deferred heartbeat re-checks the queue.
+const HEARTBEAT_YIELD: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct CommandQueue {
+    capacity: f64,
+    refill_per_sec: f64,
+    bucket: Mutex<Bucket>,
+    /// CONTROL frames waiting for (or holding) a token.
+    queued: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub fn new_queue(config: &RateLimitConfig) -> CommandQueue {
+    let capacity = f64::from(config.tokens.max(1));
+    let interval = Duration::from_millis(config.interval_ms.max(1)).as_secs_f64();
+
+    CommandQueue {
+        capacity,
+        refill_per_sec: capacity / interval,
+        bucket: Mutex::new(Bucket {
+            tokens: capacity,
+            updated: Instant::now(),
+        }),
+        queued: AtomicUsize::new(0),
+    }
+}
+
+/// Counts a CONTROL frame as queued for as long as it lives, so a caller
+/// that gives up while waiting doesn't hold heartbeats back forever.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Wait for a CONTROL slot. Resolves in submission order.
+pub async fn acquire_control(queue: &CommandQueue) {
+    queue.queued.fetch_add(1, Ordering::SeqCst);
+    let _queued = Queued(&queue.queued);
+    let mut bucket = queue.bucket.lock().await;
+
+    refill(queue, &mut bucket);
+    if bucket.tokens < 1.0 {
+        let wait = (1.0 - bucket.tokens) / queue.refill_per_sec;
+        tracing::debug!(wait_ms = (wait * 1000.0) as u64, "Rate limiting CONTROL frame");
+        tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        refill(queue, &mut bucket);
+    }
+    bucket.tokens -= 1.0;
+}
+
+/// Hold a heartbeat back until no user command is waiting.
+pub async fn yield_to_commands(queue: &CommandQueue) {
+    while queue.queued.load(Ordering::SeqCst) > 0 {
+        tokio::time::sleep(HEARTBEAT_YIELD).await;
+    }
+}
+
+fn refill(queue: &CommandQueue, bucket: &mut Bucket) {
+    let now = Instant::now();
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * queue.refill_per_sec).min(queue.capacity);
+    bucket.updated = now;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn control_frames_are_spaced_by_the_refill_rate() {
+        let queue = new_queue(&RateLimitConfig {
+            tokens: 2,
+            interval_ms: 1000,
+        });
+        let start = Instant::now();
+
+        // The burst allowance goes out immediately...
+        acquire_control(&queue).await;
+        acquire_control(&queue).await;
+        assert!(start.elapsed() < Duration::from_millis(10));
+
+        // ...then one token every 500ms
+        acquire_control(&queue).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(500) && elapsed < Duration::from_millis(600));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_burst_goes_out_in_order_with_heartbeats_held_back() {
+        let queue = std::sync::Arc::new(new_queue(&RateLimitConfig {
+            tokens: 1,
+            interval_ms: 500,
+        }));
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let start = Instant::now();
+
+        // Four commands at once, each queued before the next is submitted
+        let mut commands = Vec::new();
+        for n in 0..4 {
+            let (queue, sent) = (queue.clone(), sent.clone());
+            commands.push(tokio::spawn(async move {
+                acquire_control(&queue).await;
+                sent.lock().unwrap().push((n, start.elapsed()));
+            }));
+            tokio::task::yield_now().await;
+        }
+
+        // A heartbeat due meanwhile waits for the whole burst
+        let heartbeat = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                yield_to_commands(&queue).await;
+                start.elapsed()
+            }
+        });
+
+        for command in commands {
+            command.await.unwrap();
+        }
+        let sent = sent.lock().unwrap().clone();
+        assert_eq!(sent.iter().map(|(n, _)| *n).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        for (n, at) in &sent {
+            let due = Duration::from_millis(500 * *n as u64);
+            assert!(*at >= due && *at < due + Duration::from_millis(10), "command {n} went out at {at:?}");
+        }
+
+        let heartbeat_at = heartbeat.await.unwrap();
+        assert!(heartbeat_at >= sent[3].1 && heartbeat_at <= sent[3].1 + HEARTBEAT_YIELD);
+
+        // With nothing queued it goes straight out
+        let idle = Instant::now();
+        yield_to_commands(&queue).await;
+        assert_eq!(idle.elapsed(), Duration::ZERO);
+    }
+}
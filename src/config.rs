@@ -1,16 +1,906 @@
+use schemars::JsonSchema;
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::fmt;
 
-#[derive(Deserialize)]
+use crate::device_profile::{self, DeviceProfile, DpOverride};
+use crate::secret;
+use crate::tuya_protocol;
+
+#[derive(Deserialize, JsonSchema)]
 pub struct Config {
-    pub meaco: MeacoConfig,
+    /// The dehumidifier hearth drives. Optional with `[[device]]`
+    /// entries, whose first `load_config` then takes; see `meaco()`.
+    #[serde(default)]
+    meaco: Option<MeacoConfig>,
+    /// Devices as `[[device]]` entries, each with its own name and profile.
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+    /// Smart plugs in the same room, as `[plug.<name>]`.
+    #[serde(default)]
+    pub plug: BTreeMap<String, PlugConfig>,
+    /// Named target humidity setpoints accepted by `set_humidity`.
+    #[serde(default = "default_presets")]
+    pub presets: BTreeMap<String, u32>,
+    /// Named bundles of settings for `run_scene`, as `[scene.<name>]`.
+    #[serde(default)]
+    pub scene: BTreeMap<String, SceneConfig>,
+    #[serde(default)]
+    pub connection: ConnectionConfig,
+    #[serde(default)]
+    pub safety: SafetyConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub observe: ObserveConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub status: StatusConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub transport: TransportConfig,
+    #[serde(default)]
+    pub profile: ProfileConfig,
+    /// The profile `[profile]` resolves to, filled in by `load_config`.
+    #[serde(skip)]
+    pub device: DeviceProfile,
+    /// Settings that load but look wrong, for the caller to log.
+    #[serde(skip)]
+    pub warnings: Vec<Problem>,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    #[serde(default)]
+    pub polling: PollingConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub otlp: OtlpConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub store: StoreConfig,
+    #[serde(default)]
+    pub influxdb: InfluxConfig,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, PartialEq, JsonSchema)]
 pub struct MeacoConfig {
+    /// What tools and agents call the device, as well as its id.
+    #[serde(default)]
+    pub name: Option<String>,
     pub device_ip: String,
     pub device_id: String,
     pub local_key: String,
+    /// Other addresses the device may answer on (static IP, mDNS name,
+    /// last-discovered address), tried in order after `device_ip`.
+    #[serde(default)]
+    pub fallback_addresses: Vec<String>,
+    /// What happens to the frame seqno when the connection is re-established.
+    #[serde(default)]
+    pub seqno_on_reconnect: SeqnoPolicy,
+    /// This device's own `[polling]` settings, over the section's.
+    #[serde(default)]
+    pub polling: PollingConfig,
+}
+
+/// Some firmwares misbehave when seqno restarts at 1 mid-session, others
+/// expect exactly that from a fresh socket.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SeqnoPolicy {
+    /// Carry on counting from where the previous socket left off.
+    #[default]
+    Continue,
+    /// Start again at 1 on every new socket.
+    Reset,
+}
+
+impl MeacoConfig {
+    /// `device_ip` followed by the fallbacks, without duplicates.
+    pub fn candidate_addresses(&self) -> Vec<String> {
+        let mut addresses = vec![self.device_ip.clone()];
+        for addr in &self.fallback_addresses {
+            if !addresses.contains(addr) {
+                addresses.push(addr.clone());
+            }
+        }
+        addresses
+    }
+}
+
+/// One of several devices, as a `[[device]]` entry: connection settings
+/// as for `[meaco]`, where `name` is required, plus its profile.
+#[derive(Deserialize, JsonSchema)]
+pub struct DeviceConfig {
+    #[serde(flatten)]
+    pub device: MeacoConfig,
+    /// A built-in profile's name or a profile TOML's path; without one,
+    /// the built-in Arete Two profile.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Changes to single DPs of `profile`, for a unit whose firmware
+    /// differs from the model's.
+    #[serde(default, rename = "dp")]
+    pub dps: Vec<DpOverride>,
+    /// Only 3.3 is spoken so far.
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: String,
+    /// The profile `profile` resolves to, filled in by `load_config`.
+    #[serde(skip)]
+    pub resolved: DeviceProfile,
+}
+
+fn default_protocol_version() -> String {
+    tuya_protocol::PROTOCOL_VERSION.to_owned()
+}
+
+/// A Tuya smart plug, e.g. the one a heater hangs off. Connection
+/// settings are as for `[meaco]`; the table's key is its name.
+#[derive(Deserialize, JsonSchema)]
+pub struct PlugConfig {
+    #[serde(flatten)]
+    pub device: MeacoConfig,
+    /// A socket profile TOML, or a built-in profile's name, for plugs
+    /// whose DPs differ from Tuya's standard ones.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// The profile `profile` resolves to, filled in by `load_config`.
+    #[serde(skip)]
+    pub resolved: DeviceProfile,
+}
+
+fn default_presets() -> BTreeMap<String, u32> {
+    BTreeMap::from([
+        ("storage".to_owned(), 55),
+        ("living".to_owned(), 50),
+        ("drying".to_owned(), 40),
+    ])
+}
+
+/// Which device profile to use, and additions to it. Without a `path`
+/// it's the built-in Arete Two one.
+#[derive(Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ProfileConfig {
+    /// A device profile TOML (DP table and fault codes) for another model,
+    /// or a built-in profile's name.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Fan speed, "low" or "high". Tuya's standard dehumidifier schema
+    /// puts it on DP 5.
+    #[serde(default)]
+    pub fan_speed_dp: Option<String>,
+    /// Ioniser (anion) on/off, a boolean DP.
+    #[serde(default)]
+    pub ionizer_dp: Option<String>,
+    /// Sleep/quiet mode on/off (display off, quieter fan), a boolean DP.
+    #[serde(default)]
+    pub sleep_dp: Option<String>,
+    /// Changes to single DPs of the profile, by name.
+    #[serde(default, rename = "dp")]
+    pub dps: Vec<DpOverride>,
+    /// Fault bitmap entries that differ from the profile's, by bit.
+    #[serde(default)]
+    pub faults: Vec<crate::meaco::Fault>,
+}
+
+/// Settings `run_scene` applies together, e.g. power on, continuous mode
+/// and a 3h countdown for drying laundry.
+#[derive(Deserialize, Debug, Clone, JsonSchema)]
+pub struct SceneConfig {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub settings: crate::meaco::Settings,
+}
+
+/// Timeouts and retries for talking to the device.
+#[derive(Deserialize, Debug, Clone, JsonSchema)]
+pub struct ConnectionConfig {
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// DP_QUERY — safe to repeat, so retried once by default.
+    #[serde(default = "default_query_policy")]
+    pub query: RequestPolicy,
+    /// CONTROL — the device may have applied a write whose ACK got lost,
+    /// so no retries unless asked for.
+    #[serde(default)]
+    pub control: RequestPolicy,
+    /// HEART_BEAT — the next beat is the retry; fail fast.
+    #[serde(default = "default_heartbeat_policy")]
+    pub heartbeat: RequestPolicy,
+    /// Seconds between heartbeats, for the dehumidifier and plugs alike.
+    #[serde(default = "default_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    /// Consecutive heartbeat failures before the socket is presumed dead
+    /// and replaced, even though the OS still thinks it's open.
+    #[serde(default = "default_heartbeat_failures")]
+    pub heartbeat_failures: u32,
+    /// While replacing the socket fails, the heartbeat tries again after
+    /// this long instead of a full beat, doubling up to
+    /// `max_reconnect_backoff_ms`.
+    #[serde(default = "default_reconnect_backoff_ms")]
+    pub reconnect_backoff_ms: u64,
+    #[serde(default = "default_max_reconnect_backoff_ms")]
+    pub max_reconnect_backoff_ms: u64,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub socket: SocketConfig,
+    /// Local address to connect from, for hosts where the default route
+    /// (e.g. a VPN) doesn't reach the device.
+    #[serde(default)]
+    pub bind_address: Option<std::net::IpAddr>,
+    /// Network interface to connect through (SO_BINDTODEVICE). Linux only,
+    /// and needs CAP_NET_RAW.
+    #[serde(default)]
+    pub interface: Option<String>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: default_connect_timeout_ms(),
+            query: default_query_policy(),
+            control: RequestPolicy::default(),
+            heartbeat: default_heartbeat_policy(),
+            heartbeat_secs: default_heartbeat_secs(),
+            heartbeat_failures: default_heartbeat_failures(),
+            reconnect_backoff_ms: default_reconnect_backoff_ms(),
+            max_reconnect_backoff_ms: default_max_reconnect_backoff_ms(),
+            rate_limit: RateLimitConfig::default(),
+            socket: SocketConfig::default(),
+            bind_address: None,
+            interface: None,
+        }
+    }
+}
+
+fn default_heartbeat_secs() -> u64 {
+    10
+}
+
+fn default_heartbeat_failures() -> u32 {
+    3
+}
+
+fn default_reconnect_backoff_ms() -> u64 {
+    2000
+}
+
+fn default_max_reconnect_backoff_ms() -> u64 {
+    60_000
+}
+
+/// Token bucket for outbound CONTROL frames: at most `tokens` per
+/// `interval_ms`, with bursts up to `tokens`.
+#[derive(Deserialize, Debug, Clone, Copy, JsonSchema)]
+pub struct RateLimitConfig {
+    pub tokens: u32,
+    pub interval_ms: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            tokens: 2,
+            interval_ms: 1000,
+        }
+    }
+}
+
+/// Options set on the device socket once connected.
+#[derive(Deserialize, Debug, Clone, Copy, JsonSchema)]
+pub struct SocketConfig {
+    /// Send small frames immediately instead of letting Nagle batch them.
+    #[serde(default = "default_true")]
+    pub nodelay: bool,
+    /// Idle time before TCP keepalive probes start. Some devices drop idle
+    /// connections silently and the OS never notices otherwise. 0 disables.
+    #[serde(default = "default_keepalive_secs")]
+    pub keepalive_secs: u64,
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub keepalive_interval_secs: u64,
+    /// SO_LINGER on close. Unset leaves the OS default.
+    #[serde(default)]
+    pub linger_secs: Option<u64>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive_secs: default_keepalive_secs(),
+            keepalive_interval_secs: default_keepalive_interval_secs(),
+            linger_secs: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_keepalive_secs() -> u64 {
+    30
+}
+
+fn default_keepalive_interval_secs() -> u64 {
+    10
+}
+
+/// Per command type: how long to wait for a reply and how often to retry.
+/// Backoff doubles after each failed attempt, up to `max_backoff_ms`.
+#[derive(Deserialize, Debug, Clone, Copy, JsonSchema)]
+pub struct RequestPolicy {
+    #[serde(default = "default_request_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            timeout_ms: default_request_timeout_ms(),
+            retries: 0,
+            backoff_ms: default_backoff_ms(),
+            max_backoff_ms: default_max_backoff_ms(),
+        }
+    }
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_request_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_ms() -> u64 {
+    10_000
+}
+
+fn default_query_policy() -> RequestPolicy {
+    RequestPolicy {
+        retries: 1,
+        ..RequestPolicy::default()
+    }
+}
+
+fn default_heartbeat_policy() -> RequestPolicy {
+    RequestPolicy {
+        timeout_ms: 3000,
+        ..RequestPolicy::default()
+    }
+}
+
+/// Guard rails on what agents may do to the device.
+#[derive(Deserialize, Default, JsonSchema)]
+pub struct SafetyConfig {
+    /// While the physical child lock is engaged, refuse control tools
+    /// unless called with `override_child_lock: true`.
+    #[serde(default)]
+    pub child_lock_guard: bool,
+    /// Ask the user, via MCP elicitation, before switching off a drying
+    /// program or cancelling a running countdown.
+    #[serde(default)]
+    pub confirm_disruptive: bool,
+    /// Expose only tools that don't change anything: status, history,
+    /// faults and the like. For monitoring-only automations.
+    #[serde(default)]
+    pub read_only: bool,
+    /// If set, expose only these tools (and, with `read_only`, only the
+    /// read-only ones among them).
+    #[serde(default)]
+    pub allowed_tools: Option<Vec<String>>,
+}
+
+/// How hearth's log lines on stderr look.
+#[derive(Deserialize, Default, JsonSchema)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human-readable line per event.
+    #[default]
+    Text,
+    /// One JSON object per event, its fields (device, cmd, seqno,
+    /// latency_ms...) at the top level, for log shippers.
+    Json,
+}
+
+/// Reverse-engineering aids. Everything here is off by default.
+#[derive(Deserialize, Default, JsonSchema)]
+pub struct DebugConfig {
+    /// Log every frame as an annotated hexdump (needs trace-level logging).
+    #[serde(default)]
+    pub trace_frames: bool,
+}
+
+/// Watch-and-learn mode: record every DP value seen, to work out what
+/// undocumented DPs do. Meant to run for days, so it persists to disk.
+#[derive(Deserialize, JsonSchema)]
+pub struct ObserveConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_observe_path")]
+    pub path: String,
+    #[serde(default = "default_observe_flush_secs")]
+    pub flush_secs: u64,
+}
+
+impl Default for ObserveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_observe_path(),
+            flush_secs: default_observe_flush_secs(),
+        }
+    }
+}
+
+fn default_observe_path() -> String {
+    "dp_observations.json".into()
+}
+
+fn default_observe_flush_secs() -> u64 {
+    60
+}
+
+/// How long in-flight tool calls get to finish when hearth is stopping.
+#[derive(Deserialize, JsonSchema)]
+pub struct ShutdownConfig {
+    #[serde(default = "default_grace_secs")]
+    pub grace_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_secs: default_grace_secs(),
+        }
+    }
+}
+
+fn default_grace_secs() -> u64 {
+    10
+}
+
+/// How `get_status` reads the device.
+#[derive(Deserialize, Default, JsonSchema)]
+pub struct StatusConfig {
+    /// While the device can't be reached, answer with the last status
+    /// read, marked with its age, instead of an error.
+    #[serde(default)]
+    pub offline_fallback: bool,
+    /// Superseded by `[polling] cache_ttl_ms`, and used where that's unset.
+    #[serde(default)]
+    pub cache_ttl_ms: Option<u64>,
+    /// Keep a live status from everything the device reports, including
+    /// unprompted pushes, and answer from it while connected.
+    #[serde(default)]
+    pub passive: bool,
+    /// Keep the last-known status in this file, so it survives restarts.
+    #[serde(default)]
+    pub snapshot_path: Option<String>,
+}
+
+/// Background status polling and the status cache, as `[polling]` or a
+/// device's own `polling` table. Unset fields fall back to `[polling]`'s,
+/// then to the defaults; see `Config::polling`.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, JsonSchema)]
+pub struct PollingConfig {
+    /// Query the device every `interval_secs` whether or not anyone asks,
+    /// so status, history and notifications stay current. Default: off.
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Default: 60.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Serve a status response younger than this without asking the
+    /// device again. 0 disables the cache. Default: 2000.
+    #[serde(default)]
+    pub cache_ttl_ms: Option<u64>,
+    /// Send UPDATEDPS before each poll, asking the device to re-read its
+    /// sensors, for firmware that otherwise reports stale humidity.
+    /// Default: off.
+    #[serde(default)]
+    pub refresh_first: Option<bool>,
+}
+
+impl PollingConfig {
+    /// These settings, with `base`'s where these are unset.
+    fn or(self, base: PollingConfig) -> PollingConfig {
+        PollingConfig {
+            enabled: self.enabled.or(base.enabled),
+            interval_secs: self.interval_secs.or(base.interval_secs),
+            cache_ttl_ms: self.cache_ttl_ms.or(base.cache_ttl_ms),
+            refresh_first: self.refresh_first.or(base.refresh_first),
+        }
+    }
+}
+
+/// Polling settings in effect for one device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Polling {
+    pub enabled: bool,
+    pub interval: std::time::Duration,
+    pub cache_ttl: std::time::Duration,
+    pub refresh_first: bool,
+}
+
+/// Keeps track of whether the device is there, so tools can say it's
+/// offline without waiting for a timeout.
+#[derive(Deserialize, JsonSchema)]
+pub struct WatchdogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The device is probed when it has been quiet this long.
+    #[serde(default = "default_watchdog_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_watchdog_probe_timeout_ms")]
+    pub probe_timeout_ms: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_watchdog_interval_secs(),
+            probe_timeout_ms: default_watchdog_probe_timeout_ms(),
+        }
+    }
+}
+
+fn default_watchdog_interval_secs() -> u64 {
+    30
+}
+
+fn default_watchdog_probe_timeout_ms() -> u64 {
+    2000
+}
+
+/// A Prometheus scrape endpoint, `/metrics`.
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Loopback unless asked otherwise; there's no authentication.
+    #[serde(default = "default_metrics_bind")]
+    pub bind: std::net::SocketAddr,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: default_metrics_bind(),
+        }
+    }
+}
+
+fn default_metrics_bind() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([127, 0, 0, 1], 9464))
+}
+
+/// OpenTelemetry export to a collector over OTLP/HTTP.
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct OtlpConfig {
+    /// The collector's OTLP/HTTP base URL, e.g. "http://localhost:4318".
+    /// Nothing is exported while unset.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default = "default_otlp_service_name")]
+    pub service_name: String,
+    /// How often the connection metrics are exported.
+    #[serde(default = "default_otlp_metrics_interval_secs")]
+    pub metrics_interval_secs: u64,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: default_otlp_service_name(),
+            metrics_interval_secs: default_otlp_metrics_interval_secs(),
+        }
+    }
+}
+
+fn default_otlp_service_name() -> String {
+    "hearth".into()
+}
+
+fn default_otlp_metrics_interval_secs() -> u64 {
+    60
+}
+
+/// Measurements written to InfluxDB through its v2 HTTP API.
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct InfluxConfig {
+    /// The server's base URL, e.g. "http://localhost:8086". Nothing is
+    /// written while unset.
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub org: String,
+    #[serde(default)]
+    pub bucket: String,
+    /// An API token that may write to the bucket, or a `keyring:` or
+    /// `secrets:` reference to one.
+    #[serde(default)]
+    pub token: String,
+    #[serde(default = "default_influx_measurement")]
+    pub measurement: String,
+    /// How often the last-known status is written.
+    #[serde(default = "default_influx_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            org: String::new(),
+            bucket: String::new(),
+            token: String::new(),
+            measurement: default_influx_measurement(),
+            interval_secs: default_influx_interval_secs(),
+        }
+    }
+}
+
+fn default_influx_measurement() -> String {
+    "dehumidifier".into()
+}
+
+fn default_influx_interval_secs() -> u64 {
+    60
+}
+
+/// A SQLite database of status snapshots, changes, faults and audited
+/// commands, kept across restarts.
+#[derive(Deserialize, JsonSchema)]
+pub struct StoreConfig {
+    /// The database file. Nothing is stored while unset.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// How often the last-known status is saved, changed or not.
+    #[serde(default = "default_store_snapshot_secs")]
+    pub snapshot_secs: u64,
+    /// Rows older than this are dropped at startup and daily after.
+    #[serde(default = "default_store_retention_days")]
+    pub retention_days: u64,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            snapshot_secs: default_store_snapshot_secs(),
+            retention_days: default_store_retention_days(),
+        }
+    }
+}
+
+fn default_store_snapshot_secs() -> u64 {
+    300
+}
+
+fn default_store_retention_days() -> u64 {
+    90
+}
+
+/// Periodic readings kept on disk for `get_history`.
+#[derive(Deserialize, JsonSchema)]
+pub struct HistoryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_history_path")]
+    pub path: String,
+    /// How often the device is sampled.
+    #[serde(default = "default_history_interval_secs")]
+    pub interval_secs: u64,
+    /// Readings older than this are dropped at startup.
+    #[serde(default = "default_history_retention_days")]
+    pub retention_days: u64,
+    /// How many of the `[polling]` poller's readings to keep in memory,
+    /// for get_history without a file. 0 keeps none.
+    #[serde(default = "default_history_recent_readings")]
+    pub recent_readings: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_history_path(),
+            interval_secs: default_history_interval_secs(),
+            retention_days: default_history_retention_days(),
+            recent_readings: default_history_recent_readings(),
+        }
+    }
+}
+
+fn default_history_path() -> String {
+    "history.jsonl".into()
+}
+
+fn default_history_interval_secs() -> u64 {
+    300
+}
+
+fn default_history_recent_readings() -> usize {
+    1440
+}
+
+fn default_history_retention_days() -> u64 {
+    30
+}
+
+/// Where schedules made with `add_schedule` are kept, and what clock
+/// their times are on.
+#[derive(Deserialize, JsonSchema)]
+pub struct ScheduleConfig {
+    #[serde(default = "default_schedule_path")]
+    pub path: String,
+    /// Local time's offset from UTC, e.g. 60 for UTC+1. hearth has no
+    /// timezone database, so daylight saving means changing this.
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            path: default_schedule_path(),
+            utc_offset_minutes: 0,
+        }
+    }
+}
+
+fn default_schedule_path() -> String {
+    "schedules.json".into()
+}
+
+/// Append-only record of every control tool call and scheduled run.
+#[derive(Deserialize, JsonSchema)]
+pub struct AuditConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_audit_path")]
+    pub path: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: default_audit_path(),
+        }
+    }
+}
+
+fn default_audit_path() -> String {
+    "audit.jsonl".into()
+}
+
+impl AuditConfig {
+    /// Where to write, if auditing is on.
+    pub fn path(&self) -> Option<String> {
+        self.enabled.then(|| self.path.clone())
+    }
+}
+
+/// Where `secrets:` local keys are looked up.
+#[derive(Deserialize, Default, JsonSchema)]
+pub struct SecretsConfig {
+    /// A TOML file of `name = "key"` lines, only its owner may read.
+    /// Relative to the config file's directory.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Counting running hours towards a filter-cleaning reminder, for models
+/// that don't remind of their own accord.
+#[derive(Deserialize, JsonSchema)]
+pub struct FilterConfig {
+    /// Remind after the unit has run this many hours. Unset, nothing is
+    /// counted.
+    #[serde(default)]
+    pub reminder_hours: Option<u32>,
+    #[serde(default = "default_filter_path")]
+    pub path: String,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            reminder_hours: None,
+            path: default_filter_path(),
+        }
+    }
+}
+
+fn default_filter_path() -> String {
+    "filter.json".into()
+}
+
+/// How MCP clients reach hearth.
+#[derive(Deserialize, Clone, JsonSchema)]
+pub struct TransportConfig {
+    #[serde(default)]
+    pub mode: TransportMode,
+    /// Where the HTTP transport listens. Loopback unless asked otherwise:
+    /// anyone who can reach it can drive the device.
+    #[serde(default = "default_http_bind")]
+    pub http_bind: std::net::SocketAddr,
+    /// Where the Unix socket transport listens.
+    #[serde(default = "default_unix_path")]
+    pub unix_path: std::path::PathBuf,
+    /// Permissions for the socket file. Owner only by default; 0o660 lets
+    /// a group of supervised processes in.
+    #[serde(default = "default_unix_mode")]
+    pub unix_mode: u32,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            mode: TransportMode::default(),
+            http_bind: default_http_bind(),
+            unix_path: default_unix_path(),
+            unix_mode: default_unix_mode(),
+        }
+    }
+}
+
+fn default_unix_path() -> std::path::PathBuf {
+    "hearth.sock".into()
+}
+
+fn default_unix_mode() -> u32 {
+    0o600
+}
+
+fn default_http_bind() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([127, 0, 0, 1], 8734))
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportMode {
+    /// One client, which launched hearth and talks over stdin/stdout.
+    #[default]
+    Stdio,
+    /// Streamable HTTP (with SSE), for any number of clients on the network.
+    Http,
+    /// Newline-delimited JSON-RPC, as on stdio, over a Unix socket: one
+    /// MCP session per connection, for local clients only. Unix only.
+    Unix,
 }
 
 #[derive(Debug)]
@@ -18,6 +908,12 @@ pub enum ConfigError {
     FileNotFound(String),
     ParseError(String),
     InvalidLocalKey,
+    InvalidAddress(String),
+    /// A `HEARTH_*` variable that can't be applied.
+    InvalidOverride { var: String, reason: String },
+    /// Everything wrong with settings that parsed, so all can be fixed
+    /// at once.
+    Invalid(Vec<Problem>),
 }
 
 impl fmt::Display for ConfigError {
@@ -26,6 +922,23 @@ impl fmt::Display for ConfigError {
             ConfigError::FileNotFound(path) => write!(f, "Config file not found: {path}"),
             ConfigError::ParseError(msg) => write!(f, "Failed to parse config: {msg}"),
             ConfigError::InvalidLocalKey => write!(f, "local_key must be exactly 16 characters"),
+            ConfigError::InvalidAddress(address) => write!(
+                f,
+                "Invalid device address \"{address}\": expected an IPv4 address, an IPv6 \
+                 address (bare like fe80::1 or bracketed like [fe80::1]) or a hostname, \
+                 without a port"
+            ),
+            ConfigError::InvalidOverride { var, reason } => write!(f, "Can't apply {var}: {reason}"),
+            ConfigError::Invalid(problems) => match problems.as_slice() {
+                [problem] => write!(f, "Invalid config: {problem}"),
+                problems => {
+                    write!(f, "{} problems in the config:", problems.len())?;
+                    for problem in problems {
+                        write!(f, "\n  {problem}")?;
+                    }
+                    Ok(())
+                }
+            },
         }
     }
 }
@@ -33,15 +946,742 @@ impl fmt::Display for ConfigError {
 impl std::error::Error for ConfigError {}
 
 pub fn load_config(path: &str) -> Result<Config, ConfigError> {
-    let contents = std::fs::read_to_string(path)
-        .map_err(|_| ConfigError::FileNotFound(path.to_owned()))?;
+    let overrides = env_overrides();
+    let contents = read_file(path, &overrides)?;
 
-    let config: Config = toml::from_str(&contents)
-        .map_err(|e| ConfigError::ParseError(e.to_string()))?;
+    // Without overrides, parse the file directly so errors point into it
+    let mut config: Config = if overrides.is_empty() {
+        toml::from_str(&contents).map_err(|e| ConfigError::ParseError(e.to_string()))?
+    } else {
+        let mut doc: toml::Table = toml::from_str(&contents).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+        apply_env(&mut doc, &overrides)?;
+        toml::Value::Table(doc).try_into().map_err(|e: toml::de::Error| ConfigError::ParseError(e.to_string()))?
+    };
 
-    if config.meaco.local_key.len() != 16 {
-        return Err(ConfigError::InvalidLocalKey);
+    let mut findings = Findings::default();
+    let secrets = config.secrets.path.as_deref().and_then(|secrets_path| {
+        let relative_to = std::path::Path::new(path).parent().unwrap_or(std::path::Path::new(""));
+        let secrets_path = relative_to.join(secrets_path);
+        secret::load_file(&secrets_path.to_string_lossy())
+            .inspect_err(|e| findings.error("[secrets]", "path", e.to_string(), &e.hint()))
+            .ok()
+    });
+    // Local keys may be kept in the OS keyring or the secrets file instead
+    let mut devices: Vec<(String, &mut MeacoConfig)> = Vec::new();
+    devices.extend(config.meaco.as_mut().map(|device| ("[meaco]".to_owned(), device)));
+    devices.extend(config.devices.iter_mut().enumerate().map(|(i, entry)| (entry_section(i, &entry.device), &mut entry.device)));
+    devices.extend(config.plug.iter_mut().map(|(name, plug)| (format!("[plug.{name}]"), &mut plug.device)));
+    for (section, device) in devices {
+        match secret::resolve(&device.local_key, secrets.as_ref()) {
+            Ok(key) => device.local_key = key,
+            // An unreadable secrets file is reported already
+            Err(secret::SecretError::NoSecretsFile(_)) if config.secrets.path.is_some() => {}
+            Err(e) => findings.error(&section, "local_key", e.to_string(), &e.hint()),
+        }
+    }
+    // So may the InfluxDB token
+    match secret::resolve(&config.influxdb.token, secrets.as_ref()) {
+        Ok(token) => config.influxdb.token = token,
+        Err(secret::SecretError::NoSecretsFile(_)) if config.secrets.path.is_some() => {}
+        Err(e) => findings.error("[influxdb]", "token", e.to_string(), &e.hint()),
+    }
+
+    validate_devices(&mut config.devices, &mut findings);
+    for (section, device) in config.meaco.iter().map(|device| ("[meaco]".to_owned(), device)) {
+        check_device(&section, device, &mut findings);
+    }
+    for (name, plug) in &mut config.plug {
+        let section = format!("[plug.{name}]");
+        check_device(&section, &plug.device, &mut findings);
+        match device_profile::resolve_plug(plug.profile.as_deref()) {
+            Ok(profile) => plug.resolved = profile,
+            Err(e) => findings.error(&section, "profile", e.to_string(), ""),
+        }
     }
+    check_timing(&config, &mut findings);
 
+    // Without [meaco], the first [[device]] is the one driven, with
+    // [profile]'s additions layered on its profile and overrides
+    let mut profile = config.profile.clone();
+    if config.meaco.is_none() {
+        match config.devices.first() {
+            Some(first) => {
+                profile.path = first.profile.clone().or(profile.path);
+                profile.dps = [first.dps.clone(), profile.dps].concat();
+                config.meaco = Some(first.device.clone());
+            }
+            None => findings.error("[meaco]", "device_id", "no device is configured", "Add [meaco] or a [[device]] entry"),
+        }
+    }
+    match device_profile::resolve(&profile) {
+        Ok(profile) => config.device = profile,
+        Err(e) => findings.error("[profile]", "path", e.to_string(), ""),
+    }
+
+    if !findings.errors.is_empty() {
+        return Err(ConfigError::Invalid(findings.errors));
+    }
+    config.warnings = findings.warnings;
     Ok(config)
 }
+
+/// A JSON Schema for hearth.toml, from the types it loads into, for
+/// editors to validate and complete it with. Draft 7, which TOML editor
+/// plugins understand.
+pub fn json_schema() -> serde_json::Value {
+    let mut schema = schemars::generate::SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Config>();
+    schema.insert("title".into(), "hearth.toml".into());
+    schema.to_value()
+}
+
+/// The config as a TOML table, overrides applied but not checked. What
+/// the reloader compares to tell which sections changed.
+pub fn load_document(path: &str) -> Result<toml::Table, ConfigError> {
+    let overrides = env_overrides();
+    let mut doc: toml::Table =
+        toml::from_str(&read_file(path, &overrides)?).map_err(|e| ConfigError::ParseError(e.to_string()))?;
+    apply_env(&mut doc, &overrides)?;
+    Ok(doc)
+}
+
+fn read_file(path: &str, overrides: &[(String, String)]) -> Result<String, ConfigError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        // Everything may come from the environment instead
+        Err(_) if !overrides.is_empty() => Ok(String::new()),
+        Err(_) => Err(ConfigError::FileNotFound(path.to_owned())),
+    }
+}
+
+impl Config {
+    /// The dehumidifier hearth drives: `[meaco]`, or the first `[[device]]`.
+    pub fn meaco(&self) -> &MeacoConfig {
+        self.meaco.as_ref().expect("load_config fills in the device")
+    }
+
+    /// `[[device]]` entries besides the driven one: configured and
+    /// checked, but not connected to until hearth drives several.
+    pub fn undriven_devices(&self) -> impl Iterator<Item = &DeviceConfig> {
+        self.devices.iter().filter(|d| d.device.device_id != self.meaco().device_id)
+    }
+
+    /// The polling settings for `device`: its own, then `[polling]`'s,
+    /// then `[status] cache_ttl_ms` for the cache, then the defaults.
+    pub fn polling(&self, device: &MeacoConfig) -> Polling {
+        let legacy = PollingConfig {
+            cache_ttl_ms: self.status.cache_ttl_ms,
+            ..Default::default()
+        };
+        let merged = device.polling.or(self.polling).or(legacy);
+        Polling {
+            enabled: merged.enabled.unwrap_or(false),
+            interval: std::time::Duration::from_secs(merged.interval_secs.unwrap_or(60)),
+            cache_ttl: std::time::Duration::from_millis(merged.cache_ttl_ms.unwrap_or(2000)),
+            refresh_first: merged.refresh_first.unwrap_or(false),
+        }
+    }
+}
+
+// -- Validation --
+//
+// Settings that parse can still be wrong: a key one character short, an
+// address with a port, a backoff longer than its cap. load_config checks
+// them all and reports every problem at once, each with where it is and
+// what to do about it. Some settings only look wrong, e.g. a device id of
+// an unusual length, and are warnings: hearth starts and logs them.
+
+/// One thing wrong with a setting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Where the setting lives, e.g. `[meaco]` or `[[device]] "cellar"`.
+    pub section: String,
+    pub field: String,
+    pub message: String,
+    /// What to do about it, if there's more to say than the message.
+    pub hint: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.section, self.field, self.message)?;
+        if !self.hint.is_empty() {
+            write!(f, ". {}", self.hint)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Findings {
+    errors: Vec<Problem>,
+    warnings: Vec<Problem>,
+}
+
+impl Findings {
+    fn error(&mut self, section: &str, field: &str, message: impl Into<String>, hint: &str) {
+        self.errors.push(problem(section, field, message.into(), hint));
+    }
+
+    fn warning(&mut self, section: &str, field: &str, message: impl Into<String>, hint: &str) {
+        self.warnings.push(problem(section, field, message.into(), hint));
+    }
+}
+
+fn problem(section: &str, field: &str, message: String, hint: &str) -> Problem {
+    Problem {
+        section: section.to_owned(),
+        field: field.to_owned(),
+        message,
+        hint: hint.to_owned(),
+    }
+}
+
+/// How problems with a `[[device]]` entry name it: by name, or else by
+/// position.
+fn entry_section(position: usize, device: &MeacoConfig) -> String {
+    match device.name.as_deref().map(str::trim).unwrap_or_default() {
+        "" => format!("[[device]] #{}", position + 1),
+        name => format!("[[device]] \"{name}\""),
+    }
+}
+
+/// Whether `id` has the shape of a Tuya device id (gwId): 20 or 22
+/// letters and digits.
+fn looks_like_device_id(id: &str) -> bool {
+    matches!(id.len(), 20 | 22) && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn check_device(section: &str, device: &MeacoConfig, findings: &mut Findings) {
+    // A reference that failed to resolve is reported already
+    if device.local_key.len() != 16 && !secret::is_reference(&device.local_key) {
+        findings.error(
+            section,
+            "local_key",
+            format!("must be exactly 16 characters, not {}", device.local_key.chars().count()),
+            "Copy it from the TinyTuya wizard; it changes whenever the device is re-paired",
+        );
+    }
+    let hint = "Give an IPv4 address, an IPv6 address (fe80::1 or [fe80::1]) or a hostname, without a port";
+    if device_endpoint(&device.device_ip, 0).is_err() {
+        findings.error(section, "device_ip", format!("\"{}\" isn't an address or hostname", device.device_ip), hint);
+    }
+    for address in &device.fallback_addresses {
+        if device_endpoint(address, 0).is_err() {
+            findings.error(section, "fallback_addresses", format!("\"{address}\" isn't an address or hostname"), hint);
+        }
+    }
+    if !looks_like_device_id(&device.device_id) {
+        findings.warning(
+            section,
+            "device_id",
+            format!("\"{}\" doesn't look like a Tuya device id (20 or 22 letters and digits)", device.device_id),
+            "Check it against `tinytuya scan` or the Tuya app's device information",
+        );
+    }
+}
+
+/// Check every `[[device]]` and resolve its profile, saying which entry
+/// is at fault.
+fn validate_devices(devices: &mut [DeviceConfig], findings: &mut Findings) {
+    let mut names = std::collections::BTreeSet::new();
+    let mut ids = std::collections::BTreeSet::new();
+
+    for (position, entry) in devices.iter_mut().enumerate() {
+        let section = entry_section(position, &entry.device);
+        let name = entry.device.name.as_deref().map(str::trim).unwrap_or_default();
+
+        if name.is_empty() {
+            findings.error(&section, "name", "is required", "Tools and agents call the device by it");
+        } else if !names.insert(name.to_owned()) {
+            findings.error(&section, "name", "is taken by another device", "");
+        }
+        if !ids.insert(entry.device.device_id.clone()) {
+            findings.error(&section, "device_id", format!("{} is configured twice", entry.device.device_id), "");
+        }
+        if entry.protocol_version != tuya_protocol::PROTOCOL_VERSION {
+            findings.error(
+                &section,
+                "protocol_version",
+                format!("{} isn't supported", entry.protocol_version),
+                &format!("hearth speaks {}", tuya_protocol::PROTOCOL_VERSION),
+            );
+        }
+        check_device(&section, &entry.device, findings);
+        let profile = ProfileConfig {
+            path: entry.profile.clone(),
+            dps: entry.dps.clone(),
+            ..Default::default()
+        };
+        match device_profile::resolve(&profile) {
+            Ok(profile) => entry.resolved = profile,
+            Err(e) => findings.error(&section, "profile", e.to_string(), ""),
+        }
+    }
+}
+
+/// Timeouts, intervals and backoffs that would make hearth misbehave.
+fn check_timing(config: &Config, findings: &mut Findings) {
+    let connection = &config.connection;
+    let positive = "Use at least 1";
+    if connection.connect_timeout_ms == 0 {
+        findings.error("[connection]", "connect_timeout_ms", "is 0", positive);
+    }
+    if connection.heartbeat_secs == 0 {
+        findings.error("[connection]", "heartbeat_secs", "is 0", positive);
+    }
+    if connection.heartbeat_failures == 0 {
+        findings.error("[connection]", "heartbeat_failures", "is 0", positive);
+    }
+    if connection.reconnect_backoff_ms > connection.max_reconnect_backoff_ms {
+        findings.error(
+            "[connection]",
+            "reconnect_backoff_ms",
+            format!("is more than max_reconnect_backoff_ms ({})", connection.max_reconnect_backoff_ms),
+            "Raise the maximum or lower the starting backoff",
+        );
+    }
+    for (name, policy) in [("query", &connection.query), ("control", &connection.control), ("heartbeat", &connection.heartbeat)] {
+        let section = format!("[connection.{name}]");
+        if policy.timeout_ms == 0 {
+            findings.error(&section, "timeout_ms", "is 0", "Every request would time out at once");
+        }
+        if policy.backoff_ms > policy.max_backoff_ms {
+            findings.error(
+                &section,
+                "backoff_ms",
+                format!("is more than max_backoff_ms ({})", policy.max_backoff_ms),
+                "Raise the maximum or lower the starting backoff",
+            );
+        }
+    }
+    if connection.heartbeat.timeout_ms >= connection.heartbeat_secs.saturating_mul(1000) {
+        findings.warning(
+            "[connection.heartbeat]",
+            "timeout_ms",
+            format!("is as long as a beat ({}s)", connection.heartbeat_secs),
+            "A device that stops answering is noticed late; keep it under heartbeat_secs",
+        );
+    }
+    if connection.rate_limit.tokens == 0 || connection.rate_limit.interval_ms == 0 {
+        findings.error(
+            "[connection.rate_limit]",
+            if connection.rate_limit.tokens == 0 { "tokens" } else { "interval_ms" },
+            "is 0",
+            "No control command could be sent; use at least 1",
+        );
+    }
+
+    let polling = std::iter::once(("[polling]".to_owned(), &config.polling))
+        .chain(config.meaco.iter().map(|device| ("[meaco.polling]".to_owned(), &device.polling)))
+        .chain(
+            config
+                .devices
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (format!("{} polling", entry_section(i, &entry.device)), &entry.device.polling)),
+        );
+    for (section, polling) in polling {
+        if polling.interval_secs == Some(0) {
+            findings.error(&section, "interval_secs", "is 0", positive);
+        }
+    }
+    for (name, plug) in &config.plug {
+        if plug.device.polling != PollingConfig::default() {
+            findings.warning(&format!("[plug.{name}]"), "polling", "is ignored", "Only dehumidifiers are polled");
+        }
+    }
+    if config.metrics.enabled && config.transport.mode == TransportMode::Http && config.metrics.bind == config.transport.http_bind {
+        findings.error(
+            "[metrics]",
+            "bind",
+            format!("{} is also the HTTP transport's address", config.metrics.bind),
+            "Give /metrics a port of its own",
+        );
+    }
+    if let Some(endpoint) = &config.otlp.endpoint
+        && !(endpoint.starts_with("http://") || endpoint.starts_with("https://"))
+    {
+        findings.error(
+            "[otlp]",
+            "endpoint",
+            format!("\"{endpoint}\" isn't an http:// or https:// URL"),
+            "Give the collector's OTLP/HTTP address, e.g. http://localhost:4318",
+        );
+    }
+    if config.otlp.metrics_interval_secs == 0 {
+        findings.error("[otlp]", "metrics_interval_secs", "is 0", positive);
+    }
+    if config.status.cache_ttl_ms.is_some() {
+        findings.warning("[status]", "cache_ttl_ms", "has moved", "Set [polling] cache_ttl_ms instead");
+    }
+    if config.status.snapshot_path.as_deref() == Some("") {
+        findings.error("[status]", "snapshot_path", "is empty", "Leave it out to keep no snapshot");
+    }
+
+    if config.watchdog.enabled && config.watchdog.interval_secs == 0 {
+        findings.error("[watchdog]", "interval_secs", "is 0", positive);
+    }
+    if config.history.enabled && config.history.interval_secs == 0 {
+        findings.error("[history]", "interval_secs", "is 0", positive);
+    }
+    if let Some(url) = &config.influxdb.url {
+        let influx = &config.influxdb;
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            findings.error(
+                "[influxdb]",
+                "url",
+                format!("\"{url}\" isn't an http:// or https:// URL"),
+                "Give the server's address, e.g. http://localhost:8086",
+            );
+        }
+        for (field, value) in [("org", &influx.org), ("bucket", &influx.bucket), ("token", &influx.token)] {
+            if value.is_empty() {
+                findings.error("[influxdb]", field, "is missing", "InfluxDB's v2 API needs an org, a bucket and a token");
+            }
+        }
+        if influx.interval_secs == 0 {
+            findings.error("[influxdb]", "interval_secs", "is 0", positive);
+        }
+    }
+    if config.store.path.is_some() && config.store.snapshot_secs == 0 {
+        findings.error("[store]", "snapshot_secs", "is 0", positive);
+    }
+    if config.store.path.is_some() && config.store.retention_days == 0 {
+        findings.error("[store]", "retention_days", "is 0", positive);
+    }
+    if config.observe.enabled && config.observe.flush_secs == 0 {
+        findings.error("[observe]", "flush_secs", "is 0", positive);
+    }
+    if !(-720..=840).contains(&config.schedule.utc_offset_minutes) {
+        findings.error(
+            "[schedule]",
+            "utc_offset_minutes",
+            format!("{} is outside UTC-12 to UTC+14", config.schedule.utc_offset_minutes),
+            "It's in minutes: 60 for UTC+1",
+        );
+    }
+    if config.filter.reminder_hours == Some(0) {
+        findings.error("[filter]", "reminder_hours", "is 0", "Leave it out to turn the reminder off");
+    }
+}
+
+// -- Environment overrides --
+//
+// Any setting can come from a `HEARTH_*` variable instead of hearth.toml,
+// for containers and MCP launchers, and to keep the local key out of a
+// world-readable file. `HEARTH_<SECTION>__<KEY>` sets `key` in
+// `[section]`, with further `__` for deeper tables:
+// HEARTH_CONNECTION__QUERY__TIMEOUT_MS=8000, HEARTH_PLUG__HEATER__LOCAL_KEY.
+// Values are read as TOML, so 8000 is a number and true a boolean;
+// anything that isn't valid TOML, or replaces a string in the file, is a
+// string. Quote a string that looks like a number.
+
+/// The three settings most often kept out of the file, by shorter names.
+/// Always strings.
+const ENV_SHORTHANDS: &[(&str, &str)] = &[
+    ("HEARTH_DEVICE_IP", "meaco.device_ip"),
+    ("HEARTH_DEVICE_ID", "meaco.device_id"),
+    ("HEARTH_LOCAL_KEY", "meaco.local_key"),
+];
+
+fn env_overrides() -> Vec<(String, String)> {
+    std::env::vars().filter(|(var, _)| env_key(var).is_some()).collect()
+}
+
+/// The config path `var` sets, if it's an override.
+fn env_key(var: &str) -> Option<Vec<String>> {
+    if let Some((_, path)) = ENV_SHORTHANDS.iter().find(|(name, _)| *name == var) {
+        return Some(path.split('.').map(str::to_owned).collect());
+    }
+    let path = var.strip_prefix("HEARTH_")?;
+    let keys: Vec<String> = path.split("__").map(str::to_ascii_lowercase).collect();
+    (keys.len() > 1 && keys.iter().all(|k| !k.is_empty())).then_some(keys)
+}
+
+/// Set each override's value in `doc`, creating tables as needed.
+fn apply_env(doc: &mut toml::Table, overrides: &[(String, String)]) -> Result<(), ConfigError> {
+    for (var, raw) in overrides {
+        let Some(keys) = env_key(var) else { continue };
+        let (last, tables) = keys.split_last().expect("an override names at least two keys");
+
+        let mut table = &mut *doc;
+        for key in tables {
+            let entry = table.entry(key.clone()).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            table = entry.as_table_mut().ok_or_else(|| ConfigError::InvalidOverride {
+                var: var.clone(),
+                reason: format!("{key} is a setting, not a section"),
+            })?;
+        }
+
+        let shorthand = ENV_SHORTHANDS.iter().any(|(name, _)| name == var);
+        let value = match table.get(last) {
+            _ if shorthand => toml::Value::String(raw.clone()),
+            Some(toml::Value::String(_)) => toml::Value::String(raw.clone()),
+            _ => format!("v = {raw}")
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut parsed| parsed.remove("v"))
+                .unwrap_or_else(|| toml::Value::String(raw.clone())),
+        };
+        table.insert(last.clone(), value);
+    }
+    Ok(())
+}
+
+/// `address` with `port` appended, in a form `lookup_host` understands.
+/// IPv6 needs brackets around it once there's a port; users may write it
+/// either way.
+pub fn device_endpoint(address: &str, port: u16) -> Result<String, ConfigError> {
+    let invalid = || ConfigError::InvalidAddress(address.to_owned());
+    let address = address.trim();
+
+    if let Some(inner) = address.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+        let ip: std::net::Ipv6Addr = inner.parse().map_err(|_| invalid())?;
+        return Ok(std::net::SocketAddr::from((ip, port)).to_string());
+    }
+    if let Ok(ip) = address.parse::<std::net::IpAddr>() {
+        return Ok(std::net::SocketAddr::new(ip, port).to_string());
+    }
+
+    // Anything else is a hostname; a colon here is a port or broken IPv6
+    let hostname = !address.is_empty()
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if !hostname {
+        return Err(invalid());
+    }
+    Ok(format!("{address}:{port}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_endpoints() {
+        assert_eq!(device_endpoint("192.168.1.20", 6668).unwrap(), "192.168.1.20:6668");
+        assert_eq!(device_endpoint("fe80::1", 6668).unwrap(), "[fe80::1]:6668");
+        assert_eq!(device_endpoint("[fe80::1]", 6668).unwrap(), "[fe80::1]:6668");
+        assert_eq!(device_endpoint("meaco.local", 6668).unwrap(), "meaco.local:6668");
+
+        assert!(device_endpoint("192.168.1.20:6668", 6668).is_err());
+        assert!(device_endpoint("[meaco.local]", 6668).is_err());
+        assert!(device_endpoint("", 6668).is_err());
+    }
+
+    #[test]
+    fn scenes_bundle_settings() {
+        let config: Config = toml::from_str(
+            r#"
+            [meaco]
+            device_ip = "192.168.1.20"
+            device_id = "abc"
+            local_key = "0123456789abcdef"
+
+            [scene.laundry]
+            description = "Dry a load of laundry"
+            power = true
+            mode = "continuous"
+            countdown = "3h"
+            "#,
+        )
+        .unwrap();
+
+        let laundry = &config.scene["laundry"];
+        assert_eq!(laundry.description.as_deref(), Some("Dry a load of laundry"));
+        assert_eq!(laundry.settings.power, Some(true));
+        assert!(matches!(laundry.settings.mode, Some(crate::meaco::Mode::Continuous)));
+        assert!(matches!(laundry.settings.countdown, Some(crate::meaco::Countdown::ThreeHours)));
+        assert!(laundry.settings.humidity.is_none());
+    }
+
+    #[test]
+    fn environment_overrides_settings() {
+        let mut doc: toml::Table = toml::from_str(
+            r#"
+            [meaco]
+            device_ip = "192.168.1.20"
+            device_id = "1234"
+            local_key = "in-the-file-key!"
+            "#,
+        )
+        .unwrap();
+        let overrides = [
+            ("HEARTH_LOCAL_KEY", "0123456789123456"),
+            ("HEARTH_MEACO__DEVICE_ID", "5678"),
+            ("HEARTH_CONNECTION__QUERY__TIMEOUT_MS", "8000"),
+            ("HEARTH_STATUS__PASSIVE", "true"),
+            ("HEARTH_SAFETY__ALLOWED_TOOLS", r#"["get_status"]"#),
+            ("HEARTH_HISTORY__PATH", "/var/lib/hearth/history.jsonl"),
+            ("HEARTH_CONFIG", "elsewhere.toml"),
+        ]
+        .map(|(var, value)| (var.to_owned(), value.to_owned()));
+        apply_env(&mut doc, &overrides).unwrap();
+
+        let config: Config = toml::Value::Table(doc).try_into().unwrap();
+        assert_eq!(config.meaco().local_key, "0123456789123456");
+        assert_eq!(config.meaco().device_id, "5678");
+        assert_eq!(config.connection.query.timeout_ms, 8000);
+        assert!(config.status.passive);
+        assert_eq!(config.safety.allowed_tools, Some(vec!["get_status".to_owned()]));
+        assert_eq!(config.history.path, "/var/lib/hearth/history.jsonl");
+
+        let inside_a_setting = [("HEARTH_MEACO__DEVICE_IP__X".to_owned(), "1".to_owned())];
+        let mut doc: toml::Table = toml::from_str("[meaco]\ndevice_ip = \"x\"").unwrap();
+        assert!(matches!(
+            apply_env(&mut doc, &inside_a_setting),
+            Err(ConfigError::InvalidOverride { .. })
+        ));
+    }
+
+    /// Load `text` as a hearth.toml, through a temp file named after `test`.
+    fn load_text(test: &str, text: &str) -> Result<Config, ConfigError> {
+        let path = std::env::temp_dir().join(format!("hearth-{test}-{}.toml", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let config = load_config(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn load_error(test: &str, text: &str) -> String {
+        load_text(test, text).err().map(|e| e.to_string()).unwrap_or_default()
+    }
+
+    fn device_entry(name: &str, id: &str, key: &str) -> String {
+        format!("[[device]]\nname = \"{name}\"\ndevice_ip = \"192.168.1.20\"\ndevice_id = \"{id}\"\nlocal_key = \"{key}\"\n")
+    }
+
+    #[test]
+    fn devices_are_checked_one_by_one() {
+        let load = |text: &str| load_text("devices", text);
+        let error = |text: &str| load_error("devices", text);
+        let bedroom = device_entry("bedroom", "a1", "0123456789abcdef");
+
+        // Without [meaco], the first entry is the one driven
+        let config = load(&(bedroom.clone() + &device_entry("cellar", "b2", "fedcba9876543210"))).unwrap();
+        assert_eq!(config.meaco().name.as_deref(), Some("bedroom"));
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.devices[1].resolved.model, "MeacoDryArete2-25L");
+
+        assert!(
+            error(&(bedroom.clone() + &device_entry("cellar", "b2", "short")))
+                .starts_with("Invalid config: [[device]] \"cellar\" local_key: must be exactly 16 characters, not 5.")
+        );
+        assert!(error(&(bedroom.clone() + &device_entry("attic", "a1", "fedcba9876543210"))).contains("device_id: a1 is configured twice"));
+        assert!(error(&device_entry("", "a1", "0123456789abcdef")).starts_with("Invalid config: [[device]] #1 name: is required"));
+        assert!(error(&(bedroom.clone() + "protocol_version = \"3.4\"")).contains("protocol_version: 3.4 isn't supported"));
+    }
+
+    #[test]
+    fn built_in_profiles_are_named_and_patched() {
+        // A named built-in profile, patched for this unit's firmware
+        let patched = device_entry("bedroom", "a1", "0123456789abcdef")
+            + "profile = \"meaco_arete2_25l\"\n[[device.dp]]\nname = \"fault\"\nindex = \"20\"\n\
+               [[device.dp]]\nname = \"dehumidify_set_value\"\nmin = 30\n";
+        let config = load_text("profiles", &patched).unwrap();
+        assert_eq!(config.device.dp("fault").unwrap().index, "20");
+        assert_eq!(config.devices[0].resolved.humidity_range().unwrap().min, 30);
+
+        let error = |text: String| load_error("profiles", &text);
+        assert!(error(patched.replace("\"20\"", "\"1\"")).contains("DP 1 is declared twice"));
+        assert!(error(patched.replace("name = \"fault\"", "name = \"turbo\"")).contains("DP \"turbo\" to override isn't in"));
+        assert!(error(patched.replace("meaco_arete2_25l", "arete3")).contains("no built-in profile is called \"arete3\""));
+    }
+
+    #[test]
+    fn polling_settings_are_checked() {
+        let bedroom = device_entry("bedroom", "a1", "0123456789abcdef");
+        let error = load_error("polling", &(bedroom.clone() + "polling = { interval_secs = 0 }"));
+        assert!(error.contains("\"bedroom\" polling interval_secs: is 0"));
+        let error = load_error("polling", &(bedroom + "[polling]\ninterval_secs = 0\n"));
+        assert!(error.contains("interval_secs: is 0"));
+    }
+
+    #[test]
+    fn influxdb_settings_are_checked() {
+        let bedroom = device_entry("bedroom", "a1", "0123456789abcdef");
+        let config = load_text("influxdb", &bedroom).unwrap();
+        assert!(config.influxdb.url.is_none());
+
+        let error = load_error("influxdb", &(bedroom.clone() + "[influxdb]\nurl = \"localhost:8086\"\norg = \"home\"\n"));
+        assert!(error.contains("[influxdb] url: \"localhost:8086\" isn't an http:// or https:// URL"));
+        assert!(error.contains("[influxdb] bucket: is missing") && error.contains("[influxdb] token: is missing"));
+        assert!(!error.contains("[influxdb] org"));
+
+        let complete = bedroom + "[influxdb]\nurl = \"http://localhost:8086\"\norg = \"home\"\nbucket = \"hearth\"\ntoken = \"t0k3n\"\n";
+        let config = load_text("influxdb", &complete).unwrap();
+        assert_eq!(config.influxdb.measurement, "dehumidifier");
+        assert!(load_error("influxdb", &(complete + "interval_secs = 0\n")).contains("[influxdb] interval_secs: is 0"));
+    }
+
+    #[test]
+    fn every_config_problem_is_reported() {
+        // Every problem is reported, not just the first
+        let broken = device_entry("", "a1", "short").replace("192.168.1.20", "192.168.1.20:6668")
+            + "[connection]\nheartbeat_secs = 0\n[schedule]\nutc_offset_minutes = 2000\n";
+        assert!(load_error("problems", &broken).starts_with("5 problems in the config:"));
+        // Unusual ids only warn
+        let bedroom = device_entry("bedroom", "a1", "0123456789abcdef");
+        assert_eq!(load_text("problems", &bedroom).unwrap().warnings[0].field, "device_id");
+        let no_device = load_error("problems", "[status]\npassive = true");
+        assert!(no_device.starts_with("Invalid config: [meaco] device_id: no device is configured"));
+
+        // Not even a missing device hides the rest
+        let error = load_error("problems", "[status]\nsnapshot_path = \"\"\n");
+        assert!(error.starts_with("2 problems in the config:"), "{error}");
+        assert!(error.contains("no device is configured") && error.contains("[status] snapshot_path: is empty"));
+    }
+
+    #[test]
+    fn polling_settings_layer() {
+        let config: Config = toml::from_str(
+            r#"
+            [meaco]
+            device_ip = "192.168.1.20"
+            device_id = "abc"
+            local_key = "0123456789abcdef"
+            polling = { interval_secs = 30 }
+
+            [polling]
+            enabled = true
+            interval_secs = 120
+
+            [status]
+            cache_ttl_ms = 500
+            "#,
+        )
+        .unwrap();
+
+        // The device's own, then [polling]'s, then the old [status] TTL
+        let polling = config.polling(config.meaco());
+        assert!(polling.enabled);
+        assert_eq!(polling.interval, std::time::Duration::from_secs(30));
+        assert_eq!(polling.cache_ttl, std::time::Duration::from_millis(500));
+        assert!(!polling.refresh_first);
+
+        let bare: Config = toml::from_str("[meaco]\ndevice_ip = \"a\"\ndevice_id = \"b\"\nlocal_key = \"c\"").unwrap();
+        let defaults = bare.polling(bare.meaco());
+        assert!(!defaults.enabled);
+        assert_eq!(defaults.interval, std::time::Duration::from_secs(60));
+        assert_eq!(defaults.cache_ttl, std::time::Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn schema_covers_the_example() {
+        let schema = json_schema();
+        assert_eq!(schema["$schema"], "http://json-schema.org/draft-07/schema#");
+        let properties = schema["properties"].as_object().unwrap();
+
+        // Every table the example shows, commented out or not
+        let example = include_str!("../hearth.toml.example");
+        for line in example.lines().map(|line| line.trim_start_matches("# ")) {
+            let Some(header) = line.strip_prefix('[') else { continue };
+            let table = header.trim_start_matches('[').split(['.', ']']).next().unwrap();
+            assert!(properties.contains_key(table), "[{table}] isn't in the schema");
+        }
+        assert!(schema["definitions"]["DeviceConfig"]["required"].as_array().unwrap().contains(&"local_key".into()));
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ProfileConfig;
+use crate::meaco::{self, DpsError, Fault, HumidityRange};
+
+// -- Device profiles --
+//
+// Which DP index means what, for one model: its type, the values it
+// takes and whether hearth may write it, plus what each fault bit means.
+// Everything that reads or writes the device looks DPs up here by name,
+// so another model is a TOML file away rather than a code change. The
+// Arete Two's profile is built in and used when none is configured, as is
+// Tuya's standard socket schema for smart plugs. A device can name either
+// instead of a file, and patch single DPs of it in its config entry.
+
+/// The built-in profile, also the template for writing one.
+const ARETE_TWO: &str = include_str!("../profiles/meaco-arete-two-25l.toml");
+
+/// The built-in profile for `[plug.<name>]` devices.
+const TUYA_SOCKET: &str = include_str!("../profiles/tuya-socket.toml");
+
+/// Built-in profiles by the names a config can give them by.
+const BUILT_IN: &[(&str, &str)] = &[("meaco_arete2_25l", ARETE_TWO), ("tuya_socket", TUYA_SOCKET)];
+
+/// What kind of device a profile describes, and so which tools drive it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    #[default]
+    Dehumidifier,
+    /// Smart plugs and switches.
+    Socket,
+}
+
+/// What a DP holds, and the values it accepts. Written in a profile as
+/// `type = "integer"` with the variant's fields alongside.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DataPoint {
+    #[serde(rename = "boolean")]
+    Bool,
+    /// An integer, as Tuya's "value" type.
+    #[serde(rename = "integer")]
+    Value {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        step: Option<u32>,
+        /// Decimal places in the raw value.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scale: Option<u32>,
+    },
+    Enum {
+        values: Vec<String>,
+    },
+    /// Flags packed into an integer, bit 0 first. `labels` names them,
+    /// as Tuya's schema does, so status can say which are set.
+    Bitmap {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        labels: Vec<String>,
+    },
+    String,
+}
+
+impl DataPoint {
+    /// A bitmap DP's bit names; none for any other type.
+    pub fn labels(&self) -> &[String] {
+        match self {
+            DataPoint::Bitmap { labels } => labels,
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DpDefinition {
+    /// The DP's index on the device, e.g. "1".
+    pub index: String,
+    /// What hearth calls it; tools look DPs up by this.
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: DataPoint,
+    #[serde(default)]
+    pub writable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// How status shows the DP, where hearth has no wording of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Changes to one of a profile's DPs, found by `name`, as a config's
+/// `[[device.dp]]` entry gives them: a different index, say, where one
+/// firmware revision moved it, or other integer bounds.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DpOverride {
+    pub name: String,
+    #[serde(default)]
+    pub index: Option<String>,
+    #[serde(default)]
+    pub writable: Option<bool>,
+    #[serde(default)]
+    pub min: Option<u32>,
+    #[serde(default)]
+    pub max: Option<u32>,
+    #[serde(default)]
+    pub step: Option<u32>,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub model: String,
+    #[serde(default)]
+    pub category: Category,
+    #[serde(rename = "dp")]
+    pub dps: Vec<DpDefinition>,
+    /// Bits of the `fault` DP.
+    #[serde(default, rename = "fault")]
+    pub faults: Vec<Fault>,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io { path, source } => {
+                write!(f, "Can't read device profile {path}: {source}")
+            }
+            ProfileError::Parse(msg) => write!(f, "Failed to parse device profile: {msg}"),
+            ProfileError::Invalid(msg) => write!(f, "Invalid device profile: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        parse(ARETE_TWO).expect("built-in profile is valid")
+    }
+}
+
+impl DeviceProfile {
+    pub fn dp(&self, name: &str) -> Option<&DpDefinition> {
+        self.dps.iter().find(|dp| dp.name == name)
+    }
+
+    /// The named DP's value in a DPS object from the device.
+    pub fn read<'a>(
+        &self,
+        dps: &'a serde_json::Value,
+        name: &str,
+    ) -> Option<&'a serde_json::Value> {
+        dps.get(&self.dp(name)?.index)
+    }
+
+    /// The named integer DP's value with its `scale` applied.
+    pub fn scaled(&self, dps: &serde_json::Value, name: &str) -> Option<f64> {
+        let dp = self.dp(name)?;
+        let DataPoint::Value { scale, .. } = dp.kind else {
+            return None;
+        };
+        let raw = dps.get(&dp.index)?.as_i64()?;
+        Some(raw as f64 / 10f64.powi(scale.unwrap_or(0) as i32))
+    }
+
+    /// Whether hearth may write the named DP on this model.
+    pub fn writable(&self, name: &str) -> bool {
+        self.dp(name).is_some_and(|dp| dp.writable)
+    }
+
+    /// Every DP index, for queries that have to name them.
+    pub fn indices(&self) -> Vec<String> {
+        self.dps.iter().map(|dp| dp.index.clone()).collect()
+    }
+
+    /// The target humidity's declared bounds, if it has all three.
+    pub fn humidity_range(&self) -> Option<HumidityRange> {
+        match self.dp(meaco::TARGET_HUMIDITY)?.kind {
+            DataPoint::Value {
+                min: Some(min),
+                max: Some(max),
+                step: Some(step),
+                ..
+            } => Some(HumidityRange { min, max, step }),
+            _ => None,
+        }
+    }
+}
+
+/// DPs to send together in one CONTROL frame. Each is checked against
+/// the profile as it's added, so nothing it doesn't declare writable, or
+/// a value it doesn't accept, reaches the device.
+#[derive(Debug)]
+pub struct DpsWrite<'a> {
+    profile: &'a DeviceProfile,
+    dps: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> DpsWrite<'a> {
+    pub fn new(profile: &'a DeviceProfile) -> Self {
+        Self {
+            profile,
+            dps: serde_json::Map::new(),
+        }
+    }
+
+    /// Add the named DP, within the profile's bounds if it's an integer.
+    pub fn set(self, name: &str, value: impl Into<serde_json::Value>) -> Result<Self, DpsError> {
+        self.checked(name, value.into(), None)
+    }
+
+    /// Add an integer DP checked against `range` rather than the
+    /// profile's bounds: the target humidity's can be re-probed at
+    /// runtime and differ from what the profile says.
+    pub fn set_within(
+        self,
+        name: &str,
+        value: u32,
+        range: &HumidityRange,
+    ) -> Result<Self, DpsError> {
+        self.checked(name, value.into(), Some(range))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dps.is_empty()
+    }
+
+    /// The DPS object to send.
+    pub fn build(self) -> serde_json::Value {
+        serde_json::Value::Object(self.dps)
+    }
+
+    fn checked(
+        mut self,
+        name: &str,
+        value: serde_json::Value,
+        range: Option<&HumidityRange>,
+    ) -> Result<Self, DpsError> {
+        let dp = self
+            .profile
+            .dp(name)
+            .ok_or_else(|| DpsError::Undeclared(name.to_owned()))?;
+        if !dp.writable {
+            return Err(DpsError::NotWritable(name.to_owned()));
+        }
+        let rejected = |expected: String| DpsError::Rejected {
+            name: name.to_owned(),
+            raw: value.to_string(),
+            expected,
+        };
+
+        match &dp.kind {
+            DataPoint::Bool if !value.is_boolean() => return Err(rejected("true or false".into())),
+            DataPoint::Value { min, max, step, .. } => {
+                let number = value
+                    .as_u64()
+                    .and_then(|v| u32::try_from(v).ok())
+                    .ok_or_else(|| rejected("an integer".into()))?;
+                let range = range.copied().unwrap_or(HumidityRange {
+                    min: min.unwrap_or(0),
+                    max: max.unwrap_or(u32::MAX),
+                    step: step.unwrap_or(1),
+                });
+                if !range.accepts(number) {
+                    return Err(DpsError::OutOfRange {
+                        name: name.to_owned(),
+                        value: number,
+                        range,
+                    });
+                }
+            }
+            DataPoint::Enum { values }
+                if !value
+                    .as_str()
+                    .is_some_and(|v| values.iter().any(|a| a == v)) =>
+            {
+                return Err(rejected(values.join(", ")));
+            }
+            DataPoint::Bitmap { .. } if !value.is_u64() => {
+                return Err(rejected("a bitmap integer".into()));
+            }
+            DataPoint::String if !value.is_string() => return Err(rejected("a string".into())),
+            _ => {}
+        }
+
+        self.dps.insert(dp.index.clone(), value);
+        Ok(self)
+    }
+}
+
+/// The bits set in `bitmap`, by their labels. Bits without one are
+/// named by position, e.g. "bit9".
+pub fn decode_bitmap(bitmap: u32, labels: &[String]) -> Vec<String> {
+    (0..32)
+        .filter(|bit| bitmap & (1 << bit) != 0)
+        .map(|bit| match labels.get(bit as usize) {
+            Some(label) => label.clone(),
+            None => format!("bit{bit}"),
+        })
+        .collect()
+}
+
+pub fn parse(text: &str) -> Result<DeviceProfile, ProfileError> {
+    let profile: DeviceProfile =
+        toml::from_str(text).map_err(|e| ProfileError::Parse(e.to_string()))?;
+    validate(&profile)?;
+    Ok(profile)
+}
+
+pub fn load(path: &str) -> Result<DeviceProfile, ProfileError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ProfileError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    parse(&text)
+}
+
+/// The built-in profile called `name`, or else the file at that path.
+/// A bare word that's neither is taken for a mistyped name.
+pub fn load_named(name: &str) -> Result<DeviceProfile, ProfileError> {
+    if let Some((_, text)) = BUILT_IN.iter().find(|(builtin, _)| *builtin == name) {
+        return parse(text);
+    }
+    if !name.contains(['/', '\\', '.']) {
+        let known: Vec<&str> = BUILT_IN.iter().map(|(name, _)| *name).collect();
+        return Err(ProfileError::Invalid(format!(
+            "no built-in profile is called \"{name}\" (built in: {}); a file needs its path",
+            known.join(", ")
+        )));
+    }
+    load(name)
+}
+
+/// Apply `overrides` to `profile`'s DPs in order. Remapping a DP onto an
+/// index another still holds is left to `validate` to refuse.
+pub fn apply_overrides(profile: &mut DeviceProfile, overrides: &[DpOverride]) -> Result<(), ProfileError> {
+    for change in overrides {
+        let dp = profile
+            .dps
+            .iter_mut()
+            .find(|dp| dp.name == change.name)
+            .ok_or_else(|| ProfileError::Invalid(format!("DP \"{}\" to override isn't in \"{}\"", change.name, profile.model)))?;
+        if let Some(index) = &change.index {
+            dp.index = index.clone();
+        }
+        if let Some(writable) = change.writable {
+            dp.writable = writable;
+        }
+        if let Some(label) = &change.label {
+            dp.label = Some(label.clone());
+        }
+        if change.min.is_none() && change.max.is_none() && change.step.is_none() {
+            continue;
+        }
+        let DataPoint::Value { min, max, step, .. } = &mut dp.kind else {
+            return Err(ProfileError::Invalid(format!(
+                "DP \"{}\" isn't an integer, so has no min, max or step",
+                change.name
+            )));
+        };
+        *min = change.min.or(*min);
+        *max = change.max.or(*max);
+        *step = change.step.or(*step);
+    }
+    Ok(())
+}
+
+/// The profile `[profile]` describes: its `path`'s or the built-in one,
+/// with the shorthand DP, override and fault entries layered on top.
+pub fn resolve(config: &ProfileConfig) -> Result<DeviceProfile, ProfileError> {
+    let mut profile = match &config.path {
+        Some(path) => load_named(path)?,
+        None => DeviceProfile::default(),
+    };
+    expect_category(&profile, Category::Dehumidifier)?;
+
+    let low_high = || DataPoint::Enum {
+        values: vec!["low".to_owned(), "high".to_owned()],
+    };
+    let shorthand = [
+        (&config.fan_speed_dp, meaco::FAN_SPEED, low_high()),
+        (&config.ionizer_dp, meaco::IONIZER, DataPoint::Bool),
+        (&config.sleep_dp, meaco::SLEEP, DataPoint::Bool),
+    ];
+    for (index, name, kind) in shorthand {
+        let Some(index) = index else { continue };
+        profile
+            .dps
+            .retain(|dp| dp.name != name && dp.index != *index);
+        profile.dps.push(DpDefinition {
+            index: index.clone(),
+            name: name.to_owned(),
+            kind,
+            writable: true,
+            unit: None,
+            description: None,
+            label: None,
+        });
+    }
+    apply_overrides(&mut profile, &config.dps)?;
+
+    for fault in &config.faults {
+        profile.faults.retain(|f| f.bit != fault.bit);
+        profile.faults.push(fault.clone());
+    }
+    profile.faults.sort_by_key(|f| f.bit);
+
+    validate(&profile)?;
+    Ok(profile)
+}
+
+/// A plug's profile: the one `path` names, or the built-in socket one.
+pub fn resolve_plug(path: Option<&str>) -> Result<DeviceProfile, ProfileError> {
+    let profile = match path {
+        Some(path) => load_named(path)?,
+        None => parse(TUYA_SOCKET)?,
+    };
+    expect_category(&profile, Category::Socket)?;
+    Ok(profile)
+}
+
+fn expect_category(profile: &DeviceProfile, category: Category) -> Result<(), ProfileError> {
+    if profile.category != category {
+        return Err(ProfileError::Invalid(format!(
+            "\"{}\" is a {:?} profile, expected {category:?}",
+            profile.model, profile.category
+        )));
+    }
+    Ok(())
+}
+
+fn validate(profile: &DeviceProfile) -> Result<(), ProfileError> {
+    let invalid = |msg: String| Err(ProfileError::Invalid(msg));
+    let mut indices = BTreeSet::new();
+    let mut names = BTreeSet::new();
+
+    for dp in &profile.dps {
+        if dp.index.is_empty() || !dp.index.bytes().all(|b| b.is_ascii_digit()) {
+            return invalid(format!("DP index \"{}\" isn't a number", dp.index));
+        }
+        if !indices.insert(&dp.index) {
+            return invalid(format!("DP {} is declared twice", dp.index));
+        }
+        if !names.insert(&dp.name) {
+            return invalid(format!("two DPs are named \"{}\"", dp.name));
+        }
+        match &dp.kind {
+            DataPoint::Enum { values } if values.is_empty() => {
+                return invalid(format!("enum DP \"{}\" lists no values", dp.name));
+            }
+            DataPoint::Value {
+                min: Some(min),
+                max: Some(max),
+                ..
+            } if min > max => {
+                return invalid(format!("DP \"{}\" has min {min} above max {max}", dp.name));
+            }
+            DataPoint::Value { step: Some(0), .. } => {
+                return invalid(format!("DP \"{}\" has a step of 0", dp.name));
+            }
+            DataPoint::Bitmap { labels } if labels.len() > 32 => {
+                return invalid(format!(
+                    "bitmap DP \"{}\" labels more than 32 bits",
+                    dp.name
+                ));
+            }
+            _ => {}
+        }
+    }
+    if let Some(fault) = profile.faults.iter().find(|f| f.bit > 31) {
+        return invalid(format!(
+            "fault bit {} is past the end of the bitmap",
+            fault.bit
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_load_and_check_writes() {
+        let arete = DeviceProfile::default();
+        assert_eq!(arete.model, "MeacoDryArete2-25L");
+        assert_eq!(
+            arete.indices(),
+            ["1", "2", "4", "14", "16", "17", "18", "19", "101"]
+        );
+        assert_eq!(arete.humidity_range(), Some(meaco::ARETE_TWO_HUMIDITY));
+        assert_eq!(arete.faults.len(), 8);
+
+        let write = DpsWrite::new(&arete)
+            .set("switch", true)
+            .and_then(|w| w.set("mode", "auto"))
+            .and_then(|w| w.set("dehumidify_set_value", 50))
+            .unwrap();
+        assert_eq!(
+            write.build(),
+            serde_json::json!({"1": true, "4": "auto", "2": 50})
+        );
+
+        let set = |name: &str, value: serde_json::Value| DpsWrite::new(&arete).set(name, value);
+        assert!(matches!(
+            set("mode", "turbo".into()),
+            Err(DpsError::Rejected { .. })
+        ));
+        assert!(matches!(
+            set("switch", 1.into()),
+            Err(DpsError::Rejected { .. })
+        ));
+        assert!(matches!(
+            set("dehumidify_set_value", 52.into()),
+            Err(DpsError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            set("humidity_indoor", 50.into()),
+            Err(DpsError::NotWritable(_))
+        ));
+        assert!(matches!(
+            set("fan_speed", "low".into()),
+            Err(DpsError::Undeclared(_))
+        ));
+
+        // A probed range replaces the profile's bounds
+        let probed = HumidityRange {
+            min: 30,
+            max: 80,
+            step: 1,
+        };
+        assert!(
+            DpsWrite::new(&arete)
+                .set_within("dehumidify_set_value", 52, &probed)
+                .is_ok()
+        );
+
+        let other = parse(
+            r#"
+            model = "Generic 12L"
+            [[dp]]
+            index = "1"
+            name = "switch"
+            type = "boolean"
+            writable = true
+            [[dp]]
+            index = "3"
+            name = "dehumidify_set_value"
+            type = "integer"
+            writable = true
+            min = 30
+            max = 80
+            step = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            other.read(&serde_json::json!({"3": 45}), "dehumidify_set_value"),
+            Some(&45.into())
+        );
+        assert_eq!(
+            other.humidity_range(),
+            Some(HumidityRange {
+                min: 30,
+                max: 80,
+                step: 1
+            })
+        );
+        assert!(other.faults.is_empty());
+
+        assert!(
+            parse("model = \"x\"\n[[dp]]\nindex = \"a\"\nname = \"switch\"\ntype = \"boolean\"")
+                .is_err()
+        );
+        assert!(
+            parse("model = \"x\"\n[[dp]]\nindex = \"4\"\nname = \"mode\"\ntype = \"enum\"")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use serde::Serialize;
+use tokio::net::UdpSocket;
+
+use crate::tuya_protocol;
+
+// -- LAN discovery --
+//
+// Tuya devices broadcast an announcement every few seconds: gwId, IP,
+// protocol version. We listen for them to find out what's on the network
+// and where the configured device currently is. Anything that isn't
+// configured can be promoted into hearth.toml once the user has its key.
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiscoveredDevice {
+    /// The device's gwId — what config calls `device_id`.
+    pub device_id: String,
+    pub ip: String,
+    /// Protocol version the device announced, e.g. "3.3".
+    pub version: String,
+    pub product_key: Option<String>,
+}
+
+/// A device as last heard, for the `discover_devices` tool.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sighting {
+    #[serde(flatten)]
+    pub device: DiscoveredDevice,
+    pub configured: bool,
+    pub seen_secs_ago: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct DiscoveredDevices {
+    /// Device ids already in config — never listed as unconfigured.
+    configured: RwLock<BTreeSet<String>>,
+    /// Every device heard from, with when we last heard it.
+    devices: Mutex<BTreeMap<String, (DiscoveredDevice, Instant)>>,
+    /// Whether any broadcast port could be bound. Another Tuya tool on
+    /// the same host may hold them.
+    listening: AtomicBool,
+}
+
+pub fn new_registry<'a>(configured: impl IntoIterator<Item = &'a str>) -> DiscoveredDevices {
+    DiscoveredDevices {
+        configured: RwLock::new(configured.into_iter().map(str::to_owned).collect()),
+        devices: Mutex::default(),
+        listening: AtomicBool::new(false),
+    }
+}
+
+/// Count a device added to the config since startup as configured.
+pub fn add_configured(registry: &DiscoveredDevices, device_id: &str) {
+    registry.configured.write().expect("configured devices lock poisoned").insert(device_id.to_owned());
+}
+
+fn is_configured(registry: &DiscoveredDevices, device_id: &str) -> bool {
+    registry.configured.read().expect("configured devices lock poisoned").contains(device_id)
+}
+
+pub fn listening(registry: &DiscoveredDevices) -> bool {
+    registry.listening.load(Ordering::Relaxed)
+}
+
+/// Remember an announcement. Returns true the first time an unconfigured
+/// device is seen; later announcements just refresh its details.
+pub fn record(registry: &DiscoveredDevices, device: DiscoveredDevice) -> bool {
+    let mut devices = registry.devices.lock().expect("discovered devices lock poisoned");
+    let configured = is_configured(registry, &device.device_id);
+    let previous = devices.insert(device.device_id.clone(), (device.clone(), Instant::now()));
+
+    match previous {
+        None if !configured => {
+            tracing::info!(
+                device_id = %device.device_id,
+                ip = %device.ip,
+                version = %device.version,
+                "Discovered unconfigured Tuya device"
+            );
+            true
+        }
+        Some((old, _)) if old.ip != device.ip => {
+            tracing::info!(device_id = %device.device_id, from = %old.ip, to = %device.ip, "Device moved");
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Every unconfigured device seen since startup, by device id.
+pub fn unconfigured(registry: &DiscoveredDevices) -> Vec<DiscoveredDevice> {
+    registry
+        .devices
+        .lock()
+        .expect("discovered devices lock poisoned")
+        .values()
+        .filter(|(device, _)| !is_configured(registry, &device.device_id))
+        .map(|(device, _)| device.clone())
+        .collect()
+}
+
+/// Every device heard from since startup, configured or not.
+pub fn sightings(registry: &DiscoveredDevices) -> Vec<Sighting> {
+    registry
+        .devices
+        .lock()
+        .expect("discovered devices lock poisoned")
+        .values()
+        .map(|(device, seen)| Sighting {
+            device: device.clone(),
+            configured: is_configured(registry, &device.device_id),
+            seen_secs_ago: seen.elapsed().as_secs(),
+        })
+        .collect()
+}
+
+pub fn find(registry: &DiscoveredDevices, device_id: &str) -> Option<DiscoveredDevice> {
+    registry
+        .devices
+        .lock()
+        .expect("discovered devices lock poisoned")
+        .get(device_id)
+        .map(|(device, _)| device.clone())
+}
+
+/// The address a device last announced itself from.
+pub fn resolve_ip(registry: &DiscoveredDevices, device_id: &str) -> Option<String> {
+    find(registry, device_id).map(|device| device.ip)
+}
+
+/// Pull the fields we care about out of a decoded announcement.
+/// Devices that predate the `version` field are 3.1.
+pub fn parse_announcement(payload: &[u8]) -> Option<DiscoveredDevice> {
+    let json: serde_json::Value = serde_json::from_slice(payload).ok()?;
+    let field = |name: &str| json.get(name).and_then(|v| v.as_str()).map(str::to_owned);
+
+    Some(DiscoveredDevice {
+        device_id: field("gwId")?,
+        ip: field("ip")?,
+        version: field("version").unwrap_or_else(|| "3.1".into()),
+        product_key: field("productKey"),
+    })
+}
+
+/// Listen on both broadcast ports, recording every announcement heard.
+/// A port that can't be bound (another Tuya tool already has it) is
+/// logged and skipped — discovery is a convenience, not a requirement.
+pub async fn spawn_listener(registry: Arc<DiscoveredDevices>) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut tasks = Vec::new();
+
+    for port in tuya_protocol::BROADCAST_PORTS {
+        let socket = match UdpSocket::bind(("0.0.0.0", port)).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                tracing::warn!(port, "LAN discovery unavailable on this port: {e}");
+                continue;
+            }
+        };
+
+        registry.listening.store(true, Ordering::Relaxed);
+        let registry = registry.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            loop {
+                let len = match socket.recv_from(&mut buf).await {
+                    Ok((len, _)) => len,
+                    Err(e) => {
+                        tracing::warn!(port, "Discovery receive failed: {e}");
+                        continue;
+                    }
+                };
+
+                let announcement = tuya_protocol::parse_broadcast(&buf[..len])
+                    .ok()
+                    .and_then(|payload| parse_announcement(&payload));
+                match announcement {
+                    Some(device) => {
+                        record(&registry, device);
+                    }
+                    None => tracing::trace!(port, len, "Ignoring unrecognised broadcast"),
+                }
+            }
+        }));
+    }
+
+    tasks
+}
+
+/// A `[[device]]` entry for a promoted device, ready to append to
+/// hearth.toml.
+pub fn config_snippet(device: &DiscoveredDevice, name: &str, local_key: &str) -> String {
+    format!(
+        "[[device]]\nname = {}\ndevice_ip = \"{}\"\ndevice_id = \"{}\"\nlocal_key = \"{local_key}\"\n",
+        toml::Value::from(name),
+        device.ip,
+        device.device_id
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn announcement(device_id: &str, ip: &str) -> DiscoveredDevice {
+        DiscoveredDevice {
+            device_id: device_id.into(),
+            ip: ip.into(),
+            version: "3.3".into(),
+            product_key: None,
+        }
+    }
+
+    #[test]
+    fn lists_only_unconfigured_devices() {
+        let registry = new_registry(["meaco"]);
+
+        assert!(!record(&registry, announcement("meaco", "10.0.0.5")));
+        assert!(record(&registry, announcement("plug", "10.0.0.6")));
+        // Seen again on a new address: not new, but the address is updated
+        assert!(!record(&registry, announcement("plug", "10.0.0.7")));
+
+        assert_eq!(unconfigured(&registry), vec![announcement("plug", "10.0.0.7")]);
+        assert_eq!(resolve_ip(&registry, "meaco").as_deref(), Some("10.0.0.5"));
+    }
+
+    #[test]
+    fn parses_announcements() {
+        let payload = br#"{"ip":"10.0.0.9","gwId":"bf01","active":2,"encrypt":true,"productKey":"pk1","version":"3.3"}"#;
+        let device = parse_announcement(payload).unwrap();
+        assert_eq!(device.device_id, "bf01");
+        assert_eq!(device.ip, "10.0.0.9");
+        assert_eq!(device.version, "3.3");
+        assert_eq!(device.product_key.as_deref(), Some("pk1"));
+
+        let legacy = parse_announcement(br#"{"ip":"10.0.0.8","gwId":"old"}"#).unwrap();
+        assert_eq!(legacy.version, "3.1");
+        assert!(parse_announcement(br#"{"ip":"10.0.0.8"}"#).is_none());
+    }
+
+    #[test]
+    fn promoted_devices_become_named_entries() {
+        let snippet = config_snippet(&announcement("bf01", "10.0.0.9"), "cellar", "0123456789abcdef");
+        assert_eq!(
+            snippet,
+            "[[device]]\nname = \"cellar\"\ndevice_ip = \"10.0.0.9\"\ndevice_id = \"bf01\"\nlocal_key = \"0123456789abcdef\"\n"
+        );
+        // Any name survives the trip through TOML
+        let quoted: toml::Table = config_snippet(&announcement("bf01", "10.0.0.9"), "Sam's \"den\"", "k").parse().unwrap();
+        assert_eq!(quoted["device"][0]["name"].as_str(), Some("Sam's \"den\""));
+    }
+}
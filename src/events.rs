@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::meaco::{self, StatusChange};
+use crate::tuya_connection::ConnectionState;
+
+// -- Device event bus --
+//
+// The connection layer publishes what it sees; the server, poller and any
+// integration subscribe independently instead of polling each other.
+
+const BUS_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    /// One or more DPS values differ from the last ones seen.
+    /// Carries only the changed keys, raw as the device reported them.
+    StatusChanged { changed: serde_json::Map<String, serde_json::Value> },
+    /// Typed status fields differ from the last status hearth built,
+    /// from a query or from DPs the device volunteered.
+    FieldsChanged { changes: Vec<StatusChange> },
+    /// hearth is about to write these DPS, so changes that follow are ours.
+    ControlSent { dps: serde_json::Value },
+    /// The fault bitmap changed. `active` is empty once faults clear.
+    Fault { bitmap: u32, active: Vec<String> },
+    StateChanged { from: ConnectionState, to: ConnectionState },
+    Disconnected { reason: String },
+    Reconnected { address: String },
+}
+
+/// Broadcast sender plus a dropped-event counter per named consumer.
+/// A consumer that falls more than `BUS_CAPACITY` events behind loses the
+/// oldest ones; we count and report that rather than losing them silently.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<DeviceEvent>,
+    dropped: Arc<Mutex<BTreeMap<&'static str, Arc<AtomicU64>>>>,
+}
+
+/// A named receiver. Use `next_event` rather than the raw receiver so lag
+/// is detected and accounted for.
+pub struct Subscription {
+    name: &'static str,
+    rx: broadcast::Receiver<DeviceEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+pub fn new_bus() -> EventBus {
+    EventBus {
+        tx: broadcast::channel(BUS_CAPACITY).0,
+        dropped: Arc::default(),
+    }
+}
+
+/// Publish to whoever is listening. No subscribers is not an error.
+pub fn publish(bus: &EventBus, event: DeviceEvent) {
+    let _ = bus.tx.send(event);
+}
+
+pub fn subscribe(bus: &EventBus, name: &'static str) -> Subscription {
+    let dropped = bus
+        .dropped
+        .lock()
+        .expect("event lag lock poisoned")
+        .entry(name)
+        .or_default()
+        .clone();
+
+    Subscription {
+        name,
+        rx: bus.tx.subscribe(),
+        dropped,
+    }
+}
+
+/// Wait for the next event. Lag is logged and counted, then skipped past.
+/// Returns None once the bus is gone.
+pub async fn next_event(sub: &mut Subscription) -> Option<DeviceEvent> {
+    loop {
+        match sub.rx.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                let total = sub.dropped.fetch_add(missed, Ordering::Relaxed) + missed;
+                tracing::warn!(
+                    consumer = sub.name,
+                    dropped = missed,
+                    dropped_total = total,
+                    "Event consumer lagging, events dropped"
+                );
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Total events each consumer has lost to lag since startup.
+pub fn dropped_events(bus: &EventBus) -> BTreeMap<&'static str, u64> {
+    bus.dropped
+        .lock()
+        .expect("event lag lock poisoned")
+        .iter()
+        .map(|(name, count)| (*name, count.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// Log every event — the baseline subscriber — against `device`'s name.
+pub fn spawn_logger(bus: &EventBus, device: &str) -> tokio::task::JoinHandle<()> {
+    let mut sub = subscribe(bus, "event_log");
+    let device = device.to_owned();
+
+    tokio::spawn(async move {
+        while let Some(event) = next_event(&mut sub).await {
+            match event {
+                DeviceEvent::StatusChanged { changed } => {
+                    tracing::debug!(%device, changed = %serde_json::Value::Object(changed), "Status changed");
+                }
+                DeviceEvent::FieldsChanged { changes } => {
+                    tracing::debug!(%device, changes = %meaco::format_changes(&changes), "Status fields changed");
+                }
+                DeviceEvent::ControlSent { dps } => {
+                    tracing::debug!(%device, %dps, "Control sent");
+                }
+                DeviceEvent::Fault { bitmap, active } => {
+                    tracing::warn!(%device, bitmap, ?active, "Fault state changed");
+                }
+                DeviceEvent::StateChanged { from, to } => {
+                    tracing::info!(%device, %from, %to, "Connection state changed");
+                }
+                DeviceEvent::Disconnected { reason } => {
+                    tracing::warn!(%device, reason, "Device disconnected");
+                }
+                DeviceEvent::Reconnected { address } => {
+                    tracing::info!(%device, address, "Device reconnected");
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lagging_consumer_counts_dropped_events() {
+        let bus = new_bus();
+        let mut sub = subscribe(&bus, "slow");
+
+        let overflow = 5;
+        for _ in 0..BUS_CAPACITY + overflow {
+            publish(&bus, DeviceEvent::Disconnected { reason: "test".into() });
+        }
+
+        assert!(next_event(&mut sub).await.is_some());
+        assert_eq!(dropped_events(&bus)["slow"], overflow as u64);
+    }
+}
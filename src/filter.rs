@@ -0,0 +1,205 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::device_profile::DeviceProfile;
+use crate::events::{self, DeviceEvent, EventBus};
+use crate::meaco;
+use crate::watchdog;
+
+// -- Filter reminder --
+//
+// Some models raise a filter reminder DP of their own, which status shows
+// as reported. For the rest, hearth counts the hours the unit runs and
+// says the filter is due after `[filter] reminder_hours`. The device
+// doesn't report compressor runtime, so time switched on stands in for
+// it: the compressor rests once the target is reached, which makes the
+// reminder early rather than late. `reset_filter_reminder` records a
+// cleaning and starts the count again.
+
+/// How often the running total is saved while the unit is on, so a
+/// restart loses little of it.
+const SAVE_EVERY: Duration = Duration::from_secs(300);
+
+/// Running time since the filter was last cleaned, as kept on disk.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterUsage {
+    /// Seconds the unit has been on.
+    pub run_secs: u64,
+    /// Unix seconds. None until the first reset.
+    pub cleaned_at: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct FilterTracker {
+    usage: Mutex<FilterUsage>,
+    /// When the current run started, while the unit is on.
+    running_since: Mutex<Option<Instant>>,
+    reminder_hours: u32,
+    path: String,
+}
+
+#[derive(Debug)]
+pub enum FilterError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Io(e) => write!(f, "Filter usage file error: {e}"),
+            FilterError::Parse(msg) => write!(f, "Failed to parse filter usage file: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/// A tracker carrying on from the usage saved at `path`, if any.
+pub fn open(path: &str, reminder_hours: u32) -> Result<FilterTracker, FilterError> {
+    Ok(FilterTracker {
+        usage: Mutex::new(load(path)?),
+        running_since: Mutex::new(None),
+        reminder_hours,
+        path: path.to_owned(),
+    })
+}
+
+pub fn load(path: &str) -> Result<FilterUsage, FilterError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| FilterError::Parse(e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FilterUsage::default()),
+        Err(e) => Err(FilterError::Io(e)),
+    }
+}
+
+pub fn save(path: &str, usage: &FilterUsage) -> Result<(), FilterError> {
+    let json = serde_json::to_string_pretty(usage).map_err(|e| FilterError::Parse(e.to_string()))?;
+    std::fs::write(path, json).map_err(FilterError::Io)
+}
+
+/// Whole hours run since the filter was last cleaned, the current run
+/// included.
+pub fn hours_run(tracker: &FilterTracker) -> u32 {
+    let usage = tracker.usage.lock().expect("filter lock poisoned");
+    let running = tracker.running_since.lock().expect("filter lock poisoned").map(|since| since.elapsed());
+    let secs = usage.run_secs + running.unwrap_or_default().as_secs();
+    (secs / 3600) as u32
+}
+
+pub fn due(tracker: &FilterTracker) -> bool {
+    hours_run(tracker) >= tracker.reminder_hours
+}
+
+/// Add the current run to the total and save it, then carry on counting
+/// if the unit is `on`.
+fn checkpoint(tracker: &FilterTracker, on: bool) -> Result<(), FilterError> {
+    let mut usage = tracker.usage.lock().expect("filter lock poisoned");
+    let mut running_since = tracker.running_since.lock().expect("filter lock poisoned");
+    let now = Instant::now();
+    if let Some(since) = *running_since {
+        // Whole seconds only; the remainder stays in the current run
+        let secs = now.duration_since(since).as_secs();
+        usage.run_secs += secs;
+        *running_since = Some(since + Duration::from_secs(secs));
+    }
+    if !on {
+        *running_since = None;
+    } else if running_since.is_none() {
+        *running_since = Some(now);
+    }
+    save(&tracker.path, &usage)
+}
+
+/// The filter has been cleaned: start counting again from zero.
+pub fn reset(tracker: &FilterTracker) -> Result<(), FilterError> {
+    let mut usage = tracker.usage.lock().expect("filter lock poisoned");
+    let mut running_since = tracker.running_since.lock().expect("filter lock poisoned");
+    *usage = FilterUsage {
+        run_secs: 0,
+        cleaned_at: Some(watchdog::unix_secs(SystemTime::now())),
+    };
+    if running_since.is_some() {
+        *running_since = Some(Instant::now());
+    }
+    save(&tracker.path, &usage)
+}
+
+/// Count the time the unit is on, from the switch DP as the device
+/// reports it.
+pub fn spawn_tracker(
+    bus: &EventBus,
+    tracker: Arc<FilterTracker>,
+    profile: DeviceProfile,
+) -> tokio::task::JoinHandle<()> {
+    let mut sub = events::subscribe(bus, "filter_tracker");
+
+    tokio::spawn(async move {
+        let Some(index) = profile.dp(meaco::SWITCH).map(|dp| dp.index.clone()) else {
+            return;
+        };
+        let mut interval = tokio::time::interval(SAVE_EVERY);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let on = tokio::select! {
+                event = events::next_event(&mut sub) => match event {
+                    Some(DeviceEvent::StatusChanged { changed }) => match changed.get(&index).and_then(|v| v.as_bool()) {
+                        Some(on) => on,
+                        None => continue,
+                    },
+                    Some(_) => continue,
+                    None => return,
+                },
+                _ = interval.tick() => {
+                    if tracker.running_since.lock().expect("filter lock poisoned").is_none() {
+                        continue;
+                    }
+                    true
+                }
+            };
+            if let Err(e) = checkpoint(&tracker, on) {
+                tracing::warn!(path = %tracker.path, "Can't save filter usage: {e}");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_hours_on_until_reset() {
+        let path = std::env::temp_dir().join(format!("hearth-filter-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let tracker = open(path, 3).unwrap();
+
+        checkpoint(&tracker, true).unwrap();
+        tokio::time::advance(Duration::from_secs(2 * 3600 + 1800)).await;
+        assert_eq!(hours_run(&tracker), 2);
+        assert!(!due(&tracker));
+
+        // Time switched off doesn't count
+        checkpoint(&tracker, false).unwrap();
+        tokio::time::advance(Duration::from_secs(10 * 3600)).await;
+        checkpoint(&tracker, true).unwrap();
+        tokio::time::advance(Duration::from_secs(1800)).await;
+        assert_eq!(hours_run(&tracker), 3);
+        assert!(due(&tracker));
+
+        // What was saved carries on after a restart
+        checkpoint(&tracker, true).unwrap();
+        assert_eq!(load(path).unwrap().run_secs, 3 * 3600);
+
+        reset(&tracker).unwrap();
+        assert_eq!(hours_run(&tracker), 0);
+        assert!(load(path).unwrap().cleaned_at.is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+}
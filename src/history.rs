@@ -0,0 +1,517 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::config::HistoryConfig;
+use crate::device_profile::DeviceProfile;
+use crate::meaco::{self, DehumidifierStatus};
+use crate::tuya_connection::{self, Deadline, TuyaConnection};
+use crate::watchdog;
+
+// -- Reading history --
+//
+// Samples the device every few minutes and appends the readings to a
+// JSON-lines file, so "what was the humidity overnight?" has an answer.
+// One line per sample keeps writes cheap and a torn last line harmless;
+// queries read the file and bucket it to the resolution asked for.
+//
+// Without a file, the `[polling]` poller's readings are kept in a ring
+// buffer instead: a few hours of "what happened lately?" at no cost, gone
+// on restart.
+
+/// How long a background sample may take before it's skipped.
+const SAMPLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One sample. Fields are None if the device didn't report them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reading {
+    /// Unix seconds.
+    pub t: u64,
+    pub humidity: Option<u32>,
+    pub target: Option<u32>,
+    pub power: Option<bool>,
+    pub fault: Option<u32>,
+    /// Only on models whose profile declares a temperature DP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+}
+
+/// Readings summarised over one interval.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    /// Unix seconds.
+    pub start: u64,
+    pub readings: usize,
+    pub humidity_avg: Option<f64>,
+    pub humidity_min: Option<u32>,
+    pub humidity_max: Option<u32>,
+    /// The last target in the interval.
+    pub target: Option<u32>,
+    /// Share of readings with the power on, 0-100.
+    pub power_on_pct: Option<u32>,
+    /// Every fault bit seen in the interval.
+    pub fault: u32,
+    pub temperature_avg: Option<f64>,
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Io(std::io::Error),
+    Parse(String),
+    InvalidDate(String),
+}
+
+impl std::fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryError::Io(e) => write!(f, "History file error: {e}"),
+            HistoryError::Parse(msg) => write!(f, "Failed to encode reading: {msg}"),
+            HistoryError::InvalidDate(date) => write!(
+                f,
+                "Invalid date \"{date}\": expected YYYY-MM-DD or YYYY-MM-DD HH:MM (UTC)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+pub fn reading_from_dps(dps: &serde_json::Value, t: u64, profile: &DeviceProfile) -> Reading {
+    let number = |name| profile.read(dps, name).and_then(|v| v.as_u64()).map(|v| v as u32);
+    Reading {
+        t,
+        humidity: number(meaco::CURRENT_HUMIDITY),
+        target: number(meaco::TARGET_HUMIDITY),
+        power: profile.read(dps, meaco::SWITCH).and_then(|v| v.as_bool()),
+        fault: number(meaco::FAULT),
+        temperature: profile.scaled(dps, meaco::TEMPERATURE),
+    }
+}
+
+/// The parts of a parsed `status` a reading keeps.
+pub fn reading_from_status(t: u64, status: &DehumidifierStatus) -> Reading {
+    Reading {
+        t,
+        humidity: status.current_humidity,
+        target: Some(status.target_humidity),
+        power: Some(status.power),
+        fault: Some(status.faults.iter().fold(0, |bits, fault| bits | 1 << fault.bit)),
+        temperature: status.temperature,
+    }
+}
+
+/// The latest readings, oldest dropped first once full.
+#[derive(Debug)]
+pub struct RecentReadings {
+    readings: Mutex<VecDeque<Reading>>,
+    capacity: usize,
+}
+
+pub fn recent_readings(capacity: usize) -> RecentReadings {
+    RecentReadings {
+        readings: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+    }
+}
+
+pub fn remember(recent: &RecentReadings, reading: Reading) {
+    if recent.capacity == 0 {
+        return;
+    }
+    let mut readings = recent.readings.lock().expect("history lock poisoned");
+    if readings.len() == recent.capacity {
+        readings.pop_front();
+    }
+    readings.push_back(reading);
+}
+
+/// Every kept reading with `from <= t < to`, oldest first.
+pub fn query_recent(recent: &RecentReadings, from: u64, to: u64) -> Vec<Reading> {
+    let readings = recent.readings.lock().expect("history lock poisoned");
+    readings.iter().filter(|r| (from..to).contains(&r.t)).cloned().collect()
+}
+
+pub fn append(path: &str, reading: &Reading) -> Result<(), HistoryError> {
+    let mut line = serde_json::to_string(reading).map_err(|e| HistoryError::Parse(e.to_string()))?;
+    line.push('\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(HistoryError::Io)
+}
+
+/// Every reading with `from <= t < to`, oldest first. A missing file is
+/// an empty history; lines that don't parse (a write cut short) are skipped.
+pub fn query(path: &str, from: u64, to: u64) -> Result<Vec<Reading>, HistoryError> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(HistoryError::Io(e)),
+    };
+
+    let mut readings: Vec<Reading> = contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Reading>(line).ok())
+        .filter(|r| (from..to).contains(&r.t))
+        .collect();
+    readings.sort_by_key(|r| r.t);
+    Ok(readings)
+}
+
+/// Drop readings older than `keep_since`. Returns how many went.
+pub fn prune(path: &str, keep_since: u64) -> Result<usize, HistoryError> {
+    let all = query(path, 0, u64::MAX)?;
+    let kept: Vec<&Reading> = all.iter().filter(|r| r.t >= keep_since).collect();
+    let dropped = all.len() - kept.len();
+    if dropped == 0 {
+        return Ok(0);
+    }
+
+    let mut contents = String::new();
+    for reading in kept {
+        contents.push_str(&serde_json::to_string(reading).map_err(|e| HistoryError::Parse(e.to_string()))?);
+        contents.push('\n');
+    }
+    std::fs::write(path, contents).map_err(HistoryError::Io)?;
+    Ok(dropped)
+}
+
+/// Summarise readings into `resolution`-second buckets starting at
+/// `from`. Empty buckets are left out rather than reported as gaps.
+pub fn downsample(readings: &[Reading], from: u64, resolution: u64) -> Vec<Bucket> {
+    let resolution = resolution.max(1);
+    let mut buckets: Vec<Bucket> = Vec::new();
+
+    for reading in readings {
+        let start = from + reading.t.saturating_sub(from) / resolution * resolution;
+        if buckets.last().is_none_or(|b| b.start != start) {
+            buckets.push(Bucket {
+                start,
+                readings: 0,
+                humidity_avg: None,
+                humidity_min: None,
+                humidity_max: None,
+                target: None,
+                power_on_pct: None,
+                fault: 0,
+                temperature_avg: None,
+            });
+        }
+        let bucket = buckets.last_mut().expect("pushed above");
+        bucket.readings += 1;
+        if reading.target.is_some() {
+            bucket.target = reading.target;
+        }
+        bucket.fault |= reading.fault.unwrap_or(0);
+    }
+
+    // Averages need the whole bucket
+    for bucket in &mut buckets {
+        let end = bucket.start.saturating_add(resolution);
+        let in_bucket = readings.iter().filter(|r| (bucket.start..end).contains(&r.t));
+        let humidity: Vec<u32> = in_bucket.clone().filter_map(|r| r.humidity).collect();
+        if !humidity.is_empty() {
+            let avg = humidity.iter().sum::<u32>() as f64 / humidity.len() as f64;
+            bucket.humidity_avg = Some((avg * 10.0).round() / 10.0);
+            bucket.humidity_min = humidity.iter().min().copied();
+            bucket.humidity_max = humidity.iter().max().copied();
+        }
+        let temperature: Vec<f64> = in_bucket.clone().filter_map(|r| r.temperature).collect();
+        if !temperature.is_empty() {
+            let avg = temperature.iter().sum::<f64>() / temperature.len() as f64;
+            bucket.temperature_avg = Some((avg * 10.0).round() / 10.0);
+        }
+        let power: Vec<bool> = in_bucket.filter_map(|r| r.power).collect();
+        if !power.is_empty() {
+            let on = power.iter().filter(|&&on| on).count();
+            bucket.power_on_pct = Some((on * 100 / power.len()) as u32);
+        }
+    }
+    buckets
+}
+
+/// One line per bucket, oldest first.
+pub fn format_buckets(buckets: &[Bucket]) -> String {
+    if buckets.is_empty() {
+        return "No readings recorded in that window".into();
+    }
+
+    let mut lines = Vec::with_capacity(buckets.len());
+    for bucket in buckets {
+        let mut line = format!("{}:", format_utc(bucket.start));
+        match (bucket.humidity_avg, bucket.humidity_min, bucket.humidity_max) {
+            (Some(avg), Some(min), Some(max)) if min != max => {
+                line.push_str(&format!(" humidity {avg:.1}% ({min}-{max}%)"))
+            }
+            (Some(avg), _, _) => line.push_str(&format!(" humidity {avg:.1}%")),
+            _ => line.push_str(" humidity unknown"),
+        }
+        if let Some(temperature) = bucket.temperature_avg {
+            line.push_str(&format!(", {temperature:.1}°C"));
+        }
+        if let Some(target) = bucket.target {
+            line.push_str(&format!(", target {target}%"));
+        }
+        if let Some(on) = bucket.power_on_pct {
+            line.push_str(&format!(", on {on}% of the time"));
+        }
+        if bucket.fault != 0 {
+            line.push_str(&format!(", faults 0x{:x}", bucket.fault));
+        }
+        line.push_str(&format!(" [{} readings]", bucket.readings));
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+/// "2026-03-14 07:05 UTC". hearth has no timezone database.
+pub fn format_utc(unix: u64) -> String {
+    let (year, month, day) = civil_from_days((unix / 86_400) as i64);
+    let day_secs = unix % 86_400;
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        day_secs / 3600,
+        day_secs % 3600 / 60
+    )
+}
+
+/// "2026-03-14" or "2026-03-14 07:05" (a T separator works too), UTC,
+/// to unix seconds.
+pub fn parse_utc(text: &str) -> Result<u64, HistoryError> {
+    let invalid = || HistoryError::InvalidDate(text.to_owned());
+    let text = text.trim();
+    let (date, time) = match text.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let day_secs = match time {
+        None => 0,
+        Some(time) => {
+            let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+            let hours: u64 = hours.parse().map_err(|_| invalid())?;
+            let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+            if hours > 23 || minutes > 59 {
+                return Err(invalid());
+            }
+            hours * 3600 + minutes * 60
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    u64::try_from(days).map(|days| days * 86_400 + day_secs).map_err(|_| invalid())
+}
+
+/// The UTC year and month `unix` falls in.
+pub fn utc_month(unix: u64) -> (i64, i64) {
+    let (year, month, _) = civil_from_days((unix / 86_400) as i64);
+    (year, month)
+}
+
+/// When a UTC month starts, in unix seconds.
+pub fn month_start(year: i64, month: i64) -> u64 {
+    u64::try_from(days_from_civil(year, month, 1)).unwrap_or(0) * 86_400
+}
+
+// Civil dates from and to days since 1970-01-01 (Howard Hinnant's algorithms)
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Readings as CSV with a header row, for a spreadsheet. Times are UTC.
+pub fn to_csv(readings: &[Reading]) -> String {
+    let cell = |v: Option<String>| v.unwrap_or_default();
+    let mut csv = String::from("time_utc,unix_secs,humidity,target,power,fault,temperature\n");
+    for r in readings {
+        let (year, month, day) = civil_from_days((r.t / 86_400) as i64);
+        let day_secs = r.t % 86_400;
+        csv.push_str(&format!(
+            "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02},{},{},{},{},{},{}\n",
+            day_secs / 3600,
+            day_secs % 3600 / 60,
+            day_secs % 60,
+            r.t,
+            cell(r.humidity.map(|v| v.to_string())),
+            cell(r.target.map(|v| v.to_string())),
+            cell(r.power.map(|v| if v { "on" } else { "off" }.to_owned())),
+            cell(r.fault.map(|v| v.to_string())),
+            cell(r.temperature.map(|v| v.to_string())),
+        ));
+    }
+    csv
+}
+
+/// The window from `from` (default: a day ago) up to `to` (default: now).
+/// A bare `to` date includes that whole day.
+pub fn export_window(from: Option<&str>, to: Option<&str>, now: u64) -> Result<(u64, u64), HistoryError> {
+    let from = from.map(parse_utc).transpose()?.unwrap_or(now.saturating_sub(86_400));
+    let to = match to {
+        Some(to) if !to.trim().contains([' ', 'T']) => parse_utc(to)? + 86_400,
+        Some(to) => parse_utc(to)?,
+        None => now + 1,
+    };
+    Ok((from, to))
+}
+
+/// The file's readings from `from` up to `to` as CSV.
+pub fn export_csv(path: &str, from: Option<&str>, to: Option<&str>, now: u64) -> Result<String, HistoryError> {
+    let (from, to) = export_window(from, to, now)?;
+    Ok(to_csv(&query(path, from, to)?))
+}
+
+/// Sample the device every `interval_secs` and append what it reports,
+/// after dropping readings past the retention period. Samples the device
+/// doesn't answer are skipped; the gap shows in the history.
+pub fn spawn_recorder(
+    conn: Arc<TuyaConnection>,
+    config: &HistoryConfig,
+    profile: DeviceProfile,
+) -> tokio::task::JoinHandle<()> {
+    let path = config.path.clone();
+    let every = Duration::from_secs(config.interval_secs.max(1));
+    let retention = config.retention_days * 86_400;
+
+    let now = watchdog::unix_secs(SystemTime::now());
+    match prune(&path, now.saturating_sub(retention)) {
+        Ok(0) => {}
+        Ok(dropped) => tracing::info!(dropped, "Pruned old readings from history"),
+        Err(e) => tracing::warn!("{e}"),
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let deadline = Deadline {
+                at: Some(Instant::now() + SAMPLE_TIMEOUT),
+                ..Deadline::default()
+            };
+            let response = match tuya_connection::query_dps(&conn, &deadline).await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::debug!("Skipping history sample: {e}");
+                    continue;
+                }
+            };
+            let dps = response.get("dps").unwrap_or(&response);
+            let reading = reading_from_dps(dps, watchdog::unix_secs(SystemTime::now()), &profile);
+            if let Err(e) = append(&path, &reading) {
+                tracing::warn!("{e}");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(t: u64, humidity: u32, power: bool) -> Reading {
+        Reading { t, humidity: Some(humidity), target: Some(50), power: Some(power), fault: Some(0), temperature: None }
+    }
+
+    #[test]
+    fn stores_queries_and_buckets_readings() {
+        let path = std::env::temp_dir().join(format!("hearth-history-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        for r in [reading(0, 60, true), reading(600, 58, true), reading(1800, 55, false), reading(4000, 52, false)] {
+            append(path, &r).unwrap();
+        }
+        // A write cut short by a crash
+        std::fs::OpenOptions::new().append(true).open(path).unwrap().write_all(b"{\"t\":50").unwrap();
+
+        let readings = query(path, 0, 3600).unwrap();
+        assert_eq!(readings.len(), 3);
+
+        let buckets = downsample(&readings, 0, 1800);
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].humidity_avg, Some(59.0));
+        assert_eq!((buckets[0].humidity_min, buckets[0].humidity_max), (Some(58), Some(60)));
+        assert_eq!(buckets[0].power_on_pct, Some(100));
+        assert_eq!((buckets[1].start, buckets[1].power_on_pct), (1800, Some(0)));
+
+        assert_eq!(prune(path, 1000).unwrap(), 2);
+        assert_eq!(query(path, 0, u64::MAX).unwrap().len(), 2);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_utc(1_709_251_200 + 7 * 3600 + 5 * 60), "2024-03-01 07:05 UTC");
+    }
+
+    #[test]
+    fn recent_readings_keep_the_latest() {
+        let recent = recent_readings(3);
+        for t in 0..5 {
+            remember(&recent, reading(t * 60, 60 - t as u32, true));
+        }
+        let kept = query_recent(&recent, 0, u64::MAX);
+        assert_eq!(kept.iter().map(|r| r.t).collect::<Vec<_>>(), [120, 180, 240]);
+        assert_eq!(query_recent(&recent, 150, 240).len(), 1);
+
+        let none = recent_readings(0);
+        remember(&none, reading(0, 60, true));
+        assert!(query_recent(&none, 0, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn exports_a_date_range_as_csv() {
+        let path = std::env::temp_dir().join(format!("hearth-export-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let midnight = parse_utc("2024-03-01").unwrap();
+        assert_eq!(midnight, 1_709_251_200);
+        assert_eq!(parse_utc("2024-03-01T07:05").unwrap(), midnight + 7 * 3600 + 5 * 60);
+        assert!(matches!(parse_utc("01/03/2024"), Err(HistoryError::InvalidDate(_))));
+
+        append(path, &reading(midnight - 60, 70, true)).unwrap();
+        append(path, &Reading { humidity: None, temperature: Some(18.5), ..reading(midnight + 3661, 0, false) }).unwrap();
+        append(path, &reading(midnight + 86_400, 50, true)).unwrap();
+
+        let csv = export_csv(path, Some("2024-03-01"), Some("2024-03-01"), 0).unwrap();
+        assert_eq!(
+            csv,
+            "time_utc,unix_secs,humidity,target,power,fault,temperature\n\
+             2024-03-01 01:01:01,1709254861,,50,off,0,18.5\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
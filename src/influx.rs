@@ -0,0 +1,199 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::config::InfluxConfig;
+use crate::history::{self, Reading};
+use crate::status::{self, StatusStore};
+use crate::watchdog;
+
+// -- InfluxDB export --
+//
+// With `[influxdb] url`, the dehumidifier's last-known humidity,
+// temperature, target, power and fault bitmap are written to a bucket
+// every `interval_secs`, in line protocol through the v2 HTTP API, for
+// Grafana dashboards built on Influx. Like /metrics, this never touches
+// the device: `[polling]` is what keeps the status current.
+//
+// Each point is stamped with when the status was read, so writing the
+// same one twice overwrites rather than duplicates it. Points the server
+// didn't take are kept and sent with the next write.
+
+/// How long a write may take.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Points kept for a server that's down, the oldest dropped past this.
+const MAX_PENDING: usize = 1440;
+
+#[derive(Debug)]
+pub enum InfluxError {
+    Http(String),
+    /// The server answered, but didn't take the points.
+    Rejected { status: u16, body: String },
+}
+
+impl fmt::Display for InfluxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InfluxError::Http(e) => write!(f, "Can't reach InfluxDB: {e}"),
+            InfluxError::Rejected { status, body } => write!(f, "InfluxDB refused the write ({status}): {body}"),
+        }
+    }
+}
+
+impl std::error::Error for InfluxError {}
+
+/// A measurement or tag value, escaped as line protocol wants.
+fn escape(value: &str, measurement: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ') || (c == '=' && !measurement) || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// One point for `reading`, tagged with the device, or None if the device
+/// reported nothing worth a field.
+pub fn line(measurement: &str, device: &str, reading: &Reading) -> Option<String> {
+    let mut fields = Vec::new();
+    if let Some(humidity) = reading.humidity {
+        fields.push(format!("humidity={humidity}i"));
+    }
+    if let Some(temperature) = reading.temperature {
+        fields.push(format!("temperature={temperature}"));
+    }
+    if let Some(target) = reading.target {
+        fields.push(format!("target={target}i"));
+    }
+    if let Some(power) = reading.power {
+        fields.push(format!("power={power}"));
+    }
+    if let Some(fault) = reading.fault {
+        fields.push(format!("fault={fault}i"));
+    }
+    if fields.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{},device={} {} {}",
+        escape(measurement, true),
+        escape(device, false),
+        fields.join(","),
+        reading.t
+    ))
+}
+
+/// Write `lines` to the configured bucket, at second precision.
+pub async fn write(http: &reqwest::Client, config: &InfluxConfig, lines: &[String]) -> Result<(), InfluxError> {
+    let url = config.url.as_deref().unwrap_or_default().trim_end_matches('/');
+    let response = http
+        .post(format!("{url}/api/v2/write"))
+        .query(&[("org", config.org.as_str()), ("bucket", config.bucket.as_str()), ("precision", "s")])
+        .header(reqwest::header::AUTHORIZATION, format!("Token {}", config.token))
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(lines.join("\n"))
+        .send()
+        .await
+        .map_err(|e| InfluxError::Http(e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(InfluxError::Rejected {
+        status: status.as_u16(),
+        body: body.trim().to_owned(),
+    })
+}
+
+/// Write `device`'s last-known status every `interval_secs`, once per read.
+pub fn spawn_writer(
+    config: InfluxConfig,
+    device: String,
+    last_status: Arc<StatusStore>,
+) -> Result<tokio::task::JoinHandle<()>, InfluxError> {
+    let http = reqwest::Client::builder()
+        .timeout(WRITE_TIMEOUT)
+        .build()
+        .map_err(|e| InfluxError::Http(e.to_string()))?;
+
+    Ok(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut pending: Vec<String> = Vec::new();
+        let mut written_at = None;
+        let mut failing = false;
+
+        loop {
+            interval.tick().await;
+
+            if let Some((status, age)) = status::last_known(&last_status) {
+                let t = watchdog::unix_secs(SystemTime::now()).saturating_sub(age.as_secs());
+                // Seconds round, so the same read can come out a second apart
+                if written_at.is_none_or(|at: u64| at.abs_diff(t) > 1) {
+                    written_at = Some(t);
+                    pending.extend(line(&config.measurement, &device, &history::reading_from_status(t, &status)));
+                }
+            }
+            if pending.is_empty() {
+                continue;
+            }
+
+            match write(&http, &config, &pending).await {
+                Ok(()) => {
+                    if failing {
+                        tracing::info!(points = pending.len(), "InfluxDB writes resumed");
+                    }
+                    failing = false;
+                    pending.clear();
+                }
+                Err(e) => {
+                    // Once per outage, not every interval
+                    if !failing {
+                        tracing::warn!("{e}; keeping points to retry");
+                    }
+                    failing = true;
+                    let excess = pending.len().saturating_sub(MAX_PENDING);
+                    pending.drain(..excess);
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readings_become_line_protocol() {
+        let reading = Reading {
+            t: 1_709_251_200,
+            humidity: Some(61),
+            target: Some(50),
+            power: Some(true),
+            fault: Some(0),
+            temperature: Some(18.5),
+        };
+        assert_eq!(
+            line("dehumidifier", "bed room,1", &reading).unwrap(),
+            "dehumidifier,device=bed\\ room\\,1 humidity=61i,temperature=18.5,target=50i,power=true,fault=0i 1709251200"
+        );
+        assert_eq!(escape("a=b c", true), "a=b\\ c");
+        assert_eq!(escape("a=b", false), "a\\=b");
+
+        let empty = Reading {
+            humidity: None,
+            target: None,
+            power: None,
+            fault: None,
+            temperature: None,
+            ..reading
+        };
+        assert!(line("dehumidifier", "bedroom", &empty).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cli::InitArgs;
+use crate::cloud::{self, CloudDevice};
+use crate::discovery;
+use crate::secret;
+
+// -- Setup wizard --
+//
+// `hearth init` does what tinytuya's wizard does, then writes the config:
+// it signs in to the user's Tuya Cloud project, lists the devices on the
+// linked app account with their local keys, listens for their LAN
+// broadcasts to learn their addresses, and writes dehumidifiers as
+// [[device]] entries and plugs as [plug.<name>] tables. Devices not heard
+// on the LAN are written commented out, for the address to be filled in.
+
+/// Tuya category codes for the devices hearth drives.
+const DEHUMIDIFIER: &str = "cs";
+const PLUGS: &[&str] = &["cz", "pc"];
+
+/// A cloud device, with what the config needs besides.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub device: CloudDevice,
+    /// Its name in the config: plug table keys and keyring users.
+    pub slug: String,
+    pub ip: Option<String>,
+    /// The key itself, or a `keyring:` reference to it.
+    pub local_key: String,
+}
+
+/// A bare-key-safe name for `name`: lowercase letters, digits and
+/// underscores.
+fn slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('_') {
+            slug.push('_');
+        }
+    }
+    let slug = slug.trim_end_matches('_');
+    if slug.is_empty() { "device".to_owned() } else { slug.to_owned() }
+}
+
+/// Slugs for `devices`, numbered where names collide.
+fn unique_slugs(devices: &[CloudDevice]) -> Vec<String> {
+    let mut taken = std::collections::BTreeSet::new();
+    devices
+        .iter()
+        .map(|device| {
+            let base = slug(&device.name);
+            let mut candidate = base.clone();
+            let mut n = 2;
+            while !taken.insert(candidate.clone()) {
+                candidate = format!("{base}_{n}");
+                n += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
+fn quoted(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+/// One device's table, commented out if it wasn't heard on the LAN.
+fn render_table(header: &str, found: &Found, name: Option<&str>) -> String {
+    let mut lines = vec![header.to_owned()];
+    if let Some(name) = name {
+        lines.push(format!("name = {}", quoted(name)));
+    }
+    lines.push(format!("device_ip = {}", quoted(found.ip.as_deref().unwrap_or("192.168.1.xxx"))));
+    lines.push(format!("device_id = {}", quoted(&found.device.id)));
+    lines.push(format!("local_key = {}", quoted(&found.local_key)));
+
+    let about = format!("# {} ({})", found.device.name, found.device.product_name);
+    match found.ip {
+        Some(_) => format!("{about}\n{}\n", lines.join("\n")),
+        None => format!(
+            "{about}\n# Not heard on the LAN: set device_ip and uncomment\n{}\n",
+            lines.iter().map(|line| format!("# {line}")).collect::<Vec<_>>().join("\n")
+        ),
+    }
+}
+
+/// The config for `found`. The first dehumidifier is the one hearth
+/// drives.
+pub fn render(found: &[Found]) -> String {
+    let mut sections = vec![
+        "# Written by `hearth init` from the Tuya Cloud; from here on hearth stays on the LAN.\n\
+         # See hearth.toml.example for everything else that can be set.\n"
+            .to_owned(),
+    ];
+    let dehumidifiers: Vec<&Found> = found.iter().filter(|f| f.device.category == DEHUMIDIFIER).collect();
+    for device in &dehumidifiers {
+        // Entry names must be unique; the slug is where the app's aren't
+        let shared = dehumidifiers.iter().filter(|other| other.device.name == device.device.name).count() > 1;
+        let name = if shared { &device.slug } else { &device.device.name };
+        sections.push(render_table("[[device]]", device, Some(name)));
+    }
+    for found in found.iter().filter(|f| PLUGS.contains(&f.device.category.as_str())) {
+        sections.push(render_table(&format!("[plug.{}]", found.slug), found, None));
+    }
+
+    let others: Vec<String> = found
+        .iter()
+        .filter(|f| f.device.category != DEHUMIDIFIER && !PLUGS.contains(&f.device.category.as_str()))
+        .map(|f| format!("#   {} ({}, category {}), device_id {}", f.device.name, f.device.product_name, f.device.category, f.device.id))
+        .collect();
+    if !others.is_empty() {
+        sections.push(format!("# Also on the account, but not something hearth drives:\n{}\n", others.join("\n")));
+    }
+    sections.join("\n")
+}
+
+fn prompt(label: &str) -> Result<String, Box<dyn std::error::Error>> {
+    eprint!("{label}: ");
+    let mut value = String::new();
+    std::io::stdin().read_line(&mut value)?;
+    match value.trim() {
+        "" => Err(format!("{label} is needed").into()),
+        value => Ok(value.to_owned()),
+    }
+}
+
+/// Run the wizard and write the config to `path`.
+pub async fn run(args: &InitArgs, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if std::path::Path::new(path).exists() && !args.force {
+        return Err(format!("{path} already exists; pass --force to replace it").into());
+    }
+    eprintln!("Create a cloud project at iot.tuya.com and link your Smart Life or Tuya app to it");
+    eprintln!("(Devices > Link App Account). Its Overview page shows the Access ID and Secret.");
+    let access_id = match &args.access_id {
+        Some(id) => id.clone(),
+        None => prompt("Access ID")?,
+    };
+    let access_secret = match &args.access_secret {
+        Some(secret) => secret.clone(),
+        None => prompt("Access Secret")?,
+    };
+
+    let cloud = match cloud::connect(&args.region, &access_id, &access_secret).await {
+        Ok(cloud) => cloud,
+        Err(e @ cloud::CloudError::Api { .. }) => {
+            return Err(format!("{e}. Check the Access ID and Secret, and that --region is the project's data centre").into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let devices = cloud::devices(&cloud).await?;
+    if devices.is_empty() {
+        return Err("The project lists no devices: link your app account to it under Devices > Link App Account".into());
+    }
+
+    let registry = Arc::new(discovery::new_registry(std::iter::empty()));
+    let listeners = discovery::spawn_listener(registry.clone()).await;
+    if discovery::listening(&registry) {
+        eprintln!("{} devices on the account. Listening for them on the LAN for {}s...", devices.len(), args.listen_secs);
+        tokio::time::sleep(Duration::from_secs(args.listen_secs)).await;
+    } else {
+        eprintln!("Can't listen for LAN broadcasts (is hearth running?); addresses will need filling in");
+    }
+    for listener in listeners {
+        listener.abort();
+    }
+
+    let slugs = unique_slugs(&devices);
+    let mut found = Vec::new();
+    for (device, slug) in devices.into_iter().zip(slugs) {
+        let local_key = if args.keyring {
+            let reference = format!("{}hearth/{slug}", secret::KEYRING_PREFIX);
+            secret::store(&reference, &device.local_key)?;
+            reference
+        } else {
+            device.local_key.clone()
+        };
+        let ip = discovery::resolve_ip(&registry, &device.id);
+        eprintln!(
+            "  {} ({}): {}",
+            device.name,
+            device.category,
+            ip.as_deref().unwrap_or("not heard on the LAN")
+        );
+        found.push(Found { device, slug, ip, local_key });
+    }
+
+    std::fs::write(path, render(&found))?;
+    eprintln!("Wrote {path}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn device(n: u32, name: &str, category: &str) -> CloudDevice {
+        CloudDevice {
+            id: format!("bf{n:020}"),
+            name: name.to_owned(),
+            local_key: "0123456789abcdef".to_owned(),
+            category: category.to_owned(),
+            product_name: "Test".to_owned(),
+        }
+    }
+
+    #[test]
+    fn writes_dehumidifiers_and_plugs() {
+        let devices = vec![
+            device(1, "Bedroom Dehumidifier", "cs"),
+            device(2, "Heater plug", "cz"),
+            device(3, "Heater  plug!", "cz"),
+            device(4, "Hall light", "dj"),
+        ];
+        let slugs = unique_slugs(&devices);
+        assert_eq!(slugs, ["bedroom_dehumidifier", "heater_plug", "heater_plug_2", "hall_light"]);
+
+        let ips = [Some("192.168.1.20"), Some("192.168.1.30"), None, None];
+        let found: Vec<Found> = devices
+            .into_iter()
+            .zip(slugs)
+            .zip(ips)
+            .map(|((device, slug), ip)| Found {
+                local_key: device.local_key.clone(),
+                device,
+                slug,
+                ip: ip.map(str::to_owned),
+            })
+            .collect();
+
+        let text = render(&found);
+        let config: Config = toml::from_str(&text).unwrap();
+        assert_eq!(config.devices[0].device.name.as_deref(), Some("Bedroom Dehumidifier"));
+        assert_eq!(config.devices[0].device.device_ip, "192.168.1.20");
+        // The plug not heard on the LAN is left commented out
+        assert_eq!(config.plug.keys().collect::<Vec<_>>(), ["heater_plug"]);
+        assert!(text.contains("# [plug.heater_plug_2]"));
+        assert!(text.contains("Hall light (Test, category dj)"));
+    }
+}
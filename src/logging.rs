@@ -0,0 +1,223 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tracing_subscriber::{EnvFilter, Layer, Registry, layer::SubscriberExt, reload, util::SubscriberInitExt};
+
+use crate::config::LogFormat;
+
+/// Filter applied by SIGUSR1 when no override is active.
+const SIGNAL_FILTER: &str = "hearth=trace";
+const SIGNAL_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Log records buffered for MCP clients that fall behind.
+const CLIENT_LOG_BUFFER: usize = 256;
+
+/// Handle for swapping the tracing filter at runtime.
+pub struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// Filter in effect at startup — what every override reverts to.
+    base: String,
+    state: Mutex<OverrideState>,
+    /// hearth's own log events, for MCP clients that asked for them.
+    records: broadcast::Sender<LogRecord>,
+}
+
+/// One log event, as forwarded to MCP clients.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: tracing::Level,
+    pub target: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct OverrideState {
+    active: Option<String>,
+    revert_task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for LogControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogControl")
+            .field("base", &self.base)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    InvalidFilter(String),
+    Reload(String),
+}
+
+impl std::fmt::Display for LogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogError::InvalidFilter(msg) => write!(f, "Invalid log filter: {msg}"),
+            LogError::Reload(msg) => write!(f, "Failed to apply log filter: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+/// Install the global subscriber with a reloadable filter.
+/// Logging goes to stderr — stdout is reserved for MCP stdio transport —
+/// and to any MCP client that set a log level. With a `tracer`, spans
+/// are exported too.
+pub fn init(
+    base: String,
+    format: LogFormat,
+    tracer: Option<opentelemetry_sdk::trace::Tracer>,
+) -> Result<Arc<LogControl>, LogError> {
+    let filter = parse_filter(&base)?;
+    let (filter_layer, handle) = reload::Layer::new(filter);
+    let (records, _) = broadcast::channel(CLIENT_LOG_BUFFER);
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with((format == LogFormat::Text).then(|| tracing_subscriber::fmt::layer().with_writer(std::io::stderr)))
+        .with((format == LogFormat::Json).then(|| {
+            tracing_subscriber::fmt::layer().json().flatten_event(true).with_writer(std::io::stderr)
+        }))
+        .with(ClientLogLayer(records.clone()))
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+
+    Ok(Arc::new(LogControl {
+        handle,
+        base,
+        state: Mutex::new(OverrideState::default()),
+        records,
+    }))
+}
+
+/// Log events from here on, for forwarding to an MCP client.
+pub fn subscribe(ctrl: &LogControl) -> broadcast::Receiver<LogRecord> {
+    ctrl.records.subscribe()
+}
+
+/// Copies hearth's events (not its dependencies') to the broadcast
+/// channel. Sees the same filter as stderr.
+struct ClientLogLayer(broadcast::Sender<LogRecord>);
+
+impl<S: tracing::Subscriber> Layer<S> for ClientLogLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let meta = event.metadata();
+        if self.0.receiver_count() == 0 || !meta.target().starts_with("hearth") {
+            return;
+        }
+
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        // No receivers left is fine: nobody's listening
+        let _ = self.0.send(LogRecord {
+            level: *meta.level(),
+            target: meta.target().to_owned(),
+            message: message.0,
+        });
+    }
+}
+
+/// "Heartbeat failed: timed out failures=2", as the fmt layer would put it.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{value:?}"));
+        } else {
+            self.0.push_str(&format!(" {}={value:?}", field.name()));
+        }
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "message" {
+            self.0.insert_str(0, value);
+        } else {
+            self.0.push_str(&format!(" {}={value}", field.name()));
+        }
+    }
+}
+
+fn parse_filter(directives: &str) -> Result<EnvFilter, LogError> {
+    EnvFilter::try_new(directives).map_err(|e| LogError::InvalidFilter(e.to_string()))
+}
+
+fn apply(ctrl: &LogControl, directives: &str) -> Result<(), LogError> {
+    let filter = parse_filter(directives)?;
+    ctrl.handle
+        .reload(filter)
+        .map_err(|e| LogError::Reload(e.to_string()))
+}
+
+/// Replace the active filter. With a duration, the startup filter is
+/// restored automatically once it elapses.
+pub fn set_filter(
+    ctrl: &Arc<LogControl>,
+    directives: &str,
+    duration: Option<Duration>,
+) -> Result<(), LogError> {
+    apply(ctrl, directives)?;
+
+    let mut state = ctrl.state.lock().expect("log state lock poisoned");
+    if let Some(task) = state.revert_task.take() {
+        task.abort();
+    }
+    state.active = Some(directives.to_owned());
+
+    if let Some(duration) = duration {
+        let ctrl = ctrl.clone();
+        state.revert_task = Some(tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            tracing::info!("Log filter override expired");
+            reset(&ctrl);
+        }));
+    }
+
+    tracing::info!(filter = directives, ?duration, "Log filter changed");
+    Ok(())
+}
+
+/// Restore the startup filter and cancel any pending revert.
+pub fn reset(ctrl: &LogControl) {
+    let mut state = ctrl.state.lock().expect("log state lock poisoned");
+    if let Some(task) = state.revert_task.take() {
+        task.abort();
+    }
+    state.active = None;
+
+    // The base filter was parsed successfully at startup, so this can't fail
+    if let Err(e) = apply(ctrl, &ctrl.base) {
+        tracing::error!("Failed to restore log filter: {e}");
+    }
+}
+
+/// The filter currently in effect.
+pub fn current_filter(ctrl: &LogControl) -> String {
+    let state = ctrl.state.lock().expect("log state lock poisoned");
+    state.active.clone().unwrap_or_else(|| ctrl.base.clone())
+}
+
+/// SIGUSR1 toggles `hearth=trace` for five minutes, for capturing an
+/// intermittent fault without restarting and losing the failure state.
+#[cfg(unix)]
+pub fn spawn_signal_handler(ctrl: Arc<LogControl>) -> std::io::Result<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut usr1 = signal(SignalKind::user_defined1())?;
+
+    Ok(tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            let overridden = ctrl.state.lock().expect("log state lock poisoned").active.is_some();
+            if overridden {
+                reset(&ctrl);
+                tracing::info!("SIGUSR1: log filter restored");
+            } else if let Err(e) = set_filter(&ctrl, SIGNAL_FILTER, Some(SIGNAL_DURATION)) {
+                tracing::error!("SIGUSR1: {e}");
+            }
+        }
+    }))
+}
@@ -1,39 +1,396 @@
+mod archive;
+mod audit;
+mod cli;
+mod cloud;
+mod command_queue;
 mod config;
+mod device_profile;
+mod discovery;
+mod events;
+mod filter;
+mod history;
+mod influx;
+mod init;
+mod logging;
 mod meaco;
+mod metrics;
+mod notify;
+mod observe;
+mod plug;
+mod probe;
+mod prometheus;
+mod prompts;
+mod reload;
+mod schedule;
+mod secret;
 mod server;
+mod shutdown;
+mod status;
+mod store;
+mod telemetry;
+mod transport;
 mod tuya_connection;
 mod tuya_protocol;
+mod watchdog;
 
+use std::sync::atomic::AtomicI32;
+use std::sync::{Arc, RwLock};
+
+use clap::Parser;
 use rmcp::ServiceExt;
+use tokio_util::sync::CancellationToken;
+
+use config::TransportMode;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Logging goes to stderr — stdout is reserved for MCP stdio transport
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_env_filter("hearth=debug")
-        .init();
+    let cli = cli::Cli::parse();
+
+    // `hearth store-key keyring:hearth/bedroom` runs without a config
+    if let Some(cli::Command::StoreKey { reference }) = &cli.command {
+        return store_key(reference);
+    }
+    // `hearth config schema` describes the config rather than reading it
+    if let Some(cli::Command::Config { command: cli::ConfigCommand::Schema }) = &cli.command {
+        println!("{}", serde_json::to_string_pretty(&config::json_schema())?);
+        return Ok(());
+    }
+    // `hearth init` writes the config rather than reading it
+    if let Some(cli::Command::Init(args)) = &cli.command {
+        if let Err(e) = init::run(args, cli.config.as_deref().unwrap_or("hearth.toml")).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let config_path = cli::config_path(&cli);
+    // Printed in full: an invalid config lists each problem on a line
+    let mut config = match config::load_config(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(mode) = cli.transport {
+        config.transport.mode = mode;
+    }
+
+    // `hearth export-history [FROM] [TO]` prints readings as CSV and exits
+    if let Some(cli::Command::ExportHistory { from, to }) = &cli.command {
+        let now = watchdog::unix_secs(std::time::SystemTime::now());
+        let csv = match &config.store.path {
+            Some(path) => {
+                let (from, to) = history::export_window(from.as_deref(), to.as_deref(), now)?;
+                history::to_csv(&store::readings(&store::open(path)?, from, to)?)
+            }
+            None => history::export_csv(&config.history.path, from.as_deref(), to.as_deref(), now)?,
+        };
+        print!("{csv}");
+        return Ok(());
+    }
+
+    // `hearth export-parquet` archives whole months of readings and exits
+    if let Some(cli::Command::ExportParquet { dir, prune }) = &cli.command {
+        let Some(path) = &config.store.path else {
+            eprintln!("export-parquet archives the store's readings: set [store] path first");
+            std::process::exit(1);
+        };
+        let dir = match dir {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => std::path::Path::new(path).parent().map(std::path::Path::to_path_buf).unwrap_or_default(),
+        };
+        let now = watchdog::unix_secs(std::time::SystemTime::now());
+        for file in archive::export(&store::open(path)?, &dir, now, *prune)? {
+            println!("{}", file.display());
+        }
+        return Ok(());
+    }
+
+    // --log-level, then RUST_LOG, override the default filter. Frame
+    // tracing is only useful if the connection module actually logs at
+    // trace level.
+    let mut filter = match &cli.log_level {
+        Some(level) => cli::log_filter(level),
+        None => std::env::var("RUST_LOG").unwrap_or_else(|_| "hearth=debug".into()),
+    };
+    if config.debug.trace_frames {
+        filter.push_str(",hearth::tuya_connection=trace");
+    }
+    let telemetry = telemetry::init(&config.otlp)?;
+    let log_format = cli.log_format.unwrap_or(config.logging.format);
+    let log = logging::init(filter, log_format, telemetry.as_ref().map(telemetry::tracer))?;
+
+    #[cfg(unix)]
+    let _log_signal = logging::spawn_signal_handler(log.clone())?;
 
-    let config = config::load_config("hearth.toml")?;
     tracing::info!(
-        device_ip = %config.meaco.device_ip,
-        device_id = %config.meaco.device_id,
+        path = %config_path,
+        device = %config.meaco().name.as_deref().unwrap_or(&config.meaco().device_id),
+        device_ip = %config.meaco().device_ip,
+        device_id = %config.meaco().device_id,
+        model = %config.device.model,
         "Hearth config loaded"
     );
+    for warning in &config.warnings {
+        tracing::warn!("Config: {warning}");
+    }
+    for other in config.undriven_devices() {
+        tracing::warn!(
+            device = %other.device.name.as_deref().unwrap_or_default(),
+            model = %other.resolved.model,
+            "Configured, but hearth drives a single dehumidifier so far; not connecting"
+        );
+    }
+
+    let conn = tuya_connection::new(config.meaco(), &config.connection, &config.device);
+    tuya_connection::set_frame_tracing(&conn, config.debug.trace_frames);
+
+    // LAN broadcasts tell us what else is out there, and where the device
+    // went if DHCP hands it a new address
+    let discovered = Arc::new(discovery::new_registry(
+        std::iter::once(config.meaco())
+            .chain(config.devices.iter().map(|d| &d.device))
+            .chain(config.plug.values().map(|plug| &plug.device))
+            .map(|d| d.device_id.as_str()),
+    ));
+    let _discovery = discovery::spawn_listener(discovered.clone()).await;
+    tuya_connection::set_resolver(&conn, discovered.clone());
+
+    // Don't make startup depend on the device being online: try to connect
+    // in the background, and otherwise connect on first use.
+    let _initial_connect = tokio::spawn({
+        let conn = conn.clone();
+        async move {
+            match tuya_connection::connect(&conn).await {
+                Ok(()) => tracing::info!(
+                    device = %conn.name,
+                    address = tuya_connection::active_address(&conn),
+                    "Connected to Meaco"
+                ),
+                Err(e) => tracing::warn!(device = %conn.name, "Meaco unreachable at startup ({e}), will retry on first use"),
+            }
+        }
+    });
+
+    let _event_log = events::spawn_logger(&conn.events, &conn.name);
+    let _fault_watch = meaco::spawn_fault_watch(&conn.events, config.device.clone());
+    let _dp_101_watch = meaco::spawn_dp_101_watch(&conn.events, config.device.clone());
+    let heartbeat = tuya_connection::spawn_heartbeat(conn.clone());
+
+    let observations = if config.observe.enabled {
+        let (observations, _task) = observe::spawn_observer(&conn.events, &config.observe)?;
+        tracing::info!(path = %config.observe.path, "DP observation mode on");
+        Some(observations)
+    } else {
+        None
+    };
+
+    let last_status = Arc::new(status::open(config.status.snapshot_path.clone()));
+    let _status_tracker = config
+        .status
+        .passive
+        .then(|| status::spawn_tracker(&conn.events, last_status.clone(), config.device.clone()));
+
+    let polling = config.polling(config.meaco());
+    let recent_readings = (polling.enabled && config.history.recent_readings > 0)
+        .then(|| Arc::new(history::recent_readings(config.history.recent_readings)));
+    let _poller = polling.enabled.then(|| {
+        tracing::info!(device = %conn.name, interval_secs = polling.interval.as_secs(), "Polling the device's status");
+        status::spawn_poller(conn.clone(), last_status.clone(), config.device.clone(), polling, recent_readings.clone())
+    });
+
+    let store = match &config.store.path {
+        Some(path) => {
+            let store = Arc::new(store::open(path)?);
+            store::spawn_recorder(&conn.events, store.clone(), last_status.clone(), &config.store);
+            tracing::info!(path = %path, "Keeping snapshots, events and commands in the store");
+            Some(store)
+        }
+        None => None,
+    };
 
-    let conn = tuya_connection::connect(&config.meaco).await?;
-    tracing::info!("Connected to Meaco");
+    let availability = config.watchdog.enabled.then(|| {
+        let availability = Arc::new(watchdog::Availability::default());
+        watchdog::spawn_watchdog(conn.clone(), availability.clone(), &config.watchdog);
+        availability
+    });
 
-    let _heartbeat = tuya_connection::spawn_heartbeat(conn.clone(), 10);
+    let _history = config.history.enabled.then(|| {
+        tracing::info!(path = %config.history.path, "Recording reading history");
+        history::spawn_recorder(conn.clone(), &config.history, config.device.clone())
+    });
 
-    let mcp_server = server::HearthServer::new(conn);
-    let service = mcp_server
-        .serve(rmcp::transport::io::stdio())
-        .await
-        .inspect_err(|e| tracing::error!("Hearth MCP error: {e}"))?;
+    let filter = match config.filter.reminder_hours {
+        Some(hours) => {
+            let tracker = Arc::new(filter::open(&config.filter.path, hours)?);
+            filter::spawn_tracker(&conn.events, tracker.clone(), config.device.clone());
+            tracing::info!(path = %config.filter.path, hours, "Counting running hours towards a filter reminder");
+            Some(tracker)
+        }
+        None => None,
+    };
+
+    let schedules = Arc::new(std::sync::Mutex::new(schedule::load(&config.schedule.path)?));
+    let utc_offset = Arc::new(AtomicI32::new(config.schedule.utc_offset_minutes));
+    let _scheduler = schedule::spawn_scheduler(
+        conn.clone(),
+        schedules.clone(),
+        &config.schedule,
+        utc_offset.clone(),
+        config.safety.child_lock_guard.then(|| config.device.clone()),
+        audit::AuditSinks {
+            path: config.audit.path(),
+            store: store.clone(),
+        },
+    );
+
+    let plugs = Arc::new(RwLock::new(plug::start_all(&config.plug, &config.connection, &discovered)));
+
+    let sources = prometheus::Sources {
+        conn: conn.clone(),
+        last_status: last_status.clone(),
+        plugs: plugs.clone(),
+    };
+    if let Some(telemetry) = &telemetry {
+        telemetry::register_metrics(telemetry, &sources);
+        tracing::info!(endpoint = %config.otlp.endpoint.as_deref().unwrap_or_default(), "Exporting traces and metrics over OTLP");
+    }
+    let _metrics = config.metrics.enabled.then(|| {
+        let metrics = config.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = prometheus::serve(&metrics, sources).await {
+                tracing::error!(address = %metrics.bind, "Can't serve metrics: {e}");
+            }
+        })
+    });
+
+    if config.influxdb.url.is_some() {
+        influx::spawn_writer(config.influxdb.clone(), conn.name.clone(), last_status.clone())?;
+        tracing::info!(url = %config.influxdb.url.as_deref().unwrap_or_default(), bucket = %config.influxdb.bucket, "Writing measurements to InfluxDB");
+    }
+
+    let shutdown = shutdown::new_shutdown();
+    let mcp_server = server::HearthServer::new(
+        server::Shared {
+            conn: conn.clone(),
+            log,
+            discovered: discovered.clone(),
+            observations: observations.clone(),
+            last_status,
+            recent_readings,
+            store,
+            availability,
+            filter,
+            schedules,
+            plugs: plugs.clone(),
+            shutdown: shutdown.clone(),
+        },
+        &config,
+    );
+
+    // Settings that can change under a running session follow hearth.toml
+    let grace = std::time::Duration::from_secs(config.shutdown.grace_secs);
+    let observe_path = config.observe.path.clone();
+    let transport = config.transport.clone();
+    let _reload = reload::spawn_watcher(
+        config_path.clone(),
+        config,
+        reload::Live {
+            conn: conn.clone(),
+            server: mcp_server.clone(),
+            plugs,
+            utc_offset,
+            discovered,
+        },
+    );
+
+    // On a signal, refuse new calls and let running ones answer while the
+    // service is still up, then stop it
+    let stop = CancellationToken::new();
+    let _signal = tokio::spawn({
+        let shutdown = shutdown.clone();
+        let stop = stop.clone();
+        async move {
+            match shutdown::signalled().await {
+                Ok(signal) => tracing::info!(signal, "Received shutdown signal"),
+                Err(e) => {
+                    tracing::error!("Can't listen for shutdown signals: {e}");
+                    return;
+                }
+            }
+            shutdown::drain(&shutdown, grace).await;
+            stop.cancel();
+        }
+    });
+
+    match transport.mode {
+        TransportMode::Stdio => {
+            let stdin = shutdown::begin_on_eof(tokio::io::stdin(), shutdown.clone());
+            let service = mcp_server
+                .serve_with_ct((stdin, tokio::io::stdout()), stop)
+                .await
+                .inspect_err(|e| tracing::error!("Hearth MCP error: {e}"))?;
+
+            tracing::info!("Hearth running on stdio");
+            let reason = service.waiting().await?;
+            tracing::info!(?reason, "MCP service stopped");
+        }
+        TransportMode::Http => {
+            transport::serve_http(mcp_server, &transport, stop)
+                .await
+                .inspect_err(|e| tracing::error!("Hearth HTTP error: {e}"))?;
+            tracing::info!("HTTP transport stopped");
+        }
+        #[cfg(unix)]
+        TransportMode::Unix => {
+            transport::serve_unix(mcp_server, &transport, stop)
+                .await
+                .inspect_err(|e| tracing::error!("Hearth Unix socket error: {e}"))?;
+            tracing::info!("Unix socket transport stopped");
+        }
+        #[cfg(not(unix))]
+        TransportMode::Unix => return Err("The unix transport needs a Unix host".into()),
+    }
+
+    // Let running tool calls finish their device writes, then flush
+    let stranded = shutdown::drain(&shutdown, grace).await;
+    if stranded > 0 {
+        tracing::warn!(stranded, "Tool calls still running after the grace period");
+    }
+    heartbeat.abort();
+    tuya_connection::close(&conn).await;
+    if let Some(telemetry) = telemetry {
+        telemetry::shutdown(telemetry).await;
+    }
+    if let Some(observations) = &observations {
+        let observations = observations.lock().expect("observations lock poisoned");
+        if let Err(e) = observe::save(&observe_path, &observations) {
+            tracing::warn!("{e}");
+        }
+    }
+
+    // Stdin is read on a blocking thread that would keep the runtime alive
+    // until the client sends another line, which after a signal it won't
+    tracing::info!("Hearth stopped");
+    std::process::exit(0)
+}
 
-    tracing::info!("Hearth running on stdio");
-    service.waiting().await?;
+/// Read a local key from stdin and store it in the OS keyring.
+fn store_key(reference: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !matches!(secret::parse_reference(reference), Some(Ok(_))) {
+        return Err(secret::SecretError::InvalidReference(reference.to_owned()).into());
+    }
+    eprint!("Local key for {reference}: ");
+    let mut key = String::new();
+    std::io::stdin().read_line(&mut key)?;
+    let key = key.trim();
+    if key.len() != 16 {
+        return Err(config::ConfigError::InvalidLocalKey.into());
+    }
 
+    secret::store(reference, key)?;
+    eprintln!("Stored. Set local_key = \"{reference}\" in hearth.toml");
     Ok(())
 }
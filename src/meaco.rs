@@ -1,34 +1,49 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
-// -- Meaco Arete Two 25L — Actual DPS mapping --
-//
-// Confirmed via TinyTuya wizard + local device poll (2026-02-12).
+use crate::device_profile::{self, DeviceProfile};
+use crate::events::{self, DeviceEvent, EventBus};
+
+// -- Meaco Arete Two 25L --
 //
-// | DPS | Code                  | Type    | Notes                                      |
-// |-----|-----------------------|---------|--------------------------------------------|
-// |  1  | switch                | Boolean | Power on/off                               |
-// |  2  | dehumidify_set_value  | Integer | Target humidity 35-70%, step 5             |
-// |  4  | (unlisted)            | String  | Operating mode. Seen: "manual"             |
-// | 14  | child_lock            | Boolean |                                            |
-// | 16  | humidity_indoor       | Integer | Current humidity 0-100%                    |
-// | 17  | countdown_set         | Enum    | "cancel", "1h", "2h", "3h"                |
-// | 18  | countdown_left        | Integer | Hours remaining on countdown, 0-24         |
-// | 19  | fault                 | Bitmap  | tankfull, defrost, E1, E2, L2, L3, L4, wet |
-// |101  | (unlisted)            | String  | Unknown. Seen: "cancel"                    |
+// Typed status and commands for the dehumidifier. Which DP index holds
+// what comes from the device profile (see `device_profile`), by the names
+// below; the Arete Two's mapping is the built-in one, confirmed via
+// TinyTuya wizard + local device poll (2026-02-12).
 //
 // Device ID:  REDACTED_DEVICE_ID
-// Model:      MeacoDryArete2-25L
 // Protocol:   v3.3
 // IP:         REDACTED_IP (DHCP — may change)
 // MAC:        REDACTED_MAC
 
+// Names the profile's DPs are looked up by
+pub const SWITCH: &str = "switch";
+pub const TARGET_HUMIDITY: &str = "dehumidify_set_value";
+pub const MODE: &str = "mode";
+pub const CHILD_LOCK: &str = "child_lock";
+pub const CURRENT_HUMIDITY: &str = "humidity_indoor";
+pub const COUNTDOWN: &str = "countdown_set";
+pub const COUNTDOWN_LEFT: &str = "countdown_left";
+pub const FAULT: &str = "fault";
+pub const FAN_SPEED: &str = "fan_speed";
+pub const IONIZER: &str = "ionizer";
+pub const SLEEP: &str = "sleep";
+pub const TEMPERATURE: &str = "temp_indoor";
+/// Set by the device when the filter wants cleaning.
+pub const FILTER_REMINDER: &str = "filter_reminder";
+/// Written true to clear the device's filter reminder.
+pub const FILTER_RESET: &str = "filter_reset";
+/// Not yet understood; possibly the laundry/timer-linked setting. Status
+/// carries it raw, and `spawn_dp_101_watch` logs what it changes with.
+pub const DP_101: &str = "unknown_101";
+
 /// Operating mode.
 ///
-/// DPS 4 — only "manual" confirmed from device poll. Other values
-/// are reasonable guesses for the Meaco Arete 2 and may need updating
-/// once tested against the real device.
+/// Which of these a model accepts is up to its profile; on the Arete
+/// Two only "manual" is confirmed from a device poll. Any other value a
+/// device reports is kept as it is rather than failing the status.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Mode {
@@ -36,9 +51,13 @@ pub enum Mode {
     Auto,
     Drying,
     Continuous,
+    #[serde(untagged)]
+    Other(String),
 }
 
-/// Countdown timer setting.
+/// Countdown timer setting. Longer countdowns, which some firmwares
+/// report as "5h" or a plain hour count, are `Hours`; as with `Mode`,
+/// values hearth doesn't know are kept as reported.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum Countdown {
     #[serde(rename = "cancel")]
@@ -49,31 +68,220 @@ pub enum Countdown {
     TwoHours,
     #[serde(rename = "3h")]
     ThreeHours,
+    #[serde(untagged)]
+    Hours(u32),
+    #[serde(untagged)]
+    Other(String),
+}
+
+/// Fan speed, on models whose profile declares the DP.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FanSpeed {
+    Low,
+    High,
+}
+
+/// How much a fault matters to whoever's reading about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Normal operation worth knowing about, e.g. defrosting.
+    Informational,
+    /// Needs someone to do something, but nothing is broken.
+    Warning,
+    #[default]
+    Error,
+}
+
+/// What a fault flag means and what to do about it, from the device
+/// profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Fault {
+    /// Bit in the fault DP's bitmap.
+    pub bit: u32,
+    pub code: String,
+    #[serde(default)]
+    pub severity: Severity,
+    pub explanation: String,
+    /// What to do about it, in a sentence.
+    pub action: String,
+}
+
+/// Entries for every bit set in `bitmap`, from the profile's fault
+/// table. Bits the table doesn't explain still show up, by the fault DP's
+/// label for them if it has one.
+pub fn active_faults(bitmap: u32, profile: &DeviceProfile) -> Vec<Fault> {
+    let labels = profile.dp(FAULT).map(|dp| dp.kind.labels()).unwrap_or_default();
+    (0..32)
+        .filter(|bit| bitmap & (1 << bit) != 0)
+        .map(|bit| {
+            profile.faults.iter().find(|f| f.bit == bit).cloned().unwrap_or_else(|| {
+                let (code, explanation) = match labels.get(bit as usize) {
+                    Some(label) => (label.clone(), format!("The device reports \"{label}\", undocumented for this model")),
+                    None => (format!("bit{bit}"), format!("Undocumented fault bit {bit}")),
+                };
+                Fault {
+                    bit,
+                    code,
+                    severity: Severity::Warning,
+                    explanation,
+                    action: "Check the manual, and power-cycle the unit if it persists".to_owned(),
+                }
+            })
+        })
+        .collect()
+}
+
+/// Target humidity setpoints the device accepts: `min..=max` in `step` increments.
+///
+/// Clones of the same model disagree here, so the profile's range can be
+/// replaced at runtime by probing the device (see `probe::probe_humidity_range`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HumidityRange {
+    pub min: u32,
+    pub max: u32,
+    pub step: u32,
+}
+
+/// Target humidity bounds as documented for the Arete Two 25L, for a
+/// profile that doesn't give them.
+pub const ARETE_TWO_HUMIDITY: HumidityRange = HumidityRange { min: 35, max: 70, step: 5 };
+
+impl HumidityRange {
+    pub fn accepts(&self, value: u32) -> bool {
+        (self.min..=self.max).contains(&value) && (value - self.min).is_multiple_of(self.step)
+    }
+}
+
+impl fmt::Display for HumidityRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}, step {}", self.min, self.max, self.step)
+    }
 }
 
-/// Fault bitmap flags (DPS 19).
-/// Bit 0 = tankfull, bit 1 = defrost, bit 2 = E1, bit 3 = E2,
-/// bit 4 = L2, bit 5 = L3, bit 6 = L4, bit 7 = wet.
-const FAULT_LABELS: &[&str] = &["tankfull", "defrost", "E1", "E2", "L2", "L3", "L4", "wet"];
+/// Infer a setpoint range from the values a device was seen to accept.
+/// The step is the GCD of the gaps between accepted values.
+pub fn infer_humidity_range(accepted: &[u32]) -> Option<HumidityRange> {
+    let min = *accepted.iter().min()?;
+    let max = *accepted.iter().max()?;
+
+    let step = accepted
+        .iter()
+        .map(|v| v - min)
+        .filter(|gap| *gap > 0)
+        .fold(0, gcd);
+
+    Some(HumidityRange {
+        min,
+        max,
+        step: step.max(1),
+    })
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// A target humidity given either as a percentage or a named preset
+/// ("storage", "living", ...) defined in config.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum HumidityTarget {
+    Percent(u32),
+    Preset(String),
+}
+
+/// Resolve a target to a percentage. Preset names match case-insensitively.
+pub fn resolve_humidity_target(
+    target: &HumidityTarget,
+    presets: &BTreeMap<String, u32>,
+) -> Result<u32, DpsError> {
+    match target {
+        HumidityTarget::Percent(value) => Ok(*value),
+        HumidityTarget::Preset(name) => presets
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name.trim()))
+            .map(|(_, value)| *value)
+            .ok_or_else(|| DpsError::UnknownPreset {
+                name: name.clone(),
+                known: presets.keys().cloned().collect(),
+            }),
+    }
+}
+
+/// Settings to change together in one CONTROL frame, as `batch_set` and
+/// configured scenes give them. Absent fields are left alone.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+pub struct Settings {
+    #[schemars(description = "Turn the dehumidifier on (true) or off (false)")]
+    pub power: Option<bool>,
+    #[schemars(description = "Target humidity percentage or preset name, as for set_humidity")]
+    pub humidity: Option<HumidityTarget>,
+    #[schemars(description = "Operating mode: manual, auto, drying, or continuous")]
+    pub mode: Option<Mode>,
+    #[schemars(description = "Enable (true) or disable (false) child lock")]
+    pub child_lock: Option<bool>,
+    #[schemars(description = "Countdown timer: cancel, 1h, 2h, or 3h")]
+    pub countdown: Option<Countdown>,
+    #[schemars(description = "Fan speed: low or high, on models with a fan speed DP")]
+    pub fan_speed: Option<FanSpeed>,
+    #[schemars(description = "Ioniser on or off, on models with an ioniser DP")]
+    pub ionizer: Option<bool>,
+    #[schemars(description = "Sleep mode on or off, on models with a sleep mode DP")]
+    pub sleep_mode: Option<bool>,
+}
 
 /// Current dehumidifier status — a read-only snapshot of device data.
-#[derive(Debug, Clone, Serialize)]
+///
+/// Stored to disk as a `StatusSnapshot`. Fields added later must be
+/// optional or defaulted so older snapshots still read.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DehumidifierStatus {
     pub power: bool,
     pub target_humidity: u32,
     pub mode: Option<Mode>,
     pub current_humidity: Option<u32>,
+    /// Ambient temperature, scaled as the profile says. Only on models
+    /// whose profile declares the DP.
+    pub temperature: Option<f64>,
     pub child_lock: Option<bool>,
     pub countdown: Option<Countdown>,
     pub countdown_left: Option<u32>,
-    pub fault: Option<u32>,
+    /// Active faults, decoded with the profile. Empty if none, or if the
+    /// device doesn't report the fault DP.
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+    /// Only on models whose profile declares the DP.
+    pub fan_speed: Option<FanSpeed>,
+    /// Only on models whose profile declares the DP.
+    pub ionizer: Option<bool>,
+    /// Only on models whose profile declares the DP.
+    pub sleep: Option<bool>,
+    /// Whether the filter wants cleaning: the device's own reminder, or
+    /// hearth's count of running hours with `[filter] reminder_hours`.
+    pub filter_due: Option<bool>,
+    /// Hours run since the filter was last cleaned, when hearth counts them.
+    pub filter_hours: Option<u32>,
+    /// DP 101 as reported, e.g. "cancel"; its meaning isn't confirmed.
+    pub dp_101: Option<serde_json::Value>,
+    /// DPs the device reported that none of the above cover, by index:
+    /// ones this model's profile doesn't declare, or declares but hearth
+    /// has no field for.
+    #[serde(default)]
+    pub extra: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Debug)]
 pub enum DpsError {
     MissingField(&'static str),
     InvalidValue { field: &'static str, raw: String },
-    HumidityOutOfRange(u32),
+    OutOfRange { name: String, value: u32, range: HumidityRange },
+    UnknownPreset { name: String, known: Vec<String> },
+    /// The profile has no DP by that name.
+    Undeclared(String),
+    NotWritable(String),
+    Rejected { name: String, raw: String, expected: String },
 }
 
 impl fmt::Display for DpsError {
@@ -83,8 +291,16 @@ impl fmt::Display for DpsError {
             DpsError::InvalidValue { field, raw } => {
                 write!(f, "Invalid value for DPS {field}: {raw}")
             }
-            DpsError::HumidityOutOfRange(v) => {
-                write!(f, "Humidity {v} out of range (35-70, step 5)")
+            DpsError::OutOfRange { name, value, range } => {
+                write!(f, "{value} is out of range for {name} ({range})")
+            }
+            DpsError::UnknownPreset { name, known } => {
+                write!(f, "Unknown humidity preset \"{name}\" (known: {})", known.join(", "))
+            }
+            DpsError::Undeclared(name) => write!(f, "This model's profile has no {name} DP"),
+            DpsError::NotWritable(name) => write!(f, "DP {name} is read-only on this model"),
+            DpsError::Rejected { name, raw, expected } => {
+                write!(f, "Invalid value for {name}: {raw} (expected {expected})")
             }
         }
     }
@@ -92,117 +308,404 @@ impl fmt::Display for DpsError {
 
 impl std::error::Error for DpsError {}
 
+// -- Stored status --
+
+/// Bumped when a change to `DehumidifierStatus` would make older
+/// snapshots read wrongly, rather than just lack a new field.
+pub const STATUS_VERSION: u32 = 1;
+
+/// A status as stored on disk, tagged with the layout it was written in.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StatusSnapshot {
+    pub version: u32,
+    /// When the status was read, in Unix seconds.
+    pub taken_at: u64,
+    pub status: DehumidifierStatus,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Written by a newer hearth, in a layout this one doesn't know.
+    Newer(u32),
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Newer(version) => {
+                write!(f, "Status snapshot is version {version}, newer than this hearth's {STATUS_VERSION}")
+            }
+            SnapshotError::Invalid(msg) => write!(f, "Invalid status snapshot: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+pub fn snapshot(status: &DehumidifierStatus, taken_at: u64) -> StatusSnapshot {
+    StatusSnapshot { version: STATUS_VERSION, taken_at, status: status.clone() }
+}
+
+/// Read a stored snapshot. Older versions are read as they are: so far
+/// every change has been an added optional field.
+pub fn restore(stored: serde_json::Value) -> Result<StatusSnapshot, SnapshotError> {
+    let version = stored.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if version > u64::from(STATUS_VERSION) {
+        return Err(SnapshotError::Newer(version as u32));
+    }
+    serde_json::from_value(stored).map_err(|e| SnapshotError::Invalid(e.to_string()))
+}
+
 // -- Parsing device DPS JSON into typed status --
 
-/// Parse a DPS JSON object from the device into typed status.
-/// DPS keys are string numbers: "1", "2", "4", etc.
-/// Fields that aren't present in the response are set to None.
-pub fn parse_status(dps: &serde_json::Value) -> Result<DehumidifierStatus, DpsError> {
-    let power = dps
-        .get("1")
-        .and_then(|v| v.as_bool())
-        .ok_or(DpsError::MissingField("1 (switch)"))?;
-
-    let target_humidity = dps
-        .get("2")
-        .and_then(|v| v.as_u64())
-        .map(|v| v as u32)
-        .ok_or(DpsError::MissingField("2 (dehumidify_set_value)"))?;
-
-    let mode = dps.get("4").and_then(|v| v.as_str()).map(parse_mode).transpose()?;
-    let current_humidity = dps.get("16").and_then(|v| v.as_u64()).map(|v| v as u32);
-    let child_lock = dps.get("14").and_then(|v| v.as_bool());
-    let countdown = dps.get("17").and_then(|v| v.as_str()).map(parse_countdown).transpose()?;
-    let countdown_left = dps.get("18").and_then(|v| v.as_u64()).map(|v| v as u32);
-    let fault = dps.get("19").and_then(|v| v.as_u64()).map(|v| v as u32);
+/// The DPs `DehumidifierStatus` has a field for.
+const MODELLED_DPS: &[&str] = &[
+    SWITCH,
+    TARGET_HUMIDITY,
+    MODE,
+    CURRENT_HUMIDITY,
+    CHILD_LOCK,
+    COUNTDOWN,
+    COUNTDOWN_LEFT,
+    FAULT,
+    FAN_SPEED,
+    IONIZER,
+    SLEEP,
+    TEMPERATURE,
+    FILTER_REMINDER,
+    FILTER_RESET,
+    DP_101,
+];
+
+/// Parse a DPS JSON object from the device into typed status, finding
+/// each field by its name in `profile`. Fields that aren't present in the
+/// response, or that the profile doesn't declare, are set to None.
+pub fn parse_status(
+    dps: &serde_json::Value,
+    profile: &DeviceProfile,
+) -> Result<DehumidifierStatus, DpsError> {
+    let number = |name| profile.read(dps, name).and_then(|v| v.as_u64()).map(|v| v as u32);
+    let flag = |name| profile.read(dps, name).and_then(|v| v.as_bool());
+    let text = |name| profile.read(dps, name).and_then(|v| v.as_str());
+
+    let power = flag(SWITCH).ok_or(DpsError::MissingField(SWITCH))?;
+    let target_humidity = number(TARGET_HUMIDITY).ok_or(DpsError::MissingField(TARGET_HUMIDITY))?;
+    let mode = text(MODE).map(parse_mode);
+    let current_humidity = number(CURRENT_HUMIDITY);
+    let temperature = profile.scaled(dps, TEMPERATURE);
+    let child_lock = flag(CHILD_LOCK);
+    let countdown = match profile.read(dps, COUNTDOWN) {
+        Some(serde_json::Value::Number(n)) => n.as_u64().map(countdown_hours),
+        other => other.and_then(|v| v.as_str()).map(parse_countdown),
+    };
+    let countdown_left = number(COUNTDOWN_LEFT);
+    let faults = active_faults(number(FAULT).unwrap_or(0), profile);
+    let fan_speed = text(FAN_SPEED).map(parse_fan_speed).transpose()?;
+    let ionizer = flag(IONIZER);
+    let sleep = flag(SLEEP);
+    let filter_due = flag(FILTER_REMINDER);
+    let dp_101 = profile.read(dps, DP_101).cloned();
+
+    let modelled: Vec<&str> = MODELLED_DPS.iter().filter_map(|name| Some(profile.dp(name)?.index.as_str())).collect();
+    let extra = dps
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(index, _)| !modelled.contains(&index.as_str()))
+        .map(|(index, value)| (index.clone(), value.clone()))
+        .collect();
 
     Ok(DehumidifierStatus {
         power,
         target_humidity,
         mode,
         current_humidity,
+        temperature,
         child_lock,
         countdown,
         countdown_left,
-        fault,
+        faults,
+        fan_speed,
+        ionizer,
+        sleep,
+        filter_due,
+        filter_hours: None,
+        dp_101,
+        extra,
     })
 }
 
-fn parse_mode(s: &str) -> Result<Mode, DpsError> {
+// -- Status diffs --
+
+/// A status field that differs between two snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct StatusChange {
+    /// As in the status, e.g. "target_humidity"; "extra.<index>" for a DP
+    /// it doesn't model.
+    pub field: String,
+    /// Null if the field was absent. Faults are listed by code.
+    pub from: serde_json::Value,
+    pub to: serde_json::Value,
+}
+
+/// Every field that differs from `old` to `new`, in field name order.
+pub fn diff_status(old: &DehumidifierStatus, new: &DehumidifierStatus) -> Vec<StatusChange> {
+    let (old, new) = (status_fields(old), status_fields(new));
+    let names: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let from = old.get(name).cloned().unwrap_or_default();
+            let to = new.get(name).cloned().unwrap_or_default();
+            (from != to).then(|| StatusChange { field: name.clone(), from, to })
+        })
+        .collect()
+}
+
+/// The status as field name to value, with each extra DP a field of its
+/// own and faults reduced to their codes.
+fn status_fields(status: &DehumidifierStatus) -> BTreeMap<String, serde_json::Value> {
+    let serde_json::Value::Object(fields) = serde_json::to_value(status).expect("status serializes") else {
+        unreachable!("status serializes to an object");
+    };
+
+    let mut flat = BTreeMap::new();
+    for (name, value) in fields {
+        match (name.as_str(), value) {
+            ("extra", serde_json::Value::Object(extra)) => {
+                flat.extend(extra.into_iter().map(|(index, v)| (format!("extra.{index}"), v)));
+            }
+            ("faults", _) => {
+                let codes = status.faults.iter().map(|f| f.code.as_str()).collect::<Vec<_>>();
+                flat.insert(name, codes.into());
+            }
+            (_, value) => {
+                flat.insert(name, value);
+            }
+        }
+    }
+    flat
+}
+
+/// "target_humidity 50 → 55, power true → false"
+pub fn format_changes(changes: &[StatusChange]) -> String {
+    changes
+        .iter()
+        .map(|c| format!("{} {} → {}", c.field, c.from, c.to))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether the device reports the child lock as engaged. Absent means unlocked.
+pub fn child_lock_engaged(dps: &serde_json::Value, profile: &DeviceProfile) -> bool {
+    profile.read(dps, CHILD_LOCK).and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// What sending `planned` would interrupt, given the `current` DPS:
+/// switching off mid drying program, or cancelling a running countdown.
+pub fn disruption(
+    current: &serde_json::Value,
+    planned: &serde_json::Value,
+    profile: &DeviceProfile,
+) -> Option<String> {
+    let mut interrupted = Vec::new();
+    let running = profile.read(current, SWITCH).and_then(|v| v.as_bool()).unwrap_or(false);
+    if running
+        && profile.read(planned, SWITCH).and_then(|v| v.as_bool()) == Some(false)
+        && profile.read(current, MODE).and_then(|v| v.as_str()) == Some("drying")
+    {
+        interrupted.push("switch the dehumidifier off in the middle of its drying program".to_owned());
+    }
+    let countdown = profile.read(current, COUNTDOWN).and_then(|v| v.as_str()).filter(|c| *c != "cancel");
+    if let Some(countdown) = countdown
+        && profile.read(planned, COUNTDOWN).and_then(|v| v.as_str()) == Some("cancel")
+    {
+        interrupted.push(format!("cancel the running {countdown} countdown"));
+    }
+    let hours_left = profile.read(current, COUNTDOWN_LEFT).and_then(|v| v.as_u64()).filter(|h| *h > 0);
+    if let Some(hours) = hours_left
+        && profile.read(planned, COUNTDOWN_LEFT).and_then(|v| v.as_u64()) == Some(0)
+    {
+        interrupted.push(format!("cancel the running countdown with {hours}h left"));
+    }
+    (!interrupted.is_empty()).then(|| interrupted.join(" and "))
+}
+
+fn parse_mode(s: &str) -> Mode {
     match s {
-        "manual" => Ok(Mode::Manual),
-        "auto" => Ok(Mode::Auto),
-        "drying" => Ok(Mode::Drying),
-        "continuous" => Ok(Mode::Continuous),
-        other => Err(DpsError::InvalidValue {
-            field: "4 (mode)",
-            raw: other.to_owned(),
-        }),
+        "manual" => Mode::Manual,
+        "auto" => Mode::Auto,
+        "drying" => Mode::Drying,
+        "continuous" => Mode::Continuous,
+        other => Mode::Other(other.to_owned()),
     }
 }
 
-fn parse_countdown(s: &str) -> Result<Countdown, DpsError> {
+fn parse_countdown(s: &str) -> Countdown {
     match s {
-        "cancel" => Ok(Countdown::Cancel),
-        "1h" => Ok(Countdown::OneHour),
-        "2h" => Ok(Countdown::TwoHours),
-        "3h" => Ok(Countdown::ThreeHours),
+        "cancel" => Countdown::Cancel,
+        "1h" => Countdown::OneHour,
+        "2h" => Countdown::TwoHours,
+        "3h" => Countdown::ThreeHours,
+        other => match other.strip_suffix('h').and_then(|n| n.parse().ok()) {
+            Some(hours) => Countdown::Hours(hours),
+            None => Countdown::Other(other.to_owned()),
+        },
+    }
+}
+
+/// A countdown DP some firmwares report as an hour count instead.
+fn countdown_hours(hours: u64) -> Countdown {
+    match hours {
+        0 => Countdown::Cancel,
+        hours => parse_countdown(&format!("{hours}h")),
+    }
+}
+
+fn parse_fan_speed(s: &str) -> Result<FanSpeed, DpsError> {
+    match s {
+        "low" => Ok(FanSpeed::Low),
+        "high" => Ok(FanSpeed::High),
         other => Err(DpsError::InvalidValue {
-            field: "17 (countdown_set)",
+            field: FAN_SPEED,
             raw: other.to_owned(),
         }),
     }
 }
 
-// -- Building DPS JSON for sending to the device --
+// -- Values to write --
+//
+// What each typed setting is on the wire. `DpsWrite` checks them against
+// the profile before anything is sent.
 
-pub fn build_power_dps(on: bool) -> serde_json::Value {
-    serde_json::json!({"1": on})
+impl Mode {
+    pub fn dp_value(&self) -> &str {
+        match self {
+            Mode::Manual => "manual",
+            Mode::Auto => "auto",
+            Mode::Drying => "drying",
+            Mode::Continuous => "continuous",
+            Mode::Other(raw) => raw,
+        }
+    }
 }
 
-pub fn build_target_humidity_dps(value: u32) -> Result<serde_json::Value, DpsError> {
-    if value < 35 || value > 70 || value % 5 != 0 {
-        return Err(DpsError::HumidityOutOfRange(value));
+impl Countdown {
+    pub fn dp_value(&self) -> std::borrow::Cow<'_, str> {
+        match self {
+            Countdown::Cancel => "cancel".into(),
+            Countdown::OneHour => "1h".into(),
+            Countdown::TwoHours => "2h".into(),
+            Countdown::ThreeHours => "3h".into(),
+            Countdown::Hours(hours) => format!("{hours}h").into(),
+            Countdown::Other(raw) => raw.into(),
+        }
     }
-    Ok(serde_json::json!({"2": value}))
 }
 
-pub fn build_mode_dps(mode: &Mode) -> serde_json::Value {
-    let val = match mode {
-        Mode::Manual => "manual",
-        Mode::Auto => "auto",
-        Mode::Drying => "drying",
-        Mode::Continuous => "continuous",
-    };
-    serde_json::json!({"4": val})
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Other(raw) => write!(f, "{raw} (unrecognised)"),
+            known => write!(f, "{known:?}"),
+        }
+    }
 }
 
-pub fn build_child_lock_dps(locked: bool) -> serde_json::Value {
-    serde_json::json!({"14": locked})
+impl fmt::Display for Countdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Countdown::Hours(hours) => write!(f, "{hours}h"),
+            Countdown::Other(raw) => write!(f, "{raw} (unrecognised)"),
+            known => write!(f, "{known:?}"),
+        }
+    }
 }
 
-pub fn build_countdown_dps(countdown: &Countdown) -> serde_json::Value {
-    let val = match countdown {
-        Countdown::Cancel => "cancel",
-        Countdown::OneHour => "1h",
-        Countdown::TwoHours => "2h",
-        Countdown::ThreeHours => "3h",
-    };
-    serde_json::json!({"17": val})
+impl FanSpeed {
+    pub fn dp_value(&self) -> &'static str {
+        match self {
+            FanSpeed::Low => "low",
+            FanSpeed::High => "high",
+        }
+    }
 }
 
-/// Decode the fault bitmap into a list of active fault names.
-fn decode_faults(bitmap: u32) -> Vec<&'static str> {
-    FAULT_LABELS
-        .iter()
-        .enumerate()
-        .filter(|(i, _)| bitmap & (1 << i) != 0)
-        .map(|(_, label)| *label)
-        .collect()
+/// Turn raw fault DP changes on the event bus into typed `Fault` events.
+pub fn spawn_fault_watch(bus: &EventBus, profile: DeviceProfile) -> tokio::task::JoinHandle<()> {
+    let mut sub = events::subscribe(bus, "fault_watch");
+    let bus = bus.clone();
+
+    tokio::spawn(async move {
+        while let Some(event) = events::next_event(&mut sub).await {
+            if let DeviceEvent::StatusChanged { changed } = event
+                && let Some(bitmap) = profile
+                    .dp(FAULT)
+                    .and_then(|dp| changed.get(&dp.index))
+                    .and_then(|v| v.as_u64())
+            {
+                let bitmap = bitmap as u32;
+                events::publish(&bus, DeviceEvent::Fault {
+                    bitmap,
+                    active: decode_faults(bitmap, &profile),
+                });
+            }
+        }
+    })
+}
+
+/// Log each change of DP 101 with whatever else changed in the same
+/// report, to help pin down what it means.
+pub fn spawn_dp_101_watch(bus: &EventBus, profile: DeviceProfile) -> tokio::task::JoinHandle<()> {
+    let mut sub = events::subscribe(bus, "dp_101_watch");
+
+    tokio::spawn(async move {
+        let Some(index) = profile.dp(DP_101).map(|dp| dp.index.clone()) else {
+            return;
+        };
+        let mut last = None;
+        while let Some(event) = events::next_event(&mut sub).await {
+            let DeviceEvent::StatusChanged { changed } = event else {
+                continue;
+            };
+            let Some(value) = changed.get(&index) else {
+                continue;
+            };
+            let alongside: serde_json::Map<_, _> =
+                changed.iter().filter(|(k, _)| **k != index).map(|(k, v)| (k.clone(), v.clone())).collect();
+            match last.replace(value.clone()) {
+                None => tracing::info!(dp = %index, %value, "DP 101 first seen"),
+                Some(from) => tracing::info!(
+                    dp = %index,
+                    %from,
+                    to = %value,
+                    alongside = %serde_json::Value::Object(alongside),
+                    "DP 101 changed"
+                ),
+            }
+        }
+    })
+}
+
+/// One line on a fault, worded for whoever reads the agent's answer:
+/// errors and warnings say what to do, informational ones just explain.
+pub fn format_fault(fault: &Fault) -> String {
+    match fault.severity {
+        Severity::Error => format!("FAULT ({}): {}. {}", fault.code, fault.explanation, fault.action),
+        Severity::Warning => format!("Warning ({}): {}. {}", fault.code, fault.explanation, fault.action),
+        Severity::Informational => format!("Note: {}", fault.explanation),
+    }
+}
+
+/// Decode the fault bitmap into a list of active fault codes.
+fn decode_faults(bitmap: u32, profile: &DeviceProfile) -> Vec<String> {
+    active_faults(bitmap, profile).into_iter().map(|f| f.code).collect()
 }
 
 /// Format a DehumidifierStatus as a human-readable summary.
-pub fn format_status(status: &DehumidifierStatus) -> String {
+pub fn format_status(status: &DehumidifierStatus, profile: &DeviceProfile) -> String {
     let mut lines = Vec::new();
 
     lines.push(format!(
@@ -213,20 +716,24 @@ pub fn format_status(status: &DehumidifierStatus) -> String {
     if let Some(h) = status.current_humidity {
         lines.push(format!("Current humidity: {h}%"));
     }
+    if let Some(t) = status.temperature {
+        let unit = profile.dp(TEMPERATURE).and_then(|dp| dp.unit.as_deref()).unwrap_or("°C");
+        lines.push(format!("Temperature: {t}{unit}"));
+    }
     lines.push(format!("Target humidity: {}%", status.target_humidity));
 
     if let Some(ref mode) = status.mode {
-        lines.push(format!("Mode: {mode:?}"));
+        lines.push(format!("Mode: {mode}"));
     }
 
     if let Some(ref countdown) = status.countdown {
-        lines.push(format!("Timer: {countdown:?}"));
+        lines.push(format!("Timer: {countdown}"));
     }
 
-    if let Some(left) = status.countdown_left {
-        if left > 0 {
-            lines.push(format!("Time remaining: {left}h"));
-        }
+    if let Some(left) = status.countdown_left
+        && left > 0
+    {
+        lines.push(format!("Time remaining: {left}h"));
     }
 
     if let Some(locked) = status.child_lock {
@@ -236,12 +743,288 @@ pub fn format_status(status: &DehumidifierStatus) -> String {
         ));
     }
 
-    if let Some(fault) = status.fault {
-        if fault != 0 {
-            let names = decode_faults(fault);
-            lines.push(format!("FAULTS: {}", names.join(", ")));
+    if let Some(ref speed) = status.fan_speed {
+        lines.push(format!("Fan speed: {speed:?}"));
+    }
+
+    if let Some(on) = status.ionizer {
+        lines.push(format!("Ioniser: {}", if on { "ON" } else { "OFF" }));
+    }
+
+    if let Some(on) = status.sleep {
+        lines.push(format!("Sleep mode: {}", if on { "ON" } else { "OFF" }));
+    }
+
+    match (status.filter_due, status.filter_hours) {
+        (Some(true), Some(hours)) => lines.push(format!("Filter: due for cleaning ({hours}h run since last cleaned)")),
+        (Some(true), None) => lines.push("Filter: due for cleaning".to_owned()),
+        (_, Some(hours)) => lines.push(format!("Filter: {hours}h run since last cleaned")),
+        _ => {}
+    }
+
+    let mut faults: Vec<&Fault> = status.faults.iter().collect();
+    faults.sort_by_key(|f| std::cmp::Reverse(f.severity));
+    lines.extend(faults.into_iter().map(format_fault));
+
+    if let Some(value) = &status.dp_101 {
+        let label = profile.dp(DP_101).and_then(|dp| dp.label.as_deref()).unwrap_or("DP 101 (meaning unknown)");
+        let value = value.as_str().map(str::to_owned).unwrap_or_else(|| value.to_string());
+        lines.push(format!("{label}: {value}"));
+    }
+
+    for (index, value) in &status.extra {
+        match profile.dps.iter().find(|dp| dp.index == *index) {
+            Some(dp) => {
+                let name = dp.label.as_ref().unwrap_or(&dp.name);
+                match value.as_u64() {
+                    Some(bits) if !dp.kind.labels().is_empty() => {
+                        let set = device_profile::decode_bitmap(bits as u32, dp.kind.labels());
+                        let set = if set.is_empty() { "none".to_owned() } else { set.join(", ") };
+                        lines.push(format!("DP {index} ({name}): {set}"));
+                    }
+                    _ => lines.push(format!("DP {index} ({name}): {value}")),
+                }
+            }
+            None => lines.push(format!("DP {index}: {value}")),
         }
     }
 
     lines.join("\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProfileConfig;
+    use crate::device_profile::DpsWrite;
+
+    #[test]
+    fn default_range_matches_documented_setpoints() {
+        assert!(ARETE_TWO_HUMIDITY.accepts(35));
+        assert!(ARETE_TWO_HUMIDITY.accepts(70));
+        assert!(!ARETE_TWO_HUMIDITY.accepts(52));
+        assert!(!ARETE_TWO_HUMIDITY.accepts(75));
+        let arete = DeviceProfile::default();
+        let write = DpsWrite::new(&arete).set_within(TARGET_HUMIDITY, 30, &ARETE_TWO_HUMIDITY);
+        assert!(matches!(write, Err(DpsError::OutOfRange { value: 30, .. })));
+    }
+
+    #[test]
+    fn infer_range_from_accepted_values() {
+        let range = infer_humidity_range(&[35, 40, 45, 50, 55, 60, 65, 70]).unwrap();
+        assert_eq!(range, ARETE_TWO_HUMIDITY);
+
+        let fine = infer_humidity_range(&[30, 31, 32, 35, 80]).unwrap();
+        assert_eq!(fine, HumidityRange { min: 30, max: 80, step: 1 });
+
+        assert_eq!(infer_humidity_range(&[]), None);
+        assert_eq!(infer_humidity_range(&[50]).unwrap().step, 1);
+    }
+
+    #[test]
+    fn profile_dps_parse_only_when_declared() {
+        let dps = serde_json::json!({"1": true, "2": 50, "5": "high", "10": true, "101": "cancel", "102": false});
+
+        let arete = parse_status(&dps, &DeviceProfile::default()).unwrap();
+        assert!(arete.fan_speed.is_none());
+        assert!(arete.ionizer.is_none());
+        // Undeclared DPs are passed through rather than dropped
+        let extra: Vec<&str> = arete.extra.keys().map(String::as_str).collect();
+        assert_eq!(extra, ["10", "102", "5"]);
+        let text = format_status(&arete, &DeviceProfile::default());
+        assert!(text.contains("DP 5: \"high\""));
+        // DP 101 is modelled, though not understood
+        assert_eq!(arete.dp_101, Some("cancel".into()));
+        assert!(text.contains("DP 101 (meaning unknown): cancel"));
+
+        let profile = device_profile::resolve(&ProfileConfig {
+            fan_speed_dp: Some("5".into()),
+            ionizer_dp: Some("10".into()),
+            sleep_dp: Some("102".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let status = parse_status(&dps, &profile).unwrap();
+        assert!(matches!(status.fan_speed, Some(FanSpeed::High)));
+        assert_eq!(status.ionizer, Some(true));
+        assert_eq!(status.sleep, Some(false));
+        assert!(status.extra.is_empty());
+        let write = DpsWrite::new(&profile).set(FAN_SPEED, FanSpeed::Low.dp_value()).unwrap();
+        assert_eq!(write.build(), serde_json::json!({"5": "low"}));
+    }
+
+    #[test]
+    fn status_diff_lists_changed_fields() {
+        let arete = DeviceProfile::default();
+        let before = parse_status(&serde_json::json!({"1": true, "2": 50, "16": 61, "101": "cancel"}), &arete).unwrap();
+        let after =
+            parse_status(&serde_json::json!({"1": true, "2": 55, "19": 1, "101": "cancel", "7": 3}), &arete).unwrap();
+
+        let changes = diff_status(&before, &after);
+        let fields: Vec<&str> = changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, ["current_humidity", "extra.7", "faults", "target_humidity"]);
+        assert_eq!(changes[2].to, serde_json::json!(["tankfull"]));
+        assert!(format_changes(&changes).contains("target_humidity 50 → 55"));
+        assert!(diff_status(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn temperature_is_scaled_by_the_profile() {
+        let dps = serde_json::json!({"1": true, "2": 50, "103": 215});
+        assert!(parse_status(&dps, &DeviceProfile::default()).unwrap().temperature.is_none());
+
+        let mut profile = DeviceProfile::default();
+        profile.dps.extend(
+            device_profile::parse(
+                "model = \"x\"\n[[dp]]\nindex = \"103\"\nname = \"temp_indoor\"\ntype = \"integer\"\nscale = 1",
+            )
+            .unwrap()
+            .dps,
+        );
+        let status = parse_status(&dps, &profile).unwrap();
+        assert_eq!(status.temperature, Some(21.5));
+        assert!(status.extra.is_empty());
+        assert!(format_status(&status, &profile).contains("Temperature: 21.5°C"));
+    }
+
+    #[test]
+    fn unknown_mode_and_countdown_are_kept() {
+        let arete = DeviceProfile::default();
+        let status = parse_status(&serde_json::json!({"1": true, "2": 50, "4": "eco", "17": "later"}), &arete).unwrap();
+        assert!(matches!(&status.mode, Some(Mode::Other(raw)) if raw == "eco"));
+        assert!(matches!(&status.countdown, Some(Countdown::Other(raw)) if raw == "later"));
+        assert!(format_status(&status, &arete).contains("Mode: eco (unrecognised)"));
+        assert_eq!(serde_json::to_value(&status.mode).unwrap(), "eco");
+
+        // Known values still deserialize to their variants
+        let mode: Mode = serde_json::from_value("drying".into()).unwrap();
+        assert!(matches!(mode, Mode::Drying));
+    }
+
+    #[test]
+    fn longer_countdowns_parse_as_hours() {
+        let arete = DeviceProfile::default();
+        let countdown = |value: serde_json::Value| {
+            parse_status(&serde_json::json!({"1": true, "2": 50, "17": value}), &arete).unwrap().countdown
+        };
+        assert!(matches!(countdown("12h".into()), Some(Countdown::Hours(12))));
+        assert!(matches!(countdown(2.into()), Some(Countdown::TwoHours)));
+        assert!(matches!(countdown(0.into()), Some(Countdown::Cancel)));
+        assert_eq!(Countdown::Hours(12).dp_value(), "12h");
+
+        // Zeroing the hours left cancels a running countdown
+        let running = serde_json::json!({"1": true, "18": 5});
+        let zero = serde_json::json!({"18": 0});
+        assert_eq!(disruption(&running, &zero, &arete).unwrap(), "cancel the running countdown with 5h left");
+    }
+
+    #[test]
+    fn snapshots_round_trip_across_versions() {
+        let arete = DeviceProfile::default();
+        let status = parse_status(&serde_json::json!({"1": true, "2": 50, "4": "auto", "17": "2h", "19": 1}), &arete).unwrap();
+
+        let stored = serde_json::to_value(snapshot(&status, 1_700_000_000)).unwrap();
+        let restored = restore(stored.clone()).unwrap();
+        assert_eq!(restored.taken_at, 1_700_000_000);
+        assert_eq!(serde_json::to_value(&restored.status).unwrap(), serde_json::to_value(&status).unwrap());
+
+        // A snapshot from before faults and extra DPs were kept still reads
+        let old = serde_json::json!({
+            "version": 1,
+            "taken_at": 0,
+            "status": {"power": false, "target_humidity": 45, "mode": "drying"}
+        });
+        let restored = restore(old).unwrap().status;
+        assert!(matches!(restored.mode, Some(Mode::Drying)));
+        assert!(restored.faults.is_empty() && restored.extra.is_empty());
+
+        let mut newer = stored;
+        newer["version"] = (STATUS_VERSION + 1).into();
+        assert!(matches!(restore(newer), Err(SnapshotError::Newer(_))));
+    }
+
+    #[test]
+    fn power_off_mid_drying_is_disruptive() {
+        let arete = DeviceProfile::default();
+        let drying = serde_json::json!({"1": true, "4": "drying", "17": "2h"});
+        let off = DpsWrite::new(&arete).set(SWITCH, false).unwrap().build();
+        assert!(disruption(&drying, &off, &arete).unwrap().contains("drying program"));
+        assert!(disruption(&serde_json::json!({"1": true, "4": "manual"}), &off, &arete).is_none());
+
+        let cancel = DpsWrite::new(&arete).set(COUNTDOWN, Countdown::Cancel.dp_value()).unwrap().build();
+        assert_eq!(disruption(&drying, &cancel, &arete).unwrap(), "cancel the running 2h countdown");
+        assert!(disruption(&serde_json::json!({"17": "cancel"}), &cancel, &arete).is_none());
+    }
+
+    #[test]
+    fn faults_explained_with_profile_overrides() {
+        let arete = DeviceProfile::default();
+        let active = active_faults(0b1000_0001 | 1 << 12, &arete);
+        let codes: Vec<&str> = active.iter().map(|f| f.code.as_str()).collect();
+        assert_eq!(codes, ["tankfull", "wet", "bit12"]);
+
+        // Status leads with errors and only explains informational faults
+        let status = parse_status(&serde_json::json!({"1": true, "2": 50, "19": 0b1000_0011}), &arete).unwrap();
+        let text = format_status(&status, &arete);
+        let lines: Vec<&str> = text.lines().skip_while(|l| !l.starts_with("FAULT")).collect();
+        assert!(lines[0].starts_with("FAULT (wet): "));
+        assert!(lines[1].starts_with("Warning (tankfull): The water tank is full"));
+        assert!(lines[2].starts_with("Note: The unit is defrosting"));
+
+        let custom = Fault {
+            bit: 2,
+            code: "P1".into(),
+            severity: Severity::Error,
+            explanation: "Pump fault".into(),
+            action: "Clean the pump".into(),
+        };
+        let profile = device_profile::resolve(&ProfileConfig { faults: vec![custom.clone()], ..Default::default() }).unwrap();
+        assert_eq!(active_faults(0b100, &profile), [custom]);
+
+        // Another model's bitmaps decode by the labels its profile gives
+        let other = device_profile::parse(
+            r#"
+            model = "Generic 12L"
+            [[dp]]
+            index = "1"
+            name = "switch"
+            type = "boolean"
+            [[dp]]
+            index = "2"
+            name = "dehumidify_set_value"
+            type = "integer"
+            [[dp]]
+            index = "11"
+            name = "fault"
+            type = "bitmap"
+            labels = ["E1", "tankfull"]
+            [[dp]]
+            index = "20"
+            name = "filter"
+            type = "bitmap"
+            labels = ["filter_dirty", "filter_missing"]
+            "#,
+        )
+        .unwrap();
+        let status = parse_status(&serde_json::json!({"1": true, "2": 50, "11": 0b10, "20": 0b01}), &other).unwrap();
+        assert_eq!(status.faults[0].code, "tankfull");
+        assert!(format_status(&status, &other).ends_with("DP 20 (filter): filter_dirty"));
+    }
+
+    #[test]
+    fn humidity_target_accepts_numbers_and_presets() {
+        let presets = BTreeMap::from([("storage".to_owned(), 55), ("living".to_owned(), 50)]);
+
+        let number: HumidityTarget = serde_json::from_value(serde_json::json!(45)).unwrap();
+        assert_eq!(resolve_humidity_target(&number, &presets).unwrap(), 45);
+
+        let named: HumidityTarget = serde_json::from_value(serde_json::json!("Storage")).unwrap();
+        assert_eq!(resolve_humidity_target(&named, &presets).unwrap(), 55);
+
+        let unknown = HumidityTarget::Preset("sauna".into());
+        assert!(matches!(
+            resolve_humidity_target(&unknown, &presets),
+            Err(DpsError::UnknownPreset { .. })
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+// -- Connection metrics --
+//
+// Counters for telling a slow device apart from a bad network: round-trip
+// latency is the device plus the network, CRC errors and silent drops
+// point at the network, timeouts with clean frames point at the device.
+
+/// Upper bounds of the round-trip histogram's buckets, in milliseconds.
+pub const LATENCY_BUCKETS_MS: [u64; 8] = [25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    requests: AtomicU64,
+    timeouts: AtomicU64,
+    crc_errors: AtomicU64,
+    reconnects: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Answered requests and their summed round trips, for the mean.
+    answered: AtomicU64,
+    latency_total_us: AtomicU64,
+    latency_max_us: AtomicU64,
+    latency_last_us: AtomicU64,
+    /// Round trips per `LATENCY_BUCKETS_MS` bucket, not cumulative.
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_MS.len()],
+    /// Unix seconds of the last answered request; 0 until there is one.
+    last_answered: AtomicU64,
+    /// Unix seconds the current socket was established; 0 if never.
+    connected_since: AtomicU64,
+    /// Unix seconds of the last answered heartbeat; 0 until there is one.
+    heartbeat_last_ok: AtomicU64,
+    /// Heartbeats failed since the last one answered.
+    heartbeat_failures: AtomicU64,
+}
+
+/// Point-in-time copy of the counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    pub requests: u64,
+    pub timeouts: u64,
+    pub crc_errors: u64,
+    pub reconnects: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub latency_last_ms: f64,
+    pub latency_mean_ms: f64,
+    pub latency_max_ms: f64,
+    /// When the device last answered a request, in Unix seconds.
+    pub last_answered: Option<u64>,
+    /// When the current socket was established, in Unix seconds.
+    pub connected_since: Option<u64>,
+    pub heartbeat_last_ok: Option<u64>,
+    /// Consecutive failed heartbeats; a few in a row replace the socket.
+    pub heartbeat_failures: u64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn record_request(metrics: &ConnectionMetrics, bytes: usize) {
+    metrics.requests.fetch_add(1, Ordering::Relaxed);
+    metrics.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Round trip from the request leaving to its response arriving.
+pub fn record_latency(metrics: &ConnectionMetrics, latency: Duration) {
+    let us = latency.as_micros() as u64;
+    metrics.answered.fetch_add(1, Ordering::Relaxed);
+    metrics.latency_total_us.fetch_add(us, Ordering::Relaxed);
+    metrics.latency_max_us.fetch_max(us, Ordering::Relaxed);
+    metrics.latency_last_us.store(us, Ordering::Relaxed);
+    if let Some(bucket) = LATENCY_BUCKETS_MS.iter().position(|&le| us <= le * 1000) {
+        metrics.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+    metrics.last_answered.store(unix_now(), Ordering::Relaxed);
+}
+
+pub fn record_timeout(metrics: &ConnectionMetrics) {
+    metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_crc_error(metrics: &ConnectionMetrics) {
+    metrics.crc_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_reconnect(metrics: &ConnectionMetrics) {
+    metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_connected(metrics: &ConnectionMetrics) {
+    metrics.connected_since.store(unix_now(), Ordering::Relaxed);
+}
+
+pub fn record_heartbeat(metrics: &ConnectionMetrics, answered: bool) {
+    if answered {
+        metrics.heartbeat_last_ok.store(unix_now(), Ordering::Relaxed);
+        metrics.heartbeat_failures.store(0, Ordering::Relaxed);
+    } else {
+        metrics.heartbeat_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn record_bytes_in(metrics: &ConnectionMetrics, bytes: usize) {
+    metrics.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn snapshot(metrics: &ConnectionMetrics) -> MetricsSnapshot {
+    let ms = |us: u64| us as f64 / 1000.0;
+    let answered = metrics.answered.load(Ordering::Relaxed);
+    let total = metrics.latency_total_us.load(Ordering::Relaxed);
+
+    MetricsSnapshot {
+        requests: metrics.requests.load(Ordering::Relaxed),
+        timeouts: metrics.timeouts.load(Ordering::Relaxed),
+        crc_errors: metrics.crc_errors.load(Ordering::Relaxed),
+        reconnects: metrics.reconnects.load(Ordering::Relaxed),
+        bytes_in: metrics.bytes_in.load(Ordering::Relaxed),
+        bytes_out: metrics.bytes_out.load(Ordering::Relaxed),
+        latency_last_ms: ms(metrics.latency_last_us.load(Ordering::Relaxed)),
+        latency_mean_ms: ms(total.checked_div(answered).unwrap_or(0)),
+        latency_max_ms: ms(metrics.latency_max_us.load(Ordering::Relaxed)),
+        last_answered: Some(metrics.last_answered.load(Ordering::Relaxed)).filter(|&t| t > 0),
+        connected_since: Some(metrics.connected_since.load(Ordering::Relaxed)).filter(|&t| t > 0),
+        heartbeat_last_ok: Some(metrics.heartbeat_last_ok.load(Ordering::Relaxed)).filter(|&t| t > 0),
+        heartbeat_failures: metrics.heartbeat_failures.load(Ordering::Relaxed),
+    }
+}
+
+/// Round trips as a histogram: cumulative counts per bucket, the number
+/// answered, and their total in seconds.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum_secs: f64,
+}
+
+pub fn latency_histogram(metrics: &ConnectionMetrics) -> LatencyHistogram {
+    let mut cumulative = 0;
+    let buckets = LATENCY_BUCKETS_MS
+        .iter()
+        .zip(&metrics.latency_buckets)
+        .map(|(&le, count)| {
+            cumulative += count.load(Ordering::Relaxed);
+            (le as f64 / 1000.0, cumulative)
+        })
+        .collect();
+    LatencyHistogram {
+        buckets,
+        count: metrics.answered.load(Ordering::Relaxed),
+        sum_secs: metrics.latency_total_us.load(Ordering::Relaxed) as f64 / 1e6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_summary() {
+        let metrics = ConnectionMetrics::default();
+        record_latency(&metrics, Duration::from_millis(10));
+        record_latency(&metrics, Duration::from_millis(30));
+        record_latency(&metrics, Duration::from_millis(20));
+
+        let snap = snapshot(&metrics);
+        assert_eq!(snap.latency_last_ms, 20.0);
+        assert_eq!(snap.latency_mean_ms, 20.0);
+        assert_eq!(snap.latency_max_ms, 30.0);
+        assert!(snap.last_answered.is_some());
+
+        let histogram = latency_histogram(&metrics);
+        assert_eq!(histogram.buckets[0], (0.025, 2));
+        assert_eq!(histogram.buckets[1], (0.05, 3));
+        assert_eq!(histogram.count, 3);
+        assert!((histogram.sum_secs - 0.06).abs() < 1e-9);
+
+        record_heartbeat(&metrics, false);
+        record_heartbeat(&metrics, false);
+        assert_eq!(snapshot(&metrics).heartbeat_failures, 2);
+        record_heartbeat(&metrics, true);
+        let snap = snapshot(&metrics);
+        assert_eq!(snap.heartbeat_failures, 0);
+        assert!(snap.heartbeat_last_ok.is_some());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use rmcp::{
+    Peer, RoleServer,
+    model::{
+        CustomNotification, LoggingLevel, LoggingMessageNotificationParam,
+        ResourceUpdatedNotificationParam, ServerNotification,
+    },
+};
+
+use crate::events::{self, DeviceEvent, EventBus};
+use crate::logging::LogRecord;
+use crate::status;
+use crate::tuya_connection::ConnectionState;
+
+// -- Client notifications --
+//
+// Tell the MCP client when the device drops off the network or comes back,
+// so an agent knows it's unreachable before it tries a command. Sent as a
+// custom notification, and as a log message to clients that subscribed
+// with `logging/setLevel`. Clients subscribed to the status resource also
+// hear whenever a DP changes, whether from a query or a device push.
+// Clients that set a log level also get hearth's own log events at or
+// above it: reconnects, heartbeat failures, tool call outcomes.
+
+pub const CONNECTION_NOTIFICATION: &str = "notifications/hearth/connection";
+
+/// What the client asked to hear about.
+#[derive(Debug, Default)]
+pub struct ClientSubscriptions {
+    /// The MCP log level it asked for. Nothing is logged to it until then.
+    log_level: Mutex<Option<LoggingLevel>>,
+    /// Resource URIs it subscribed to.
+    resources: Mutex<BTreeSet<String>>,
+}
+
+pub fn set_level(client: &ClientSubscriptions, level: LoggingLevel) {
+    *client.log_level.lock().expect("client subscriptions lock poisoned") = Some(level);
+}
+
+fn wants(client: &ClientSubscriptions, level: LoggingLevel) -> bool {
+    // LoggingLevel variants are declared least to most severe
+    let min = *client.log_level.lock().expect("client subscriptions lock poisoned");
+    min.is_some_and(|min| level as u8 >= min as u8)
+}
+
+pub fn subscribe(client: &ClientSubscriptions, uri: &str) {
+    client.resources.lock().expect("client subscriptions lock poisoned").insert(uri.to_owned());
+}
+
+pub fn unsubscribe(client: &ClientSubscriptions, uri: &str) {
+    client.resources.lock().expect("client subscriptions lock poisoned").remove(uri);
+}
+
+fn subscribed(client: &ClientSubscriptions, uri: &str) -> bool {
+    client.resources.lock().expect("client subscriptions lock poisoned").contains(uri)
+}
+
+fn mcp_level(level: tracing::Level) -> LoggingLevel {
+    match level {
+        tracing::Level::ERROR => LoggingLevel::Error,
+        tracing::Level::WARN => LoggingLevel::Warning,
+        tracing::Level::INFO => LoggingLevel::Info,
+        tracing::Level::DEBUG | tracing::Level::TRACE => LoggingLevel::Debug,
+    }
+}
+
+/// Forward log records to `peer` at or above the level it set. Records
+/// dropped while the client lagged are counted in a warning to it.
+pub fn spawn_log_forwarder(
+    mut records: tokio::sync::broadcast::Receiver<LogRecord>,
+    peer: Peer<RoleServer>,
+    client: std::sync::Arc<ClientSubscriptions>,
+) -> tokio::task::JoinHandle<()> {
+    use tokio::sync::broadcast::error::RecvError;
+
+    tokio::spawn(async move {
+        loop {
+            let (level, logger, message) = match records.recv().await {
+                Ok(record) => (mcp_level(record.level), record.target, record.message),
+                Err(RecvError::Lagged(dropped)) => (
+                    LoggingLevel::Warning,
+                    "hearth".to_owned(),
+                    format!("{dropped} log messages dropped: the client fell behind"),
+                ),
+                Err(RecvError::Closed) => break,
+            };
+            if peer.is_transport_closed() {
+                break;
+            }
+            if !wants(&client, level) {
+                continue;
+            }
+
+            let sent = peer
+                .notify_logging_message(LoggingMessageNotificationParam {
+                    level,
+                    logger: Some(logger),
+                    data: message.into(),
+                })
+                .await;
+            // Not logged: it would only be forwarded to the same dead client
+            if sent.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Whether the device can be reached in `state`, if that says either way.
+/// Degraded is slow, not gone.
+pub fn reachable(state: ConnectionState) -> Option<bool> {
+    match state {
+        ConnectionState::Ready | ConnectionState::Degraded => Some(true),
+        ConnectionState::Closed => Some(false),
+        ConnectionState::Connecting | ConnectionState::Reconnecting => None,
+    }
+}
+
+/// The new reachability if moving to `to` changes it.
+fn transition(last: Option<bool>, to: ConnectionState) -> Option<bool> {
+    reachable(to).filter(|&now| last != Some(now))
+}
+
+/// Notify `peer` of every reachability change from `initial` onwards, and
+/// of status changes if it subscribed. Stops when the client goes away.
+pub fn spawn_notifier(
+    bus: &EventBus,
+    device: &str,
+    initial: ConnectionState,
+    peer: Peer<RoleServer>,
+    client: std::sync::Arc<ClientSubscriptions>,
+) -> tokio::task::JoinHandle<()> {
+    let mut sub = events::subscribe(bus, "client_notifier");
+    let device = device.to_owned();
+
+    tokio::spawn(async move {
+        let mut last = reachable(initial);
+        let mut reason = None;
+
+        while let Some(event) = events::next_event(&mut sub).await {
+            let to = match event {
+                DeviceEvent::StatusChanged { .. } if subscribed(&client, status::STATUS_URI) => {
+                    let uri = status::STATUS_URI.to_owned();
+                    if let Err(e) = peer.notify_resource_updated(ResourceUpdatedNotificationParam { uri }).await {
+                        tracing::debug!("Client gone, no more status notifications: {e}");
+                        break;
+                    }
+                    continue;
+                }
+                DeviceEvent::Disconnected { reason: why } => {
+                    reason = Some(why);
+                    continue;
+                }
+                DeviceEvent::StateChanged { to, .. } => to,
+                _ => continue,
+            };
+            let Some(now) = transition(last, to) else {
+                continue;
+            };
+            last = Some(now);
+
+            let (level, message) = if now {
+                reason = None;
+                (LoggingLevel::Info, format!("{device} reachable again"))
+            } else {
+                let why = reason.as_deref().unwrap_or("no address answered");
+                (LoggingLevel::Warning, format!("{device} unreachable: {why}"))
+            };
+
+            let params = serde_json::json!({ "device": device, "reachable": now, "state": to, "message": message });
+            let mut sent = peer
+                .send_notification(ServerNotification::CustomNotification(CustomNotification::new(
+                    CONNECTION_NOTIFICATION,
+                    Some(params),
+                )))
+                .await;
+            if sent.is_ok() && wants(&client, level) {
+                sent = peer
+                    .notify_logging_message(LoggingMessageNotificationParam {
+                        level,
+                        logger: Some("hearth".into()),
+                        data: message.into(),
+                    })
+                    .await;
+            }
+            if let Err(e) = sent {
+                tracing::debug!("Client gone, no more connection notifications: {e}");
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reachability_changes_are_reported() {
+        use ConnectionState::*;
+
+        assert_eq!(transition(None, Connecting), None);
+        assert_eq!(transition(None, Ready), Some(true));
+        assert_eq!(transition(Some(true), Degraded), None);
+        assert_eq!(transition(Some(true), Reconnecting), None);
+        assert_eq!(transition(Some(true), Closed), Some(false));
+        assert_eq!(transition(Some(false), Closed), None);
+        assert_eq!(transition(Some(false), Ready), Some(true));
+
+        let client = ClientSubscriptions::default();
+        assert!(!wants(&client, LoggingLevel::Warning));
+        set_level(&client, LoggingLevel::Warning);
+        assert!(wants(&client, LoggingLevel::Error));
+        assert!(!wants(&client, LoggingLevel::Info));
+        assert!(wants(&client, mcp_level(tracing::Level::WARN)));
+        assert!(!wants(&client, mcp_level(tracing::Level::TRACE)));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::config::ObserveConfig;
+use crate::events::{self, DeviceEvent, EventBus};
+
+// -- Watch-and-learn DP observation --
+//
+// Half the DPS table is guesswork. Left running for a few days, this
+// records every value each DP takes and which changes came from the
+// physical panel rather than from hearth, so unknown DPs can be matched
+// up with the buttons that move them.
+
+/// Changes this soon after one of our own writes are attributed to it.
+const WRITE_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Observations {
+    pub dps: BTreeMap<String, DpObservation>,
+    /// DPs that changed together without a write from hearth, e.g. "4+101",
+    /// with how often. A panel button usually shows up as one group.
+    pub external_groups: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DpObservation {
+    /// Every value seen (as JSON) and how often it was reported.
+    pub values: BTreeMap<String, u64>,
+    /// "from -> to" and how often.
+    pub transitions: BTreeMap<String, u64>,
+    /// Changes that no write from hearth explains.
+    pub external_changes: u64,
+    /// Unix seconds.
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Last value seen, kept so transitions carry across restarts.
+    last: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+pub enum ObserveError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl std::fmt::Display for ObserveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObserveError::Io(e) => write!(f, "Observation file error: {e}"),
+            ObserveError::Parse(msg) => write!(f, "Failed to parse observation file: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ObserveError {}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Fold one batch of changed DPs into the observations. Values equal to
+/// the last one seen are not changes (a reconnect re-reports everything).
+pub fn observe(
+    obs: &mut Observations,
+    changed: &serde_json::Map<String, serde_json::Value>,
+    external: bool,
+    now: u64,
+) {
+    let mut moved = Vec::new();
+
+    for (key, value) in changed {
+        let dp = obs.dps.entry(key.clone()).or_insert_with(|| DpObservation {
+            first_seen: now,
+            ..DpObservation::default()
+        });
+        dp.last_seen = now;
+
+        if dp.last.as_ref() == Some(value) {
+            continue;
+        }
+        *dp.values.entry(value.to_string()).or_default() += 1;
+        if let Some(last) = dp.last.replace(value.clone()) {
+            *dp.transitions.entry(format!("{last} -> {value}")).or_default() += 1;
+            if external {
+                dp.external_changes += 1;
+                moved.push(key.as_str());
+            }
+        }
+    }
+
+    if external && !moved.is_empty() {
+        *obs.external_groups.entry(moved.join("+")).or_default() += 1;
+    }
+}
+
+/// Human-readable summary, most revealing parts first.
+pub fn format_report(obs: &Observations) -> String {
+    if obs.dps.is_empty() {
+        return "No DP values observed yet".into();
+    }
+
+    let mut lines = vec!["DP observations:".to_owned()];
+    for (key, dp) in &obs.dps {
+        let values: Vec<String> = dp.values.iter().map(|(v, n)| format!("{v} ×{n}")).collect();
+        lines.push(format!(
+            "  DPS {key}: {} external change(s); values {}",
+            dp.external_changes,
+            values.join(", ")
+        ));
+        for (transition, count) in &dp.transitions {
+            lines.push(format!("      {transition} ×{count}"));
+        }
+    }
+
+    if !obs.external_groups.is_empty() {
+        lines.push("Changed together without a hearth write (panel or app):".into());
+        for (group, count) in &obs.external_groups {
+            lines.push(format!("  {group} ×{count}"));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Load earlier observations. A missing file is a fresh start.
+pub fn load(path: &str) -> Result<Observations, ObserveError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            serde_json::from_str(&contents).map_err(|e| ObserveError::Parse(e.to_string()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Observations::default()),
+        Err(e) => Err(ObserveError::Io(e)),
+    }
+}
+
+pub fn save(path: &str, obs: &Observations) -> Result<(), ObserveError> {
+    let json = serde_json::to_string_pretty(obs).map_err(|e| ObserveError::Parse(e.to_string()))?;
+    std::fs::write(path, json).map_err(ObserveError::Io)
+}
+
+/// Record every DP change seen on the bus, saving to disk periodically.
+pub fn spawn_observer(
+    bus: &EventBus,
+    config: &ObserveConfig,
+) -> Result<(Arc<Mutex<Observations>>, tokio::task::JoinHandle<()>), ObserveError> {
+    let observations = Arc::new(Mutex::new(load(&config.path)?));
+    let mut sub = events::subscribe(bus, "dp_observer");
+    let path = config.path.clone();
+    let flush_every = Duration::from_secs(config.flush_secs.max(1));
+
+    let task = tokio::spawn({
+        let observations = observations.clone();
+        async move {
+            let mut last_write: Option<Instant> = None;
+            let mut flush = tokio::time::interval(flush_every);
+            let mut dirty = false;
+
+            loop {
+                tokio::select! {
+                    event = events::next_event(&mut sub) => match event {
+                        Some(DeviceEvent::ControlSent { .. }) => last_write = Some(Instant::now()),
+                        Some(DeviceEvent::StatusChanged { changed }) => {
+                            let external = last_write.is_none_or(|at| at.elapsed() > WRITE_WINDOW);
+                            let mut obs = observations.lock().expect("observations lock poisoned");
+                            observe(&mut obs, &changed, external, unix_now());
+                            dirty = true;
+                        }
+                        Some(_) => {}
+                        None => break,
+                    },
+                    _ = flush.tick(), if dirty => {
+                        let obs = observations.lock().expect("observations lock poisoned");
+                        match save(&path, &obs) {
+                            Ok(()) => dirty = false,
+                            Err(e) => tracing::warn!("{e}"),
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok((observations, task))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn changed(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn records_values_transitions_and_external_groups() {
+        let mut obs = Observations::default();
+
+        observe(&mut obs, &changed(json!({"4": "manual", "101": 0})), true, 1);
+        // Reconnect re-reports the same values: not a change
+        observe(&mut obs, &changed(json!({"4": "manual", "101": 0})), true, 2);
+        // Someone presses a panel button
+        observe(&mut obs, &changed(json!({"4": "auto", "101": 1})), true, 3);
+        // hearth writes DPS 4
+        observe(&mut obs, &changed(json!({"4": "manual"})), false, 4);
+
+        let mode = &obs.dps["4"];
+        assert_eq!(mode.values["\"manual\""], 2);
+        assert_eq!(mode.transitions["\"manual\" -> \"auto\""], 1);
+        assert_eq!(mode.external_changes, 1);
+        assert_eq!((mode.first_seen, mode.last_seen), (1, 4));
+        assert_eq!(obs.external_groups["101+4"], 1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::config::{ConnectionConfig, MeacoConfig, PlugConfig};
+use crate::device_profile::DeviceProfile;
+use crate::discovery::DiscoveredDevices;
+use crate::meaco::DpsError;
+use crate::tuya_connection::{self, TuyaConnection};
+
+// -- Smart plugs --
+//
+// Tuya switch/socket devices alongside the dehumidifier, e.g. the plug a
+// heater in the same room hangs off. Each has its own connection and
+// heartbeat; tools can switch it and read what it's drawing. DPs are
+// looked up in the plug's profile, Tuya's standard socket one unless
+// `[plug.<name>] profile` says otherwise.
+
+// Names the socket profile's DPs are looked up by
+pub const SWITCH: &str = "switch_1";
+pub const POWER: &str = "cur_power";
+pub const VOLTAGE: &str = "cur_voltage";
+pub const CURRENT: &str = "cur_current";
+pub const ENERGY: &str = "add_ele";
+
+#[derive(Debug, Clone)]
+pub struct Plug {
+    pub conn: Arc<TuyaConnection>,
+    pub profile: DeviceProfile,
+}
+
+/// A plug's state. Energy readings are absent on plugs without metering.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PlugStatus {
+    pub on: bool,
+    pub power_w: Option<f64>,
+    pub voltage_v: Option<f64>,
+    pub current_ma: Option<f64>,
+    /// Energy since the plug last reported it.
+    pub energy_kwh: Option<f64>,
+}
+
+/// Connect to every configured plug in the background, keyed by name.
+pub fn start_all(
+    plugs: &BTreeMap<String, PlugConfig>,
+    policy: &ConnectionConfig,
+    discovered: &Arc<DiscoveredDevices>,
+) -> BTreeMap<String, Plug> {
+    plugs
+        .iter()
+        .map(|(name, config)| (name.clone(), start(name, config, policy, discovered)))
+        .collect()
+}
+
+/// Connect to one plug in the background, with its heartbeat running.
+pub fn start(
+    name: &str,
+    config: &PlugConfig,
+    policy: &ConnectionConfig,
+    discovered: &Arc<DiscoveredDevices>,
+) -> Plug {
+    // Logs and metrics call it by its table key
+    let device = MeacoConfig {
+        name: Some(name.to_owned()),
+        ..config.device.clone()
+    };
+    let conn = tuya_connection::new(&device, policy, &config.resolved);
+    tuya_connection::set_resolver(&conn, discovered.clone());
+    tuya_connection::spawn_heartbeat(conn.clone());
+    tokio::spawn({
+        let conn = conn.clone();
+        let name = name.to_owned();
+        async move {
+            match tuya_connection::connect(&conn).await {
+                Ok(()) => tracing::info!(device = %name, "Connected to plug"),
+                Err(e) => tracing::warn!(device = %name, "Plug unreachable at startup ({e}), will retry on first use"),
+            }
+        }
+    });
+    Plug {
+        conn,
+        profile: config.resolved.clone(),
+    }
+}
+
+pub fn parse_status(dps: &serde_json::Value, profile: &DeviceProfile) -> Result<PlugStatus, DpsError> {
+    let on = profile
+        .read(dps, SWITCH)
+        .and_then(|v| v.as_bool())
+        .ok_or(DpsError::MissingField(SWITCH))?;
+
+    Ok(PlugStatus {
+        on,
+        power_w: profile.scaled(dps, POWER),
+        voltage_v: profile.scaled(dps, VOLTAGE),
+        current_ma: profile.scaled(dps, CURRENT),
+        energy_kwh: profile.scaled(dps, ENERGY),
+    })
+}
+
+pub fn format_status(name: &str, status: &PlugStatus) -> String {
+    let mut lines = vec![format!("{name}: {}", if status.on { "ON" } else { "OFF" })];
+    if let Some(power) = status.power_w {
+        lines.push(format!("Drawing: {power} W"));
+    }
+    if let Some(voltage) = status.voltage_v {
+        lines.push(format!("Voltage: {voltage} V"));
+    }
+    if let Some(current) = status.current_ma {
+        lines.push(format!("Current: {current} mA"));
+    }
+    if let Some(energy) = status.energy_kwh {
+        lines.push(format!("Energy since last report: {energy} kWh"));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_profile::{self, DpsWrite};
+
+    #[test]
+    fn socket_status_applies_scale() {
+        let profile = device_profile::resolve_plug(None).unwrap();
+        let dps = serde_json::json!({"1": true, "18": 5210, "19": 11975, "20": 2301, "17": 12});
+
+        let status = parse_status(&dps, &profile).unwrap();
+        assert!(status.on);
+        assert_eq!(status.power_w, Some(1197.5));
+        assert_eq!(status.voltage_v, Some(230.1));
+        assert_eq!(status.current_ma, Some(5210.0));
+        assert_eq!(status.energy_kwh, Some(0.012));
+
+        // A plug without metering reports only the switch
+        let bare = parse_status(&serde_json::json!({"1": false}), &profile).unwrap();
+        assert!(bare.power_w.is_none());
+        let off = DpsWrite::new(&profile).set(SWITCH, false).unwrap();
+        assert_eq!(off.build(), serde_json::json!({"1": false}));
+
+        // The dehumidifier's profile has no switch_1
+        assert!(parse_status(&dps, &DeviceProfile::default()).is_err());
+    }
+}
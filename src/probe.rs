@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::meaco::{self, HumidityRange};
+use crate::tuya_connection::{self, ConnectionError, Deadline, TuyaConnection};
+
+// -- Target humidity setpoint discovery --
+//
+// Firmware clones of the same model accept different setpoints, and a
+// rejected value is a silent no-op rather than an error. The only reliable
+// way to learn the range is to write candidates and read back what stuck.
+
+/// Coarse sweep: covers every range seen on Tuya dehumidifiers so far.
+const COARSE_CANDIDATES: std::ops::RangeInclusive<u32> = 20..=90;
+const COARSE_STEP: usize = 5;
+
+/// Offsets tried above the lowest accepted value to detect finer steps.
+const FINE_OFFSETS: &[u32] = &[1, 2];
+
+/// Give the device time to apply a write before reading it back.
+/// Also keeps us well under the rate that upsets some Wi-Fi modules.
+const SETTLE_DELAY: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeReport {
+    pub range: Option<HumidityRange>,
+    pub accepted: Vec<u32>,
+    pub rejected: Vec<u32>,
+    pub restored_to: u32,
+}
+
+#[derive(Debug)]
+pub enum ProbeError {
+    Connection(ConnectionError),
+    UnreadableTarget,
+}
+
+impl std::fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeError::Connection(e) => write!(f, "{e}"),
+            ProbeError::UnreadableTarget => {
+                write!(f, "Device did not report a target humidity")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl From<ConnectionError> for ProbeError {
+    fn from(e: ConnectionError) -> Self {
+        ProbeError::Connection(e)
+    }
+}
+
+/// Read the target humidity DP, `dp`, from a fresh query.
+async fn read_target(conn: &TuyaConnection, dp: &str, deadline: &Deadline) -> Result<u32, ProbeError> {
+    let response = tuya_connection::query_dps(conn, deadline).await?;
+    let dps = response.get("dps").unwrap_or(&response);
+
+    dps.get(dp)
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .ok_or(ProbeError::UnreadableTarget)
+}
+
+/// Write a candidate setpoint and report whether the device kept it.
+async fn try_setpoint(
+    conn: &TuyaConnection,
+    dp: &str,
+    value: u32,
+    deadline: &Deadline,
+) -> Result<bool, ProbeError> {
+    tuya_connection::set_dps(conn, raw_target_dps(dp, value), deadline).await?;
+    tokio::time::sleep(SETTLE_DELAY).await;
+    Ok(read_target(conn, dp, deadline).await? == value)
+}
+
+/// Sweep candidate setpoints, infer the accepted range/step, then restore
+/// the original target. The original is restored even if a probe fails
+/// or the caller's deadline cuts the sweep short. `dp` is the target
+/// humidity's index in the device profile.
+pub async fn probe_humidity_range(
+    conn: &TuyaConnection,
+    dp: &str,
+    deadline: &Deadline,
+) -> Result<ProbeReport, ProbeError> {
+    let original = read_target(conn, dp, deadline).await?;
+    tracing::info!(original, "Probing target humidity setpoints");
+
+    let result = sweep(conn, dp, deadline).await;
+
+    // Best effort restore — report the sweep error over a restore error.
+    // Deliberately unbounded: a cancelled probe must not strand the setpoint.
+    let restore =
+        tuya_connection::set_dps(conn, raw_target_dps(dp, original), &Deadline::default()).await;
+    let (accepted, rejected) = result?;
+    restore?;
+
+    let range = meaco::infer_humidity_range(&accepted);
+    tracing::info!(?range, ?accepted, "Setpoint probe complete");
+
+    Ok(ProbeReport {
+        range,
+        accepted,
+        rejected,
+        restored_to: original,
+    })
+}
+
+async fn sweep(
+    conn: &TuyaConnection,
+    dp: &str,
+    deadline: &Deadline,
+) -> Result<(Vec<u32>, Vec<u32>), ProbeError> {
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+
+    for value in COARSE_CANDIDATES.step_by(COARSE_STEP) {
+        if try_setpoint(conn, dp, value, deadline).await? {
+            accepted.push(value);
+        } else {
+            rejected.push(value);
+        }
+    }
+
+    if let (Some(&min), Some(&max)) = (accepted.first(), accepted.last()) {
+        for offset in FINE_OFFSETS {
+            let value = min + offset;
+            if value >= max {
+                break;
+            }
+            if try_setpoint(conn, dp, value, deadline).await? {
+                accepted.push(value);
+            } else {
+                rejected.push(value);
+            }
+        }
+    }
+
+    accepted.sort_unstable();
+    rejected.sort_unstable();
+    Ok((accepted, rejected))
+}
+
+/// Raw target humidity write, bypassing range validation — the probe is
+/// how we learn it.
+fn raw_target_dps(dp: &str, value: u32) -> serde_json::Value {
+    serde_json::json!({ dp: value })
+}
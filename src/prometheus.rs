@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+
+use crate::config::MetricsConfig;
+use crate::meaco::DehumidifierStatus;
+use crate::metrics::LatencyHistogram;
+use crate::plug::Plug;
+use crate::status::{self, StatusStore};
+use crate::tuya_connection::{self, ConnectionState, Diagnostics, TuyaConnection};
+
+// -- Prometheus exporter --
+//
+// With `[metrics] enabled`, `/metrics` serves the connection counters
+// every device keeps, and the dehumidifier's last-known status as gauges,
+// in Prometheus' text format. Scrapes never touch the device: the status
+// is whatever was last read, with its age, so `[polling]` is what keeps
+// it current between tool calls.
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// What a scrape reads from.
+#[derive(Clone)]
+pub struct Sources {
+    pub conn: Arc<TuyaConnection>,
+    pub last_status: Arc<StatusStore>,
+    pub plugs: Arc<RwLock<BTreeMap<String, Plug>>>,
+}
+
+/// A metric family's name, help text and value.
+type Metric<T> = (&'static str, &'static str, fn(&T) -> Option<f64>);
+
+/// One device's numbers at scrape time.
+pub struct Sample {
+    pub diagnostics: Diagnostics,
+    pub latency: LatencyHistogram,
+    /// Only for the dehumidifier, once it has been read.
+    pub status: Option<(DehumidifierStatus, Duration)>,
+}
+
+fn sample(conn: &TuyaConnection, status: Option<(DehumidifierStatus, Duration)>) -> Sample {
+    Sample {
+        diagnostics: tuya_connection::diagnostics(conn),
+        latency: tuya_connection::latency_histogram(conn),
+        status,
+    }
+}
+
+/// Every device's numbers, the dehumidifier's first.
+pub fn collect(sources: &Sources) -> Vec<Sample> {
+    let mut samples = vec![sample(&sources.conn, status::last_known(&sources.last_status))];
+    let plugs = sources.plugs.read().expect("plugs lock poisoned");
+    samples.extend(plugs.values().map(|plug| sample(&plug.conn, None)));
+    samples
+}
+
+/// Whether hearth has a working socket to the device.
+pub fn is_up(sample: &Sample) -> bool {
+    matches!(sample.diagnostics.state, ConnectionState::Ready | ConnectionState::Degraded)
+}
+
+/// A label value, escaped as the text format wants.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// One metric family: its HELP and TYPE, then a line per labelled value.
+fn family(out: &mut String, name: &str, kind: &str, help: &str, rows: impl IntoIterator<Item = (String, f64)>) {
+    let rows: Vec<_> = rows.into_iter().collect();
+    if rows.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in rows {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+/// The text exposition of `samples`.
+pub fn render(samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let device = |s: &Sample| format!("device=\"{}\"", escape(&s.diagnostics.device));
+    let per_device = |value: fn(&Sample) -> Option<f64>| {
+        samples.iter().filter_map(move |s| value(s).map(|v| (device(s), v))).collect::<Vec<_>>()
+    };
+
+    family(&mut out, "hearth_up", "gauge", "Whether hearth has a working socket to the device.", per_device(|s| {
+        Some(if is_up(s) { 1.0 } else { 0.0 })
+    }));
+    let counters: [Metric<Sample>; 6] = [
+        ("hearth_requests_total", "Requests sent to the device.", |s| Some(s.diagnostics.metrics.requests as f64)),
+        ("hearth_timeouts_total", "Requests the device didn't answer in time.", |s| Some(s.diagnostics.metrics.timeouts as f64)),
+        ("hearth_reconnects_total", "Sockets replaced after failing.", |s| Some(s.diagnostics.metrics.reconnects as f64)),
+        ("hearth_crc_errors_total", "Frames received with a bad CRC.", |s| Some(s.diagnostics.metrics.crc_errors as f64)),
+        ("hearth_received_bytes_total", "Bytes read from the device.", |s| Some(s.diagnostics.metrics.bytes_in as f64)),
+        ("hearth_sent_bytes_total", "Bytes written to the device.", |s| Some(s.diagnostics.metrics.bytes_out as f64)),
+    ];
+    for (name, help, value) in counters {
+        family(&mut out, name, "counter", help, per_device(value));
+    }
+    family(&mut out, "hearth_heartbeat_failures", "gauge", "Heartbeats failed in a row.", per_device(|s| {
+        Some(s.diagnostics.metrics.heartbeat_failures as f64)
+    }));
+
+    let name = "hearth_request_duration_seconds";
+    let _ = writeln!(out, "# HELP {name} Round trip of answered requests.");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for s in samples {
+        let device = device(s);
+        for (le, count) in &s.latency.buckets {
+            let _ = writeln!(out, "{name}_bucket{{{device},le=\"{le}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{device},le=\"+Inf\"}} {}", s.latency.count);
+        let _ = writeln!(out, "{name}_sum{{{device}}} {}", s.latency.sum_secs);
+        let _ = writeln!(out, "{name}_count{{{device}}} {}", s.latency.count);
+    }
+
+    let read: Vec<(String, &DehumidifierStatus, Duration)> = samples
+        .iter()
+        .filter_map(|s| s.status.as_ref().map(|(status, age)| (device(s), status, *age)))
+        .collect();
+    let gauges: [Metric<DehumidifierStatus>; 4] = [
+        ("hearth_humidity_percent", "Relative humidity the device last reported.", |st| {
+            st.current_humidity.map(f64::from)
+        }),
+        ("hearth_target_humidity_percent", "The humidity the device is set to.", |st| Some(f64::from(st.target_humidity))),
+        ("hearth_power", "Whether the device is on.", |st| Some(if st.power { 1.0 } else { 0.0 })),
+        ("hearth_fault_bits", "Active fault bits, as the fault DP's bitmap.", |st| {
+            Some(st.faults.iter().fold(0u64, |bits, fault| bits | 1 << fault.bit) as f64)
+        }),
+    ];
+    for (name, help, value) in gauges {
+        let rows = read.iter().filter_map(|(device, status, _)| value(status).map(|v| (device.clone(), v)));
+        family(&mut out, name, "gauge", help, rows);
+    }
+    family(
+        &mut out,
+        "hearth_status_age_seconds",
+        "gauge",
+        "How long ago the status above was read.",
+        read.iter().map(|(device, _, age)| (device.clone(), age.as_secs_f64())),
+    );
+    out
+}
+
+async fn handle(sources: &Sources, request: Request<hyper::body::Incoming>) -> Response<Full<Bytes>> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Full::new(Bytes::from_static(b"Not found; try /metrics\n")));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let body = render(&collect(sources));
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    response
+        .headers_mut()
+        .insert(hyper::header::CONTENT_TYPE, hyper::header::HeaderValue::from_static(CONTENT_TYPE));
+    response
+}
+
+/// Serve `/metrics` on `[metrics] bind` for as long as hearth runs.
+pub async fn serve(config: &MetricsConfig, sources: Sources) -> std::io::Result<()> {
+    let listener = TcpListener::bind(config.bind).await?;
+    tracing::info!(address = %listener.local_addr()?, "Serving Prometheus metrics on /metrics");
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!("Metrics accept failed: {e}");
+                continue;
+            }
+        };
+        let sources = sources.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let sources = sources.clone();
+                async move { Ok::<_, Infallible>(handle(&sources, request).await) }
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!(%peer, "Metrics connection ended: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SeqnoPolicy;
+    use crate::metrics::{self, MetricsSnapshot};
+
+    #[test]
+    fn renders_counters_histogram_and_status() {
+        let status: DehumidifierStatus = serde_json::from_value(serde_json::json!({
+            "power": true,
+            "target_humidity": 50,
+            "current_humidity": 61,
+            "faults": [{"bit": 1, "code": "E2", "explanation": "", "action": ""}],
+        }))
+        .unwrap();
+        let sample = Sample {
+            diagnostics: Diagnostics {
+                device: "bed\"room".to_owned(),
+                state: ConnectionState::Ready,
+                address: String::new(),
+                next_seqno: 1,
+                seqno_policy: SeqnoPolicy::Continue,
+                dropped_events: BTreeMap::new(),
+                metrics: MetricsSnapshot {
+                    requests: 7,
+                    timeouts: 2,
+                    ..metrics::snapshot(&metrics::ConnectionMetrics::default())
+                },
+            },
+            latency: LatencyHistogram {
+                buckets: vec![(0.05, 1), (0.1, 3)],
+                count: 4,
+                sum_secs: 0.5,
+            },
+            status: Some((status, Duration::from_secs(12))),
+        };
+
+        let text = render(&[sample]);
+        assert!(text.contains("# TYPE hearth_requests_total counter\nhearth_requests_total{device=\"bed\\\"room\"} 7\n"));
+        assert!(text.contains("hearth_timeouts_total{device=\"bed\\\"room\"} 2\n"));
+        assert!(text.contains("hearth_request_duration_seconds_bucket{device=\"bed\\\"room\",le=\"0.1\"} 3\n"));
+        assert!(text.contains("hearth_request_duration_seconds_bucket{device=\"bed\\\"room\",le=\"+Inf\"} 4\n"));
+        assert!(text.contains("hearth_humidity_percent{device=\"bed\\\"room\"} 61\n"));
+        assert!(text.contains("hearth_fault_bits{device=\"bed\\\"room\"} 2\n"));
+        assert!(text.contains("hearth_status_age_seconds{device=\"bed\\\"room\"} 12\n"));
+    }
+}
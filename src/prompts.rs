@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rmcp::model::{GetPromptResult, JsonObject, Prompt, PromptArgument, PromptMessage, PromptMessageRole};
+
+use crate::meaco::HumidityRange;
+
+// -- Prompts --
+//
+// Canned workflows for clients that surface MCP prompts in their UI. Each
+// expands to a user message walking the model through the tools; nothing
+// here touches the device itself.
+
+const DRY_LAUNDRY: &str = "dry_laundry";
+const WEEKEND_AWAY: &str = "weekend_away";
+const DIAGNOSE_HUMIDITY: &str = "diagnose_humidity";
+
+#[derive(Debug)]
+pub enum PromptError {
+    Unknown(String),
+    InvalidArgument { name: &'static str, reason: String },
+}
+
+impl fmt::Display for PromptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(f, "No prompt named {name:?}"),
+            Self::InvalidArgument { name, reason } => write!(f, "Invalid argument {name}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PromptError {}
+
+fn argument(name: &str, description: &str) -> PromptArgument {
+    PromptArgument {
+        name: name.into(),
+        title: None,
+        description: Some(description.into()),
+        required: Some(false),
+    }
+}
+
+pub fn list() -> Vec<Prompt> {
+    vec![
+        Prompt::new(
+            DRY_LAUNDRY,
+            Some("Dry my laundry: drying mode, optionally switching off after a few hours"),
+            Some(vec![argument("hours", "Switch off after 1, 2 or 3 hours; leave out to run until stopped")]),
+        ),
+        Prompt::new(
+            WEEKEND_AWAY,
+            Some("Prepare for the weekend away: a safe unattended setpoint with the panel locked"),
+            Some(vec![argument(
+                "target",
+                "Humidity to hold while away, as a percentage or preset name (default: storage)",
+            )]),
+        ),
+        Prompt::new(
+            DIAGNOSE_HUMIDITY,
+            Some("Diagnose why humidity isn't dropping"),
+            None,
+        ),
+    ]
+}
+
+fn string_arg<'a>(args: Option<&'a JsonObject>, name: &str) -> Option<&'a str> {
+    args.and_then(|args| args.get(name)).and_then(|v| v.as_str()).filter(|s| !s.trim().is_empty())
+}
+
+/// Expand the named prompt. Presets pick the default away setpoint.
+pub fn expand(
+    name: &str,
+    args: Option<&JsonObject>,
+    presets: &BTreeMap<String, u32>,
+) -> Result<GetPromptResult, PromptError> {
+    let (description, text) = match name {
+        DRY_LAUNDRY => ("Dry my laundry", dry_laundry(string_arg(args, "hours"))?),
+        WEEKEND_AWAY => ("Prepare for the weekend away", weekend_away(string_arg(args, "target"), presets)),
+        DIAGNOSE_HUMIDITY => ("Diagnose why humidity isn't dropping", diagnose_humidity()),
+        other => return Err(PromptError::Unknown(other.to_owned())),
+    };
+
+    Ok(GetPromptResult {
+        description: Some(description.into()),
+        messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+    })
+}
+
+fn dry_laundry(hours: Option<&str>) -> Result<String, PromptError> {
+    let countdown = match hours.map(str::trim) {
+        None => None,
+        Some(h @ ("1" | "2" | "3")) => Some(format!("{h}h")),
+        Some(other) => {
+            return Err(PromptError::InvalidArgument {
+                name: "hours",
+                reason: format!("expected 1, 2 or 3, got {other:?}"),
+            });
+        }
+    };
+
+    let mut text = String::from(
+        "I've hung up laundry to dry next to the dehumidifier. Please set it up for that:\n\
+         1. Call get_status. If the tank is full or a fault is active, stop and tell me what to fix first.\n\
+         2. If it's off, call power with on = true.\n\
+         3. Call set_mode with drying.\n",
+    );
+    match countdown {
+        Some(countdown) => text.push_str(&format!("4. Call set_countdown with {countdown} so it switches itself off.\n")),
+        None => text.push_str("4. Call set_countdown with cancel so it keeps running until I stop it.\n"),
+    }
+    text.push_str("Finish with get_status and tell me the current humidity and what you changed.");
+    Ok(text)
+}
+
+fn weekend_away(target: Option<&str>, presets: &BTreeMap<String, u32>) -> String {
+    let target = match target {
+        Some(target) => target.trim().to_owned(),
+        None if presets.contains_key("storage") => "storage".to_owned(),
+        None => "55".to_owned(),
+    };
+
+    format!(
+        "I'm going away for the weekend and the dehumidifier will run unattended. Please prepare it:\n\
+         1. Call get_status. The tank can't be emptied while I'm away: if it's full or nearly so, \
+         tell me to empty it before I leave, and if a fault is active, stop and report it.\n\
+         2. Make sure it's on (power with on = true) and call set_countdown with cancel so it doesn't switch itself off.\n\
+         3. Call set_mode with manual, then set_humidity with {target} so it holds a steady, economical level.\n\
+         4. Call set_child_lock with locked = true so nobody changes it from the panel.\n\
+         Finish with get_status and summarise the settings it's left in."
+    )
+}
+
+fn diagnose_humidity() -> String {
+    "The humidity in the room isn't dropping. Please work out why:\n\
+     1. Call get_status. Check power, the fault flags (a full tank or defrost stops dehumidifying), \
+     the mode, and whether the current humidity is already at or below the target, in which case the \
+     compressor rests by design.\n\
+     2. If the countdown is set, it may have switched itself off; check whether it's still running.\n\
+     3. Call get_device_info. Timeouts, reconnects or CRC errors mean hearth may be seeing stale \
+     readings rather than the device misbehaving.\n\
+     4. Call query_dps for the raw DP map and get_dp_observations for how readings have moved over time; \
+     a reading that never changes suggests a stuck sensor.\n\
+     Tell me the most likely cause, the evidence for it, and what to try next. Don't change any \
+     settings without asking me first."
+        .to_owned()
+}
+
+// -- Argument completion --
+//
+// MCP completion requests name a prompt or resource and an argument. The
+// values are answered by argument name, so the same names complete the
+// same way wherever they appear: a prompt's `target` like a tool's
+// `humidity`, `mode` and `countdown` from the enums, `device` and `scene`
+// from config.
+
+/// What the values come from, beyond the fixed enums.
+pub struct CompletionSource<'a> {
+    pub presets: &'a BTreeMap<String, u32>,
+    pub scenes: Vec<&'a str>,
+    pub devices: Vec<&'a str>,
+    pub humidity_range: HumidityRange,
+}
+
+/// Values for `argument` starting with `typed`, case-insensitively.
+pub fn complete(argument: &str, typed: &str, source: &CompletionSource<'_>) -> Vec<String> {
+    let owned = |values: &[&str]| values.iter().map(|v| (*v).to_owned()).collect::<Vec<_>>();
+    let candidates = match argument {
+        "mode" => owned(&["manual", "auto", "drying", "continuous"]),
+        "countdown" => owned(&["cancel", "1h", "2h", "3h"]),
+        "speed" | "fan_speed" => owned(&["low", "high"]),
+        "hours" => owned(&["1", "2", "3"]),
+        "device" => owned(&source.devices),
+        "scene" | "name" => owned(&source.scenes),
+        "humidity" | "target" => {
+            let range = source.humidity_range;
+            let mut values: Vec<String> = source.presets.keys().cloned().collect();
+            values.extend((range.min..=range.max).step_by(range.step.max(1) as usize).map(|v| v.to_string()));
+            values
+        }
+        _ => Vec::new(),
+    };
+
+    let typed = typed.trim().to_ascii_lowercase();
+    candidates
+        .into_iter()
+        .filter(|value| value.to_ascii_lowercase().starts_with(&typed))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_listed_prompt_expands() {
+        let presets = BTreeMap::from([("storage".to_owned(), 55)]);
+        for prompt in list() {
+            let result = expand(&prompt.name, None, &presets).unwrap();
+            assert_eq!(result.messages.len(), 1, "{}", prompt.name);
+        }
+
+        let hours = serde_json::json!({ "hours": "4" });
+        let err = expand(DRY_LAUNDRY, hours.as_object(), &presets).unwrap_err();
+        assert!(matches!(err, PromptError::InvalidArgument { name: "hours", .. }));
+        assert!(matches!(expand("nope", None, &presets), Err(PromptError::Unknown(_))));
+    }
+
+    #[test]
+    fn arguments_complete_by_name() {
+        let presets = BTreeMap::from([("storage".to_owned(), 55), ("living".to_owned(), 50)]);
+        let source = CompletionSource {
+            presets: &presets,
+            scenes: vec!["laundry", "away"],
+            devices: vec!["dehumidifier", "bf12"],
+            humidity_range: crate::meaco::ARETE_TWO_HUMIDITY,
+        };
+
+        assert_eq!(complete("mode", "D", &source), ["drying"]);
+        assert_eq!(complete("countdown", "", &source).len(), 4);
+        assert_eq!(complete("scene", "la", &source), ["laundry"]);
+        assert_eq!(complete("device", "b", &source), ["bf12"]);
+        assert_eq!(complete("target", "s", &source), ["storage"]);
+        assert_eq!(complete("humidity", "4", &source), ["40", "45"]);
+        assert!(complete("unknown", "", &source).is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{self, Config, MeacoConfig};
+use crate::discovery::{self, DiscoveredDevices};
+use crate::plug::{self, Plug};
+use crate::server::HearthServer;
+use crate::tuya_connection::{self, TuyaConnection};
+
+// -- Config reload --
+//
+// hearth.toml is checked for changes every few seconds, and what can
+// change under a running MCP session does: presets and scenes, connection
+// timeouts and retries, the schedule clock, new plugs, and where the
+// dehumidifier and plugs are on the network. Anything else (another local
+// key, a different profile, the transport) is logged as needing a
+// restart. A file that no longer loads is reported and the running config
+// kept.
+
+/// How often the config file's modification time is checked.
+const CHECK_EVERY: Duration = Duration::from_secs(2);
+
+/// What a reload can change, shared with the tasks that use it.
+pub struct Live {
+    pub conn: Arc<TuyaConnection>,
+    pub server: HearthServer,
+    pub plugs: Arc<RwLock<BTreeMap<String, Plug>>>,
+    /// The scheduler's clock, `[schedule] utc_offset_minutes`.
+    pub utc_offset: Arc<AtomicI32>,
+    pub discovered: Arc<DiscoveredDevices>,
+}
+
+/// Top-level tables and keys that differ between two configs, including
+/// any only one of them has.
+pub fn changed_sections(old: &toml::Table, new: &toml::Table) -> BTreeSet<String> {
+    old.keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect()
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn needs_restart(setting: &str) {
+    tracing::warn!(setting, "Config changed; restart hearth to apply this");
+}
+
+/// Whether `now` is the device `was`, at most at another address.
+fn same_device(was: &MeacoConfig, now: &MeacoConfig) -> bool {
+    let moved = MeacoConfig {
+        device_ip: now.device_ip.clone(),
+        fallback_addresses: now.fallback_addresses.clone(),
+        ..was.clone()
+    };
+    moved == *now
+}
+
+/// Point a connection at the device's new addresses. If the one it's on
+/// is gone, the socket is closed and the next request connects afresh.
+async fn readdress(conn: &TuyaConnection, device: &MeacoConfig, name: &str) {
+    if !tuya_connection::set_addresses(conn, device) {
+        tuya_connection::close(conn).await;
+    }
+    tracing::info!(device = %name, addresses = ?device.candidate_addresses(), "Device addresses updated");
+}
+
+/// Watch `path` and apply each change to `config`, the config hearth
+/// started with.
+pub fn spawn_watcher(path: String, config: Config, live: Live) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut config = config;
+        let mut doc = match config::load_document(&path) {
+            Ok(doc) => doc,
+            Err(e) => {
+                tracing::warn!("Not watching the config for changes: {e}");
+                return;
+            }
+        };
+        let mut seen = modified(&path);
+        let mut interval = tokio::time::interval(CHECK_EVERY);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            let now = modified(&path);
+            if now == seen {
+                continue;
+            }
+            seen = now;
+
+            let loaded = config::load_document(&path).and_then(|new_doc| Ok((new_doc, config::load_config(&path)?)));
+            let (new_doc, new_config) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    tracing::warn!("Config change not applied, carrying on with the running config: {e}");
+                    continue;
+                }
+            };
+            let sections = changed_sections(&doc, &new_doc);
+            if sections.is_empty() {
+                continue;
+            }
+            tracing::info!(?sections, "Config changed, applying");
+            for warning in new_config.warnings.iter().filter(|w| !config.warnings.contains(w)) {
+                tracing::warn!("Config: {warning}");
+            }
+            apply(&live, &config, &new_config, &doc, &new_doc, &sections).await;
+            config = new_config;
+            doc = new_doc;
+        }
+    })
+}
+
+async fn apply(
+    live: &Live,
+    old: &Config,
+    new: &Config,
+    old_doc: &toml::Table,
+    new_doc: &toml::Table,
+    sections: &BTreeSet<String>,
+) {
+    for section in sections {
+        match section.as_str() {
+            "presets" | "scene" => live.server.reload_settings(new),
+            "connection" => {
+                tuya_connection::set_policy(&live.conn, &new.connection);
+                for plug in live.plugs.read().expect("plugs lock poisoned").values() {
+                    tuya_connection::set_policy(&plug.conn, &new.connection);
+                }
+                // The request queue's limiter is built once
+                let rate_limit = |doc: &toml::Table| doc.get("connection").and_then(|c| c.get("rate_limit")).cloned();
+                if rate_limit(old_doc) != rate_limit(new_doc) {
+                    needs_restart("connection.rate_limit");
+                }
+            }
+            "schedule" => {
+                live.utc_offset.store(new.schedule.utc_offset_minutes, Ordering::Relaxed);
+                if old.schedule.path != new.schedule.path {
+                    needs_restart("schedule.path");
+                }
+            }
+            "plug" => reload_plugs(live, old, new).await,
+            // Together, below: either may hold the driven device
+            "meaco" | "device" => {}
+            other => needs_restart(other),
+        }
+    }
+    if sections.contains("meaco") || sections.contains("device") {
+        reload_devices(live, old, new).await;
+    }
+}
+
+async fn reload_devices(live: &Live, old: &Config, new: &Config) {
+    let (was, now) = (old.meaco(), new.meaco());
+    if !same_device(was, now) {
+        needs_restart("the dehumidifier's id, key, name, seqno_on_reconnect or polling");
+    } else if was.candidate_addresses() != now.candidate_addresses() {
+        readdress(&live.conn, now, now.name.as_deref().unwrap_or("dehumidifier")).await;
+    }
+    if old.device != new.device {
+        needs_restart("the dehumidifier's profile or DP overrides");
+    }
+
+    live.server.reload_devices(new);
+    for device in new.undriven_devices() {
+        let id = &device.device.device_id;
+        if old.devices.iter().any(|d| d.device.device_id == *id) {
+            continue;
+        }
+        discovery::add_configured(&live.discovered, id);
+        tracing::warn!(
+            device = %device.device.name.as_deref().unwrap_or_default(),
+            model = %device.resolved.model,
+            "Configured, but hearth drives a single dehumidifier so far; not connecting"
+        );
+    }
+}
+
+async fn reload_plugs(live: &Live, old: &Config, new: &Config) {
+    if live.plugs.read().expect("plugs lock poisoned").is_empty() && !new.plug.is_empty() {
+        // With no plugs at startup, the plug tools were left out
+        needs_restart("plug, to offer get_plug_status and set_plug_power");
+    }
+
+    for (name, config) in &new.plug {
+        let running = live.plugs.read().expect("plugs lock poisoned").get(name).cloned();
+        match (old.plug.get(name), running) {
+            (Some(was), Some(plug)) => {
+                if !same_device(&was.device, &config.device) || was.profile != config.profile {
+                    needs_restart(&format!("plug.{name}"));
+                } else if was.device.candidate_addresses() != config.device.candidate_addresses() {
+                    readdress(&plug.conn, &config.device, name).await;
+                }
+            }
+            (None, None) => {
+                discovery::add_configured(&live.discovered, &config.device.device_id);
+                let plug = plug::start(name, config, &new.connection, &live.discovered);
+                live.plugs.write().expect("plugs lock poisoned").insert(name.clone(), plug);
+                tracing::info!(device = %name, "Plug added");
+            }
+            // Removed and added back: still connected as it was
+            _ => needs_restart(&format!("plug.{name}")),
+        }
+    }
+    for name in old.plug.keys().filter(|name| !new.plug.contains_key(*name)) {
+        needs_restart(&format!("plug.{name}, to disconnect it"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_sections_are_reported() {
+        let old: toml::Table = toml::from_str(
+            r#"
+            presets = { storage = 55 }
+            [meaco]
+            device_ip = "10.0.0.2"
+            [connection.query]
+            timeout_ms = 5000
+            [transport]
+            mode = "stdio"
+            "#,
+        )
+        .unwrap();
+        let new: toml::Table = toml::from_str(
+            r#"
+            presets = { storage = 55 }
+            [meaco]
+            device_ip = "10.0.0.9"
+            [connection.query]
+            timeout_ms = 8000
+            [plug.heater]
+            device_ip = "10.0.0.7"
+            "#,
+        )
+        .unwrap();
+
+        let sections: Vec<_> = changed_sections(&old, &new).into_iter().collect();
+        assert_eq!(sections, ["connection", "meaco", "plug", "transport"]);
+        assert!(changed_sections(&old, &old).is_empty());
+    }
+}
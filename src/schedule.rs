@@ -0,0 +1,380 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::audit::{self, AuditSinks};
+use crate::config::ScheduleConfig;
+use crate::device_profile::DeviceProfile;
+use crate::meaco::{self, Mode};
+use crate::tuya_connection::{self, Deadline, TuyaConnection};
+use crate::watchdog;
+
+// -- Schedules --
+//
+// "Weekdays at 07:00, set 50% and power on." Schedules live in a JSON
+// file next to the config so they survive restarts, and a background task
+// checks them a few times a minute. A schedule that comes due while
+// hearth is down is skipped, not run late.
+//
+// A schedule is an automation, so `[safety] child_lock_guard` stops it
+// as it stops agents: with the lock engaged, the run is skipped and
+// audited as blocked.
+
+/// How often the scheduler looks for due schedules. Well under a minute,
+/// so none is missed.
+const CHECK_EVERY: Duration = Duration::from_secs(15);
+
+/// How long a scheduled write may take.
+const RUN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The audited outcome of a run the child lock stopped.
+const BLOCKED: &str = "blocked by child lock";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+const WEEK: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// What a schedule changes, for listing it back.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleActions {
+    pub power: Option<bool>,
+    pub humidity: Option<u32>,
+    pub mode: Option<Mode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u32,
+    pub days: BTreeSet<Weekday>,
+    /// Minutes after local midnight.
+    pub minute: u32,
+    pub actions: ScheduleActions,
+    /// The DPs sent, validated when the schedule was added.
+    pub dps: serde_json::Value,
+    /// Unix minute of the last run, so a due schedule runs once.
+    #[serde(default)]
+    pub last_run: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Schedules {
+    next_id: u32,
+    pub entries: Vec<Schedule>,
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    Io(std::io::Error),
+    Parse(String),
+    InvalidDays(String),
+    InvalidTime(String),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Io(e) => write!(f, "Schedule file error: {e}"),
+            ScheduleError::Parse(msg) => write!(f, "Failed to parse schedule file: {msg}"),
+            ScheduleError::InvalidDays(days) => write!(
+                f,
+                "Invalid days \"{days}\": expected daily, weekdays, weekends or a list like mon,wed,fri"
+            ),
+            ScheduleError::InvalidTime(time) => write!(f, "Invalid time \"{time}\": expected HH:MM"),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// "daily", "weekdays", "weekends", or days like "mon,wed,fri".
+pub fn parse_days(text: &str) -> Result<BTreeSet<Weekday>, ScheduleError> {
+    let invalid = || ScheduleError::InvalidDays(text.to_owned());
+    match text.trim().to_ascii_lowercase().as_str() {
+        "daily" | "every day" | "everyday" => return Ok(WEEK.into_iter().collect()),
+        "weekdays" => return Ok(WEEK[..5].iter().copied().collect()),
+        "weekends" => return Ok(WEEK[5..].iter().copied().collect()),
+        _ => {}
+    }
+
+    let mut days = BTreeSet::new();
+    for day in text.split([',', ' ']).map(str::trim).filter(|d| !d.is_empty()) {
+        let day = day.to_ascii_lowercase();
+        let found = WEEK
+            .iter()
+            .find(|w| day.starts_with(&format!("{w:?}").to_ascii_lowercase()))
+            .ok_or_else(invalid)?;
+        days.insert(*found);
+    }
+    if days.is_empty() {
+        return Err(invalid());
+    }
+    Ok(days)
+}
+
+/// "07:00" to minutes after midnight.
+pub fn parse_time(text: &str) -> Result<u32, ScheduleError> {
+    let invalid = || ScheduleError::InvalidTime(text.to_owned());
+    let (hours, minutes) = text.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+fn format_days(days: &BTreeSet<Weekday>) -> String {
+    match days.len() {
+        7 => "daily".into(),
+        5 if days.iter().all(|d| *d <= Weekday::Fri) => "weekdays".into(),
+        2 if days.iter().all(|d| *d >= Weekday::Sat) => "weekends".into(),
+        _ => days
+            .iter()
+            .map(|d| format!("{d:?}").to_ascii_lowercase())
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+pub fn describe(schedule: &Schedule) -> String {
+    let actions = &schedule.actions;
+    let mut changes = Vec::new();
+    if let Some(humidity) = actions.humidity {
+        changes.push(format!("set humidity {humidity}%"));
+    }
+    if let Some(mode) = &actions.mode {
+        changes.push(format!("mode {mode}"));
+    }
+    if let Some(on) = actions.power {
+        changes.push(format!("power {}", if on { "on" } else { "off" }));
+    }
+    format!(
+        "#{} {} {:02}:{:02}: {}",
+        schedule.id,
+        format_days(&schedule.days),
+        schedule.minute / 60,
+        schedule.minute % 60,
+        changes.join(" and ")
+    )
+}
+
+pub fn add(
+    schedules: &mut Schedules,
+    days: BTreeSet<Weekday>,
+    minute: u32,
+    actions: ScheduleActions,
+    dps: serde_json::Value,
+) -> &Schedule {
+    schedules.next_id += 1;
+    schedules.entries.push(Schedule {
+        id: schedules.next_id,
+        days,
+        minute,
+        actions,
+        dps,
+        last_run: None,
+    });
+    schedules.entries.last().expect("pushed above")
+}
+
+/// Whether a schedule with that id existed.
+pub fn delete(schedules: &mut Schedules, id: u32) -> bool {
+    let before = schedules.entries.len();
+    schedules.entries.retain(|s| s.id != id);
+    schedules.entries.len() != before
+}
+
+/// Schedules due at `unix` (UTC seconds), marked as run. `offset` is the
+/// local time's offset from UTC in minutes.
+pub fn take_due(schedules: &mut Schedules, unix: u64, offset: i32) -> Vec<Schedule> {
+    let local = unix as i64 + i64::from(offset) * 60;
+    let days = local.div_euclid(86_400);
+    // 1970-01-01 was a Thursday
+    let weekday = WEEK[(days + 3).rem_euclid(7) as usize];
+    let minute = (local.rem_euclid(86_400) / 60) as u32;
+    let unix_minute = unix / 60;
+
+    let mut due = Vec::new();
+    for schedule in &mut schedules.entries {
+        if schedule.minute == minute
+            && schedule.days.contains(&weekday)
+            && schedule.last_run != Some(unix_minute)
+        {
+            schedule.last_run = Some(unix_minute);
+            due.push(schedule.clone());
+        }
+    }
+    due
+}
+
+/// Load saved schedules. A missing file means none.
+pub fn load(path: &str) -> Result<Schedules, ScheduleError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| ScheduleError::Parse(e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Schedules::default()),
+        Err(e) => Err(ScheduleError::Io(e)),
+    }
+}
+
+pub fn save(path: &str, schedules: &Schedules) -> Result<(), ScheduleError> {
+    let json = serde_json::to_string_pretty(schedules).map_err(|e| ScheduleError::Parse(e.to_string()))?;
+    std::fs::write(path, json).map_err(ScheduleError::Io)
+}
+
+/// Whether a schedule must be skipped, given the DPS the device reports:
+/// with the guard on (`guard` holds the profile to read them with), while
+/// the child lock is engaged.
+pub fn blocked_by_child_lock(dps: &serde_json::Value, guard: Option<&DeviceProfile>) -> bool {
+    guard.is_some_and(|profile| meaco::child_lock_engaged(dps, profile))
+}
+
+/// Send `schedule`'s DPS, unless the guard finds the child lock engaged.
+/// The error is the outcome to audit.
+async fn run(
+    conn: &TuyaConnection,
+    schedule: &Schedule,
+    guard: Option<&DeviceProfile>,
+    deadline: &Deadline,
+) -> Result<(), String> {
+    if guard.is_some() {
+        let response = tuya_connection::query_dps(conn, deadline)
+            .await
+            .map_err(|e| format!("Failed to check child lock: {e}"))?;
+        let dps = response.get("dps").unwrap_or(&response);
+        if blocked_by_child_lock(dps, guard) {
+            return Err(BLOCKED.to_owned());
+        }
+    }
+    tuya_connection::set_dps(conn, schedule.dps.clone(), deadline)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Run schedules as they come due. `utc_offset` is shared so a reloaded
+/// config can move the clock. `child_lock_guard` is the profile to check
+/// the lock with, when `[safety] child_lock_guard` is on.
+pub fn spawn_scheduler(
+    conn: Arc<TuyaConnection>,
+    schedules: Arc<Mutex<Schedules>>,
+    config: &ScheduleConfig,
+    utc_offset: Arc<AtomicI32>,
+    child_lock_guard: Option<DeviceProfile>,
+    audit_sinks: AuditSinks,
+) -> tokio::task::JoinHandle<()> {
+    let path = config.path.clone();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_EVERY);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let now = watchdog::unix_secs(SystemTime::now());
+            let due = {
+                let mut schedules = schedules.lock().expect("schedules lock poisoned");
+                let due = take_due(&mut schedules, now, utc_offset.load(Ordering::Relaxed));
+                if !due.is_empty()
+                    && let Err(e) = save(&path, &schedules)
+                {
+                    tracing::warn!("{e}");
+                }
+                due
+            };
+
+            for schedule in due {
+                let deadline = Deadline {
+                    at: Some(Instant::now() + RUN_TIMEOUT),
+                    ..Deadline::default()
+                };
+                let (result, writes) = audit::scope(run(&conn, &schedule, child_lock_guard.as_ref(), &deadline)).await;
+                let outcome = match result {
+                    Ok(()) => {
+                        tracing::info!(schedule = %describe(&schedule), "Ran schedule");
+                        "ok".to_owned()
+                    }
+                    Err(outcome) if outcome == BLOCKED => {
+                        tracing::warn!(schedule = %describe(&schedule), "Schedule skipped, child lock engaged");
+                        outcome
+                    }
+                    Err(outcome) => {
+                        tracing::warn!(schedule = %describe(&schedule), "Schedule failed: {outcome}");
+                        outcome
+                    }
+                };
+                if audit::is_on(&audit_sinks) {
+                    let source = format!("schedule #{}", schedule.id);
+                    let actions = serde_json::to_value(&schedule.actions).unwrap_or_default();
+                    audit::log(&audit_sinks, source, None, actions, writes, outcome);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekday_schedule_runs_once_when_due() {
+        let mut schedules = Schedules::default();
+        let days = parse_days("weekdays").unwrap();
+        let actions = ScheduleActions { power: Some(true), humidity: Some(50), mode: None };
+        let id = add(&mut schedules, days, parse_time("07:00").unwrap(), actions, serde_json::json!({"1": true, "2": 50})).id;
+        assert_eq!(describe(&schedules.entries[0]), "#1 weekdays 07:00: set humidity 50% and power on");
+
+        // 2024-03-01 was a Friday, 2024-03-02 a Saturday
+        let friday_seven = 1_709_251_200 + 7 * 3600;
+        assert_eq!(take_due(&mut schedules, friday_seven, 0).len(), 1);
+        assert!(take_due(&mut schedules, friday_seven + 20, 0).is_empty());
+        assert!(take_due(&mut schedules, friday_seven + 86_400, 0).is_empty());
+        // 07:00 at UTC+1 is 06:00 UTC
+        assert_eq!(take_due(&mut schedules, friday_seven - 3600 - 7 * 86_400, 60).len(), 1);
+
+        assert_eq!(parse_days("Mon, wednesday").unwrap().len(), 2);
+        assert!(parse_days("someday").is_err());
+        assert!(parse_time("25:00").is_err());
+        assert!(delete(&mut schedules, id));
+        assert!(!delete(&mut schedules, id));
+    }
+
+    #[test]
+    fn child_lock_blocks_schedules_when_guarded() {
+        let profile = DeviceProfile::default();
+        let index = &profile.dp(meaco::CHILD_LOCK).expect("default profile has a child lock").index;
+        let locked = serde_json::json!({ index.as_str(): true, "1": false });
+        let unlocked = serde_json::json!({ index.as_str(): false, "1": false });
+
+        assert!(blocked_by_child_lock(&locked, Some(&profile)));
+        assert!(!blocked_by_child_lock(&unlocked, Some(&profile)));
+        // Without [safety] child_lock_guard, schedules run regardless
+        assert!(!blocked_by_child_lock(&locked, None));
+        // Absent means unlocked
+        assert!(!blocked_by_child_lock(&serde_json::json!({ "1": true }), Some(&profile)));
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+// -- Keyring secrets --
+//
+// A local key can stay out of hearth.toml: `local_key =
+// "keyring:hearth/bedroom"` reads it from the OS credential store (macOS
+// Keychain, Secret Service on Linux, Windows Credential Manager) under
+// service "hearth" and user "bedroom" when the config loads. `hearth
+// store-key keyring:hearth/bedroom` puts it there.
+//
+// Hosts without a credential store can keep keys in a secrets file
+// instead, kept out of version control along with hearth.toml's history:
+// `[secrets] path` names it, `local_key = "secrets:bedroom"` reads its
+// `bedroom` entry. hearth refuses the file if other users can read it.
+
+pub const KEYRING_PREFIX: &str = "keyring:";
+pub const SECRETS_PREFIX: &str = "secrets:";
+
+/// A secrets file's entries, by name.
+#[derive(Debug, Default)]
+pub struct SecretsFile {
+    pub path: String,
+    entries: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// A `keyring:` value that doesn't name a service and user.
+    InvalidReference(String),
+    Keyring { reference: String, reason: String },
+    /// A `secrets:` value with no `[secrets] path` to look it up in.
+    NoSecretsFile(String),
+    SecretsFile { path: String, reason: String },
+    /// A secrets file that users besides its owner may read.
+    Exposed { path: String, mode: u32 },
+    NotInFile { name: String, path: String },
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::InvalidReference(value) => {
+                write!(f, "\"{value}\" should be keyring:<service>/<user>, e.g. keyring:hearth/bedroom")
+            }
+            SecretError::Keyring { reference, reason } => write!(f, "Keyring entry {reference}: {reason}"),
+            SecretError::NoSecretsFile(value) => write!(f, "\"{value}\" needs a secrets file, and none is set"),
+            SecretError::SecretsFile { path, reason } => write!(f, "Can't read secrets file {path}: {reason}"),
+            SecretError::Exposed { path, mode } => {
+                write!(f, "Secrets file {path} is readable by other users (mode {mode:03o})")
+            }
+            SecretError::NotInFile { name, path } => write!(f, "No \"{name}\" entry in secrets file {path}"),
+        }
+    }
+}
+
+impl SecretError {
+    /// What to do about it, for the config problem it becomes.
+    pub fn hint(&self) -> String {
+        match self {
+            SecretError::InvalidReference(_) | SecretError::Keyring { .. } => {
+                "Store it with `hearth store-key`".to_owned()
+            }
+            SecretError::NoSecretsFile(_) => "Set [secrets] path".to_owned(),
+            SecretError::SecretsFile { .. } => "It's a TOML file of name = \"key\" lines".to_owned(),
+            SecretError::Exposed { path, .. } => format!("Run `chmod 600 {path}`"),
+            SecretError::NotInFile { name, .. } => format!("Add {name} = \"<16-character key>\" to it"),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// The service and user a `keyring:` value names, or None for a value
+/// that's the secret itself.
+pub fn parse_reference(value: &str) -> Option<Result<(&str, &str), SecretError>> {
+    let rest = value.strip_prefix(KEYRING_PREFIX)?;
+    Some(match rest.split_once('/') {
+        Some((service, user)) if !service.is_empty() && !user.is_empty() => Ok((service, user)),
+        _ => Err(SecretError::InvalidReference(value.to_owned())),
+    })
+}
+
+/// Whether `value` names a secret kept elsewhere rather than being one.
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(KEYRING_PREFIX) || value.starts_with(SECRETS_PREFIX)
+}
+
+/// Read the secrets file at `path`, which only its owner may read.
+pub fn load_file(path: &str) -> Result<SecretsFile, SecretError> {
+    let unreadable = |reason: String| SecretError::SecretsFile {
+        path: path.to_owned(),
+        reason,
+    };
+    let meta = std::fs::metadata(path).map_err(|e| unreadable(e.to_string()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = meta.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(SecretError::Exposed {
+                path: path.to_owned(),
+                mode,
+            });
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = meta;
+
+    let text = std::fs::read_to_string(path).map_err(|e| unreadable(e.to_string()))?;
+    let entries = toml::from_str(&text).map_err(|e: toml::de::Error| unreadable(e.message().to_owned()))?;
+    Ok(SecretsFile {
+        path: path.to_owned(),
+        entries,
+    })
+}
+
+fn entry(reference: &str) -> Result<keyring::Entry, SecretError> {
+    let (service, user) = parse_reference(reference)
+        .unwrap_or_else(|| Err(SecretError::InvalidReference(reference.to_owned())))?;
+    keyring::Entry::new(service, user).map_err(|e| keyring_error(reference, e))
+}
+
+fn keyring_error(reference: &str, e: keyring::Error) -> SecretError {
+    SecretError::Keyring {
+        reference: reference.to_owned(),
+        reason: e.to_string(),
+    }
+}
+
+/// `value` itself, or the secret stored under it if it's a `keyring:`
+/// or `secrets:` reference.
+pub fn resolve(value: &str, file: Option<&SecretsFile>) -> Result<String, SecretError> {
+    if let Some(name) = value.strip_prefix(SECRETS_PREFIX) {
+        let file = file.ok_or_else(|| SecretError::NoSecretsFile(value.to_owned()))?;
+        return file.entries.get(name).cloned().ok_or_else(|| SecretError::NotInFile {
+            name: name.to_owned(),
+            path: file.path.clone(),
+        });
+    }
+    if parse_reference(value).is_none() {
+        return Ok(value.to_owned());
+    }
+    entry(value)?.get_password().map_err(|e| keyring_error(value, e))
+}
+
+/// Store `secret` under `reference`, replacing any secret already there.
+pub fn store(reference: &str, secret: &str) -> Result<(), SecretError> {
+    entry(reference)?.set_password(secret).map_err(|e| keyring_error(reference, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_name_a_service_and_user() {
+        assert!(matches!(parse_reference("keyring:hearth/bedroom"), Some(Ok(("hearth", "bedroom")))));
+        assert!(matches!(parse_reference("keyring:bedroom"), Some(Err(_))));
+        assert!(matches!(parse_reference("keyring:/bedroom"), Some(Err(_))));
+        assert!(parse_reference("0123456789abcdef").is_none());
+
+        // A plain key never touches the keyring
+        assert_eq!(resolve("0123456789abcdef", None).unwrap(), "0123456789abcdef");
+        assert!(matches!(resolve("keyring:nope", None), Err(SecretError::InvalidReference(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secrets_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("hearth-secrets-{}.toml", std::process::id()));
+        std::fs::write(&path, "bedroom = \"0123456789abcdef\"\n").unwrap();
+        let chmod = |mode| std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        let path_str = path.to_str().unwrap();
+
+        chmod(0o600);
+        let file = load_file(path_str).unwrap();
+        assert_eq!(resolve("secrets:bedroom", Some(&file)).unwrap(), "0123456789abcdef");
+        assert!(matches!(resolve("secrets:cellar", Some(&file)), Err(SecretError::NotInFile { .. })));
+        assert!(matches!(resolve("secrets:bedroom", None), Err(SecretError::NoSecretsFile(_))));
+
+        chmod(0o644);
+        assert!(matches!(load_file(path_str), Err(SecretError::Exposed { mode: 0o644, .. })));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,45 +1,469 @@
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
 
 use rmcp::{
-    ErrorData as McpError, ServerHandler,
-    handler::server::{router::tool::ToolRouter, wrapper::Parameters},
-    model::{CallToolResult, Content, ServerCapabilities, ServerInfo},
-    schemars, tool, tool_handler, tool_router,
+    ErrorData as McpError, RoleServer, ServerHandler,
+    handler::server::{router::tool::ToolRouter, tool::ToolCallContext, wrapper::Parameters},
+    model::{
+        AnnotateAble, CallToolRequestParams, CallToolResult, CompleteRequestParams, CompleteResult,
+        CompletionInfo, Content, GetPromptRequestParams,
+        GetPromptResult, ListPromptsResult, ListResourcesResult, ListToolsResult, PaginatedRequestParams, RawResource, ReadResourceRequestParams,
+        ReadResourceResult, ResourceContents, ServerCapabilities, ServerInfo, SetLevelRequestParams,
+        SubscribeRequestParams, Tool, UnsubscribeRequestParams,
+    },
+    Peer, schemars, service::{ElicitationError, NotificationContext, RequestContext}, tool, tool_router,
 };
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use crate::meaco::{self, Countdown, Mode};
-use crate::tuya_connection::{self, TuyaConnection};
+use crate::audit::{self, AuditSinks};
+use crate::config::{Config, ConnectionConfig, MeacoConfig, SceneConfig};
+use crate::device_profile::{Category, DeviceProfile, DpsWrite};
+use crate::discovery::{self, DiscoveredDevices};
+use crate::events::{self, DeviceEvent};
+use crate::filter::{self, FilterTracker};
+use crate::history::{self, Reading, RecentReadings};
+use crate::logging::{self, LogControl};
+use crate::meaco::{self, Countdown, DehumidifierStatus, FanSpeed, HumidityRange, HumidityTarget, Mode, Settings};
+use crate::notify::{self, ClientSubscriptions};
+use crate::observe::{self, Observations};
+use crate::plug::{self, Plug};
+use crate::probe;
+use crate::prompts;
+use crate::schedule::{self, ScheduleActions, Schedules};
+use crate::shutdown::{self, Shutdown};
+use crate::status::{self, StatusStore};
+use crate::store::{self, Store};
+use crate::telemetry;
+use crate::tuya_connection::{self, ConnectionError, ConnectionState, Deadline, TuyaConnection};
+use crate::tuya_protocol;
+use crate::watchdog::{self, Availability};
+
+/// Control tools and the profile DP each writes; a model whose profile
+/// doesn't declare the DP writable doesn't get the tool.
+const DP_TOOLS: &[(&str, &str)] = &[
+    ("power", meaco::SWITCH),
+    ("set_humidity", meaco::TARGET_HUMIDITY),
+    ("set_mode", meaco::MODE),
+    ("set_child_lock", meaco::CHILD_LOCK),
+    ("set_countdown", meaco::COUNTDOWN),
+    ("set_timer_hours", meaco::COUNTDOWN_LEFT),
+    ("set_fan_speed", meaco::FAN_SPEED),
+    ("set_ionizer", meaco::IONIZER),
+    ("set_sleep_mode", meaco::SLEEP),
+    ("probe_humidity_range", meaco::TARGET_HUMIDITY),
+];
+
+/// Tools that never talk to the dehumidifier, so needn't wait their turn.
+const UNORDERED_TOOLS: &[&str] = &[
+    "get_plug_status",
+    "set_plug_power",
+    "get_device_info",
+    "list_devices",
+    "ping",
+    "discover_devices",
+    "list_discovered_devices",
+    "get_dp_observations",
+    "get_history",
+    "export_history",
+    "get_events",
+    "set_log_level",
+];
+
+/// How long `discover_devices` listens when nothing has been heard yet
+/// and the caller didn't say. Devices announce about every 5 seconds.
+const DEFAULT_SCAN_SECS: u64 = 6;
+
+/// How long the user gets to answer a confirmation request.
+const CONFIRM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// `_meta` field a client can set on a tool call to say how long it will
+/// wait for the result, in milliseconds.
+const TIMEOUT_META_KEY: &str = "timeoutMs";
+
+const HISTORY_OFF: &str =
+    "History is off. Set path under [store], enabled = true under [history], or under [polling] to keep recent readings in memory, in hearth.toml and restart";
+
+const STORE_OFF: &str = "The event store is off. Set path under [store] in hearth.toml and restart";
+
+/// "bf12…9a3c": enough to tell devices apart, not enough to reuse.
+fn redact(id: &str) -> String {
+    let chars: Vec<char> = id.chars().collect();
+    if chars.len() <= 8 {
+        return "…".into();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{head}…{tail}")
+}
+
+/// Names in `allowed` that aren't among `router`'s tools.
+fn unknown_tools<'a>(router: &ToolRouter<HearthServer>, allowed: &'a [String]) -> Vec<&'a str> {
+    allowed
+        .iter()
+        .filter(|name| !router.has_route(name))
+        .map(String::as_str)
+        .collect()
+}
+
+/// When `call_tool` received the request, so time spent queued counts
+/// against the client's timeout.
+#[derive(Clone, Copy)]
+struct Received(tokio::time::Instant);
+
+/// The device turn an ordered tool call holds, in its request extensions,
+/// so a call waiting on the user can hand it back meanwhile.
+#[derive(Clone)]
+struct DeviceTurn(Arc<Mutex<Option<tokio::sync::OwnedMutexGuard<()>>>>);
+
+/// Resolves when `at` passes, or never without one.
+async fn expiry(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// When the client's own timeout, if it sent one, runs out.
+fn client_timeout(ctx: &RequestContext<RoleServer>) -> Option<tokio::time::Instant> {
+    let ms = ctx.meta.0.get(TIMEOUT_META_KEY).and_then(|v| v.as_u64())?;
+    let received = ctx
+        .extensions
+        .get::<Received>()
+        .map_or_else(tokio::time::Instant::now, |r| r.0);
+    Some(received + std::time::Duration::from_millis(ms))
+}
+
+/// The caller's deadline: any timeout it sent, and cancellation when it
+/// gives up. A client that gives up shouldn't leave us holding the device.
+///
+/// The MCP service also cancels every request when it tears down. That
+/// isn't the client changing its mind, so during shutdown it's ignored and
+/// the call gets the grace period to finish its device writes.
+fn request_deadline(ctx: &RequestContext<RoleServer>, shutdown: &Arc<Shutdown>) -> Deadline {
+    let at = client_timeout(ctx);
+
+    // Lives until the request is answered, which cancels `ctx.ct`
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        let request = ctx.ct.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            request.cancelled().await;
+            if !shutdown::draining(&shutdown) {
+                cancel.cancel();
+            }
+        }
+    });
+
+    Deadline { at, cancel }
+}
 
 // -- Tool parameter structs --
 
+// What every device write takes, flattened into its params. A plain
+// comment: a doc comment would become each tool's schema description.
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct CommandOptions {
+    #[serde(default)]
+    #[schemars(description = "Required when the child lock guard is on and the child lock is engaged")]
+    pub override_child_lock: bool,
+    #[serde(default)]
+    #[schemars(description = "Device name or id, as listed by list_devices. Defaults to the configured device")]
+    pub device: Option<String>,
+}
+
+// Flattened into the writes that can interrupt something, which may ask
+// the user first.
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct Confirmed {
+    #[serde(default)]
+    #[schemars(description = "Set only once the user has confirmed this themselves, when hearth asks for confirmation and the client can't put the question to them")]
+    pub confirmed: bool,
+}
+
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct PowerParams {
     #[schemars(description = "Turn dehumidifier on (true) or off (false)")]
     pub on: bool,
+    #[serde(flatten)]
+    pub confirmed: Confirmed,
+    #[serde(flatten)]
+    pub command: CommandOptions,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SetHumidityParams {
-    #[schemars(description = "Target humidity percentage (35-70, in steps of 5)")]
-    pub humidity: u32,
+    #[schemars(description = "Target humidity percentage, or a preset name such as \"storage\", \"living\" or \"drying\"")]
+    pub humidity: HumidityTarget,
+    #[serde(flatten)]
+    pub command: CommandOptions,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SetModeParams {
     #[schemars(description = "Operating mode: manual, auto, drying, or continuous")]
     pub mode: Mode,
+    #[serde(flatten)]
+    pub command: CommandOptions,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SetChildLockParams {
     #[schemars(description = "Enable (true) or disable (false) child lock")]
     pub locked: bool,
+    #[serde(flatten)]
+    pub command: CommandOptions,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SetCountdownParams {
     #[schemars(description = "Countdown timer: cancel, 1h, 2h, or 3h")]
     pub countdown: Countdown,
+    #[serde(flatten)]
+    pub confirmed: Confirmed,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetTimerHoursParams {
+    #[schemars(description = "Hours until the dehumidifier switches itself off; 0 cancels the countdown")]
+    pub hours: u32,
+    #[serde(flatten)]
+    pub confirmed: Confirmed,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ResetFilterReminderParams {
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetFanSpeedParams {
+    #[schemars(description = "Fan speed: low or high")]
+    pub speed: FanSpeed,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetIonizerParams {
+    #[schemars(description = "Turn the ioniser on (true) or off (false)")]
+    pub on: bool,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetSleepModeParams {
+    #[schemars(description = "Turn sleep mode (display off, quieter fan) on (true) or off (false)")]
+    pub on: bool,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct BatchSetParams {
+    #[serde(flatten)]
+    pub settings: Settings,
+    #[serde(flatten)]
+    pub confirmed: Confirmed,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct RunSceneParams {
+    #[schemars(description = "Scene name, as listed by list_scenes")]
+    pub name: String,
+    #[serde(flatten)]
+    pub confirmed: Confirmed,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetDpsParams {
+    #[schemars(description = "DPS index to value, sent as-is, e.g. {\"101\": \"cancel\"}. Indexes are numeric strings")]
+    pub dps: serde_json::Map<String, serde_json::Value>,
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct PlugStatusParams {
+    #[serde(default)]
+    #[schemars(description = "Plug name or id, as listed by list_devices. May be left out when only one plug is configured")]
+    pub device: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetPlugPowerParams {
+    #[schemars(description = "Turn the plug on (true) or off (false)")]
+    pub on: bool,
+    #[serde(default)]
+    #[schemars(description = "Plug name or id, as listed by list_devices. May be left out when only one plug is configured")]
+    pub device: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProbeParams {
+    #[serde(flatten)]
+    pub command: CommandOptions,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct DiscoverDevicesParams {
+    #[schemars(description = "Keep listening this many seconds before answering. Devices announce every few seconds; omit to report what has been heard since startup, or to scan briefly if nothing has been")]
+    pub wait_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct PromoteDeviceParams {
+    #[schemars(description = "Device id (gwId) from list_discovered_devices")]
+    pub device_id: String,
+    #[schemars(description = "The device's 16-character local key, e.g. from tinytuya or the Tuya IoT platform")]
+    pub local_key: String,
+    #[serde(default)]
+    #[schemars(description = "What to call the device, e.g. \"cellar\". Defaults to one made from its id")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GetDeviceInfoParams {
+    #[serde(default)]
+    #[schemars(description = "Show only the ends of the device id, e.g. to paste the output somewhere public")]
+    pub redact_id: bool,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GetHistoryParams {
+    #[schemars(description = "How far back to start, in hours (default 12)")]
+    pub since_hours: Option<u64>,
+    #[schemars(description = "Stop this many hours ago (default 0, now)")]
+    pub until_hours_ago: Option<u64>,
+    #[schemars(description = "Summarise readings over intervals of this many minutes (default 30)")]
+    pub resolution_minutes: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ExportHistoryParams {
+    #[schemars(description = "Start, as YYYY-MM-DD or YYYY-MM-DD HH:MM in UTC (default: 24 hours ago)")]
+    pub from: Option<String>,
+    #[schemars(description = "End, as YYYY-MM-DD (that whole day included) or YYYY-MM-DD HH:MM in UTC (default: now)")]
+    pub to: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GetEventsParams {
+    #[schemars(description = "How far back to start, in hours (default 24)")]
+    pub since_hours: Option<u64>,
+    #[schemars(description = "Stop this many hours ago (default 0, now)")]
+    pub until_hours_ago: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct AddScheduleParams {
+    #[schemars(description = "When: daily, weekdays, weekends, or days like \"mon,wed,fri\"")]
+    pub days: String,
+    #[schemars(description = "Local time as HH:MM, on the clock set by [schedule] utc_offset_minutes")]
+    pub time: String,
+    #[schemars(description = "Turn the dehumidifier on (true) or off (false)")]
+    pub power: Option<bool>,
+    #[schemars(description = "Target humidity percentage or preset name, as for set_humidity")]
+    pub humidity: Option<HumidityTarget>,
+    #[schemars(description = "Operating mode: manual, auto, drying, or continuous")]
+    pub mode: Option<Mode>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct DeleteScheduleParams {
+    #[schemars(description = "Schedule id, as shown by list_schedules")]
+    pub id: u32,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetLogLevelParams {
+    #[schemars(description = "Tracing filter directives, e.g. \"hearth=debug,hearth::tuya_connection=trace\". Omit to restore the startup filter")]
+    pub filter: Option<String>,
+    #[schemars(description = "Revert to the startup filter after this many seconds. Omit to keep the filter until changed")]
+    pub duration_secs: Option<u64>,
+}
+
+/// What a confirmation request asks the user for.
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+struct Confirmation {
+    #[schemars(description = "Go ahead")]
+    confirm: bool,
+}
+
+rmcp::elicit_safe!(Confirmation);
+
+// -- Tool output structs --
+
+/// `get_status` structured content, alongside the text summary.
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct StatusOutput {
+    /// The device's configured name, or its id without one.
+    pub device: String,
+    /// Absent only if the device's DPS couldn't be parsed; see `raw_dps`.
+    pub status: Option<DehumidifierStatus>,
+    /// The device's DPS as reported, when they couldn't be parsed.
+    pub raw_dps: Option<serde_json::Value>,
+    /// Fields that differ from the status last returned to any client,
+    /// with old and new values. Empty on the first read.
+    pub what_changed: Vec<meaco::StatusChange>,
+    /// Set when the device is unreachable and this is the last status read:
+    /// how many seconds old it is.
+    pub age_secs: Option<u64>,
+    /// Connection state, e.g. "ready" or "closed".
+    pub connection: String,
+}
+
+/// `get_faults` structured content.
+#[derive(Debug, serde::Serialize, schemars::JsonSchema)]
+pub struct FaultsOutput {
+    /// The fault DP as reported.
+    pub bitmap: u32,
+    pub faults: Vec<meaco::Fault>,
+    /// Set when the device is unreachable and this is from the last status
+    /// read: how many seconds old it is.
+    pub age_secs: Option<u64>,
+}
+
+/// A tool result with both a text summary and structured content.
+fn structured(text: String, output: &impl serde::Serialize) -> Result<CallToolResult, McpError> {
+    let value = serde_json::to_value(output).map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+    let mut result = CallToolResult::success(vec![Content::text(text)]);
+    result.structured_content = Some(value);
+    Ok(result)
+}
+
+/// Add the percentages `range` allows to `set_humidity`'s description, and
+/// those and the configured presets to its humidity argument's, so
+/// clients are told exactly what will be accepted.
+fn describe_humidity_range(tool: &mut Tool, range: &HumidityRange, presets: &BTreeMap<String, u32>) {
+    let description = tool.description.as_deref().unwrap_or_default();
+    tool.description =
+        Some(format!("{description}. Percentages: {range}, unless probe_humidity_range found otherwise").into());
+
+    let mut schema = (*tool.input_schema).clone();
+    if let Some(humidity) = schema
+        .get_mut("properties")
+        .and_then(|p| p.get_mut("humidity"))
+        .and_then(|h| h.as_object_mut())
+    {
+        let names: Vec<String> = presets.keys().map(|name| format!("\"{name}\"")).collect();
+        let description = format!("Target humidity percentage ({range}), or a preset name: {}", names.join(", "));
+        humidity.insert("description".to_owned(), description.into());
+    }
+    tool.input_schema = Arc::new(schema);
 }
 
 // -- MCP Server --
@@ -47,130 +471,2068 @@ pub struct SetCountdownParams {
 #[derive(Debug, Clone)]
 pub struct HearthServer {
     conn: Arc<TuyaConnection>,
+    humidity_range: Arc<RwLock<HumidityRange>>,
+    /// Presets and scenes are replaced when the config is reloaded.
+    presets: Arc<RwLock<BTreeMap<String, u32>>>,
+    scenes: Arc<RwLock<BTreeMap<String, SceneConfig>>>,
+    log: Arc<LogControl>,
+    discovered: Arc<DiscoveredDevices>,
+    /// Present when observation mode is on.
+    observations: Option<Arc<Mutex<Observations>>>,
+    shutdown: Arc<Shutdown>,
+    /// Device tool calls take turns, so a status read submitted after a
+    /// write sees its result. tokio's Mutex queues waiters in FIFO order.
+    device_turn: Arc<tokio::sync::Mutex<()>>,
+    /// Last status read, for `get_status` to reuse within `status_ttl` or
+    /// fall back on while the device is unreachable (if `offline_fallback`
+    /// is set). Cleared by every other device tool. In passive mode it also
+    /// tracks what the device reports unprompted.
+    last_status: Arc<StatusStore>,
+    status_ttl: std::time::Duration,
+    offline_fallback: bool,
+    passive: bool,
+    /// Present when the availability watchdog is on.
+    availability: Option<Arc<Availability>>,
+    /// Present when hearth counts running hours towards a filter reminder.
+    filter: Option<Arc<FilterTracker>>,
+    /// What the client asked to be sent, via `logging/setLevel` and
+    /// `resources/subscribe`.
+    client_subs: Arc<ClientSubscriptions>,
+    /// `[meaco] name`, which control tools' `device` argument may use.
+    device_name: Option<String>,
+    /// Which DP means what on this model.
+    profile: Arc<DeviceProfile>,
+    /// Smart plugs by name, shared with the config reloader.
+    plugs: Arc<RwLock<BTreeMap<String, Plug>>>,
+    /// `[[device]]` entries hearth doesn't drive yet, so `device` arguments
+    /// naming them, and list_devices, can say so.
+    undriven: Arc<RwLock<Vec<UndrivenDevice>>>,
+    /// The readings file, when history is on.
+    history_path: Option<String>,
+    /// The poller's latest readings, answering for history without a file.
+    recent_readings: Option<Arc<RecentReadings>>,
+    /// The SQLite store, when `[store] path` is set.
+    store: Option<Arc<Store>>,
+    /// Shared with the scheduler task, which runs them.
+    schedules: Arc<Mutex<Schedules>>,
+    schedule_path: String,
+    /// Where control tool calls are recorded, if anywhere.
+    audit: AuditSinks,
+    child_lock_guard: bool,
+    confirm_disruptive: bool,
     tool_router: ToolRouter<Self>,
 }
 
-#[tool_router]
+/// A configured `[[device]]` hearth doesn't connect to.
+#[derive(Debug, Clone)]
+struct UndrivenDevice {
+    name: Option<String>,
+    device_id: String,
+    category: Category,
+    model: String,
+    address: String,
+}
+
+fn undriven_devices(config: &Config) -> Vec<UndrivenDevice> {
+    config
+        .undriven_devices()
+        .map(|d| UndrivenDevice {
+            name: d.device.name.clone(),
+            device_id: d.device.device_id.clone(),
+            category: d.resolved.category,
+            model: d.resolved.model.clone(),
+            address: d.device.device_ip.clone(),
+        })
+        .collect()
+}
+
 impl HearthServer {
-    pub fn new(conn: Arc<TuyaConnection>) -> Self {
-        Self {
-            conn,
-            tool_router: Self::tool_router(),
+    /// There's only one device, but a `device` argument naming another
+    /// must not quietly drive this one.
+    fn check_device(&self, device: Option<&str>) -> Result<(), McpError> {
+        let Some(device) = device.map(str::trim) else {
+            return Ok(());
+        };
+        if device == self.conn.device_id || self.device_name.as_deref() == Some(device) {
+            return Ok(());
+        }
+        if let Some((name, _)) = self.find_plug(device) {
+            return Err(McpError::invalid_params(
+                format!("\"{name}\" is a plug: use get_plug_status or set_plug_power"),
+                None,
+            ));
         }
+        let undriven = self.undriven.read().expect("devices lock poisoned");
+        if let Some(other) = undriven.iter().find(|d| d.device_id == device || d.name.as_deref() == Some(device)) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "\"{device}\" is configured but not driven: hearth controls only \"{}\" so far",
+                    self.device_name.as_deref().unwrap_or(&self.conn.device_id)
+                ),
+                Some(serde_json::json!({ "device_id": other.device_id })),
+            ));
+        }
+
+        let valid: Vec<String> = self
+            .device_name
+            .iter()
+            .chain(std::iter::once(&self.conn.device_id))
+            .map(|d| format!("\"{d}\""))
+            .collect();
+        Err(McpError::invalid_params(
+            format!("Unknown device \"{device}\". Valid choices: {}", valid.join(", ")),
+            None,
+        ))
     }
 
-    #[tool(description = "Get the current status of the Meaco dehumidifier including humidity, power state, mode, timer, and fault status")]
-    async fn get_status(&self) -> Result<CallToolResult, McpError> {
-        let response = tuya_connection::query_dps(&self.conn)
-            .await
-            .map_err(|e| McpError::internal_error(format!("Failed to query device: {e}"), None))?;
+    fn presets(&self) -> BTreeMap<String, u32> {
+        self.presets.read().expect("presets lock poisoned").clone()
+    }
 
-        let dps_data = response
-            .get("dps")
-            .unwrap_or(&response);
+    fn scenes(&self) -> BTreeMap<String, SceneConfig> {
+        self.scenes.read().expect("scenes lock poisoned").clone()
+    }
+
+    fn plugs(&self) -> BTreeMap<String, Plug> {
+        self.plugs.read().expect("plugs lock poisoned").clone()
+    }
+
+    /// Take up a reloaded config's `[[device]]` entries.
+    pub fn reload_devices(&self, config: &Config) {
+        *self.undriven.write().expect("devices lock poisoned") = undriven_devices(config);
+    }
+
+    /// Take up the presets and scenes of a reloaded config.
+    pub fn reload_settings(&self, config: &Config) {
+        *self.presets.write().expect("presets lock poisoned") = config.presets.clone();
+        *self.scenes.write().expect("scenes lock poisoned") = config.scene.clone();
+    }
+
+    fn find_plug(&self, device: &str) -> Option<(String, Plug)> {
+        self.plugs()
+            .into_iter()
+            .find(|(name, plug)| *name == device || plug.conn.device_id == device)
+    }
 
-        match meaco::parse_status(dps_data) {
-            Ok(status) => Ok(CallToolResult::success(vec![Content::text(
-                meaco::format_status(&status),
-            )])),
-            Err(_) => Ok(CallToolResult::success(vec![Content::text(
-                format!("Raw DPS: {response}"),
-            )])),
+    /// The plug `device` names, or the only one when it names none.
+    fn plug(&self, device: Option<&str>) -> Result<(String, Plug), McpError> {
+        let plugs = self.plugs();
+        let names = || plugs.keys().map(|name| format!("\"{name}\"")).collect::<Vec<_>>().join(", ");
+        match device.map(str::trim) {
+            Some(device) => self.find_plug(device).ok_or_else(|| {
+                McpError::invalid_params(format!("Unknown plug \"{device}\". Valid choices: {}", names()), None)
+            }),
+            None if plugs.len() == 1 => Ok(plugs.into_iter().next().expect("one plug")),
+            None => Err(McpError::invalid_params(
+                format!("Say which plug with device: {}", names()),
+                None,
+            )),
         }
     }
 
-    #[tool(description = "Turn the Meaco dehumidifier on or off")]
-    async fn power(
+    /// With the guard on, an engaged child lock blocks writes unless the
+    /// caller explicitly overrides it. The lock stops the kids; it should
+    /// stop casual agent commands (and automations) too.
+    async fn check_child_lock(
         &self,
-        Parameters(PowerParams { on }): Parameters<PowerParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let dps_val = meaco::build_power_dps(on);
-        tuya_connection::set_dps(&self.conn, dps_val)
+        override_child_lock: bool,
+        deadline: &Deadline,
+    ) -> Result<(), McpError> {
+        if !self.child_lock_guard || override_child_lock {
+            return Ok(());
+        }
+
+        let response = tuya_connection::query_dps(&self.conn, deadline)
             .await
-            .map_err(|e| McpError::internal_error(format!("Failed to set power: {e}"), None))?;
+            .map_err(|e| McpError::internal_error(format!("Failed to check child lock: {e}"), None))?;
+        let dps_data = response.get("dps").unwrap_or(&response);
 
-        let state = if on { "ON" } else { "OFF" };
-        Ok(CallToolResult::success(vec![Content::text(
-            format!("Dehumidifier turned {state}"),
-        )]))
+        if meaco::child_lock_engaged(dps_data, &self.profile) {
+            return Err(McpError::invalid_request(
+                "Child lock is engaged, so device controls are blocked. \
+                 Retry with override_child_lock: true only if the user explicitly asked for this.",
+                None,
+            ));
+        }
+        Ok(())
     }
 
-    #[tool(description = "Set the target humidity percentage (35-70 in steps of 5)")]
-    async fn set_humidity(
+    /// The one DPS object for `settings`, and what it changes in words.
+    /// Fails, before anything is sent, if any field is invalid.
+    fn plan_settings(&self, settings: &Settings) -> Result<(serde_json::Value, Vec<String>), McpError> {
+        let invalid = |e: meaco::DpsError| McpError::invalid_params(format!("{e}"), None);
+
+        let mut write = DpsWrite::new(&self.profile);
+        let mut changes = Vec::new();
+        if let Some(on) = settings.power {
+            write = write.set(meaco::SWITCH, on).map_err(invalid)?;
+            changes.push(format!("power {}", if on { "ON" } else { "OFF" }));
+        }
+        if let Some(humidity) = &settings.humidity {
+            let humidity = meaco::resolve_humidity_target(humidity, &self.presets()).map_err(invalid)?;
+            let range = *self.humidity_range.read().expect("humidity range lock poisoned");
+            write = write.set_within(meaco::TARGET_HUMIDITY, humidity, &range).map_err(invalid)?;
+            changes.push(format!("target humidity {humidity}%"));
+        }
+        if let Some(mode) = &settings.mode {
+            write = write.set(meaco::MODE, mode.dp_value()).map_err(invalid)?;
+            changes.push(format!("mode {mode}"));
+        }
+        if let Some(locked) = settings.child_lock {
+            write = write.set(meaco::CHILD_LOCK, locked).map_err(invalid)?;
+            changes.push(format!("child lock {}", if locked { "ON" } else { "OFF" }));
+        }
+        if let Some(countdown) = &settings.countdown {
+            write = write.set(meaco::COUNTDOWN, countdown.dp_value()).map_err(invalid)?;
+            changes.push(format!("timer {countdown}"));
+        }
+        if let Some(speed) = &settings.fan_speed {
+            write = write.set(meaco::FAN_SPEED, speed.dp_value()).map_err(invalid)?;
+            changes.push(format!("fan speed {speed:?}"));
+        }
+        if let Some(on) = settings.ionizer {
+            write = write.set(meaco::IONIZER, on).map_err(invalid)?;
+            changes.push(format!("ioniser {}", if on { "ON" } else { "OFF" }));
+        }
+        if let Some(on) = settings.sleep_mode {
+            write = write.set(meaco::SLEEP, on).map_err(invalid)?;
+            changes.push(format!("sleep mode {}", if on { "ON" } else { "OFF" }));
+        }
+        if write.is_empty() {
+            return Err(McpError::invalid_params("Nothing to set", None));
+        }
+        Ok((write.build(), changes))
+    }
+
+    /// With confirmation on, a write that would interrupt a drying program
+    /// or a running countdown goes ahead only once the user says so. A
+    /// client that can't ask them gets the question back instead.
+    ///
+    /// The device turn is handed back while the question is open, so other
+    /// calls aren't stuck behind it, and the device is checked again once
+    /// the user answers.
+    async fn confirm_disruption(
         &self,
-        Parameters(SetHumidityParams { humidity }): Parameters<SetHumidityParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let dps_val = meaco::build_target_humidity_dps(humidity)
-            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        planned: &serde_json::Value,
+        confirmed: bool,
+        deadline: &Deadline,
+        ctx: &RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        if !self.confirm_disruptive || confirmed {
+            return Ok(());
+        }
+        let Some(disruption) = self.disruption(planned, deadline).await? else {
+            return Ok(());
+        };
 
-        tuya_connection::set_dps(&self.conn, dps_val)
-            .await
-            .map_err(|e| McpError::internal_error(format!("Failed to set humidity: {e}"), None))?;
+        let turn = ctx.extensions.get::<DeviceTurn>();
+        if let Some(turn) = turn {
+            turn.0.lock().expect("device turn lock poisoned").take();
+        }
+        let answer = self.ask_to_confirm(&disruption, deadline, &ctx.peer).await;
+        if let Some(turn) = turn {
+            let guard = tokio::select! {
+                guard = self.device_turn.clone().lock_owned() => guard,
+                () = deadline.cancel.cancelled() => {
+                    return Err(McpError::internal_error("Request cancelled while queued", None));
+                }
+                () = expiry(deadline.at) => {
+                    return Err(McpError::internal_error("Request deadline exceeded while queued", None));
+                }
+            };
+            *turn.0.lock().expect("device turn lock poisoned") = Some(guard);
+        }
+        answer?;
 
-        Ok(CallToolResult::success(vec![Content::text(
-            format!("Target humidity set to {humidity}%"),
-        )]))
+        // Another call may have changed things while the user was asked
+        match self.disruption(planned, deadline).await? {
+            Some(now) if now != disruption => Err(McpError::invalid_request(
+                format!("Not done: the device changed while waiting for confirmation, and this would now {now}"),
+                None,
+            )),
+            _ => Ok(()),
+        }
     }
 
-    #[tool(description = "Set the operating mode: manual, auto, drying, or continuous")]
-    async fn set_mode(
-        &self,
-        Parameters(SetModeParams { mode }): Parameters<SetModeParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let dps_val = meaco::build_mode_dps(&mode);
-        tuya_connection::set_dps(&self.conn, dps_val)
+    /// What `planned` would interrupt on the device right now, if anything.
+    async fn disruption(&self, planned: &serde_json::Value, deadline: &Deadline) -> Result<Option<String>, McpError> {
+        let response = tuya_connection::query_dps(&self.conn, deadline)
             .await
-            .map_err(|e| McpError::internal_error(format!("Failed to set mode: {e}"), None))?;
+            .map_err(|e| McpError::internal_error(format!("Failed to check what's running: {e}"), None))?;
+        Ok(meaco::disruption(response.get("dps").unwrap_or(&response), planned, &self.profile))
+    }
 
-        Ok(CallToolResult::success(vec![Content::text(
-            format!("Mode set to {mode:?}"),
-        )]))
+    /// Put the question to the user, for no longer than the request has left.
+    async fn ask_to_confirm(
+        &self,
+        disruption: &str,
+        deadline: &Deadline,
+        peer: &Peer<RoleServer>,
+    ) -> Result<(), McpError> {
+        let timeout = deadline.at.map_or(CONFIRM_TIMEOUT, |at| {
+            at.saturating_duration_since(tokio::time::Instant::now()).min(CONFIRM_TIMEOUT)
+        });
+        if timeout.is_zero() {
+            return Err(McpError::internal_error("Request deadline exceeded before confirmation", None));
+        }
+
+        let question = format!("An agent wants to {disruption}. Go ahead?");
+        let answer = tokio::select! {
+            answer = peer.elicit_with_timeout::<Confirmation>(question, Some(timeout)) => answer,
+            () = deadline.cancel.cancelled() => {
+                return Err(McpError::internal_error("Request cancelled while waiting for confirmation", None));
+            }
+        };
+        match answer {
+            Ok(Some(Confirmation { confirm: true })) => Ok(()),
+            Err(ElicitationError::CapabilityNotSupported) => Err(McpError::invalid_request(
+                format!(
+                    "This would {disruption}. Ask the user first, and retry with confirmed: true \
+                     only if they explicitly agree."
+                ),
+                None,
+            )),
+            Err(ElicitationError::Service(e)) => {
+                tracing::warn!("Confirmation request failed: {e}");
+                Err(McpError::invalid_request(
+                    format!("Not done: couldn't get the user's confirmation to {disruption}"),
+                    None,
+                ))
+            }
+            _ => Err(McpError::invalid_request(
+                format!("Not done: the user didn't confirm they want to {disruption}"),
+                None,
+            )),
+        }
     }
 
-    #[tool(description = "Enable or disable the child lock")]
-    async fn set_child_lock(
+    /// Tools that may change something, by their annotations.
+    fn is_control_tool(&self, name: &str) -> bool {
+        self.tool_router
+            .get(name)
+            .is_some_and(|tool| tool.annotations.as_ref().and_then(|a| a.read_only_hint) != Some(true))
+    }
+
+    /// Connection state for the foot of a status report.
+    /// `text` headed by the device's name, if it has one.
+    fn named(&self, text: String) -> String {
+        match &self.device_name {
+            Some(name) => format!("Device: {name}\n{text}"),
+            None => text,
+        }
+    }
+
+    fn connection_summary(&self) -> String {
+        let diag = tuya_connection::diagnostics(&self.conn);
+        let mut connection = format!(
+            "Connection: {} via {} (next seqno {}, {:?} on reconnect)",
+            diag.state, diag.address, diag.next_seqno, diag.seqno_policy
+        );
+        let lagging: Vec<String> = diag
+            .dropped_events
+            .iter()
+            .filter(|(_, dropped)| **dropped > 0)
+            .map(|(name, dropped)| format!("{name} dropped {dropped}"))
+            .collect();
+        if !lagging.is_empty() {
+            connection.push_str(&format!("\nEvent consumers lagging: {}", lagging.join(", ")));
+        }
+        connection
+    }
+
+    /// The status as text and structured content: live, cached, freshly
+    /// queried or, for an unreachable device, the last one read.
+    async fn current_status(
         &self,
-        Parameters(SetChildLockParams { locked }): Parameters<SetChildLockParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let dps_val = meaco::build_child_lock_dps(locked);
-        tuya_connection::set_dps(&self.conn, dps_val)
-            .await
-            .map_err(|e| McpError::internal_error(format!("Failed to set child lock: {e}"), None))?;
+        ctx: &RequestContext<RoleServer>,
+    ) -> Result<(String, StatusOutput), McpError> {
+        // The reader hears every push while the socket is up, so a live
+        // status is as fresh as a query would be
+        let connected = matches!(
+            tuya_connection::state(&self.conn),
+            ConnectionState::Ready | ConnectionState::Degraded
+        );
+        if self.passive
+            && connected
+            && let Some(status) = status::live_status(&self.last_status, &self.profile)
+        {
+            return self.fresh_status(Ok(status), None);
+        }
 
-        let state = if locked { "enabled" } else { "disabled" };
-        Ok(CallToolResult::success(vec![Content::text(
-            format!("Child lock {state}"),
-        )]))
+        let response = match status::cached(&self.last_status, self.status_ttl) {
+            Some(response) => response,
+            None => {
+                // Known to be gone: say so now rather than after a timeout
+                if let Some(offline) = self.offline_notice() {
+                    return self.last_known_status(&offline);
+                }
+                let deadline = request_deadline(ctx, &self.shutdown);
+                match tuya_connection::query_dps(&self.conn, &deadline).await {
+                    Ok(response) => {
+                        status::cache(&self.last_status, &response);
+                        response
+                    }
+                    Err(e) => return self.status_query_failed(e),
+                }
+            }
+        };
+
+        let dps_data = response
+            .get("dps")
+            .unwrap_or(&response);
+
+        let status = meaco::parse_status(dps_data, &self.profile);
+        if let Ok(status) = &status {
+            let changes = status::remember(&self.last_status, status);
+            if !changes.is_empty() {
+                events::publish(&self.conn.events, DeviceEvent::FieldsChanged { changes });
+            }
+        }
+        self.fresh_status(status, Some(dps_data))
     }
 
-    #[tool(description = "Set the countdown timer: cancel, 1h, 2h, or 3h")]
-    async fn set_countdown(
+    /// A status just read from the device, or its raw DPS if they didn't parse.
+    fn fresh_status(
         &self,
-        Parameters(SetCountdownParams { countdown }): Parameters<SetCountdownParams>,
-    ) -> Result<CallToolResult, McpError> {
-        let dps_val = meaco::build_countdown_dps(&countdown);
-        tuya_connection::set_dps(&self.conn, dps_val)
-            .await
-            .map_err(|e| McpError::internal_error(format!("Failed to set countdown: {e}"), None))?;
+        status: Result<DehumidifierStatus, meaco::DpsError>,
+        dps: Option<&serde_json::Value>,
+    ) -> Result<(String, StatusOutput), McpError> {
+        let connection = self.connection_summary();
+        let (text, status, raw_dps, what_changed) = match status {
+            Ok(mut status) => {
+                self.count_filter_hours(&mut status);
+                let mut text = meaco::format_status(&status, &self.profile);
+                let what_changed = status::what_changed(&self.last_status, &status);
+                if !what_changed.is_empty() {
+                    text.push_str(&format!("\nChanged since last read: {}", meaco::format_changes(&what_changed)));
+                }
+                (format!("{text}\n{connection}"), Some(status), None, what_changed)
+            }
+            Err(_) => {
+                let raw = dps.cloned().unwrap_or_default();
+                (format!("Raw DPS: {raw}\n{connection}"), None, Some(raw), Vec::new())
+            }
+        };
+        Ok((self.named(text), StatusOutput {
+            device: self.conn.name.clone(),
+            status,
+            raw_dps,
+            what_changed,
+            age_secs: None,
+            connection: tuya_connection::state(&self.conn).to_string(),
+        }))
+    }
 
-        Ok(CallToolResult::success(vec![Content::text(
-            format!("Countdown set to {countdown:?}"),
-        )]))
+    /// Add hearth's count of running hours to `status`, and judge from it
+    /// whether the filter is due where the device doesn't say.
+    fn count_filter_hours(&self, status: &mut DehumidifierStatus) {
+        let Some(tracker) = &self.filter else {
+            return;
+        };
+        status.filter_hours = Some(filter::hours_run(tracker));
+        status.filter_due = status.filter_due.or(Some(filter::due(tracker)));
+    }
+
+    /// Whether get_history has anything to answer from.
+    fn keeps_history(&self) -> bool {
+        self.store.is_some() || self.history_path.is_some() || self.recent_readings.is_some()
+    }
+
+    /// Readings with `from <= t < to`: the store's when there is one, the
+    /// file's when history is on, else the poller's in memory.
+    fn readings(&self, from: u64, to: u64) -> Result<Vec<Reading>, McpError> {
+        if let Some(store) = &self.store {
+            return store::readings(store, from, to).map_err(|e| McpError::internal_error(e.to_string(), None));
+        }
+        match (&self.history_path, &self.recent_readings) {
+            (Some(path), _) => history::query(path, from, to).map_err(|e| McpError::internal_error(e.to_string(), None)),
+            (None, Some(recent)) => Ok(history::query_recent(recent, from, to)),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    fn offline_notice(&self) -> Option<String> {
+        watchdog::offline_notice(self.availability.as_deref()?, &self.conn)
+    }
+
+    /// A failed status query. An unreachable device is reported like one
+    /// the watchdog already knows is offline.
+    fn status_query_failed(&self, e: ConnectionError) -> Result<(String, StatusOutput), McpError> {
+        let unreachable = matches!(
+            e,
+            ConnectionError::Tcp(_)
+                | ConnectionError::Timeout
+                | ConnectionError::Closed
+                | ConnectionError::DeadlineExceeded
+        );
+        if unreachable {
+            return self.last_known_status(&format!("unreachable ({e})"));
+        }
+        let state = tuya_connection::state(&self.conn);
+        Err(McpError::internal_error(
+            format!("Failed to query device (connection {state}): {e}"),
+            None,
+        ))
+    }
+
+    /// The device can't be reached: with `offline_fallback`, answer with
+    /// the last status read and its age; otherwise an error.
+    fn last_known_status(&self, why: &str) -> Result<(String, StatusOutput), McpError> {
+        match status::last_known(&self.last_status) {
+            Some((mut last, age)) if self.offline_fallback => {
+                self.count_filter_hours(&mut last);
+                let text = format!(
+                    "{}\nAs of {}, device currently {why}\n{}",
+                    meaco::format_status(&last, &self.profile),
+                    status::format_age(age),
+                    self.connection_summary()
+                );
+                Ok((self.named(text), StatusOutput {
+                    device: self.conn.name.clone(),
+                    status: Some(last),
+                    raw_dps: None,
+                    what_changed: Vec::new(),
+                    age_secs: Some(age.as_secs()),
+                    connection: tuya_connection::state(&self.conn).to_string(),
+                }))
+            }
+            _ => {
+                let state = tuya_connection::state(&self.conn);
+                Err(McpError::internal_error(
+                    format!("Device {why} (connection {state})"),
+                    None,
+                ))
+            }
+        }
     }
 }
 
-#[tool_handler]
-impl ServerHandler for HearthServer {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            instructions: Some(
-                "Hearth — sovereign home system. \
-                 Controls: Meaco Arete Two 25L dehumidifier via Tuya protocol v3.3. \
-                 Available tools: get_status, power, set_humidity, set_mode, set_child_lock, set_countdown."
-                    .into(),
-            ),
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            ..Default::default()
+/// The state the server shares with the tasks main starts alongside it.
+pub struct Shared {
+    pub conn: Arc<TuyaConnection>,
+    pub log: Arc<LogControl>,
+    pub discovered: Arc<DiscoveredDevices>,
+    pub observations: Option<Arc<Mutex<Observations>>>,
+    pub last_status: Arc<StatusStore>,
+    pub recent_readings: Option<Arc<RecentReadings>>,
+    pub store: Option<Arc<Store>>,
+    pub availability: Option<Arc<Availability>>,
+    pub filter: Option<Arc<FilterTracker>>,
+    pub schedules: Arc<Mutex<Schedules>>,
+    pub plugs: Arc<RwLock<BTreeMap<String, Plug>>>,
+    pub shutdown: Arc<Shutdown>,
+}
+
+#[tool_router]
+impl HearthServer {
+    pub fn new(shared: Shared, config: &Config) -> Self {
+        let Shared {
+            conn,
+            log,
+            discovered,
+            observations,
+            last_status,
+            recent_readings,
+            store,
+            availability,
+            filter,
+            schedules,
+            plugs,
+            shutdown,
+        } = shared;
+
+        Self {
+            conn,
+            humidity_range: Arc::new(RwLock::new(Self::profile_humidity_range(config))),
+            presets: Arc::new(RwLock::new(config.presets.clone())),
+            scenes: Arc::new(RwLock::new(config.scene.clone())),
+            log,
+            discovered,
+            observations,
+            shutdown,
+            device_turn: Arc::default(),
+            last_status,
+            status_ttl: config.polling(config.meaco()).cache_ttl,
+            offline_fallback: config.status.offline_fallback,
+            passive: config.status.passive,
+            availability,
+            filter,
+            client_subs: Arc::default(),
+            device_name: config.meaco().name.clone(),
+            profile: Arc::new(config.device.clone()),
+            plugs,
+            undriven: Arc::new(RwLock::new(undriven_devices(config))),
+            history_path: config.history.enabled.then(|| config.history.path.clone()),
+            recent_readings,
+            audit: AuditSinks {
+                path: config.audit.path(),
+                store: store.clone(),
+            },
+            store,
+            schedules,
+            schedule_path: config.schedule.path.clone(),
+            child_lock_guard: config.safety.child_lock_guard,
+            confirm_disruptive: config.safety.confirm_disruptive,
+            tool_router: Self::exposed_tools(config),
+        }
+    }
+
+    /// Every tool the config lets clients see: none for DPs this model's
+    /// profile doesn't declare, and with `[safety] read_only` or
+    /// `allowed_tools`, only those.
+    fn exposed_tools(config: &Config) -> ToolRouter<Self> {
+        let mut router = Self::profile_tools(config);
+        let safety = &config.safety;
+
+        if let Some(allowed) = &safety.allowed_tools {
+            for name in unknown_tools(&router, allowed) {
+                tracing::warn!(tool = %name, "allowed_tools names a tool hearth doesn't have");
+            }
+            for tool in router.list_all() {
+                if !allowed.iter().any(|name| *name == tool.name) {
+                    router.remove_route(&tool.name);
+                }
+            }
+        }
+        if safety.read_only {
+            for tool in router.list_all() {
+                let read_only = tool.annotations.as_ref().and_then(|a| a.read_only_hint) == Some(true);
+                if !read_only {
+                    router.remove_route(&tool.name);
+                }
+            }
+        }
+        router
+    }
+
+    /// The target humidity's bounds from the profile, or the Arete Two's
+    /// if it doesn't give them all.
+    fn profile_humidity_range(config: &Config) -> HumidityRange {
+        config.device.humidity_range().unwrap_or(meaco::ARETE_TWO_HUMIDITY)
+    }
+
+    /// Every tool, less those for DPs this model's profile doesn't
+    /// declare writable, reset_filter_reminder when there's no reminder to
+    /// reset, and the plug tools when there are no plugs. The
+    /// humidity range set_humidity describes comes from the profile too.
+    fn profile_tools(config: &Config) -> ToolRouter<Self> {
+        let mut router = Self::tool_router();
+        for (tool, dp) in DP_TOOLS {
+            if !config.device.writable(dp) {
+                router.remove_route(tool);
+            }
+        }
+        if !config.device.writable(meaco::FILTER_RESET) && config.filter.reminder_hours.is_none() {
+            router.remove_route("reset_filter_reminder");
+        }
+        if config.plug.is_empty() {
+            router.remove_route("get_plug_status");
+            router.remove_route("set_plug_power");
+        }
+        if let Some(route) = router.map.get_mut("set_humidity") {
+            describe_humidity_range(&mut route.attr, &Self::profile_humidity_range(config), &config.presets);
+        }
+        router
+    }
+
+    /// A handle for another client: shares the device and everything
+    /// learned about it, but not what the last client subscribed to.
+    pub fn for_session(&self) -> Self {
+        Self {
+            client_subs: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    #[tool(
+        description = "Get the current status of the Meaco dehumidifier including humidity, power state, mode, timer, and fault status",
+        output_schema = rmcp::handler::server::tool::schema_for_output::<StatusOutput>()
+            .expect("StatusOutput is an object"),
+        annotations(read_only_hint = true)
+    )]
+    async fn get_status(&self, ctx: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+        let (text, output) = self.current_status(&ctx).await?;
+        structured(text, &output)
+    }
+
+    #[tool(
+        description = "Get device metadata and connection health: name, model and optional DPs, device id, address, protocol version, what the device announced on the LAN (firmware hints), connection uptime, reconnects, heartbeat health, request latency, timeouts, CRC errors and bytes in/out. Use it to tell a slow device from a bad network",
+        annotations(read_only_hint = true)
+    )]
+    async fn get_device_info(
+        &self,
+        Parameters(GetDeviceInfoParams { redact_id }): Parameters<GetDeviceInfoParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let diagnostics = tuya_connection::diagnostics(&self.conn);
+        let now = watchdog::unix_secs(std::time::SystemTime::now());
+        let uptime_secs = diagnostics
+            .metrics
+            .connected_since
+            .filter(|_| matches!(diagnostics.state, ConnectionState::Ready | ConnectionState::Degraded))
+            .map(|since| now.saturating_sub(since));
+        let device_id = if redact_id {
+            redact(&self.conn.device_id)
+        } else {
+            self.conn.device_id.clone()
+        };
+        // Broadcasts carry the device's own protocol version and product key
+        let announced = discovery::find(&self.discovered, &self.conn.device_id).map(|device| {
+            serde_json::json!({
+                "ip": device.ip,
+                "version": device.version,
+                "product_key": device.product_key,
+            })
+        });
+
+        let info = serde_json::json!({
+            "name": self.device_name,
+            "model": self.profile.model,
+            "profile": self.profile.dps,
+            "device_id": device_id,
+            "address": diagnostics.address,
+            "protocol_version": tuya_protocol::PROTOCOL_VERSION,
+            "announced": announced,
+            "uptime_secs": uptime_secs,
+            "heartbeat": {
+                "healthy": diagnostics.metrics.heartbeat_failures == 0,
+                "last_ok": diagnostics.metrics.heartbeat_last_ok,
+                "consecutive_failures": diagnostics.metrics.heartbeat_failures,
+            },
+            "connection": diagnostics,
+            "availability": self.availability.as_deref().map(watchdog::snapshot),
+        });
+        let json = serde_json::to_string_pretty(&info)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    /// Unordered: like the background heartbeat, it shouldn't wait behind
+    /// a long probe to find out whether the device is there.
+    #[tool(
+        description = "Cheap liveness check: one heartbeat round trip, without a status query. Reports whether the device answered, the latency, connection state, and when it last answered anything before this (Unix seconds)",
+        annotations(read_only_hint = true)
+    )]
+    async fn ping(&self, ctx: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+        let last_answered = tuya_connection::diagnostics(&self.conn).metrics.last_answered;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+
+        let started = tokio::time::Instant::now();
+        let result = tuya_connection::ping(&self.conn, &deadline).await;
+        let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let health = serde_json::json!({
+            "alive": result.is_ok(),
+            "latency_ms": result.is_ok().then_some(latency_ms),
+            "error": result.as_ref().err().map(|e| e.to_string()),
+            "state": tuya_connection::state(&self.conn),
+            "address": tuya_connection::active_address(&self.conn),
+            "last_answered": last_answered,
+        });
+        let json = serde_json::to_string_pretty(&health)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    /// The dehumidifier first, then any plugs, then configured devices
+    /// hearth doesn't drive.
+    #[tool(
+        description = "List the devices hearth controls (the dehumidifier and any smart plugs) with their category, model, address, connection state, availability and when each was last heard from (Unix seconds), then any configured but not driven, as \"not connected\". Call it before targeting a device",
+        annotations(read_only_hint = true)
+    )]
+    async fn list_devices(&self) -> Result<CallToolResult, McpError> {
+        let state = tuya_connection::state(&self.conn);
+        // The watchdog knows better than a socket that hasn't failed yet
+        let available = match &self.availability {
+            Some(availability) => watchdog::snapshot(availability).available,
+            None => notify::reachable(state),
+        };
+        let last_seen = |conn: &TuyaConnection| {
+            tuya_connection::last_heard(conn)
+                .map(|heard| watchdog::unix_secs(std::time::SystemTime::now() - heard.elapsed()))
+        };
+
+        let mut devices = vec![serde_json::json!({
+            "name": self.device_name,
+            "device_id": self.conn.device_id,
+            "category": self.profile.category,
+            "model": self.profile.model,
+            "address": tuya_connection::active_address(&self.conn),
+            "state": state,
+            "available": available,
+            "last_seen": last_seen(&self.conn),
+        })];
+        for (name, plug) in self.plugs() {
+            let state = tuya_connection::state(&plug.conn);
+            devices.push(serde_json::json!({
+                "name": name,
+                "device_id": plug.conn.device_id,
+                "category": plug.profile.category,
+                "model": plug.profile.model,
+                "address": tuya_connection::active_address(&plug.conn),
+                "state": state,
+                "available": notify::reachable(state),
+                "last_seen": last_seen(&plug.conn),
+            }));
+        }
+        for other in self.undriven.read().expect("devices lock poisoned").iter() {
+            devices.push(serde_json::json!({
+                "name": other.name,
+                "device_id": other.device_id,
+                "category": other.category,
+                "model": other.model,
+                "address": other.address,
+                "state": "not connected",
+                "available": false,
+                "last_seen": null,
+                "driven": false,
+            }));
+        }
+        let json = serde_json::to_string_pretty(&devices)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    /// Hidden unless a plug is configured; like all plug tools, doesn't
+    /// wait behind dehumidifier calls.
+    #[tool(
+        description = "Read a smart plug: whether it's on and, on plugs that meter it, the power it's drawing (W), voltage, current and energy",
+        output_schema = rmcp::handler::server::tool::schema_for_output::<plug::PlugStatus>()
+            .expect("PlugStatus is an object"),
+        annotations(read_only_hint = true)
+    )]
+    async fn get_plug_status(
+        &self,
+        Parameters(PlugStatusParams { device }): Parameters<PlugStatusParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let (name, plug) = self.plug(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        let response = tuya_connection::query_dps(&plug.conn, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to read plug {name}: {e}"), None))?;
+        let dps = response.get("dps").unwrap_or(&response);
+
+        let status = plug::parse_status(dps, &plug.profile)
+            .map_err(|e| McpError::internal_error(format!("{e}. Raw DPS: {dps}"), None))?;
+        structured(plug::format_status(&name, &status), &status)
+    }
+
+    /// Hidden unless a plug is configured.
+    #[tool(
+        description = "Switch a smart plug on or off, e.g. the heater in the same room",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_plug_power(
+        &self,
+        Parameters(SetPlugPowerParams { on, device }): Parameters<SetPlugPowerParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let (name, plug) = self.plug(device.as_deref())?;
+        let dps_val = DpsWrite::new(&plug.profile).set(plug::SWITCH, on)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        tuya_connection::set_dps(&plug.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to switch plug {name}: {e}"), None))?;
+
+        let state = if on { "ON" } else { "OFF" };
+        Ok(CallToolResult::success(vec![Content::text(format!("Plug {name} turned {state}"))]))
+    }
+
+    #[tool(
+        description = "Turn the Meaco dehumidifier on or off",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn power(
+        &self,
+        Parameters(PowerParams { on, confirmed: Confirmed { confirmed }, command: CommandOptions { override_child_lock, device } }): Parameters<PowerParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::SWITCH, on)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        self.confirm_disruption(&dps_val, confirmed, &deadline, &ctx).await?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set power: {e}"), None))?;
+
+        let state = if on { "ON" } else { "OFF" };
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Dehumidifier turned {state}"),
+        )]))
+    }
+
+    #[tool(
+        description = "Set the target humidity, either as a percentage or as a named preset like \"storage\"",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_humidity(
+        &self,
+        Parameters(SetHumidityParams { humidity, command: CommandOptions { override_child_lock, device } }): Parameters<SetHumidityParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        let humidity = meaco::resolve_humidity_target(&humidity, &self.presets())
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        let range = *self.humidity_range.read().expect("humidity range lock poisoned");
+        let dps_val = DpsWrite::new(&self.profile).set_within(meaco::TARGET_HUMIDITY, humidity, &range)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set humidity: {e}"), None))?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Target humidity set to {humidity}%"),
+        )]))
+    }
+
+    #[tool(
+        description = "Set several things at once (power, humidity, mode, child lock, countdown...) in a single write, so the device applies all or none of them. Give only the fields to change",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn batch_set(
+        &self,
+        Parameters(params): Parameters<BatchSetParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(params.command.device.as_deref())?;
+        // Validate every field before anything is sent
+        let (dps_val, changes) = self.plan_settings(&params.settings)?;
+
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(params.command.override_child_lock, &deadline).await?;
+        self.confirm_disruption(&dps_val, params.confirmed.confirmed, &deadline, &ctx).await?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to apply batch: {e}"), None))?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Set {}", changes.join(", ")),
+        )]))
+    }
+
+    #[tool(
+        description = "List the scenes configured in hearth.toml: named bundles of settings that run_scene applies in one write",
+        annotations(read_only_hint = true)
+    )]
+    async fn list_scenes(&self) -> Result<CallToolResult, McpError> {
+        let scenes = self.scenes();
+        if scenes.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No scenes configured. Add [scene.<name>] tables to hearth.toml",
+            )]));
+        }
+
+        let mut lines = Vec::new();
+        for (name, scene) in &scenes {
+            let changes = match self.plan_settings(&scene.settings) {
+                Ok((_, changes)) => changes.join(", "),
+                Err(e) => format!("unusable: {}", e.message),
+            };
+            match &scene.description {
+                Some(description) => lines.push(format!("{name}: {description} ({changes})")),
+                None => lines.push(format!("{name}: {changes}")),
+            }
+        }
+        Ok(CallToolResult::success(vec![Content::text(lines.join("\n"))]))
+    }
+
+    #[tool(
+        description = "Run a scene from list_scenes: applies all of its settings in a single write",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn run_scene(
+        &self,
+        Parameters(RunSceneParams { name, confirmed: Confirmed { confirmed }, command: CommandOptions { override_child_lock, device } }): Parameters<RunSceneParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let scenes = self.scenes();
+        let Some((name, scene)) = scenes.iter().find(|(scene, _)| scene.eq_ignore_ascii_case(name.trim())) else {
+            let known: Vec<&str> = scenes.keys().map(String::as_str).collect();
+            return Err(McpError::invalid_params(
+                format!("No scene named \"{name}\". Configured: {}", known.join(", ")),
+                None,
+            ));
+        };
+        let (dps_val, changes) = self.plan_settings(&scene.settings)?;
+
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+        self.confirm_disruption(&dps_val, confirmed, &deadline, &ctx).await?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to run scene {name}: {e}"), None))?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Scene {name}: set {}", changes.join(", ")),
+        )]))
+    }
+
+    #[tool(
+        description = "Discover which target humidity setpoints the device accepts by writing candidates and reading them back. Takes around 20 seconds, restores the original target afterwards, and updates the range set_humidity validates against",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = false)
+    )]
+    async fn probe_humidity_range(
+        &self,
+        Parameters(ProbeParams { command: CommandOptions { override_child_lock, device } }): Parameters<ProbeParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let Some(dp) = self.profile.dp(meaco::TARGET_HUMIDITY) else {
+            return Err(McpError::invalid_params("This model's profile has no target humidity DP", None));
+        };
+        let report = probe::probe_humidity_range(&self.conn, &dp.index, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Setpoint probe failed: {e}"), None))?;
+
+        let Some(range) = report.range else {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "Device accepted none of the candidate setpoints (rejected: {:?}). \
+                 Keeping the existing range.",
+                report.rejected
+            ))]));
+        };
+
+        *self.humidity_range.write().expect("humidity range lock poisoned") = range;
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Accepted setpoints: {range}\nAccepted: {:?}\nRejected: {:?}\nTarget restored to {}%",
+            report.accepted, report.rejected, report.restored_to
+        ))]))
+    }
+
+    #[tool(
+        description = "Set the operating mode: manual, auto, drying, or continuous",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_mode(
+        &self,
+        Parameters(SetModeParams { mode, command: CommandOptions { override_child_lock, device } }): Parameters<SetModeParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::MODE, mode.dp_value())
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set mode: {e}"), None))?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Mode set to {mode}"),
+        )]))
+    }
+
+    /// Hidden unless the profile declares a writable `fan_speed` DP.
+    #[tool(
+        description = "Set the fan speed: low or high",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_fan_speed(
+        &self,
+        Parameters(SetFanSpeedParams { speed, command: CommandOptions { override_child_lock, device } }): Parameters<SetFanSpeedParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::FAN_SPEED, speed.dp_value())
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set fan speed: {e}"), None))?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Fan speed set to {speed:?}"),
+        )]))
+    }
+
+    /// Hidden unless the profile declares a writable `ionizer` DP.
+    #[tool(
+        description = "Turn the ioniser (anion generator) on or off",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_ionizer(
+        &self,
+        Parameters(SetIonizerParams { on, command: CommandOptions { override_child_lock, device } }): Parameters<SetIonizerParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::IONIZER, on)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set ioniser: {e}"), None))?;
+
+        let state = if on { "ON" } else { "OFF" };
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Ioniser turned {state}"),
+        )]))
+    }
+
+    /// Hidden unless the profile declares a writable `sleep` DP.
+    #[tool(
+        description = "Turn sleep mode on or off: display off and a quieter fan, for bedrooms at night",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_sleep_mode(
+        &self,
+        Parameters(SetSleepModeParams { on, command: CommandOptions { override_child_lock, device } }): Parameters<SetSleepModeParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::SLEEP, on)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set sleep mode: {e}"), None))?;
+
+        let state = if on { "ON" } else { "OFF" };
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Sleep mode turned {state}"),
+        )]))
+    }
+
+    #[tool(
+        description = "Enable or disable the child lock",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_child_lock(
+        &self,
+        Parameters(SetChildLockParams { locked, command: CommandOptions { override_child_lock, device } }): Parameters<SetChildLockParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        // Engaging the lock is always allowed; releasing it needs the override
+        if !locked {
+            self.check_child_lock(override_child_lock, &deadline).await?;
+        }
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::CHILD_LOCK, locked)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set child lock: {e}"), None))?;
+
+        let state = if locked { "enabled" } else { "disabled" };
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Child lock {state}"),
+        )]))
+    }
+
+    #[tool(
+        description = "Set the countdown timer: cancel, 1h, 2h, or 3h",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_countdown(
+        &self,
+        Parameters(SetCountdownParams { countdown, confirmed: Confirmed { confirmed }, command: CommandOptions { override_child_lock, device } }): Parameters<SetCountdownParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::COUNTDOWN, countdown.dp_value())
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        self.confirm_disruption(&dps_val, confirmed, &deadline, &ctx).await?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set countdown: {e}"), None))?;
+
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Countdown set to {countdown}"),
+        )]))
+    }
+
+    /// Hidden unless the profile declares `countdown_left` writable.
+    #[tool(
+        description = "Set the countdown timer to any number of hours the model accepts (up to 24 on most), for firmwares that take an hour count rather than just 1h, 2h or 3h. 0 cancels it",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_timer_hours(
+        &self,
+        Parameters(SetTimerHoursParams { hours, confirmed: Confirmed { confirmed }, command: CommandOptions { override_child_lock, device } }): Parameters<SetTimerHoursParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps_val = DpsWrite::new(&self.profile).set(meaco::COUNTDOWN_LEFT, hours)
+            .map(DpsWrite::build)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+        self.confirm_disruption(&dps_val, confirmed, &deadline, &ctx).await?;
+        tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set countdown: {e}"), None))?;
+
+        let text = match hours {
+            0 => "Countdown cancelled".to_owned(),
+            hours => format!("Countdown set to {hours}h"),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    /// Hidden unless the profile declares `filter_reset` writable or
+    /// `[filter] reminder_hours` is set.
+    #[tool(
+        description = "Record that the filter has been cleaned: clears the unit's filter reminder and restarts hearth's count of running hours towards the next one",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn reset_filter_reminder(
+        &self,
+        Parameters(ResetFilterReminderParams { command: CommandOptions { override_child_lock, device } }): Parameters<ResetFilterReminderParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        let mut done = Vec::new();
+        if self.profile.writable(meaco::FILTER_RESET) {
+            let deadline = request_deadline(&ctx, &self.shutdown);
+            self.check_child_lock(override_child_lock, &deadline).await?;
+            let dps_val = DpsWrite::new(&self.profile).set(meaco::FILTER_RESET, true)
+                .map(DpsWrite::build)
+                .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+            tuya_connection::set_dps(&self.conn, dps_val, &deadline)
+                .await
+                .map_err(|e| McpError::internal_error(format!("Failed to reset the filter reminder: {e}"), None))?;
+            done.push("Filter reminder cleared on the unit".to_owned());
+        }
+        if let Some(tracker) = &self.filter {
+            filter::reset(tracker).map_err(|e| McpError::internal_error(e.to_string(), None))?;
+            done.push("Running hours counted from zero".to_owned());
+        }
+        Ok(CallToolResult::success(vec![Content::text(done.join("\n"))]))
+    }
+
+    #[tool(
+        description = "Explain any active faults (full tank, defrosting, sensor errors...) with what each means and what to do about it",
+        output_schema = rmcp::handler::server::tool::schema_for_output::<FaultsOutput>()
+            .expect("FaultsOutput is an object"),
+        annotations(read_only_hint = true)
+    )]
+    async fn get_faults(&self, ctx: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+        let (_, output) = self.current_status(&ctx).await?;
+        let Some(status) = output.status else {
+            return Err(McpError::internal_error(
+                "The device's DPS couldn't be parsed; see query_dps",
+                None,
+            ));
+        };
+
+        let faults = status.faults;
+        let bitmap = faults.iter().fold(0, |bitmap, f| bitmap | 1 << f.bit);
+        let mut text = match faults.len() {
+            0 => "No faults".to_owned(),
+            _ => faults.iter().map(meaco::format_fault).collect::<Vec<_>>().join("\n"),
+        };
+        if let Some(age) = output.age_secs {
+            text.push_str(&format!(
+                "\nAs of {}, device currently unreachable",
+                status::format_age(std::time::Duration::from_secs(age))
+            ));
+        }
+        structured(text, &FaultsOutput { bitmap, faults, age_secs: output.age_secs })
+    }
+
+    #[tool(
+        description = "Query the device and return its complete DPS map as raw JSON, exactly as reported, including DPs hearth doesn't model",
+        annotations(read_only_hint = true)
+    )]
+    async fn query_dps(&self, ctx: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        let response = tuya_connection::query_dps(&self.conn, &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to query device: {e}"), None))?;
+        status::cache(&self.last_status, &response);
+
+        let dps = response.get("dps").unwrap_or(&response);
+        let json = serde_json::to_string_pretty(dps)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    #[tool(
+        description = "Reverse-engineering escape hatch: write arbitrary DPS values to the device exactly as given, including DPs hearth doesn't model. Prefer the typed tools for anything they cover",
+        annotations(read_only_hint = false, destructive_hint = true, idempotent_hint = true)
+    )]
+    async fn set_dps(
+        &self,
+        Parameters(SetDpsParams { dps, command: CommandOptions { override_child_lock, device } }): Parameters<SetDpsParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_device(device.as_deref())?;
+        if dps.is_empty() {
+            return Err(McpError::invalid_params("dps must name at least one DPS index", None));
+        }
+        if let Some(key) = dps.keys().find(|key| key.parse::<u32>().is_err()) {
+            return Err(McpError::invalid_params(
+                format!("DPS index \"{key}\" is not a number"),
+                None,
+            ));
+        }
+
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        self.check_child_lock(override_child_lock, &deadline).await?;
+
+        let dps = serde_json::Value::Object(dps);
+        tracing::info!(device = %self.conn.name, %dps, "Raw DPS write");
+        let response = tuya_connection::set_dps(&self.conn, dps.clone(), &deadline)
+            .await
+            .map_err(|e| McpError::internal_error(format!("Failed to set DPS: {e}"), None))?;
+
+        let mut text = format!("Sent DPS {dps}");
+        if !response.is_null() {
+            text.push_str(&format!("\nDevice replied: {response}"));
+        }
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(
+        description = "List every Tuya device heard announcing itself on the LAN (UDP 6666/6667): id (gwId), IP, protocol version, product key, and whether hearth is configured for it. Scans for a few seconds if nothing has been heard yet. Use it when the device seems to have changed IP",
+        annotations(read_only_hint = true)
+    )]
+    async fn discover_devices(
+        &self,
+        Parameters(DiscoverDevicesParams { wait_secs }): Parameters<DiscoverDevicesParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if !discovery::listening(&self.discovered) {
+            return Err(McpError::internal_error(
+                "LAN discovery isn't running: UDP ports 6666/6667 couldn't be bound (is another Tuya tool using them?)",
+                None,
+            ));
+        }
+
+        let wait_secs = wait_secs.or_else(|| {
+            discovery::sightings(&self.discovered).is_empty().then_some(DEFAULT_SCAN_SECS)
+        });
+        if let Some(secs) = wait_secs {
+            tokio::select! {
+                () = tokio::time::sleep(std::time::Duration::from_secs(secs)) => {}
+                () = ctx.ct.cancelled() => {}
+            }
+        }
+
+        let sightings = discovery::sightings(&self.discovered);
+        if sightings.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No Tuya broadcasts heard yet. Retry with wait_secs of 10 or more",
+            )]));
+        }
+
+        let mut text = serde_json::to_string_pretty(&sightings)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        let address = tuya_connection::active_address(&self.conn);
+        if let Some(ip) = discovery::resolve_ip(&self.discovered, &self.conn.device_id)
+            && ip != address
+        {
+            text.push_str(&format!(
+                "\nThe configured device is announcing from {ip}, but hearth is using {address}"
+            ));
+        }
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(
+        description = "List Tuya devices seen on the LAN that aren't configured in hearth, with their id, IP, protocol version and product key",
+        annotations(read_only_hint = true)
+    )]
+    async fn list_discovered_devices(&self) -> Result<CallToolResult, McpError> {
+        let devices = discovery::unconfigured(&self.discovered);
+        if devices.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No unconfigured devices discovered yet",
+            )]));
+        }
+
+        let json = serde_json::to_string_pretty(&devices)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    #[tool(
+        description = "Promote a discovered device: checks the supplied local key by querying the device with it, then returns the [[device]] entry to append to hearth.toml",
+        annotations(read_only_hint = true)
+    )]
+    async fn promote_device(
+        &self,
+        Parameters(PromoteDeviceParams { device_id, local_key, name }): Parameters<PromoteDeviceParams>,
+        ctx: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let deadline = request_deadline(&ctx, &self.shutdown);
+        let Some(device) = discovery::find(&self.discovered, &device_id) else {
+            return Err(McpError::invalid_params(
+                format!("No discovered device with id {device_id}. Use list_discovered_devices to see what has been found"),
+                None,
+            ));
+        };
+        if local_key.len() != 16 {
+            return Err(McpError::invalid_params(
+                format!("Local key must be exactly 16 characters, got {}", local_key.len()),
+                None,
+            ));
+        }
+        let name = name.unwrap_or_else(|| {
+            let id: String = device.device_id.chars().rev().take(6).collect();
+            format!("device-{}", id.chars().rev().collect::<String>())
+        });
+        let taken = self.device_name.as_deref() == Some(name.as_str())
+            || self.plugs().contains_key(&name)
+            || self.undriven.read().expect("devices lock poisoned").iter().any(|other| other.name.as_deref() == Some(name.as_str()));
+        if taken {
+            return Err(McpError::invalid_params(
+                format!("A device is already called \"{name}\". Pick another name"),
+                None,
+            ));
+        }
+
+        // A wrong key makes the device's reply undecryptable, which shows
+        // up here as a timeout rather than anything more specific
+        let candidate = MeacoConfig {
+            name: None,
+            device_ip: device.ip.clone(),
+            device_id: device.device_id.clone(),
+            local_key: local_key.clone(),
+            fallback_addresses: Vec::new(),
+            seqno_on_reconnect: Default::default(),
+            polling: Default::default(),
+        };
+        let conn = tuya_connection::new(&candidate, &ConnectionConfig::default(), &self.profile);
+        tuya_connection::query_dps(&conn, &deadline).await.map_err(|e| {
+            McpError::internal_error(
+                format!("Could not verify the local key against {} ({e}). Check the key and that the device is on", device.ip),
+                None,
+            )
+        })?;
+
+        let mut text = format!(
+            "Local key verified. Append this to hearth.toml, keeping the devices already there:
+
+{}",
+            discovery::config_snippet(&device, &name, &local_key)
+        );
+        if device.version != "3.3" {
+            text.push_str(&format!(
+                "
+Note: the device announced protocol {}, but hearth only speaks 3.3.",
+                device.version
+            ));
+        }
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(
+        description = "Report what observation mode has learned: every value each DP has taken, transitions, and which DPs change together when someone uses the physical panel. Use it to work out what undocumented DPs control",
+        annotations(read_only_hint = true)
+    )]
+    async fn get_dp_observations(&self) -> Result<CallToolResult, McpError> {
+        let Some(observations) = &self.observations else {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "Observation mode is off. Set enabled = true under [observe] in hearth.toml and restart",
+            )]));
+        };
+
+        let report = observe::format_report(&observations.lock().expect("observations lock poisoned"));
+        Ok(CallToolResult::success(vec![Content::text(report)]))
+    }
+
+    #[tool(
+        description = "Recorded humidity, target, power and faults over a time window, summarised per interval. Answers questions like \"what was the humidity overnight?\" without guessing",
+        annotations(read_only_hint = true)
+    )]
+    async fn get_history(
+        &self,
+        Parameters(GetHistoryParams { since_hours, until_hours_ago, resolution_minutes }): Parameters<GetHistoryParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if !self.keeps_history() {
+            return Ok(CallToolResult::success(vec![Content::text(HISTORY_OFF)]));
+        }
+
+        let since_hours = since_hours.unwrap_or(12);
+        let until_hours_ago = until_hours_ago.unwrap_or(0);
+        if until_hours_ago >= since_hours {
+            return Err(McpError::invalid_params(
+                "until_hours_ago must be less than since_hours",
+                None,
+            ));
+        }
+        let resolution = resolution_minutes.unwrap_or(30).max(1).saturating_mul(60);
+
+        let now = watchdog::unix_secs(std::time::SystemTime::now());
+        let from = now.saturating_sub(since_hours.saturating_mul(3600)) / resolution * resolution;
+        let to = now.saturating_sub(until_hours_ago.saturating_mul(3600));
+        let readings = self.readings(from, to)?;
+
+        let report = history::format_buckets(&history::downsample(&readings, from, resolution));
+        Ok(CallToolResult::success(vec![Content::text(report)]))
+    }
+
+    #[tool(
+        description = "Export recorded readings (time, humidity, target, power, fault bitmap) for a date range as CSV, one row per sample, for a spreadsheet",
+        annotations(read_only_hint = true)
+    )]
+    async fn export_history(
+        &self,
+        Parameters(ExportHistoryParams { from, to }): Parameters<ExportHistoryParams>,
+    ) -> Result<CallToolResult, McpError> {
+        if !self.keeps_history() {
+            return Ok(CallToolResult::success(vec![Content::text(HISTORY_OFF)]));
+        }
+        let now = watchdog::unix_secs(std::time::SystemTime::now());
+        let (from, to) = history::export_window(from.as_deref(), to.as_deref(), now)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        let csv = history::to_csv(&self.readings(from, to)?);
+        Ok(CallToolResult::success(vec![Content::text(csv)]))
+    }
+
+    #[tool(
+        description = "What happened over a time window: status changes, faults, the connection dropping, and commands sent by agents or schedules, oldest first. Pair with get_history to explain why humidity did what it did",
+        annotations(read_only_hint = true)
+    )]
+    async fn get_events(
+        &self,
+        Parameters(GetEventsParams { since_hours, until_hours_ago }): Parameters<GetEventsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(store) = &self.store else {
+            return Ok(CallToolResult::success(vec![Content::text(STORE_OFF)]));
+        };
+
+        let since_hours = since_hours.unwrap_or(24);
+        let until_hours_ago = until_hours_ago.unwrap_or(0);
+        if until_hours_ago >= since_hours {
+            return Err(McpError::invalid_params(
+                "until_hours_ago must be less than since_hours",
+                None,
+            ));
+        }
+        let now = watchdog::unix_secs(std::time::SystemTime::now());
+        let from = now.saturating_sub(since_hours.saturating_mul(3600));
+        let to = now.saturating_sub(until_hours_ago.saturating_mul(3600)).saturating_add(1);
+        let events = store::events(store, from, to).map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![Content::text(store::format_events(&events))]))
+    }
+
+    #[tool(
+        description = "Add a recurring schedule, e.g. weekdays at 07:00 set humidity 50 and power on. Schedules persist across restarts and run even when no client is connected",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = false)
+    )]
+    async fn add_schedule(
+        &self,
+        Parameters(params): Parameters<AddScheduleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let invalid = |e: &dyn std::fmt::Display| McpError::invalid_params(e.to_string(), None);
+        let days = schedule::parse_days(&params.days).map_err(|e| invalid(&e))?;
+        let minute = schedule::parse_time(&params.time).map_err(|e| invalid(&e))?;
+
+        let mut write = DpsWrite::new(&self.profile);
+        let mut actions = ScheduleActions::default();
+        if let Some(on) = params.power {
+            write = write.set(meaco::SWITCH, on).map_err(|e| invalid(&e))?;
+            actions.power = Some(on);
+        }
+        if let Some(humidity) = &params.humidity {
+            let humidity = meaco::resolve_humidity_target(humidity, &self.presets()).map_err(|e| invalid(&e))?;
+            let range = *self.humidity_range.read().expect("humidity range lock poisoned");
+            write = write.set_within(meaco::TARGET_HUMIDITY, humidity, &range).map_err(|e| invalid(&e))?;
+            actions.humidity = Some(humidity);
+        }
+        if let Some(mode) = &params.mode {
+            write = write.set(meaco::MODE, mode.dp_value()).map_err(|e| invalid(&e))?;
+            actions.mode = Some(mode.clone());
+        }
+        if write.is_empty() {
+            return Err(McpError::invalid_params("Nothing to schedule", None));
+        }
+
+        let mut schedules = self.schedules.lock().expect("schedules lock poisoned");
+        let added = schedule::describe(schedule::add(&mut schedules, days, minute, actions, write.build()));
+        schedule::save(&self.schedule_path, &schedules)
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![Content::text(format!("Added {added}"))]))
+    }
+
+    #[tool(
+        description = "List the recurring schedules with their ids",
+        annotations(read_only_hint = true)
+    )]
+    async fn list_schedules(&self) -> Result<CallToolResult, McpError> {
+        let schedules = self.schedules.lock().expect("schedules lock poisoned");
+        let text = if schedules.entries.is_empty() {
+            "No schedules".to_owned()
+        } else {
+            schedules.entries.iter().map(schedule::describe).collect::<Vec<_>>().join("\n")
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(
+        description = "Delete a recurring schedule by id",
+        annotations(read_only_hint = false, destructive_hint = true, idempotent_hint = true)
+    )]
+    async fn delete_schedule(
+        &self,
+        Parameters(DeleteScheduleParams { id }): Parameters<DeleteScheduleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let mut schedules = self.schedules.lock().expect("schedules lock poisoned");
+        if !schedule::delete(&mut schedules, id) {
+            return Err(McpError::invalid_params(format!("No schedule #{id}"), None));
+        }
+        schedule::save(&self.schedule_path, &schedules)
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        Ok(CallToolResult::success(vec![Content::text(format!("Deleted schedule #{id}"))]))
+    }
+
+    #[tool(
+        description = "Admin: change hearth's log filter at runtime, optionally for a limited time (e.g. trace the connection layer for 300 seconds while reproducing an issue)",
+        annotations(read_only_hint = false, destructive_hint = false, idempotent_hint = true)
+    )]
+    async fn set_log_level(
+        &self,
+        Parameters(SetLogLevelParams { filter, duration_secs }): Parameters<SetLogLevelParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(filter) = filter else {
+            logging::reset(&self.log);
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "Log filter restored to \"{}\"",
+                logging::current_filter(&self.log)
+            ))]));
+        };
+
+        let duration = duration_secs.map(std::time::Duration::from_secs);
+        logging::set_filter(&self.log, &filter, duration)
+            .map_err(|e| McpError::invalid_params(format!("{e}"), None))?;
+
+        let until = match duration_secs {
+            Some(secs) => format!(" for {secs}s"),
+            None => String::new(),
+        };
+        Ok(CallToolResult::success(vec![Content::text(
+            format!("Log filter set to \"{filter}\"{until}"),
+        )]))
+    }
+}
+
+impl HearthServer {
+    /// Every tool call passes the shutdown gate, so once hearth starts
+    /// stopping no new device commands begin.
+    async fn traced_call_tool(
+        &self,
+        request: CallToolRequestParams,
+        mut context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        context.extensions.insert(Received(tokio::time::Instant::now()));
+        let Some(_in_flight) = shutdown::track(&self.shutdown) else {
+            return Err(McpError::internal_error("hearth is shutting down", None));
+        };
+
+        let name = request.name.clone();
+        let arguments = request.arguments.clone();
+        let client = context.peer.peer_info().map(|info| info.client_info.name.clone());
+        let turn = if UNORDERED_TOOLS.contains(&name.as_ref()) {
+            None
+        } else {
+            tokio::select! {
+                turn = self.device_turn.clone().lock_owned() => Some(DeviceTurn(Arc::new(Mutex::new(Some(turn))))),
+                () = context.ct.cancelled() => {
+                    return Err(McpError::internal_error("Request cancelled while queued", None));
+                }
+                () = expiry(client_timeout(&context)) => {
+                    return Err(McpError::internal_error("Request deadline exceeded while queued", None));
+                }
+            }
+        };
+        if let Some(turn) = &turn {
+            context.extensions.insert(turn.clone());
+        }
+        let tcc = ToolCallContext::new(self, request, context);
+        let (result, writes) = audit::scope(self.tool_router.call(tcc)).await;
+        if audit::is_on(&self.audit)
+            && self.is_control_tool(&name)
+        {
+            let outcome = match &result {
+                Ok(r) if r.is_error != Some(true) => "ok".to_owned(),
+                Ok(r) => r
+                    .content
+                    .first()
+                    .and_then(|c| c.as_text())
+                    .map_or_else(|| "tool error".to_owned(), |t| t.text.clone()),
+                Err(e) => e.message.to_string(),
+            };
+            let arguments = arguments.map(serde_json::Value::Object).unwrap_or_default();
+            audit::log(&self.audit, name.to_string(), client, arguments, writes, outcome);
+        }
+        match &result {
+            Err(e) => tracing::warn!(tool = %name, "Tool call failed: {}", e.message),
+            Ok(_) if turn.is_some() => tracing::info!(tool = %name, "Tool call done"),
+            Ok(_) => tracing::debug!(tool = %name, "Tool call done"),
+        }
+
+        // Whatever another device tool did, the next status read must see it
+        if turn.is_some() && !matches!(name.as_ref(), "get_status" | "get_faults" | "query_dps") {
+            status::invalidate(&self.last_status);
+        }
+        result
+    }
+}
+
+impl ServerHandler for HearthServer {
+    /// Each call is a `tool_call` span, continuing the caller's trace when
+    /// it sends one.
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let span = tracing::info_span!("tool_call", tool = %request.name);
+        let headers = context.extensions.get::<hyper::http::request::Parts>().map(|parts| &parts.headers);
+        // Only fails without an exporter, when there's no trace to join
+        let _ = span.set_parent(telemetry::remote_parent(&context.meta.0, headers));
+        self.traced_call_tool(request, context).instrument(span).await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: self.tool_router.list_all(),
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    fn get_tool(&self, name: &str) -> Option<Tool> {
+        self.tool_router.get(name).cloned()
+    }
+
+    /// From here on the client hears when the device drops off or returns,
+    /// and hearth's log once it sets a level.
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        tracing::info!("client initialized");
+        notify::spawn_log_forwarder(logging::subscribe(&self.log), context.peer.clone(), self.client_subs.clone());
+        notify::spawn_notifier(
+            &self.conn.events,
+            &self.conn.name,
+            tuya_connection::state(&self.conn),
+            context.peer,
+            self.client_subs.clone(),
+        );
+    }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        notify::set_level(&self.client_subs, request.level);
+        Ok(())
+    }
+
+    async fn complete(
+        &self,
+        request: CompleteRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, McpError> {
+        let (presets, scenes, plugs) = (self.presets(), self.scenes(), self.plugs());
+        let source = prompts::CompletionSource {
+            presets: &presets,
+            scenes: scenes.keys().map(String::as_str).collect(),
+            devices: self
+                .device_name
+                .iter()
+                .map(String::as_str)
+                .chain([self.conn.device_id.as_str()])
+                .chain(plugs.keys().map(String::as_str))
+                .collect(),
+            humidity_range: *self.humidity_range.read().expect("humidity range lock poisoned"),
+        };
+        let mut values = prompts::complete(&request.argument.name, &request.argument.value, &source);
+        let total = values.len();
+        values.truncate(CompletionInfo::MAX_VALUES);
+
+        Ok(CompleteResult {
+            completion: CompletionInfo {
+                has_more: Some(total > values.len()),
+                total: Some(total as u32),
+                values,
+            },
+        })
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        Ok(ListPromptsResult::with_all_items(prompts::list()))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        prompts::expand(&request.name, request.arguments.as_ref(), &self.presets())
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let mut status = RawResource::new(status::STATUS_URI, "status");
+        status.description = Some(
+            "Dehumidifier status as JSON, as returned by get_status. Subscribe to hear when it changes".into(),
+        );
+        status.mime_type = Some("application/json".into());
+
+        Ok(ListResourcesResult {
+            resources: vec![status.no_annotation()],
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    /// Reading the status resource counts as a device call: it waits its
+    /// turn behind tool calls.
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        if request.uri != status::STATUS_URI {
+            return Err(McpError::resource_not_found(format!("No resource {}", request.uri), None));
+        }
+
+        // Like a tool call: through the shutdown gate, and given up on
+        // when the client cancels
+        let Some(_in_flight) = shutdown::track(&self.shutdown) else {
+            return Err(McpError::internal_error("hearth is shutting down", None));
+        };
+        let read = async {
+            let _turn = self.device_turn.lock().await;
+            self.current_status(&context).await
+        };
+        let (_, output) = tokio::select! {
+            result = read => result?,
+            () = context.ct.cancelled() => {
+                return Err(McpError::internal_error("Request cancelled", None));
+            }
+        };
+        let json = serde_json::to_string_pretty(&output)
+            .map_err(|e| McpError::internal_error(format!("{e}"), None))?;
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: request.uri,
+                mime_type: Some("application/json".into()),
+                text: json,
+                meta: None,
+            }],
+        })
+    }
+
+    async fn subscribe(
+        &self,
+        request: SubscribeRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        if request.uri != status::STATUS_URI {
+            return Err(McpError::resource_not_found(format!("No resource {}", request.uri), None));
+        }
+        notify::subscribe(&self.client_subs, &request.uri);
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &self,
+        request: UnsubscribeRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        notify::unsubscribe(&self.client_subs, &request.uri);
+        Ok(())
+    }
+
+    fn get_info(&self) -> ServerInfo {
+        let (scenes, plugs) = (self.scenes(), self.plugs());
+        // The tools this config exposes, so the list can't go stale
+        let mut tools: Vec<String> = self.tool_router.list_all().into_iter().map(|tool| tool.name.into_owned()).collect();
+        tools.sort();
+        // What this config drives, rather than any one model
+        let label = |name: Option<&str>, device_id: &str, model: &str| {
+            format!("\"{}\" ({model})", name.unwrap_or(device_id))
+        };
+        let mut controls = format!(
+            "{} via Tuya protocol v{}",
+            label(self.device_name.as_deref(), &self.conn.device_id, &self.profile.model),
+            tuya_protocol::PROTOCOL_VERSION
+        );
+        let undriven: Vec<String> = self
+            .undriven
+            .read()
+            .expect("devices lock poisoned")
+            .iter()
+            .map(|other| label(other.name.as_deref(), &other.device_id, &other.model))
+            .collect();
+        if !undriven.is_empty() {
+            controls.push_str(&format!("; also configured but not driven: {}", undriven.join(", ")));
+        }
+        let presets: Vec<String> = self
+            .presets()
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}%"))
+            .collect();
+
+        ServerInfo {
+            instructions: Some(format!(
+                "Hearth — sovereign home system. \
+                 Controls: {}. \
+                 Available tools: {}. \
+                 Smart plugs: {}. \
+                 Humidity presets for set_humidity: {}. \
+                 Scenes for run_scene: {}. \
+                 Prompts: dry_laundry, weekend_away, diagnose_humidity. \
+                 The hearth://status resource holds the same status as JSON; subscribe to it to hear when a DP changes. \
+                 A notifications/hearth/connection notification reports when the dehumidifier becomes unreachable or reachable again.",
+                controls,
+                tools.join(", "),
+                if plugs.is_empty() {
+                    "none configured".to_owned()
+                } else {
+                    plugs.keys().cloned().collect::<Vec<_>>().join(", ")
+                },
+                presets.join(", "),
+                if scenes.is_empty() {
+                    "none configured".to_owned()
+                } else {
+                    scenes.keys().cloned().collect::<Vec<_>>().join(", ")
+                },
+            )),
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_logging()
+                .enable_completions()
+                .enable_prompts()
+                .enable_resources()
+                .enable_resources_subscribe()
+                .build(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_profile;
+
+    /// Tools that talk to the dehumidifier or change what hearth sends it,
+    /// so take their turn. Every tool is here or in `UNORDERED_TOOLS`.
+    const ORDERED_TOOLS: &[&str] = &[
+        "get_status",
+        "get_faults",
+        "query_dps",
+        "power",
+        "set_humidity",
+        "set_mode",
+        "set_fan_speed",
+        "set_ionizer",
+        "set_sleep_mode",
+        "set_child_lock",
+        "set_countdown",
+        "set_timer_hours",
+        "reset_filter_reminder",
+        "set_dps",
+        "batch_set",
+        "list_scenes",
+        "run_scene",
+        "probe_humidity_range",
+        "promote_device",
+        "add_schedule",
+        "list_schedules",
+        "delete_schedule",
+    ];
+
+    fn config(safety: &str) -> Config {
+        let mut config: Config = toml::from_str(&format!(
+            "[meaco]\ndevice_ip = \"192.168.1.20\"\ndevice_id = \"abc\"\nlocal_key = \"0123456789abcdef\"\n[safety]\n{safety}"
+        ))
+        .unwrap();
+        config.device = device_profile::load_named("meaco_arete2_25l").unwrap();
+        config
+    }
+
+    fn names(router: &ToolRouter<HearthServer>) -> Vec<String> {
+        let mut names: Vec<String> = router.list_all().into_iter().map(|tool| tool.name.into_owned()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn safety_settings_narrow_the_tools() {
+        let everything = HearthServer::exposed_tools(&config(""));
+        assert!(everything.has_route("power") && everything.has_route("set_dps"));
+
+        // read_only leaves only what can't change anything
+        let read_only = HearthServer::exposed_tools(&config("read_only = true"));
+        assert!(read_only.has_route("get_status"));
+        for tool in read_only.list_all() {
+            let hint = tool.annotations.as_ref().and_then(|a| a.read_only_hint);
+            assert_eq!(hint, Some(true), "{} is exposed read-only", tool.name);
+        }
+        for tool in everything.list_all() {
+            let hint = tool.annotations.as_ref().and_then(|a| a.read_only_hint);
+            assert_eq!(read_only.has_route(&tool.name), hint == Some(true), "{}", tool.name);
+        }
+
+        // An allowlist exposes exactly what it names
+        let allowed = config("allowed_tools = [\"get_status\", \"power\", \"turbo\"]");
+        assert_eq!(names(&HearthServer::exposed_tools(&allowed)), ["get_status", "power"]);
+        let allowlist = allowed.safety.allowed_tools.as_deref().unwrap();
+        assert_eq!(unknown_tools(&HearthServer::profile_tools(&allowed), allowlist), ["turbo"]);
+    }
+
+    #[test]
+    fn every_tool_is_known_to_be_ordered_or_not() {
+        let router = HearthServer::tool_router();
+        for name in names(&router) {
+            let unordered = UNORDERED_TOOLS.contains(&name.as_str());
+            let ordered = ORDERED_TOOLS.contains(&name.as_str());
+            assert!(unordered != ordered, "{name} belongs in exactly one of UNORDERED_TOOLS and ORDERED_TOOLS");
+        }
+        for name in UNORDERED_TOOLS.iter().chain(ORDERED_TOOLS) {
+            assert!(router.has_route(name), "{name} isn't a tool");
         }
     }
 }
@@ -0,0 +1,162 @@
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+// -- Coordinated shutdown --
+//
+// Stop taking new tool calls, give the ones already running (and the
+// device writes they issued) a grace period to finish, then let main
+// flush what needs flushing and exit. Transport-agnostic: every tool
+// call goes through the same gate however it arrived.
+
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    /// When shutdown began. The grace period runs from here, however many
+    /// places wait on it.
+    began: OnceLock<Instant>,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Held for the duration of one tool call.
+pub struct InFlight(Arc<Shutdown>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+pub fn new_shutdown() -> Arc<Shutdown> {
+    Arc::default()
+}
+
+/// Admit a new tool call, or None once shutdown has begun.
+pub fn track(shutdown: &Arc<Shutdown>) -> Option<InFlight> {
+    shutdown.in_flight.fetch_add(1, Ordering::SeqCst);
+    let guard = InFlight(shutdown.clone());
+    if draining(shutdown) {
+        return None;
+    }
+    Some(guard)
+}
+
+/// Stop admitting tool calls. Idempotent.
+pub fn begin(shutdown: &Shutdown) {
+    if shutdown.began.set(Instant::now()).is_ok() {
+        tracing::info!(
+            in_flight = shutdown.in_flight.load(Ordering::SeqCst),
+            "Shutting down, no longer accepting tool calls"
+        );
+    }
+}
+
+pub fn draining(shutdown: &Shutdown) -> bool {
+    shutdown.began.get().is_some()
+}
+
+/// Wait for in-flight tool calls to finish, up to `grace` after shutdown
+/// began. Returns how many were still running when we stopped waiting.
+pub async fn drain(shutdown: &Shutdown, grace: Duration) -> usize {
+    begin(shutdown);
+    let until = *shutdown.began.get().expect("begin sets the start") + grace;
+
+    let wait = async {
+        loop {
+            let idle = shutdown.idle.notified();
+            if shutdown.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    };
+
+    let _ = tokio::time::timeout_at(until, wait).await;
+    shutdown.in_flight.load(Ordering::SeqCst)
+}
+
+/// Resolves on Ctrl-C/SIGINT or, on unix, SIGTERM.
+pub async fn signalled() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|()| "SIGINT"),
+            _ = term.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|()| "Ctrl-C")
+    }
+}
+
+/// Wraps the MCP input stream so end of input starts the shutdown before
+/// the MCP service tears down, while in-flight calls can still finish.
+pub struct BeginOnEof<R> {
+    inner: R,
+    shutdown: Arc<Shutdown>,
+}
+
+pub fn begin_on_eof<R>(inner: R, shutdown: Arc<Shutdown>) -> BeginOnEof<R> {
+    BeginOnEof { inner, shutdown }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for BeginOnEof<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll
+            && buf.filled().len() == before
+            && buf.remaining() > 0
+        {
+            begin(&self.shutdown);
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_waits_for_in_flight_calls() {
+        let shutdown = new_shutdown();
+        let call = track(&shutdown).unwrap();
+
+        begin(&shutdown);
+        assert!(track(&shutdown).is_none());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            drop(call);
+        });
+        assert_eq!(drain(&shutdown, Duration::from_secs(5)).await, 0);
+
+        let _stuck = InFlight({
+            shutdown.in_flight.fetch_add(1, Ordering::SeqCst);
+            shutdown.clone()
+        });
+        // The grace period counts from begin(), not from each drain call
+        assert_eq!(drain(&shutdown, Duration::from_secs(2)).await, 1);
+        let start = Instant::now();
+        assert_eq!(drain(&shutdown, Duration::from_secs(2)).await, 1);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
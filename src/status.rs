@@ -0,0 +1,332 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use tokio::time::Instant;
+
+use crate::config::Polling;
+use crate::device_profile::DeviceProfile;
+use crate::events::{self, DeviceEvent, EventBus};
+use crate::history::{self, RecentReadings};
+use crate::meaco::{self, DehumidifierStatus, StatusChange};
+use crate::tuya_connection::{self, Deadline, TuyaConnection};
+use crate::watchdog;
+
+// -- Last-known status --
+//
+// The last status read from the device and when. While the device is
+// unreachable, `get_status` can answer with this and how old it is
+// instead of an error — agents cope with stale data far better than
+// with nothing. A very recent query response is also served again as-is,
+// since agents like to ask for status several times in one turn.
+//
+// In passive mode a tracker also folds in every DP the device volunteers
+// (panel changes, fault reports) as it arrives, so the status is current
+// without asking.
+//
+// With `[polling] enabled`, a poller queries the device on an interval
+// whether or not anyone asks, so the cache, the last-known status and the
+// change notifications stay current between tool calls.
+//
+// Each new status is diffed against the one before, for `FieldsChanged`
+// events, and against the last one a client was given, for get_status's
+// what_changed.
+//
+// With `[status] snapshot_path`, the last-known status is also kept on
+// disk, so the offline fallback has an answer straight after a restart.
+
+/// The MCP resource clients read, or subscribe to, for the status.
+pub const STATUS_URI: &str = "hearth://status";
+
+/// How long one poll may take, UPDATEDPS included.
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct StatusStore {
+    last: Mutex<Option<(DehumidifierStatus, Instant)>>,
+    /// The last raw query response, reused while younger than the TTL.
+    response: Mutex<Option<(serde_json::Value, Instant)>>,
+    /// Every DP value the device has reported, queried or volunteered.
+    /// Only kept up to date while a tracker runs.
+    live: Mutex<serde_json::Map<String, serde_json::Value>>,
+    /// Set by a write until a query confirms its effect: the device's
+    /// push can lag its ACK, and a read after a write must see the write.
+    live_unconfirmed: AtomicBool,
+    /// The status get_status last answered with.
+    reported: Mutex<Option<DehumidifierStatus>>,
+    snapshot_path: Option<String>,
+}
+
+/// A store starting from the status saved at `snapshot_path`, if there
+/// is one, and saving every new status there.
+pub fn open(snapshot_path: Option<String>) -> StatusStore {
+    let last = snapshot_path.as_deref().and_then(load_snapshot);
+    StatusStore {
+        last: Mutex::new(last),
+        snapshot_path,
+        ..Default::default()
+    }
+}
+
+fn load_snapshot(path: &str) -> Option<(DehumidifierStatus, Instant)> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            tracing::warn!(path, "Can't read the stored status: {e}");
+            return None;
+        }
+    };
+    let snapshot = serde_json::from_str(&text)
+        .map_err(|e| meaco::SnapshotError::Invalid(e.to_string()))
+        .and_then(meaco::restore);
+    match snapshot {
+        Ok(snapshot) => {
+            let age = watchdog::unix_secs(SystemTime::now()).saturating_sub(snapshot.taken_at);
+            let at = Instant::now().checked_sub(Duration::from_secs(age)).unwrap_or_else(Instant::now);
+            Some((snapshot.status, at))
+        }
+        Err(e) => {
+            tracing::warn!(path, "Ignoring the stored status: {e}");
+            None
+        }
+    }
+}
+
+fn save_snapshot(path: &str, status: &DehumidifierStatus) {
+    let snapshot = meaco::snapshot(status, watchdog::unix_secs(SystemTime::now()));
+    let json = serde_json::to_string(&snapshot).expect("status serializes");
+    if let Err(e) = std::fs::write(path, json) {
+        tracing::warn!(path, "Can't store the status: {e}");
+    }
+}
+
+/// Keep `status` as the last-known one, returning what changed since the
+/// previous. Nothing has, the first time.
+pub fn remember(store: &StatusStore, status: &DehumidifierStatus) -> Vec<StatusChange> {
+    let previous = store.last.lock().expect("status lock poisoned").replace((status.clone(), Instant::now()));
+    if let Some(path) = &store.snapshot_path {
+        save_snapshot(path, status);
+    }
+    previous.map(|(previous, _)| meaco::diff_status(&previous, status)).unwrap_or_default()
+}
+
+/// What changed in `status` since a client was last given one, which it
+/// becomes.
+pub fn what_changed(store: &StatusStore, status: &DehumidifierStatus) -> Vec<StatusChange> {
+    let previous = store.reported.lock().expect("status lock poisoned").replace(status.clone());
+    previous.map(|previous| meaco::diff_status(&previous, status)).unwrap_or_default()
+}
+
+/// The last status read, with its age.
+pub fn last_known(store: &StatusStore) -> Option<(DehumidifierStatus, Duration)> {
+    store
+        .last
+        .lock()
+        .expect("status lock poisoned")
+        .as_ref()
+        .map(|(status, at)| (status.clone(), at.elapsed()))
+}
+
+/// The last query response, if it's younger than `ttl`.
+///
+/// Concurrent `get_status` calls coalesce without further locking here:
+/// device tools take turns, so the second finds the first's response.
+pub fn cached(store: &StatusStore, ttl: Duration) -> Option<serde_json::Value> {
+    store
+        .response
+        .lock()
+        .expect("status lock poisoned")
+        .as_ref()
+        .filter(|(_, at)| at.elapsed() < ttl)
+        .map(|(response, _)| response.clone())
+}
+
+pub fn cache(store: &StatusStore, response: &serde_json::Value) {
+    *store.response.lock().expect("status lock poisoned") = Some((response.clone(), Instant::now()));
+    store.live_unconfirmed.store(false, Ordering::Relaxed);
+}
+
+/// Forget the cached response, e.g. after a write changed the device.
+pub fn invalidate(store: &StatusStore) {
+    *store.response.lock().expect("status lock poisoned") = None;
+    store.live_unconfirmed.store(true, Ordering::Relaxed);
+}
+
+/// The status as the device last reported it, once every required DP has
+/// been heard and no write since is awaiting confirmation.
+pub fn live_status(store: &StatusStore, profile: &DeviceProfile) -> Option<DehumidifierStatus> {
+    if store.live_unconfirmed.load(Ordering::Relaxed) {
+        return None;
+    }
+    parse_live(store, profile)
+}
+
+fn parse_live(store: &StatusStore, profile: &DeviceProfile) -> Option<DehumidifierStatus> {
+    let live = store.live.lock().expect("status lock poisoned");
+    meaco::parse_status(&serde_json::Value::Object(live.clone()), profile).ok()
+}
+
+/// Fold every DP change on the bus into the live status, publishing the
+/// fields that change. The socket's reader already ingests every frame
+/// the device sends between requests; this keeps what it hears.
+pub fn spawn_tracker(
+    bus: &EventBus,
+    store: Arc<StatusStore>,
+    profile: DeviceProfile,
+) -> tokio::task::JoinHandle<()> {
+    let mut sub = events::subscribe(bus, "status_tracker");
+    let bus = bus.clone();
+
+    tokio::spawn(async move {
+        while let Some(event) = events::next_event(&mut sub).await {
+            let DeviceEvent::StatusChanged { changed } = event else {
+                continue;
+            };
+            store.live.lock().expect("status lock poisoned").extend(changed);
+            if let Some(status) = parse_live(&store, &profile) {
+                let changes = remember(&store, &status);
+                if !changes.is_empty() {
+                    events::publish(&bus, DeviceEvent::FieldsChanged { changes });
+                }
+            }
+        }
+    })
+}
+
+/// Query the device every `polling.interval`, keeping each response as
+/// the cached one and its status as the last-known, publishing the fields
+/// that change, and adding a reading to `recent`. Polls the device
+/// doesn't answer are skipped.
+pub fn spawn_poller(
+    conn: Arc<TuyaConnection>,
+    store: Arc<StatusStore>,
+    profile: DeviceProfile,
+    polling: Polling,
+    recent: Option<Arc<RecentReadings>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(polling.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick is immediate, while startup is still connecting
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let deadline = Deadline {
+                at: Some(Instant::now() + POLL_TIMEOUT),
+                ..Deadline::default()
+            };
+            if polling.refresh_first
+                && let Err(e) = tuya_connection::refresh_dps(&conn, &deadline).await
+            {
+                tracing::debug!(device = %conn.name, "Skipping poll, UPDATEDPS failed: {e}");
+                continue;
+            }
+            let response = match tuya_connection::query_dps(&conn, &deadline).await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::debug!(device = %conn.name, "Skipping poll: {e}");
+                    continue;
+                }
+            };
+            cache(&store, &response);
+            let dps = response.get("dps").unwrap_or(&response);
+            if let Some(recent) = &recent {
+                let now = watchdog::unix_secs(SystemTime::now());
+                history::remember(recent, history::reading_from_dps(dps, now, &profile));
+            }
+            match meaco::parse_status(dps, &profile) {
+                Ok(status) => {
+                    let changes = remember(&store, &status);
+                    if !changes.is_empty() {
+                        events::publish(&conn.events, DeviceEvent::FieldsChanged { changes });
+                    }
+                }
+                Err(e) => tracing::debug!(device = %conn.name, "Polled DPs don't parse: {e}"),
+            }
+        }
+    })
+}
+
+/// "6 minutes ago", to the coarsest unit that fits.
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (n, unit) = match secs {
+        0..60 => (secs, "second"),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    format!("{n} {unit}{plural} ago")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_use_the_coarsest_unit() {
+        assert_eq!(format_age(Duration::from_secs(1)), "1 second ago");
+        assert_eq!(format_age(Duration::from_secs(59)), "59 seconds ago");
+        assert_eq!(format_age(Duration::from_secs(6 * 60 + 30)), "6 minutes ago");
+        assert_eq!(format_age(Duration::from_secs(3600)), "1 hour ago");
+        assert_eq!(format_age(Duration::from_secs(3 * 86400)), "3 days ago");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn responses_are_cached_for_the_ttl() {
+        let store = StatusStore::default();
+        let ttl = Duration::from_secs(2);
+        cache(&store, &serde_json::json!({"dps": {"1": true}}));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(cached(&store, ttl).is_some());
+        assert!(cached(&store, Duration::ZERO).is_none());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(cached(&store, ttl).is_none());
+
+        cache(&store, &serde_json::json!({}));
+        invalidate(&store);
+        assert!(cached(&store, ttl).is_none());
+    }
+
+    #[tokio::test]
+    async fn tracker_builds_status_from_pushes() {
+        let bus = events::new_bus();
+        let store = Arc::new(StatusStore::default());
+        let profile = DeviceProfile::default();
+        let mut fields = events::subscribe(&bus, "test");
+        let tracker = spawn_tracker(&bus, store.clone(), profile.clone());
+
+        let changed = |dps: serde_json::Value| DeviceEvent::StatusChanged {
+            changed: dps.as_object().unwrap().clone(),
+        };
+        // A humidity push alone isn't a status yet; the rest arrives later
+        events::publish(&bus, changed(serde_json::json!({"16": 61})));
+        events::publish(&bus, changed(serde_json::json!({"1": true, "2": 50})));
+        events::publish(&bus, changed(serde_json::json!({"16": 58})));
+
+        // Only the last push changes a status that already existed
+        let changes = loop {
+            if let Some(DeviceEvent::FieldsChanged { changes }) = events::next_event(&mut fields).await {
+                break changes;
+            }
+        };
+        tracker.abort();
+        assert_eq!(meaco::format_changes(&changes), "current_humidity 61 → 58");
+
+        let status = live_status(&store, &profile).unwrap();
+        assert_eq!((status.power, status.target_humidity), (true, 50));
+        assert_eq!(status.current_humidity, Some(58));
+        assert!(last_known(&store).is_some());
+
+        // After a write, only a query's answer will do
+        invalidate(&store);
+        assert!(live_status(&store, &profile).is_none());
+        cache(&store, &serde_json::json!({}));
+        assert!(live_status(&store, &profile).is_some());
+    }
+}
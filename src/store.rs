@@ -0,0 +1,374 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use rusqlite::{Connection, params};
+
+use crate::audit::AuditEntry;
+use crate::config::StoreConfig;
+use crate::events::{self, DeviceEvent, EventBus};
+use crate::history::{self, Reading};
+use crate::meaco::{self, DehumidifierStatus};
+use crate::status::{self, StatusStore};
+use crate::watchdog;
+
+// -- SQLite store --
+//
+// With `[store] path`, what hearth sees goes into a SQLite database that
+// outlives restarts: the status every few minutes and whenever it
+// changes, each change and fault, the connection dropping and coming
+// back, and every audited command. That answers "why was the room damp on
+// Tuesday?" after the fact: the readings say what the humidity did, the
+// events and commands say why. get_history reads its readings from here
+// when it's set, get_events the rest.
+//
+// Writes are small and rare, so they happen inline on whichever task
+// sees the event rather than on a writer thread of their own.
+
+/// How often rows past the retention period are dropped while running.
+const PRUNE_EVERY: Duration = Duration::from_secs(86_400);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS snapshots (
+        t INTEGER NOT NULL,
+        humidity INTEGER,
+        target INTEGER,
+        power INTEGER,
+        fault INTEGER,
+        temperature REAL,
+        status TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS snapshots_t ON snapshots (t);
+    CREATE TABLE IF NOT EXISTS events (
+        t INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        summary TEXT NOT NULL,
+        detail TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_t ON events (t);
+    CREATE TABLE IF NOT EXISTS audit (
+        t INTEGER NOT NULL,
+        source TEXT NOT NULL,
+        client TEXT,
+        arguments TEXT NOT NULL,
+        writes TEXT NOT NULL,
+        outcome TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS audit_t ON audit (t);
+";
+
+pub struct Store {
+    db: Mutex<Connection>,
+}
+
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store").finish_non_exhaustive()
+    }
+}
+
+/// A change, fault, connection event or command, as get_events lists it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEvent {
+    /// Unix seconds.
+    pub t: u64,
+    /// "change", "fault", "disconnected", "reconnected" or "command".
+    pub kind: String,
+    pub summary: String,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Open { path: String, reason: String },
+    Sqlite(rusqlite::Error),
+}
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Open { path, reason } => write!(f, "Can't open store {path}: {reason}"),
+            StoreError::Sqlite(e) => write!(f, "Store error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+/// Open the database at `path`, creating it and its tables if need be.
+pub fn open(path: &str) -> Result<Store, StoreError> {
+    let failed = |e: rusqlite::Error| StoreError::Open {
+        path: path.to_owned(),
+        reason: e.to_string(),
+    };
+    let db = Connection::open(path).map_err(failed)?;
+    db.execute_batch(SCHEMA).map_err(failed)?;
+    Ok(Store { db: Mutex::new(db) })
+}
+
+fn db(store: &Store) -> std::sync::MutexGuard<'_, Connection> {
+    store.db.lock().expect("store lock poisoned")
+}
+
+/// Unix seconds as SQLite stores them; "forever" fits too.
+fn secs(t: u64) -> i64 {
+    i64::try_from(t).unwrap_or(i64::MAX)
+}
+
+fn json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+pub fn record_status(store: &Store, t: u64, status: &DehumidifierStatus) -> Result<(), StoreError> {
+    let r = history::reading_from_status(t, status);
+    db(store).execute(
+        "INSERT INTO snapshots (t, humidity, target, power, fault, temperature, status) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![secs(r.t), r.humidity, r.target, r.power, r.fault, r.temperature, json(status)],
+    )?;
+    Ok(())
+}
+
+pub fn record_event(store: &Store, t: u64, kind: &str, summary: &str, detail: &serde_json::Value) -> Result<(), StoreError> {
+    db(store).execute(
+        "INSERT INTO events (t, kind, summary, detail) VALUES (?1, ?2, ?3, ?4)",
+        params![secs(t), kind, summary, json(detail)],
+    )?;
+    Ok(())
+}
+
+pub fn record_audit(store: &Store, entry: &AuditEntry) -> Result<(), StoreError> {
+    db(store).execute(
+        "INSERT INTO audit (t, source, client, arguments, writes, outcome) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![secs(entry.t), entry.source, entry.client, json(&entry.arguments), json(&entry.writes), entry.outcome],
+    )?;
+    Ok(())
+}
+
+/// Every snapshot with `from <= t < to`, oldest first, as readings.
+pub fn readings(store: &Store, from: u64, to: u64) -> Result<Vec<Reading>, StoreError> {
+    let db = db(store);
+    let mut query = db.prepare_cached(
+        "SELECT t, humidity, target, power, fault, temperature FROM snapshots WHERE t >= ?1 AND t < ?2 ORDER BY t",
+    )?;
+    let rows = query.query_map(params![secs(from), secs(to)], |row| {
+        Ok(Reading {
+            t: row.get::<_, i64>(0)?.max(0) as u64,
+            humidity: row.get(1)?,
+            target: row.get(2)?,
+            power: row.get(3)?,
+            fault: row.get(4)?,
+            temperature: row.get(5)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Events and commands with `from <= t < to`, oldest first.
+pub fn events(store: &Store, from: u64, to: u64) -> Result<Vec<StoredEvent>, StoreError> {
+    let db = db(store);
+    let mut query = db.prepare_cached(
+        "SELECT t, kind, summary FROM events WHERE t >= ?1 AND t < ?2
+         UNION ALL
+         SELECT t, 'command', source || coalesce(' by ' || client, '') || ': ' || outcome
+           FROM audit WHERE t >= ?1 AND t < ?2
+         ORDER BY t",
+    )?;
+    let rows = query.query_map(params![secs(from), secs(to)], |row| {
+        Ok(StoredEvent {
+            t: row.get::<_, i64>(0)?.max(0) as u64,
+            kind: row.get(1)?,
+            summary: row.get(2)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// When the oldest snapshot was taken, if there is one.
+pub fn first_reading(store: &Store) -> Result<Option<u64>, StoreError> {
+    let t: Option<i64> = db(store).query_row("SELECT min(t) FROM snapshots", [], |row| row.get(0))?;
+    Ok(t.map(|t| t.max(0) as u64))
+}
+
+/// Drop the snapshots with `from <= t < to`, once they're kept elsewhere.
+/// Returns how many went.
+pub fn drop_readings(store: &Store, from: u64, to: u64) -> Result<usize, StoreError> {
+    Ok(db(store).execute("DELETE FROM snapshots WHERE t >= ?1 AND t < ?2", params![secs(from), secs(to)])?)
+}
+
+/// Drop every row older than `keep_since`. Returns how many went.
+pub fn prune(store: &Store, keep_since: u64) -> Result<usize, StoreError> {
+    let db = db(store);
+    let mut dropped = 0;
+    for table in ["snapshots", "events", "audit"] {
+        dropped += db.execute(&format!("DELETE FROM {table} WHERE t < ?1"), [secs(keep_since)])?;
+    }
+    Ok(dropped)
+}
+
+/// One line per event, oldest first.
+pub fn format_events(events: &[StoredEvent]) -> String {
+    if events.is_empty() {
+        return "No events recorded in that window".into();
+    }
+    events
+        .iter()
+        .map(|e| format!("{} {}: {}", history::format_utc(e.t), e.kind, e.summary))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn prune_expired(store: &Store, retention_days: u64) {
+    let now = watchdog::unix_secs(SystemTime::now());
+    match prune(store, now.saturating_sub(retention_days.saturating_mul(86_400))) {
+        Ok(0) => {}
+        Ok(dropped) => tracing::info!(dropped, "Pruned old rows from the store"),
+        Err(e) => tracing::warn!("{e}"),
+    }
+}
+
+/// Store what happens on `bus`, and the last-known status every
+/// `snapshot_secs` while it's current, pruning past the retention period
+/// at startup and daily.
+pub fn spawn_recorder(
+    bus: &EventBus,
+    store: Arc<Store>,
+    last_status: Arc<StatusStore>,
+    config: &StoreConfig,
+) -> tokio::task::JoinHandle<()> {
+    let mut sub = events::subscribe(bus, "store");
+    let every = Duration::from_secs(config.snapshot_secs.max(1));
+    let retention_days = config.retention_days;
+    prune_expired(&store, retention_days);
+
+    tokio::spawn(async move {
+        let mut snapshots = tokio::time::interval(every);
+        snapshots.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut pruning = tokio::time::interval(PRUNE_EVERY);
+        pruning.tick().await;
+        // A status is saved once, however many ticks see it
+        let mut saved_at = None;
+
+        loop {
+            let event = tokio::select! {
+                event = events::next_event(&mut sub) => match event {
+                    Some(event) => Some(event),
+                    None => return,
+                },
+                _ = snapshots.tick() => None,
+                _ = pruning.tick() => {
+                    prune_expired(&store, retention_days);
+                    continue;
+                }
+            };
+            let now = watchdog::unix_secs(SystemTime::now());
+            let recorded = match event {
+                // A new status: save it along with what changed
+                Some(DeviceEvent::FieldsChanged { changes }) => {
+                    let detail = serde_json::to_value(&changes).unwrap_or_default();
+                    record_event(&store, now, "change", &meaco::format_changes(&changes), &detail)
+                        .and_then(|()| save_status(&store, &last_status, Duration::MAX, &mut saved_at))
+                }
+                Some(DeviceEvent::Fault { bitmap, active }) => {
+                    let summary = if active.is_empty() { "cleared".to_owned() } else { active.join(", ") };
+                    let detail = serde_json::json!({ "bitmap": bitmap, "active": active });
+                    record_event(&store, now, "fault", &summary, &detail)
+                }
+                Some(DeviceEvent::Disconnected { reason }) => {
+                    record_event(&store, now, "disconnected", &reason, &serde_json::Value::Null)
+                }
+                Some(DeviceEvent::Reconnected { address }) => {
+                    record_event(&store, now, "reconnected", &address, &serde_json::Value::Null)
+                }
+                Some(_) => Ok(()),
+                // Only a status read since the last tick is worth saving
+                None => save_status(&store, &last_status, every, &mut saved_at),
+            };
+            if let Err(e) = recorded {
+                tracing::warn!("{e}");
+            }
+        }
+    })
+}
+
+/// Save the last-known status, stamped with when it was read, unless it's
+/// older than `max_age` or already saved.
+fn save_status(
+    store: &Store,
+    last_status: &StatusStore,
+    max_age: Duration,
+    saved_at: &mut Option<u64>,
+) -> Result<(), StoreError> {
+    let Some((status, age)) = status::last_known(last_status) else {
+        return Ok(());
+    };
+    let t = watchdog::unix_secs(SystemTime::now()).saturating_sub(age.as_secs());
+    // Seconds round, so the same read can come out a second apart
+    if age > max_age || saved_at.is_some_and(|saved| saved.abs_diff(t) <= 1) {
+        return Ok(());
+    }
+    record_status(store, t, &status)?;
+    *saved_at = Some(t);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditWrite;
+
+    #[test]
+    fn keeps_snapshots_events_and_commands_across_opens() {
+        let path = std::env::temp_dir().join(format!("hearth-store-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path = path.to_str().unwrap();
+
+        let status: DehumidifierStatus = serde_json::from_value(serde_json::json!({
+            "power": true,
+            "target_humidity": 50,
+            "current_humidity": 64,
+            "faults": [{"bit": 1, "code": "E2", "explanation": "", "action": ""}],
+        }))
+        .unwrap();
+        {
+            let store = open(path).unwrap();
+            record_status(&store, 100, &status).unwrap();
+            record_event(&store, 200, "fault", "E2", &serde_json::json!({"bitmap": 2})).unwrap();
+            record_audit(&store, &AuditEntry {
+                t: 300,
+                source: "set_power".into(),
+                client: Some("claude".into()),
+                arguments: serde_json::json!({"on": false}),
+                writes: vec![AuditWrite { dps: serde_json::json!({"1": false}), response: None, error: None }],
+                outcome: "ok".into(),
+            })
+            .unwrap();
+            record_event(&store, 5000, "disconnected", "timed out", &serde_json::Value::Null).unwrap();
+        }
+
+        // As after a restart
+        let store = open(path).unwrap();
+        let readings = readings(&store, 0, 1000).unwrap();
+        assert_eq!(readings, [Reading {
+            t: 100,
+            humidity: Some(64),
+            target: Some(50),
+            power: Some(true),
+            fault: Some(2),
+            temperature: None,
+        }]);
+        let listed = format_events(&events(&store, 0, 1000).unwrap());
+        assert_eq!(
+            listed,
+            "1970-01-01 00:03 UTC fault: E2\n1970-01-01 00:05 UTC command: set_power by claude: ok"
+        );
+
+        assert_eq!(prune(&store, 1000).unwrap(), 3);
+        assert_eq!(events(&store, 0, u64::MAX).unwrap().len(), 1);
+        drop(store);
+        std::fs::remove_file(path).unwrap();
+    }
+}
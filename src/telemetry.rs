@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Meter, MeterProvider as _};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+
+use crate::config::OtlpConfig;
+use crate::prometheus::{self, Sample, Sources};
+
+// -- OpenTelemetry export --
+//
+// With `[otlp] endpoint` set, each MCP tool call is exported as a trace:
+// a `tool_call` span with child spans for building each frame, its TCP
+// round trip and parsing the reply. A `traceparent` in the request's
+// `_meta`, or on the HTTP transport its header, makes the call part of
+// the agent's own trace. The connection metrics /metrics serves go out
+// as OTel instruments on an interval. Both travel over OTLP/HTTP.
+
+/// Trace context fields, as W3C Trace Context names them.
+const CONTEXT_FIELDS: [&str; 2] = ["traceparent", "tracestate"];
+
+/// The exporters, kept to flush on the way out.
+pub struct Telemetry {
+    traces: SdkTracerProvider,
+    metrics: SdkMeterProvider,
+}
+
+#[derive(Debug)]
+pub struct TelemetryError(String);
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Can't set up OTLP export: {}", self.0)
+    }
+}
+
+impl std::error::Error for TelemetryError {}
+
+/// Exporters for `[otlp]`, or None when no endpoint is set.
+pub fn init(config: &OtlpConfig) -> Result<Option<Telemetry>, TelemetryError> {
+    let Some(endpoint) = &config.endpoint else {
+        return Ok(None);
+    };
+    let base = endpoint.trim_end_matches('/');
+    let failed = |e: opentelemetry_otlp::ExporterBuildError| TelemetryError(e.to_string());
+    let resource = Resource::builder().with_service_name(config.service_name.clone()).build();
+
+    let spans = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{base}/v1/traces"))
+        .build()
+        .map_err(failed)?;
+    let traces = SdkTracerProvider::builder()
+        .with_batch_exporter(spans)
+        .with_resource(resource.clone())
+        .build();
+
+    let exporter = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{base}/v1/metrics"))
+        .build()
+        .map_err(failed)?;
+    let reader = PeriodicReader::builder(exporter)
+        .with_interval(Duration::from_secs(config.metrics_interval_secs))
+        .build();
+    let metrics = SdkMeterProvider::builder().with_reader(reader).with_resource(resource).build();
+
+    Ok(Some(Telemetry { traces, metrics }))
+}
+
+/// The tracer the tracing subscriber exports spans with.
+pub fn tracer(telemetry: &Telemetry) -> Tracer {
+    telemetry.traces.tracer("hearth")
+}
+
+fn observe_counter(meter: &Meter, sources: &Sources, name: &'static str, description: &'static str, unit: &'static str, value: fn(&Sample) -> u64) {
+    let sources = sources.clone();
+    meter
+        .u64_observable_counter(name)
+        .with_description(description)
+        .with_unit(unit)
+        .with_callback(move |observer| {
+            for sample in prometheus::collect(&sources) {
+                observer.observe(value(&sample), &[KeyValue::new("device", sample.diagnostics.device.clone())]);
+            }
+        })
+        .build();
+}
+
+fn observe_gauge(meter: &Meter, sources: &Sources, name: &'static str, description: &'static str, unit: &'static str, value: fn(&Sample) -> f64) {
+    let sources = sources.clone();
+    meter
+        .f64_observable_gauge(name)
+        .with_description(description)
+        .with_unit(unit)
+        .with_callback(move |observer| {
+            for sample in prometheus::collect(&sources) {
+                observer.observe(value(&sample), &[KeyValue::new("device", sample.diagnostics.device.clone())]);
+            }
+        })
+        .build();
+}
+
+/// Export every connection's counters, labelled by device, each interval.
+pub fn register_metrics(telemetry: &Telemetry, sources: &Sources) {
+    let meter = telemetry.metrics.meter("hearth");
+    observe_counter(&meter, sources, "hearth.requests", "Requests sent to the device", "{request}", |s| s.diagnostics.metrics.requests);
+    observe_counter(&meter, sources, "hearth.timeouts", "Requests the device didn't answer in time", "{request}", |s| s.diagnostics.metrics.timeouts);
+    observe_counter(&meter, sources, "hearth.reconnects", "Sockets replaced after failing", "{connection}", |s| s.diagnostics.metrics.reconnects);
+    observe_counter(&meter, sources, "hearth.crc_errors", "Frames received with a bad CRC", "{frame}", |s| s.diagnostics.metrics.crc_errors);
+    observe_counter(&meter, sources, "hearth.received", "Bytes read from the device", "By", |s| s.diagnostics.metrics.bytes_in);
+    observe_counter(&meter, sources, "hearth.sent", "Bytes written to the device", "By", |s| s.diagnostics.metrics.bytes_out);
+    observe_gauge(&meter, sources, "hearth.up", "Whether hearth has a working socket to the device", "1", |s| {
+        if prometheus::is_up(s) { 1.0 } else { 0.0 }
+    });
+    observe_gauge(&meter, sources, "hearth.latency.last", "Round trip of the last answered request", "ms", |s| s.diagnostics.metrics.latency_last_ms);
+    observe_gauge(&meter, sources, "hearth.latency.mean", "Mean round trip of answered requests", "ms", |s| s.diagnostics.metrics.latency_mean_ms);
+    observe_gauge(&meter, sources, "hearth.latency.max", "Slowest round trip of an answered request", "ms", |s| s.diagnostics.metrics.latency_max_ms);
+    observe_gauge(&meter, sources, "hearth.heartbeat.failures", "Heartbeats failed in a row", "{heartbeat}", |s| {
+        s.diagnostics.metrics.heartbeat_failures as f64
+    });
+}
+
+/// The trace a tool call continues, from `traceparent` and `tracestate`
+/// in its `_meta` or, failing that, its HTTP headers. An empty context
+/// when there are none: the call starts a trace of its own.
+pub fn remote_parent(
+    meta: &serde_json::Map<String, serde_json::Value>,
+    headers: Option<&hyper::http::HeaderMap>,
+) -> opentelemetry::Context {
+    let mut carrier = HashMap::new();
+    for field in CONTEXT_FIELDS {
+        let from_meta = meta.get(field).and_then(|v| v.as_str());
+        let from_header = headers.and_then(|h| h.get(field)).and_then(|v| v.to_str().ok());
+        if let Some(value) = from_meta.or(from_header) {
+            carrier.insert(field.to_owned(), value.to_owned());
+        }
+    }
+    TraceContextPropagator::new().extract(&carrier)
+}
+
+/// `make()`'s span inside a tool call, and none outside one, so
+/// heartbeats and pollers don't each start a trace.
+pub fn child_span(make: impl FnOnce() -> tracing::Span) -> tracing::Span {
+    if tracing::Span::current().is_none() {
+        tracing::Span::none()
+    } else {
+        make()
+    }
+}
+
+/// Export what's left. The exporters block, so off the runtime's threads.
+pub async fn shutdown(telemetry: Telemetry) {
+    let flushed = tokio::task::spawn_blocking(move || {
+        let traces = telemetry.traces.shutdown();
+        let metrics = telemetry.metrics.shutdown();
+        traces.and(metrics)
+    })
+    .await;
+    match flushed {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!("OTLP export didn't finish: {e}"),
+        Err(e) => tracing::warn!("OTLP export didn't finish: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceContextExt;
+
+    #[test]
+    fn calls_continue_the_callers_trace() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let trace_id = |cx: &opentelemetry::Context| cx.span().span_context().trace_id().to_string();
+
+        let meta = serde_json::json!({ "traceparent": traceparent });
+        let cx = remote_parent(meta.as_object().unwrap(), None);
+        assert_eq!(trace_id(&cx), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(cx.span().span_context().is_remote());
+
+        let mut headers = hyper::http::HeaderMap::new();
+        headers.insert("traceparent", traceparent.replace("4bf9", "0000").parse().unwrap());
+        let cx = remote_parent(&serde_json::Map::new(), Some(&headers));
+        assert_eq!(trace_id(&cx), "00002f3577b34da6a3ce929d0e0e4736");
+
+        // Neither: a trace of its own
+        assert!(!remote_parent(&serde_json::Map::new(), None).span().span_context().is_valid());
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use rmcp::ServiceExt;
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use rmcp::transport::{StreamableHttpServerConfig, StreamableHttpService};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::TransportConfig;
+use crate::server::HearthServer;
+
+// -- Streamable HTTP transport --
+//
+// Serves the same tools over the network so hearth can run next to the
+// device (on a Pi, say) and be used from several machines. Each MCP
+// session gets its own server handle sharing the one device connection;
+// device calls from all sessions still take turns. There's no
+// authentication: bind to loopback or a trusted network.
+
+/// Serve MCP over HTTP until `stop` is cancelled. Any path answers.
+pub async fn serve_http(
+    server: HearthServer,
+    config: &TransportConfig,
+    stop: CancellationToken,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(config.http_bind).await?;
+    tracing::info!(address = %listener.local_addr()?, "Hearth running on streamable HTTP");
+
+    let service = TowerToHyperService::new(StreamableHttpService::new(
+        move || Ok(server.for_session()),
+        Arc::new(LocalSessionManager::default()),
+        StreamableHttpServerConfig {
+            cancellation_token: stop.child_token(),
+            ..Default::default()
+        },
+    ));
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            _ = stop.cancelled() => return Ok(()),
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                // Running out of file descriptors and the like: the
+                // listener itself is fine, so keep going
+                Err(e) => {
+                    tracing::warn!("HTTP accept failed: {e}");
+                    continue;
+                }
+            },
+        };
+
+        tracing::debug!(%peer, "HTTP client connected");
+        let service = service.clone();
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            let conn = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service);
+            tokio::pin!(conn);
+            tokio::select! {
+                result = conn.as_mut() => {
+                    if let Err(e) = result {
+                        tracing::debug!(%peer, "HTTP connection ended: {e}");
+                    }
+                }
+                // Let a response being written finish, then close
+                _ = stop.cancelled() => {
+                    conn.as_mut().graceful_shutdown();
+                    let _ = conn.await;
+                }
+            }
+        });
+    }
+}
+
+// -- Unix socket transport --
+//
+// For a supervisor running several MCP servers on one host: no stdio
+// pipes to own and no TCP port to expose. Who may connect is down to the
+// socket file's permissions.
+
+/// Serve MCP over a Unix socket until `stop` is cancelled, one session
+/// per connection. Removes the socket file when done.
+#[cfg(unix)]
+pub async fn serve_unix(
+    server: HearthServer,
+    config: &TransportConfig,
+    stop: CancellationToken,
+) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = &config.unix_path;
+    remove_stale_socket(path).await?;
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(config.unix_mode))?;
+    tracing::info!(path = %path.display(), mode = format!("{:o}", config.unix_mode), "Hearth running on Unix socket");
+
+    loop {
+        let stream = tokio::select! {
+            _ = stop.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Unix socket accept failed: {e}");
+                    continue;
+                }
+            },
+        };
+
+        let server = server.for_session();
+        let stop = stop.child_token();
+        tokio::spawn(async move {
+            match server.serve_with_ct(stream, stop).await {
+                Ok(service) => {
+                    let reason = service.waiting().await;
+                    tracing::debug!(?reason, "Unix socket client gone");
+                }
+                Err(e) => tracing::warn!("Unix socket client failed to initialize: {e}"),
+            }
+        });
+    }
+
+    drop(listener);
+    if let Err(e) = std::fs::remove_file(path) {
+        tracing::warn!(path = %path.display(), "Can't remove socket: {e}");
+    }
+    Ok(())
+}
+
+/// A socket file left behind by a hearth that crashed would make bind
+/// fail. Remove it, unless something still answers on it.
+#[cfg(unix)]
+async fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {}
+        // Not ours to delete: let bind report it
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    if tokio::net::UnixStream::connect(path).await.is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        ));
+    }
+    tracing::info!(path = %path.display(), "Removing stale socket");
+    std::fs::remove_file(path)
+}
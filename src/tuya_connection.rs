@@ -1,22 +1,115 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{Mutex, MutexGuard, oneshot};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use crate::config::MeacoConfig;
+use crate::audit;
+use crate::command_queue::{self, CommandQueue};
+use crate::config::{self, ConnectionConfig, MeacoConfig, RequestPolicy, SeqnoPolicy, SocketConfig};
+use crate::discovery::{self, DiscoveredDevices};
+use crate::events::{self, DeviceEvent, EventBus};
+use crate::device_profile::DeviceProfile;
+use crate::metrics::{self, ConnectionMetrics, MetricsSnapshot};
+use crate::telemetry;
 use crate::tuya_protocol::{
-    self, TuyaFrame, TuyaMessage, ProtocolError,
-    HEADER_SIZE, PREFIX,
-    CMD_HEART_BEAT, CMD_CONTROL, CMD_DP_QUERY,
+    self, PayloadAssembler, TuyaFrame, TuyaMessage, ProtocolError,
+    HEADER_SIZE, MAX_FRAME_LENGTH, PREFIX,
+    CMD_HEART_BEAT, CMD_CONTROL, CMD_DP_QUERY, CMD_CONTROL_NEW, CMD_STATUS, CMD_UPDATEDPS,
 };
 
+/// Connection lifecycle, surfaced via `DeviceEvent::StateChanged`.
+///
+/// Connecting → Ready on first connect. Ready ⇄ Degraded as requests time
+/// out or succeed on a live socket. A dead socket goes Reconnecting, then
+/// Ready again or Closed if no candidate address answers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Connecting,
+    Ready,
+    Degraded,
+    Reconnecting,
+    Closed,
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Ready => "ready",
+            ConnectionState::Degraded => "degraded",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Closed => "closed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// State shared between request senders and the background reader task.
+struct Shared {
+    /// The device's name, for log lines.
+    name: String,
+    local_key: [u8; 16],
+    /// Requests awaiting a response, keyed by the seqno they were sent with,
+    /// along with the command a response has to carry.
+    pending: std::sync::Mutex<HashMap<u32, (u32, oneshot::Sender<TuyaMessage>)>>,
+    trace_frames: AtomicBool,
+    state: std::sync::Mutex<ConnectionState>,
+    events: EventBus,
+    metrics: ConnectionMetrics,
+    /// When the device last sent us anything at all.
+    last_heard: std::sync::Mutex<Option<Instant>>,
+    /// Every DP value the device has reported. Outlives the reader task, so a
+    /// reconnect's first status only reports what actually changed.
+    known_values: std::sync::Mutex<serde_json::Map<String, serde_json::Value>>,
+}
+
 /// Shared connection data. Not an object — just data that systems operate on.
+///
+/// The socket is established lazily on first use and re-established after
+/// failures. Writes go through `writer`; a background reader task owns the read half,
+/// parses every inbound frame and routes it to the waiter registered under
+/// its seqno. Several requests can be in flight at once.
 pub struct TuyaConnection {
-    pub stream: Mutex<TcpStream>,
+    /// None until first connected.
+    writer: Mutex<Option<OwnedWriteHalf>>,
+    reader: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+    shared: Arc<Shared>,
     pub device_id: String,
+    /// What logs, notifications and metrics call the device: its
+    /// configured name, or else its id.
+    pub name: String,
     pub local_key: [u8; 16],
+    /// Candidate addresses in preference order; reconnects rotate through
+    /// them. The configured ones come first, then at most one address
+    /// learned from discovery after the device moved.
+    addresses: std::sync::RwLock<Vec<String>>,
+    configured_addresses: AtomicUsize,
+    active_address: AtomicUsize,
+    /// Where discovery last heard the device, consulted when no known
+    /// address answers.
+    resolver: std::sync::OnceLock<Arc<DiscoveredDevices>>,
+    /// Survives reconnects unless the policy says otherwise.
     seqno: AtomicU32,
+    seqno_policy: SeqnoPolicy,
+    /// Query with an explicit DP list (CONTROL_NEW) rather than DP_QUERY.
+    /// Flips whenever the device refuses one form and answers the other.
+    list_query: AtomicBool,
+    /// Every DP the profile declares, for list queries.
+    known_dps: Vec<String>,
+    /// Replaced when the config is reloaded. The rate limit is the
+    /// queue's, fixed at startup.
+    policy: std::sync::RwLock<ConnectionConfig>,
+    queue: CommandQueue,
+    /// Status changes and connection transitions, for anyone to subscribe to.
+    pub events: EventBus,
 }
 
 impl std::fmt::Debug for TuyaConnection {
@@ -27,11 +120,39 @@ impl std::fmt::Debug for TuyaConnection {
     }
 }
 
+/// How long a caller is prepared to wait, and a way for it to give up early.
+///
+/// A request never outlives its deadline: queueing, connecting, the
+/// response wait and retry backoff all stop when it passes or the token
+/// is cancelled. The default waits as long as the command policy allows.
+#[derive(Debug, Clone, Default)]
+pub struct Deadline {
+    pub at: Option<Instant>,
+    pub cancel: CancellationToken,
+}
+
+impl Drop for TuyaConnection {
+    /// The reader task only holds the shared half, so stop it explicitly
+    /// rather than leaving it parked on a socket nobody can write to.
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.lock().expect("reader lock poisoned").take() {
+            reader.abort();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ConnectionError {
     Tcp(std::io::Error),
     Protocol(ProtocolError),
     Timeout,
+    Closed,
+    /// The caller's deadline passed before the device answered.
+    DeadlineExceeded,
+    /// The caller cancelled the request.
+    Cancelled,
+    /// The device answered, but refused the request.
+    Rejected(String),
 }
 
 impl std::fmt::Display for ConnectionError {
@@ -40,6 +161,10 @@ impl std::fmt::Display for ConnectionError {
             ConnectionError::Tcp(e) => write!(f, "TCP error: {e}"),
             ConnectionError::Protocol(e) => write!(f, "Protocol error: {e}"),
             ConnectionError::Timeout => write!(f, "Connection timed out"),
+            ConnectionError::Closed => write!(f, "Connection closed by device"),
+            ConnectionError::DeadlineExceeded => write!(f, "Request deadline exceeded"),
+            ConnectionError::Cancelled => write!(f, "Request cancelled"),
+            ConnectionError::Rejected(reason) => write!(f, "Device rejected the request: {reason}"),
         }
     }
 }
@@ -68,30 +193,387 @@ fn local_key_from_config(config: &MeacoConfig) -> [u8; 16] {
     key
 }
 
-/// Connect to the Tuya device over TCP port 6668.
-pub async fn connect(config: &MeacoConfig) -> Result<Arc<TuyaConnection>, ConnectionError> {
-    let addr = format!("{}:6668", config.device_ip);
+/// Open a TCP stream to one address on port 6668.
+async fn open_stream(device: &str, address: &str, policy: &ConnectionConfig) -> Result<TcpStream, ConnectionError> {
+    // Config addresses are validated at load; a discovered one could still be junk
+    let addr = config::device_endpoint(address, 6668).map_err(|e| {
+        ConnectionError::Tcp(std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))
+    })?;
 
     let stream = tokio::time::timeout(
-        std::time::Duration::from_secs(5),
-        TcpStream::connect(&addr),
+        Duration::from_millis(policy.connect_timeout_ms),
+        connect_from(&addr, policy),
     )
     .await
     .map_err(|_| ConnectionError::Timeout)?
     .map_err(ConnectionError::Tcp)?;
 
-    tracing::info!(addr = %addr, "Connected to Tuya device");
+    // A socket without its options still works, just less well
+    if let Err(e) = configure_socket(&stream, &policy.socket) {
+        tracing::warn!(device = %device, addr = %addr, "Failed to set socket options: {e}");
+    }
 
-    Ok(Arc::new(TuyaConnection {
-        stream: Mutex::new(stream),
+    tracing::info!(device = %device, addr = %addr, "Connected to Tuya device");
+    Ok(stream)
+}
+
+/// Connect, from the configured local address or interface if there is one.
+async fn connect_from(addr: &str, policy: &ConnectionConfig) -> std::io::Result<TcpStream> {
+    if policy.bind_address.is_none() && policy.interface.is_none() {
+        return TcpStream::connect(addr).await;
+    }
+
+    let mut last_err = std::io::Error::new(
+        std::io::ErrorKind::AddrNotAvailable,
+        format!("{addr} has no address reachable from the configured bind address"),
+    );
+    for target in tokio::net::lookup_host(addr).await? {
+        let socket = if target.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+
+        if let Some(ip) = policy.bind_address {
+            if ip.is_ipv4() != target.is_ipv4() {
+                continue;
+            }
+            socket.bind(std::net::SocketAddr::new(ip, 0))?;
+        }
+        if let Some(interface) = &policy.interface {
+            bind_interface(&socket, interface)?;
+        }
+
+        match socket.connect(target).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_interface(socket: &TcpSocket, interface: &str) -> std::io::Result<()> {
+    socket2::SockRef::from(socket).bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_interface(_socket: &TcpSocket, _interface: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on Linux",
+    ))
+}
+
+fn configure_socket(stream: &TcpStream, config: &SocketConfig) -> std::io::Result<()> {
+    let socket = socket2::SockRef::from(stream);
+
+    socket.set_tcp_nodelay(config.nodelay)?;
+    if config.keepalive_secs > 0 {
+        let keepalive = socket2::TcpKeepalive::new()
+            .with_time(Duration::from_secs(config.keepalive_secs))
+            .with_interval(Duration::from_secs(config.keepalive_interval_secs.max(1)));
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+    if let Some(secs) = config.linger_secs {
+        socket.set_linger(Some(Duration::from_secs(secs)))?;
+    }
+    Ok(())
+}
+
+/// Try each address once, starting at `start` and wrapping around.
+/// Returns the index that answered, or the last error seen.
+async fn open_any(
+    device: &str,
+    addresses: &[String],
+    start: usize,
+    policy: &ConnectionConfig,
+) -> Result<(usize, TcpStream), ConnectionError> {
+    let mut last_err = ConnectionError::Timeout;
+
+    for offset in 0..addresses.len() {
+        let index = (start + offset) % addresses.len();
+        match open_stream(device, &addresses[index], policy).await {
+            Ok(stream) => return Ok((index, stream)),
+            Err(e) => {
+                tracing::warn!(device = %device, address = %addresses[index], "Connect failed: {e}");
+                last_err = e;
+            }
+        }
+    }
+
+    Err(last_err)
+}
+
+/// Build the connection data for a device without touching the network.
+/// The socket is opened on first use (or by `connect`), so hearth can start
+/// while the device is offline.
+pub fn new(config: &MeacoConfig, policy: &ConnectionConfig, profile: &DeviceProfile) -> Arc<TuyaConnection> {
+    let local_key = local_key_from_config(config);
+    let events = events::new_bus();
+    let addresses = config.candidate_addresses();
+    let name = config.name.clone().unwrap_or_else(|| config.device_id.clone());
+
+    Arc::new(TuyaConnection {
+        writer: Mutex::new(None),
+        reader: std::sync::Mutex::new(None),
+        shared: Arc::new(Shared {
+            name: name.clone(),
+            local_key,
+            pending: Default::default(),
+            trace_frames: AtomicBool::new(false),
+            state: std::sync::Mutex::new(ConnectionState::Connecting),
+            events: events.clone(),
+            metrics: ConnectionMetrics::default(),
+            last_heard: std::sync::Mutex::new(None),
+            known_values: Default::default(),
+        }),
         device_id: config.device_id.to_owned(),
-        local_key: local_key_from_config(config),
+        name,
+        local_key,
+        configured_addresses: AtomicUsize::new(addresses.len()),
+        addresses: std::sync::RwLock::new(addresses),
+        active_address: AtomicUsize::new(0),
+        resolver: std::sync::OnceLock::new(),
         seqno: AtomicU32::new(1),
-    }))
+        seqno_policy: config.seqno_on_reconnect,
+        list_query: AtomicBool::new(false),
+        known_dps: profile.indices(),
+        policy: std::sync::RwLock::new(policy.clone()),
+        queue: command_queue::new_queue(&policy.rate_limit),
+        events,
+    })
+}
+
+/// Connect to the Tuya device over TCP port 6668 if not already connected,
+/// trying `device_ip` first and then each fallback address in order.
+pub async fn connect(conn: &TuyaConnection) -> Result<(), ConnectionError> {
+    writer_ready(conn).await.map(drop)
+}
+
+/// Lock the writer, establishing the socket first if there isn't a live one.
+async fn writer_ready(
+    conn: &TuyaConnection,
+) -> Result<MutexGuard<'_, Option<OwnedWriteHalf>>, ConnectionError> {
+    let mut writer = conn.writer.lock().await;
+
+    // Connecting/Reconnecting while we hold the lock means an earlier
+    // attempt was abandoned part way through, so start it again
+    let live = matches!(state(conn), ConnectionState::Ready | ConnectionState::Degraded);
+    if writer.is_none() || !live {
+        let start = conn.active_address.load(Ordering::Relaxed);
+        establish(conn, &mut writer, start).await?;
+    }
+
+    Ok(writer)
+}
+
+/// Open a socket (rotating through addresses from `start`), start its
+/// reader task and install the write half. Replaces any previous socket.
+async fn establish(
+    conn: &TuyaConnection,
+    writer: &mut Option<OwnedWriteHalf>,
+    start: usize,
+) -> Result<(), ConnectionError> {
+    let reconnecting = writer.is_some();
+    set_state(&conn.shared, if reconnecting {
+        ConnectionState::Reconnecting
+    } else {
+        ConnectionState::Connecting
+    });
+
+    let addresses = conn.addresses.read().expect("addresses lock poisoned").clone();
+    let (index, stream) = match open_any(&conn.name, &addresses, start, &current_policy(conn)).await {
+        Ok(opened) => opened,
+        Err(e) => match rediscover(conn, &addresses).await {
+            Some(opened) => opened,
+            None => {
+                set_state(&conn.shared, ConnectionState::Closed);
+                return Err(e);
+            }
+        },
+    };
+    attach(conn, writer, stream, index);
+
+    if reconnecting {
+        metrics::record_reconnect(&conn.shared.metrics);
+        if conn.seqno_policy == SeqnoPolicy::Reset {
+            conn.seqno.store(1, Ordering::Relaxed);
+        }
+        tracing::debug!(
+            device = %conn.name,
+            seqno = conn.seqno.load(Ordering::Relaxed),
+            policy = ?conn.seqno_policy,
+            "Seqno after reconnect"
+        );
+        events::publish(&conn.events, DeviceEvent::Reconnected {
+            address: active_address(conn),
+        });
+    }
+    Ok(())
+}
+
+/// Last resort when every known address is dead: DHCP may have moved the
+/// device, so try wherever its LAN broadcasts now come from. An address
+/// that answers replaces any earlier discovered one.
+async fn rediscover(conn: &TuyaConnection, tried: &[String]) -> Option<(usize, TcpStream)> {
+    let ip = discovery::resolve_ip(conn.resolver.get()?, &conn.device_id)?;
+    if tried.contains(&ip) {
+        return None;
+    }
+
+    tracing::info!(device = %conn.name, address = %ip, "Known addresses unreachable, trying address from discovery");
+    let stream = match open_stream(&conn.name, &ip, &current_policy(conn)).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::warn!(device = %conn.name, address = %ip, "Discovered address unreachable: {e}");
+            return None;
+        }
+    };
+
+    let mut addresses = conn.addresses.write().expect("addresses lock poisoned");
+    addresses.truncate(conn.configured_addresses.load(Ordering::Relaxed));
+    addresses.push(ip);
+    Some((addresses.len() - 1, stream))
+}
+
+/// Let reconnects fall back to the address discovery last saw the device at.
+pub fn set_resolver(conn: &TuyaConnection, registry: Arc<DiscoveredDevices>) {
+    let _ = conn.resolver.set(registry);
+}
+
+/// Split an open stream, swap in a fresh reader task and mark the
+/// connection ready.
+fn attach(
+    conn: &TuyaConnection,
+    writer: &mut Option<OwnedWriteHalf>,
+    stream: TcpStream,
+    index: usize,
+) {
+    let (read_half, write_half) = stream.into_split();
+
+    // Anything still waiting was sent on the old stream and will never be answered
+    fail_pending(&conn.shared);
+
+    let reader = spawn_reader(read_half, conn.shared.clone());
+    let old_reader = conn.reader.lock().expect("reader lock poisoned").replace(reader);
+    if let Some(old_reader) = old_reader {
+        old_reader.abort();
+    }
+
+    *writer = Some(write_half);
+    conn.active_address.store(index, Ordering::Relaxed);
+    metrics::record_connected(&conn.shared.metrics);
+    set_state(&conn.shared, ConnectionState::Ready);
+}
+
+fn set_state(shared: &Shared, to: ConnectionState) {
+    let from = std::mem::replace(&mut *shared.state.lock().expect("state lock poisoned"), to);
+    if from != to {
+        events::publish(&shared.events, DeviceEvent::StateChanged { from, to });
+    }
+}
+
+/// Where the connection currently is in its lifecycle.
+pub fn state(conn: &TuyaConnection) -> ConnectionState {
+    *conn.shared.state.lock().expect("state lock poisoned")
+}
+
+/// Replace a dead stream. Starts from the address after the one that just
+/// failed, so a device that moved gets found without waiting on the old IP.
+pub async fn reconnect(conn: &TuyaConnection) -> Result<(), ConnectionError> {
+    let mut writer = conn.writer.lock().await;
+    let failed = conn.active_address.load(Ordering::Relaxed);
+    establish(conn, &mut writer, failed + 1).await
+}
+
+/// Close the socket cleanly. The FIN lets the device free its single
+/// connection slot now, rather than when its idle timer notices.
+pub async fn close(conn: &TuyaConnection) {
+    let mut writer = conn.writer.lock().await;
+    if let Some(mut stream) = writer.take()
+        && let Err(e) = stream.shutdown().await
+    {
+        tracing::debug!(device = %conn.name, "Socket shutdown failed: {e}");
+    }
+    if let Some(reader) = conn.reader.lock().expect("reader lock poisoned").take() {
+        reader.abort();
+    }
+    fail_pending(&conn.shared);
+    set_state(&conn.shared, ConnectionState::Closed);
+}
+
+/// Drop every pending sender so their waiters see `ConnectionError::Closed`.
+fn fail_pending(shared: &Shared) {
+    shared.pending.lock().expect("pending lock poisoned").clear();
+}
+
+/// Point-in-time view of the connection for diagnostics output.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Diagnostics {
+    /// Which device these are, by name: the label to keep them apart by.
+    pub device: String,
+    pub state: ConnectionState,
+    pub address: String,
+    /// The seqno the next request will be sent with.
+    pub next_seqno: u32,
+    pub seqno_policy: SeqnoPolicy,
+    /// Events lost to lag, per event bus consumer.
+    pub dropped_events: std::collections::BTreeMap<&'static str, u64>,
+    pub metrics: MetricsSnapshot,
+}
+
+pub fn diagnostics(conn: &TuyaConnection) -> Diagnostics {
+    Diagnostics {
+        device: conn.name.clone(),
+        state: state(conn),
+        address: active_address(conn),
+        next_seqno: conn.seqno.load(Ordering::Relaxed),
+        seqno_policy: conn.seqno_policy,
+        dropped_events: events::dropped_events(&conn.events),
+        metrics: metrics::snapshot(&conn.shared.metrics),
+    }
+}
+
+/// Round trips so far, bucketed for the metrics endpoint.
+pub fn latency_histogram(conn: &TuyaConnection) -> metrics::LatencyHistogram {
+    metrics::latency_histogram(&conn.shared.metrics)
+}
+
+/// The address the current stream is connected to.
+pub fn active_address(conn: &TuyaConnection) -> String {
+    let addresses = conn.addresses.read().expect("addresses lock poisoned");
+    addresses[conn.active_address.load(Ordering::Relaxed) % addresses.len()].clone()
+}
+
+/// Toggle annotated hexdumps of every frame at `trace` level.
+pub fn set_frame_tracing(conn: &TuyaConnection, enabled: bool) {
+    conn.shared.trace_frames.store(enabled, Ordering::Relaxed);
+}
+
+fn trace_outbound(frame: &TuyaFrame, json_payload: &[u8]) {
+    tracing::trace!(
+        "TX {}\nplaintext: {}\n{}",
+        tuya_protocol::describe_frame(&frame.bytes),
+        String::from_utf8_lossy(json_payload),
+        tuya_protocol::hexdump(&frame.bytes),
+    );
+}
+
+fn trace_inbound(raw: &[u8], parsed: &Result<TuyaMessage, ProtocolError>) {
+    let decrypted = match parsed {
+        Ok(msg) => format!(
+            "retcode={} plaintext: {}",
+            msg.retcode,
+            String::from_utf8_lossy(&msg.payload)
+        ),
+        Err(e) => format!("parse failed: {e}"),
+    };
+    tracing::trace!(
+        "RX {}\n{decrypted}\n{}",
+        tuya_protocol::describe_frame(raw),
+        tuya_protocol::hexdump(raw),
+    );
 }
 
 /// Write a frame to the TCP stream.
-async fn write_frame(stream: &mut TcpStream, frame: &TuyaFrame) -> Result<(), ConnectionError> {
+async fn write_frame(stream: &mut OwnedWriteHalf, frame: &TuyaFrame) -> Result<(), ConnectionError> {
     stream.write_all(&frame.bytes).await?;
     stream.flush().await?;
     Ok(())
@@ -100,8 +582,10 @@ async fn write_frame(stream: &mut TcpStream, frame: &TuyaFrame) -> Result<(), Co
 /// Read a complete frame from the TCP stream.
 /// Reads the 16-byte header first to get the length, then reads the rest.
 async fn read_frame(
-    stream: &mut TcpStream,
+    stream: &mut OwnedReadHalf,
     local_key: &[u8; 16],
+    trace: bool,
+    metrics: &ConnectionMetrics,
 ) -> Result<TuyaMessage, ConnectionError> {
     // Read header (16 bytes)
     let mut header = [0u8; HEADER_SIZE];
@@ -115,85 +599,717 @@ async fn read_frame(
 
     // Extract length to know how much more to read
     let length = u32::from_be_bytes([header[12], header[13], header[14], header[15]]) as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(ProtocolError::FrameTooLarge(length).into());
+    }
 
     // Read the rest: retcode + payload + crc + suffix
     let mut rest = vec![0u8; length];
     stream.read_exact(&mut rest).await?;
+    metrics::record_bytes_in(metrics, HEADER_SIZE + length);
 
     // Reassemble complete frame for parsing
     let mut full_frame = Vec::with_capacity(HEADER_SIZE + length);
     full_frame.extend_from_slice(&header);
     full_frame.extend_from_slice(&rest);
 
-    tuya_protocol::parse_frame(&full_frame, local_key).map_err(ConnectionError::Protocol)
+    let parsed = tuya_protocol::parse_frame(&full_frame, local_key);
+    if trace {
+        trace_inbound(&full_frame, &parsed);
+    }
+
+    parsed.map_err(ConnectionError::Protocol)
+}
+
+/// Whether the stream is still aligned on frame boundaries after an error.
+/// Bad CRC or undecryptable payloads only spoil one frame; a bad prefix or
+/// absurd length means we've lost our place in the byte stream.
+fn recoverable(e: &ConnectionError) -> bool {
+    matches!(
+        e,
+        ConnectionError::Protocol(
+            ProtocolError::CrcMismatch { .. }
+                | ProtocolError::InvalidSuffix(_)
+                | ProtocolError::DecryptionFailed
+                | ProtocolError::PayloadTooShort
+        )
+    )
+}
+
+/// Background task: read every inbound frame, reassemble split payloads and
+/// hand each complete message to whoever is waiting on its seqno — if it
+/// answers the command they sent. Exits when the stream dies, failing all
+/// outstanding requests.
+fn spawn_reader(mut stream: OwnedReadHalf, shared: Arc<Shared>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut assembler = PayloadAssembler::default();
+
+        loop {
+            let trace = shared.trace_frames.load(Ordering::Relaxed);
+            let frame = match read_frame(&mut stream, &shared.local_key, trace, &shared.metrics).await {
+                Ok(frame) => {
+                    *shared.last_heard.lock().expect("last heard lock poisoned") = Some(Instant::now());
+                    frame
+                }
+                Err(e) if recoverable(&e) => {
+                    if let ConnectionError::Protocol(ProtocolError::CrcMismatch { .. }) = e {
+                        metrics::record_crc_error(&shared.metrics);
+                    }
+                    tracing::warn!(device = %shared.name, "Dropping bad frame: {e}");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!(device = %shared.name, "Reader stopped: {e}");
+                    events::publish(&shared.events, DeviceEvent::Disconnected {
+                        reason: e.to_string(),
+                    });
+                    set_state(&shared, ConnectionState::Closed);
+                    break;
+                }
+            };
+
+            let seqno = frame.seqno;
+            let Some(msg) = tuya_protocol::reassemble(&mut assembler, frame) else {
+                tracing::debug!(device = %shared.name, seqno, "Partial payload, awaiting next frame");
+                continue;
+            };
+
+            // Query replies, control ACKs and unsolicited pushes all carry DPS
+            let changed = merge_dps(
+                &mut shared.known_values.lock().expect("known values lock poisoned"),
+                &msg.payload,
+            );
+            if !changed.is_empty() {
+                events::publish(&shared.events, DeviceEvent::StatusChanged { changed });
+            }
+
+            let waiter = {
+                let mut pending = shared.pending.lock().expect("pending lock poisoned");
+                // A heartbeat ACK or push can reuse a seqno we're waiting on;
+                // leave the waiter for the frame that actually answers it.
+                match pending.get(&msg.seqno) {
+                    Some((cmd, _)) if answers(*cmd, msg.cmd) => pending.remove(&msg.seqno),
+                    _ => None,
+                }
+            };
+            match waiter {
+                // The waiter may have timed out in the meantime — nothing to do
+                Some((_, tx)) => {
+                    let _ = tx.send(msg);
+                }
+                None => tracing::debug!(
+                    device = %shared.name,
+                    seqno = msg.seqno,
+                    cmd = tuya_protocol::cmd_name(msg.cmd),
+                    "Unsolicited frame"
+                ),
+            }
+        }
+
+        fail_pending(&shared);
+    })
+}
+
+/// Whether a reply with command `reply` can answer a request sent as `sent`.
+/// A CONTROL_NEW query may come back as a STATUS frame.
+fn answers(sent: u32, reply: u32) -> bool {
+    reply == sent || (sent == CMD_CONTROL_NEW && reply == CMD_STATUS)
+}
+
+/// Merge any `dps` object in a payload into the known values, returning
+/// the entries that are new or differ.
+fn merge_dps(
+    known: &mut serde_json::Map<String, serde_json::Value>,
+    payload: &[u8],
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut changed = serde_json::Map::new();
+
+    let Ok(serde_json::Value::Object(mut body)) = serde_json::from_slice(payload) else {
+        return changed;
+    };
+    let Some(serde_json::Value::Object(dps)) = body.remove("dps") else {
+        return changed;
+    };
+
+    for (key, value) in dps {
+        if known.get(&key) != Some(&value) {
+            known.insert(key.clone(), value.clone());
+            changed.insert(key, value);
+        }
+    }
+    changed
 }
 
-/// Send a frame and receive the response.
-/// Holds the stream lock for the duration to ensure request-response pairing.
+/// Retry/timeout policy for a command type.
+fn policy_for(conn: &TuyaConnection, cmd: u32) -> RequestPolicy {
+    let policy = conn.policy.read().expect("policy lock poisoned");
+    match cmd {
+        CMD_HEART_BEAT => policy.heartbeat,
+        CMD_CONTROL => policy.control,
+        _ => policy.query,
+    }
+}
+
+fn current_policy(conn: &TuyaConnection) -> ConnectionConfig {
+    conn.policy.read().expect("policy lock poisoned").clone()
+}
+
+/// Use new timeouts, retries and socket settings from the next request
+/// or socket on.
+pub fn set_policy(conn: &TuyaConnection, policy: &ConnectionConfig) {
+    *conn.policy.write().expect("policy lock poisoned") = policy.clone();
+}
+
+/// Replace the configured addresses, e.g. after the config changed. Any
+/// address learned from discovery is dropped with them. Returns whether
+/// the socket's address is among the new ones; if not, the caller should
+/// close it so the next request connects to one that is.
+pub fn set_addresses(conn: &TuyaConnection, config: &MeacoConfig) -> bool {
+    let current = active_address(conn);
+    let new = config.candidate_addresses();
+    let still_valid = new.contains(&current);
+
+    let mut addresses = conn.addresses.write().expect("addresses lock poisoned");
+    conn.configured_addresses.store(new.len(), Ordering::Relaxed);
+    conn.active_address.store(new.iter().position(|a| *a == current).unwrap_or(0), Ordering::Relaxed);
+    *addresses = new;
+    still_valid
+}
+
+/// Whether a failed attempt is worth repeating. Protocol errors mean the
+/// device answered with something we can't use; trying again won't help.
+fn retryable(e: &ConnectionError) -> bool {
+    matches!(
+        e,
+        ConnectionError::Timeout | ConnectionError::Closed | ConnectionError::Tcp(_)
+    )
+}
+
+/// Run `fut` unless the caller's deadline passes or it cancels first.
+async fn within<T>(
+    deadline: &Deadline,
+    fut: impl Future<Output = Result<T, ConnectionError>>,
+) -> Result<T, ConnectionError> {
+    let bounded = async {
+        match deadline.at {
+            Some(at) => tokio::time::timeout_at(at, fut)
+                .await
+                .unwrap_or(Err(ConnectionError::DeadlineExceeded)),
+            None => fut.await,
+        }
+    };
+
+    tokio::select! {
+        result = bounded => result,
+        () = deadline.cancel.cancelled() => Err(ConnectionError::Cancelled),
+    }
+}
+
+/// Send a request and wait for its response, retrying with exponential
+/// backoff according to the command type's policy. Gives up early if the
+/// caller's deadline passes or it cancels.
 pub async fn send_receive(
     conn: &TuyaConnection,
     cmd: u32,
     json_payload: &[u8],
+    deadline: &Deadline,
 ) -> Result<TuyaMessage, ConnectionError> {
-    let seqno = next_seqno(conn);
-    let frame = tuya_protocol::build_frame(seqno, cmd, json_payload, &conn.local_key);
+    let policy = policy_for(conn, cmd);
+    let timeout = Duration::from_millis(policy.timeout_ms);
+    let mut backoff = Duration::from_millis(policy.backoff_ms);
+    let mut attempt = 0;
 
-    let mut stream = conn.stream.lock().await;
+    loop {
+        match send_once(conn, cmd, json_payload, timeout, deadline).await {
+            Err(e) if attempt < policy.retries && retryable(&e) => {
+                attempt += 1;
+                tracing::debug!(
+                    device = %conn.name,
+                    cmd = tuya_protocol::cmd_name(cmd),
+                    attempt,
+                    "Request failed ({e}), retrying in {backoff:?}"
+                );
+                within(deadline, async {
+                    tokio::time::sleep(backoff).await;
+                    Ok(())
+                })
+                .await?;
+                backoff = (backoff * 2).min(Duration::from_millis(policy.max_backoff_ms));
+            }
+            result => return result,
+        }
+    }
+}
 
-    write_frame(&mut stream, &frame).await?;
+/// Send a frame and wait for the response routed back by seqno.
+/// The write lock is only held while writing, so independent requests
+/// (e.g. a heartbeat during a slow DP query) don't block each other.
+///
+/// The deadline applies everywhere except the write itself — abandoning
+/// a half-written frame would desync the stream for everyone.
+async fn send_once(
+    conn: &TuyaConnection,
+    cmd: u32,
+    json_payload: &[u8],
+    timeout: Duration,
+    deadline: &Deadline,
+) -> Result<TuyaMessage, ConnectionError> {
+    within(deadline, async {
+        match cmd {
+            CMD_CONTROL => command_queue::acquire_control(&conn.queue).await,
+            CMD_HEART_BEAT => command_queue::yield_to_commands(&conn.queue).await,
+            _ => {}
+        }
+        Ok(())
+    })
+    .await?;
 
-    // Read response, with a timeout
-    let msg = tokio::time::timeout(
-        std::time::Duration::from_secs(5),
-        read_frame(&mut stream, &conn.local_key),
-    )
-    .await
-    .map_err(|_| ConnectionError::Timeout)??;
+    let cmd_name = tuya_protocol::cmd_name(cmd);
+    let round_trip = telemetry::child_span(|| {
+        tracing::info_span!("round_trip", device = %conn.name, cmd = cmd_name, seqno = tracing::field::Empty)
+    });
+    let result = async {
+        // Establishing the socket fails any pending waiters, so only register
+        // once we hold a live writer. Number the frame only then too: a
+        // reconnect under SeqnoPolicy::Reset restarts the count.
+        let mut writer = within(deadline, writer_ready(conn)).await?;
+        let seqno = next_seqno(conn);
+        tracing::Span::current().record("seqno", seqno);
+        let frame = telemetry::child_span(|| tracing::info_span!("build_frame", cmd = cmd_name, seqno))
+            .in_scope(|| tuya_protocol::build_frame(seqno, cmd, json_payload, &conn.local_key));
+        if conn.shared.trace_frames.load(Ordering::Relaxed) {
+            trace_outbound(&frame, json_payload);
+        }
+        let (tx, rx) = oneshot::channel();
+        conn.shared.pending.lock().expect("pending lock poisoned").insert(seqno, (cmd, tx));
+
+        let stream = writer.as_mut().expect("writer_ready installs a stream");
+        let written = write_frame(stream, &frame).await;
+        drop(writer);
+        metrics::record_request(&conn.shared.metrics, frame.bytes.len());
+        let sent_at = Instant::now();
+
+        let result = match written {
+            Ok(()) => {
+                within(deadline, async {
+                    match tokio::time::timeout(timeout, rx).await {
+                        Ok(Ok(msg)) => Ok(msg),
+                        Ok(Err(_)) => Err(ConnectionError::Closed),
+                        Err(_) => Err(ConnectionError::Timeout),
+                    }
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        if result.is_err() {
+            conn.shared.pending.lock().expect("pending lock poisoned").remove(&seqno);
+        }
+
+        match result {
+            Ok(_) => {
+                let latency = sent_at.elapsed();
+                metrics::record_latency(&conn.shared.metrics, latency);
+                set_state(&conn.shared, ConnectionState::Ready);
+                // Heartbeats have a trace-level line of their own
+                if cmd != CMD_HEART_BEAT {
+                    tracing::debug!(
+                        device = %conn.name,
+                        cmd = cmd_name,
+                        seqno,
+                        latency_ms = latency.as_secs_f64() * 1000.0,
+                        "Request answered"
+                    );
+                }
+            }
+            Err(ConnectionError::Timeout) => {
+                metrics::record_timeout(&conn.shared.metrics);
+                set_state(&conn.shared, ConnectionState::Degraded);
+            }
+            Err(_) => {}
+        }
+        result
+    }
+    .instrument(round_trip)
+    .await;
+
+    // A dead socket won't come back on its own — reconnect now so the next
+    // request doesn't fail the same way, but still report this failure.
+    if let Err(ConnectionError::Tcp(_) | ConnectionError::Closed) = result {
+        tracing::warn!(device = %conn.name, "Connection lost, reconnecting");
+        if let Err(e) = reconnect(conn).await {
+            tracing::warn!(device = %conn.name, "Reconnect failed: {e}");
+        }
+    }
+
+    result
+}
+
+/// Query all data points from the device. Firmware revisions differ on
+/// which query form they answer, so a refusal is retried once in the other
+/// form, and whichever works is used first from then on.
+pub async fn query_dps(
+    conn: &TuyaConnection,
+    deadline: &Deadline,
+) -> Result<serde_json::Value, ConnectionError> {
+    let list_query = conn.list_query.load(Ordering::Relaxed);
+    let mut msg = send_query(conn, list_query, deadline).await?;
 
-    Ok(msg)
+    if tuya_protocol::is_rejection(&msg) {
+        tracing::info!(
+            device = %conn.name,
+            reason = %rejection_reason(&msg),
+            "Device refused the status query, retrying in the other form"
+        );
+        msg = send_query(conn, !list_query, deadline).await?;
+        if tuya_protocol::is_rejection(&msg) {
+            return Err(ConnectionError::Rejected(rejection_reason(&msg)));
+        }
+        conn.list_query.store(!list_query, Ordering::Relaxed);
+    }
+
+    Ok(parse_payload(conn, &msg))
 }
 
-/// Query all data points from the device.
-pub async fn query_dps(conn: &TuyaConnection) -> Result<serde_json::Value, ConnectionError> {
-    let json = tuya_protocol::build_dp_query_json(&conn.device_id);
-    let msg = send_receive(conn, CMD_DP_QUERY, &json).await?;
+async fn send_query(
+    conn: &TuyaConnection,
+    list_query: bool,
+    deadline: &Deadline,
+) -> Result<TuyaMessage, ConnectionError> {
+    if list_query {
+        let json = tuya_protocol::build_dp_list_query_json(&conn.device_id, &conn.known_dps);
+        send_receive(conn, CMD_CONTROL_NEW, &json, deadline).await
+    } else {
+        let json = tuya_protocol::build_dp_query_json(&conn.device_id);
+        send_receive(conn, CMD_DP_QUERY, &json, deadline).await
+    }
+}
 
-    let response: serde_json::Value =
-        serde_json::from_slice(&msg.payload).unwrap_or(serde_json::Value::Null);
+/// A reply's JSON payload, or null if it has none.
+fn parse_payload(conn: &TuyaConnection, msg: &TuyaMessage) -> serde_json::Value {
+    telemetry::child_span(|| tracing::info_span!("parse", device = %conn.name, bytes = msg.payload.len()))
+        .in_scope(|| serde_json::from_slice(&msg.payload).unwrap_or(serde_json::Value::Null))
+}
 
-    Ok(response)
+fn rejection_reason(msg: &TuyaMessage) -> String {
+    match std::str::from_utf8(&msg.payload) {
+        Ok(text) if !text.is_empty() => format!("{text} (retcode {})", msg.retcode),
+        _ => format!("retcode {}", msg.retcode),
+    }
 }
 
 /// Set data points on the device.
 pub async fn set_dps(
     conn: &TuyaConnection,
     dps: serde_json::Value,
+    deadline: &Deadline,
 ) -> Result<serde_json::Value, ConnectionError> {
     let json = tuya_protocol::build_control_json(&conn.device_id, &dps);
-    let msg = send_receive(conn, CMD_CONTROL, &json).await?;
+    // Published first: the device's status push can beat its ACK back
+    events::publish(&conn.events, DeviceEvent::ControlSent { dps: dps.clone() });
+    let result = send_receive(conn, CMD_CONTROL, &json, deadline).await.map(|msg| parse_payload(conn, &msg));
+
+    audit::record_write(&dps, &result);
+    result
+}
+
+/// Ask the device to refresh its DPs (UPDATEDPS). Not every firmware
+/// acknowledges it, so nothing waits for a reply: what it refreshes
+/// arrives as pushes, ahead of a query sent after it.
+pub async fn refresh_dps(conn: &TuyaConnection, deadline: &Deadline) -> Result<(), ConnectionError> {
+    let json = tuya_protocol::build_update_dps_json(&conn.known_dps);
+    // Numbered only once the writer is live, as in send_once
+    let mut writer = within(deadline, writer_ready(conn)).await?;
+    let frame = tuya_protocol::build_frame(next_seqno(conn), CMD_UPDATEDPS, &json, &conn.local_key);
+    if conn.shared.trace_frames.load(Ordering::Relaxed) {
+        trace_outbound(&frame, &json);
+    }
+    let stream = writer.as_mut().expect("writer_ready installs a stream");
+    write_frame(stream, &frame).await?;
+    metrics::record_request(&conn.shared.metrics, frame.bytes.len());
+    Ok(())
+}
 
-    let response: serde_json::Value =
-        serde_json::from_slice(&msg.payload).unwrap_or(serde_json::Value::Null);
+/// One heartbeat round trip.
+pub async fn ping(conn: &TuyaConnection, deadline: &Deadline) -> Result<(), ConnectionError> {
+    let json = tuya_protocol::build_heartbeat_json();
+    send_receive(conn, CMD_HEART_BEAT, &json, deadline).await.map(|_| ())
+}
 
-    Ok(response)
+/// When the device last sent a frame, on this socket or an earlier one.
+pub fn last_heard(conn: &TuyaConnection) -> Option<Instant> {
+    *conn.shared.last_heard.lock().expect("last heard lock poisoned")
 }
 
-/// Spawn a heartbeat task that pings the device every `interval_secs` seconds.
-pub fn spawn_heartbeat(
-    conn: Arc<TuyaConnection>,
-    interval_secs: u64,
-) -> tokio::task::JoinHandle<()> {
+/// Spawn a heartbeat task that pings the device every `heartbeat_secs`.
+/// A device that stops answering without closing the socket would otherwise
+/// leave every tool call to time out, so after `heartbeat_failures` misses
+/// in a row the socket is replaced. While that fails, beats come faster,
+/// backing off from `reconnect_backoff_ms`, until the device is back.
+pub fn spawn_heartbeat(conn: Arc<TuyaConnection>) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        let deadline = Deadline::default();
+        let mut failures = 0;
+        // Set while the device can't be reconnected to
+        let mut backoff: Option<Duration> = None;
 
         loop {
-            interval.tick().await;
+            // Read each time, as a reloaded config may change it
+            let policy = current_policy(&conn);
+            let max_backoff = Duration::from_millis(policy.max_reconnect_backoff_ms);
+            tokio::time::sleep(backoff.unwrap_or(Duration::from_secs(policy.heartbeat_secs.max(1)))).await;
 
-            let json = tuya_protocol::build_heartbeat_json();
-            match send_receive(&conn, CMD_HEART_BEAT, &json).await {
-                Ok(_) => tracing::trace!("Heartbeat OK"),
-                Err(e) => tracing::warn!("Heartbeat failed: {e}"),
+            let result = ping(&conn, &deadline).await;
+            metrics::record_heartbeat(&conn.shared.metrics, result.is_ok());
+            match (result, backoff) {
+                (Ok(_), _) => {
+                    failures = 0;
+                    backoff = None;
+                    tracing::trace!(device = %conn.name, "Heartbeat OK");
+                }
+                // With the socket gone, each beat is a reconnect attempt
+                (Err(e), Some(wait)) => {
+                    let wait = (wait * 2).min(max_backoff);
+                    tracing::warn!(device = %conn.name, "Reconnect failed: {e}, retrying in {wait:?}");
+                    backoff = Some(wait);
+                }
+                (Err(e), None) => {
+                    failures += 1;
+                    tracing::warn!(device = %conn.name, failures, "Heartbeat failed: {e}");
+                }
+            }
+
+            if failures >= policy.heartbeat_failures.max(1) {
+                tracing::warn!(device = %conn.name, failures, "Device unresponsive, replacing connection");
+                failures = 0;
+                set_state(&conn.shared, ConnectionState::Closed);
+                if let Err(e) = reconnect(&conn).await {
+                    let wait = Duration::from_millis(policy.reconnect_backoff_ms).min(max_backoff);
+                    tracing::warn!(device = %conn.name, "Reconnect failed: {e}, retrying in {wait:?}");
+                    backoff = Some(wait);
+                }
             }
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+
+    /// An unnamed device at `addr`, keyed with `KEY`.
+    fn test_device(addr: &str) -> MeacoConfig {
+        MeacoConfig {
+            name: None,
+            device_ip: addr.into(),
+            device_id: "test".into(),
+            local_key: String::from_utf8(KEY.to_vec()).unwrap(),
+            fallback_addresses: Vec::new(),
+            seqno_on_reconnect: SeqnoPolicy::Continue,
+            polling: Default::default(),
+        }
+    }
+
+    /// Device-style response frame: header, retcode, encrypted payload, footer.
+    fn response(seqno: u32, cmd: u32, payload: &[u8]) -> Vec<u8> {
+        let encrypted = tuya_protocol::encrypt_payload(payload, &KEY);
+        let length =
+            (tuya_protocol::RETCODE_SIZE + encrypted.len() + tuya_protocol::FOOTER_SIZE) as u32;
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&PREFIX.to_be_bytes());
+        frame.extend_from_slice(&seqno.to_be_bytes());
+        frame.extend_from_slice(&cmd.to_be_bytes());
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(&encrypted);
+        let crc = crc32fast::hash(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame.extend_from_slice(&tuya_protocol::SUFFIX.to_be_bytes());
+        frame
+    }
+
+    /// The same, but with a plaintext error string as payload.
+    fn rejection(seqno: u32, cmd: u32, reason: &[u8]) -> Vec<u8> {
+        let length = (tuya_protocol::RETCODE_SIZE + reason.len() + tuya_protocol::FOOTER_SIZE) as u32;
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&PREFIX.to_be_bytes());
+        frame.extend_from_slice(&seqno.to_be_bytes());
+        frame.extend_from_slice(&cmd.to_be_bytes());
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(reason);
+        let crc = crc32fast::hash(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame.extend_from_slice(&tuya_protocol::SUFFIX.to_be_bytes());
+        frame
+    }
+
+    /// Read one outbound frame and return its seqno.
+    async fn read_request(stream: &mut TcpStream) -> u32 {
+        read_request_cmd(stream).await.0
+    }
+
+    /// Read one outbound frame and return its seqno and command.
+    async fn read_request_cmd(stream: &mut TcpStream) -> (u32, u32) {
+        let mut header = [0u8; HEADER_SIZE];
+        stream.read_exact(&mut header).await.unwrap();
+        let length = u32::from_be_bytes([header[12], header[13], header[14], header[15]]);
+        let mut rest = vec![0u8; length as usize];
+        stream.read_exact(&mut rest).await.unwrap();
+        let word = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        (word(4), word(8))
+    }
+
+    #[tokio::test]
+    async fn responses_are_routed_by_seqno() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Fake device: answers two requests in reverse order, with an
+        // unsolicited status push and a stray heartbeat ACK reusing the
+        // first request's seqno in between.
+        let device = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let first = read_request(&mut stream).await;
+            let second = read_request(&mut stream).await;
+            stream.write_all(&response(second, CMD_CONTROL, b"{\"second\":true}")).await.unwrap();
+            stream.write_all(&response(0, tuya_protocol::CMD_STATUS, b"{\"dps\":{}}")).await.unwrap();
+            stream.write_all(&response(first, CMD_HEART_BEAT, b"")).await.unwrap();
+            stream.write_all(&response(first, CMD_DP_QUERY, b"{\"first\":true}")).await.unwrap();
+            // Hold the socket open until the client is done
+            let _ = stream.read(&mut [0u8; 1]).await;
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let conn = new(&test_device("127.0.0.1"), &ConnectionConfig::default(), &DeviceProfile::default());
+        // Unnamed, it goes by its id
+        assert_eq!(diagnostics(&conn).device, "test");
+        attach(&conn, &mut *conn.writer.lock().await, stream, 0);
+
+        let deadline = Deadline::default();
+        let (a, b) = tokio::join!(
+            send_receive(&conn, CMD_DP_QUERY, b"{}", &deadline),
+            async {
+                // Make sure the second request goes out second
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                send_receive(&conn, CMD_CONTROL, b"{}", &deadline).await
+            },
+        );
+
+        assert_eq!(a.unwrap().payload, b"{\"first\":true}");
+        assert_eq!(b.unwrap().payload, b"{\"second\":true}");
+        drop(conn);
+        device.abort();
+    }
+
+    #[tokio::test]
+    async fn caller_deadline_cuts_the_wait_short() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Fake device: reads the request and never answers
+        let device = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await;
+            let _ = stream.read(&mut [0u8; 1]).await;
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let conn = new(&test_device("127.0.0.1"), &ConnectionConfig::default(), &DeviceProfile::default());
+        attach(&conn, &mut *conn.writer.lock().await, stream, 0);
+
+        let deadline = Deadline {
+            at: Some(Instant::now() + Duration::from_millis(100)),
+            ..Deadline::default()
+        };
+        let start = Instant::now();
+        let result = send_receive(&conn, CMD_DP_QUERY, b"{}", &deadline).await;
+
+        assert!(matches!(result, Err(ConnectionError::DeadlineExceeded)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(conn.shared.pending.lock().unwrap().is_empty());
+        // The device was never slow by its own policy
+        assert_eq!(state(&conn), ConnectionState::Ready);
+        drop(conn);
+        device.abort();
+    }
+
+    #[tokio::test]
+    async fn refused_query_falls_back_to_the_dp_list_form() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Fake device: refuses DP_QUERY in the clear, answers CONTROL_NEW
+        // with a STATUS frame.
+        let device = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut commands = Vec::new();
+            for _ in 0..3 {
+                let (seqno, cmd) = read_request_cmd(&mut stream).await;
+                commands.push(cmd);
+                let reply = match cmd {
+                    CMD_DP_QUERY => rejection(seqno, cmd, b"data format error"),
+                    _ => response(seqno, CMD_STATUS, b"{\"dps\":{\"1\":true}}"),
+                };
+                stream.write_all(&reply).await.unwrap();
+            }
+            commands
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let conn = new(&test_device("127.0.0.1"), &ConnectionConfig::default(), &DeviceProfile::default());
+        attach(&conn, &mut *conn.writer.lock().await, stream, 0);
+
+        let deadline = Deadline::default();
+        for _ in 0..2 {
+            let status = query_dps(&conn, &deadline).await.unwrap();
+            assert_eq!(status["dps"]["1"], true);
+        }
+        // Once the list form works it's tried first
+        assert_eq!(device.await.unwrap(), [CMD_DP_QUERY, CMD_CONTROL_NEW, CMD_CONTROL_NEW]);
+    }
+
+    #[tokio::test]
+    async fn reconnecting_reports_only_what_changed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Fake device: on each connection, pushes a status with the power
+        // unchanged and the humidity moving on.
+        let device = tokio::spawn(async move {
+            for humidity in [50, 55] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let status = format!("{{\"dps\":{{\"1\":true,\"6\":{humidity}}}}}");
+                stream.write_all(&response(0, CMD_STATUS, status.as_bytes())).await.unwrap();
+                let _ = stream.read(&mut [0u8; 1]).await;
+            }
+        });
+
+        let conn = new(&test_device("127.0.0.1"), &ConnectionConfig::default(), &DeviceProfile::default());
+        let mut sub = events::subscribe(&conn.events, "test");
+        let mut changes = Vec::new();
+        for _ in 0..2 {
+            let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            attach(&conn, &mut *conn.writer.lock().await, stream, 0);
+            loop {
+                if let Some(DeviceEvent::StatusChanged { changed }) = events::next_event(&mut sub).await {
+                    changes.push(serde_json::Value::Object(changed));
+                    break;
+                }
+            }
+        }
+
+        assert_eq!(changes, [
+            serde_json::json!({"1": true, "6": 50}),
+            serde_json::json!({"6": 55}),
+        ]);
+        drop(conn);
+        device.abort();
+    }
+}
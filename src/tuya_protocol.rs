@@ -1,4 +1,5 @@
 use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, BlockDecryptMut, KeyInit};
+use std::collections::HashMap;
 use std::fmt;
 
 type Aes128EcbEnc = ecb::Encryptor<aes::Aes128>;
@@ -17,14 +18,32 @@ pub const SUFFIX_SIZE: usize = 4;
 pub const FOOTER_SIZE: usize = CRC_SIZE + SUFFIX_SIZE; // 8
 pub const RETCODE_SIZE: usize = 4;
 
+// Upper bound on the length field we'll accept off the wire. Real device
+// frames are a few hundred bytes; anything bigger is garbage or hostile.
+pub const MAX_FRAME_LENGTH: usize = 64 * 1024;
+
+// Upper bound on a JSON payload reassembled from several frames.
+pub const MAX_REASSEMBLED_SIZE: usize = 256 * 1024;
+
 // Command codes
 pub const CMD_CONTROL: u32 = 0x07;
-#[allow(dead_code)]
 pub const CMD_STATUS: u32 = 0x08;
 pub const CMD_HEART_BEAT: u32 = 0x09;
 pub const CMD_DP_QUERY: u32 = 0x0A;
+pub const CMD_CONTROL_NEW: u32 = 0x0D;
 pub const CMD_UPDATEDPS: u32 = 0x12;
 
+// LAN discovery broadcasts: UDP 6666 in the clear (3.1 devices), UDP 6667
+// encrypted with a key shared by every device, md5("yGAdlopoPVldABfn").
+pub const BROADCAST_PORTS: [u16; 2] = [6666, 6667];
+const BROADCAST_KEY: [u8; 16] = [
+    0x6c, 0x1e, 0xc8, 0xe2, 0xbb, 0x9b, 0xb5, 0x9a,
+    0xb5, 0x0b, 0x0d, 0xaf, 0x64, 0x9b, 0x41, 0x0a,
+];
+
+/// The protocol version hearth speaks.
+pub const PROTOCOL_VERSION: &str = "3.3";
+
 // Version header: "3.3" + 12 zero bytes
 const VERSION_HEADER: [u8; 15] = *b"3.3\0\0\0\0\0\0\0\0\0\0\0\0";
 
@@ -54,6 +73,7 @@ pub enum ProtocolError {
     InvalidSuffix(u32),
     CrcMismatch { expected: u32, actual: u32 },
     PayloadTooShort,
+    FrameTooLarge(usize),
     DecryptionFailed,
 }
 
@@ -66,6 +86,9 @@ impl fmt::Display for ProtocolError {
                 write!(f, "CRC mismatch: expected {expected:#010x}, got {actual:#010x}")
             }
             ProtocolError::PayloadTooShort => write!(f, "Payload too short"),
+            ProtocolError::FrameTooLarge(len) => {
+                write!(f, "Frame length {len} exceeds {MAX_FRAME_LENGTH} bytes")
+            }
             ProtocolError::DecryptionFailed => write!(f, "AES decryption failed"),
         }
     }
@@ -138,6 +161,78 @@ pub fn build_frame(seqno: u32, cmd: u32, json_payload: &[u8], local_key: &[u8; 1
 /// Parse a raw byte buffer into a TuyaMessage.
 /// Validates prefix, suffix, CRC32. Decrypts payload.
 pub fn parse_frame(data: &[u8], local_key: &[u8; 16]) -> Result<TuyaMessage, ProtocolError> {
+    let (seqno, cmd, retcode, raw_payload) = unframe(data)?;
+
+    // Empty payload (e.g. heartbeat response)
+    if raw_payload.is_empty() {
+        return Ok(TuyaMessage {
+            seqno,
+            cmd,
+            retcode,
+            payload: Vec::new(),
+        });
+    }
+
+    // Check for "3.3" version header in the clear — strip before decrypting
+    let ciphertext = if raw_payload.len() >= VERSION_HEADER.len()
+        && &raw_payload[..3] == b"3.3"
+    {
+        &raw_payload[VERSION_HEADER.len()..]
+    } else {
+        raw_payload
+    };
+
+    if ciphertext.is_empty() {
+        return Ok(TuyaMessage {
+            seqno,
+            cmd,
+            retcode,
+            payload: Vec::new(),
+        });
+    }
+
+    // Some firmware rejects a request with a bare "data format error" in
+    // the clear rather than anything encrypted
+    let payload = match decrypt_payload(ciphertext, local_key) {
+        Ok(payload) => payload,
+        Err(_) if is_plaintext(raw_payload) => raw_payload.to_vec(),
+        Err(e) => return Err(e),
+    };
+
+    Ok(TuyaMessage {
+        seqno,
+        cmd,
+        retcode,
+        payload,
+    })
+}
+
+fn is_plaintext(data: &[u8]) -> bool {
+    data.iter().all(|&b| b.is_ascii_graphic() || b == b' ')
+}
+
+/// Did the device refuse the request? A nonzero retcode, or an error
+/// string where the JSON should be.
+pub fn is_rejection(msg: &TuyaMessage) -> bool {
+    msg.retcode != 0
+        || (!msg.payload.is_empty()
+            && serde_json::from_slice::<serde::de::IgnoredAny>(&msg.payload).is_err())
+}
+
+/// Decode a LAN discovery broadcast to its JSON announcement.
+pub fn parse_broadcast(data: &[u8]) -> Result<Vec<u8>, ProtocolError> {
+    let (_, _, _, raw_payload) = unframe(data)?;
+
+    // Plaintext is checked first: it can occasionally pass for validly
+    // padded ciphertext, which would turn a good announcement into noise
+    if raw_payload.first() == Some(&b'{') {
+        return Ok(raw_payload.to_vec());
+    }
+    decrypt_payload(raw_payload, &BROADCAST_KEY)
+}
+
+/// Validate framing and CRC, returning (seqno, cmd, retcode, raw payload).
+fn unframe(data: &[u8]) -> Result<(u32, u32, u32, &[u8]), ProtocolError> {
     if data.len() < HEADER_SIZE + FOOTER_SIZE {
         return Err(ProtocolError::PayloadTooShort);
     }
@@ -151,9 +246,14 @@ pub fn parse_frame(data: &[u8], local_key: &[u8; 16]) -> Result<TuyaMessage, Pro
     let seqno = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
     let cmd = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
     let length = u32::from_be_bytes([data[12], data[13], data[14], data[15]]) as usize;
+    if length > MAX_FRAME_LENGTH {
+        return Err(ProtocolError::FrameTooLarge(length));
+    }
 
+    // The length field must at least cover retcode + CRC + suffix, otherwise
+    // the offsets below would run backwards into the header.
     let total_size = HEADER_SIZE + length;
-    if data.len() < total_size {
+    if length < RETCODE_SIZE + FOOTER_SIZE || data.len() < total_size {
         return Err(ProtocolError::PayloadTooShort);
     }
 
@@ -190,42 +290,142 @@ pub fn parse_frame(data: &[u8], local_key: &[u8; 16]) -> Result<TuyaMessage, Pro
     let retcode = u32::from_be_bytes([data[16], data[17], data[18], data[19]]);
     let raw_payload = &data[HEADER_SIZE + RETCODE_SIZE..crc_offset];
 
-    // Empty payload (e.g. heartbeat response)
-    if raw_payload.is_empty() {
-        return Ok(TuyaMessage {
-            seqno,
-            cmd,
-            retcode,
-            payload: Vec::new(),
-        });
+    Ok((seqno, cmd, retcode, raw_payload))
+}
+
+// -- Pure functions: multi-frame reassembly --
+
+/// How many frames later a fragment's continuation may still arrive.
+const MAX_PARTIAL_AGE: u64 = 8;
+
+/// Fragments held at once; the oldest goes to make room.
+const MAX_PARTIALS: usize = 4;
+
+/// Partial JSON payloads, for devices that split large DP reports across
+/// several frames. Keyed by seqno and command, since pushes all reuse
+/// seqno 0.
+#[derive(Debug, Default)]
+pub struct PayloadAssembler {
+    /// Each fragment with the frame count it arrived at.
+    pub partial: HashMap<(u32, u32), (u64, Vec<u8>)>,
+    /// Frames fed in so far: a fragment's age is counted in frames.
+    frames: u64,
+}
+
+enum JsonState {
+    Complete,
+    Truncated,
+    Invalid,
+}
+
+fn json_state(payload: &[u8]) -> JsonState {
+    match serde_json::from_slice::<serde::de::IgnoredAny>(payload) {
+        Ok(_) => JsonState::Complete,
+        Err(e) if e.is_eof() => JsonState::Truncated,
+        Err(_) => JsonState::Invalid,
     }
+}
 
-    // Check for "3.3" version header in the clear — strip before decrypting
-    let ciphertext = if raw_payload.len() >= VERSION_HEADER.len()
-        && &raw_payload[..3] == b"3.3"
-    {
-        &raw_payload[VERSION_HEADER.len()..]
-    } else {
-        raw_payload
+/// Feed a decrypted message into the assembler.
+///
+/// Returns the message once its payload is complete JSON — immediately for
+/// the common single-frame case. A payload that is truncated JSON is held
+/// until the next frame with the same seqno and command arrives, for at
+/// most `MAX_PARTIAL_AGE` frames. A frame that doesn't continue the held
+/// fragment (a fresh push reusing its seqno) replaces it. Payloads that
+/// aren't JSON at all (empty ACKs, plaintext errors) pass straight through.
+pub fn reassemble(assembler: &mut PayloadAssembler, msg: TuyaMessage) -> Option<TuyaMessage> {
+    assembler.frames += 1;
+    let now = assembler.frames;
+    assembler.partial.retain(|_, (at, _)| now - *at <= MAX_PARTIAL_AGE);
+
+    let key = (msg.seqno, msg.cmd);
+    let mut payload = match assembler.partial.remove(&key) {
+        Some((_, mut buf)) => {
+            buf.extend_from_slice(&msg.payload);
+            let glued_wrong = matches!(json_state(&buf), JsonState::Invalid)
+                && !matches!(json_state(&msg.payload), JsonState::Invalid);
+            if glued_wrong { msg.payload } else { buf }
+        }
+        None => msg.payload,
     };
 
-    if ciphertext.is_empty() {
-        return Ok(TuyaMessage {
-            seqno,
-            cmd,
-            retcode,
-            payload: Vec::new(),
-        });
+    let truncated = !payload.is_empty()
+        && payload.len() < MAX_REASSEMBLED_SIZE
+        && matches!(json_state(&payload), JsonState::Truncated);
+
+    if truncated {
+        if assembler.partial.len() >= MAX_PARTIALS
+            && let Some(oldest) = assembler.partial.iter().min_by_key(|(_, (at, _))| *at).map(|(key, _)| *key)
+        {
+            assembler.partial.remove(&oldest);
+        }
+        assembler.partial.insert(key, (now, std::mem::take(&mut payload)));
+        return None;
     }
 
-    let payload = decrypt_payload(ciphertext, local_key)?;
+    Some(TuyaMessage { payload, ..msg })
+}
 
-    Ok(TuyaMessage {
-        seqno,
-        cmd,
-        retcode,
-        payload,
-    })
+// -- Pure functions: wire tracing --
+
+pub fn cmd_name(cmd: u32) -> &'static str {
+    match cmd {
+        CMD_CONTROL => "CONTROL",
+        CMD_STATUS => "STATUS",
+        CMD_HEART_BEAT => "HEART_BEAT",
+        CMD_DP_QUERY => "DP_QUERY",
+        CMD_CONTROL_NEW => "CONTROL_NEW",
+        CMD_UPDATEDPS => "UPDATEDPS",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Decode the envelope fields of a raw frame into a one-line summary.
+/// Tolerates truncated input — this is for logging, not validation.
+pub fn describe_frame(data: &[u8]) -> String {
+    let word = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let (Some(prefix), Some(seqno), Some(cmd), Some(length)) = (word(0), word(4), word(8), word(12))
+    else {
+        return format!("truncated frame ({} bytes)", data.len());
+    };
+
+    let mut out = format!(
+        "prefix={prefix:#010x} seqno={seqno} cmd={cmd:#04x}({}) length={length}",
+        cmd_name(cmd)
+    );
+
+    let end = HEADER_SIZE + length as usize;
+    if end <= data.len() && end >= HEADER_SIZE + FOOTER_SIZE {
+        if let Some(crc) = word(end - FOOTER_SIZE) {
+            out.push_str(&format!(" crc={crc:#010x}"));
+        }
+        if let Some(suffix) = word(end - SUFFIX_SIZE) {
+            out.push_str(&format!(" suffix={suffix:#010x}"));
+        }
+    }
+
+    out
+}
+
+/// Classic offset / hex / ASCII dump, 16 bytes per line.
+pub fn hexdump(data: &[u8]) -> String {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+            format!("{:04x}  {:<47}  |{ascii}|", i * 16, hex.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 // -- Pure functions: JSON payload builders --
@@ -241,6 +441,16 @@ pub fn build_dp_query_json(device_id: &str) -> Vec<u8> {
     .expect("JSON serialization cannot fail for known-good data")
 }
 
+/// The "control new" form of a status query: names the DPs wanted, each
+/// with a null value. For firmware that rejects a plain DP_QUERY.
+pub fn build_dp_list_query_json(device_id: &str, dps: &[String]) -> Vec<u8> {
+    let dps: serde_json::Map<String, serde_json::Value> = dps
+        .iter()
+        .map(|dp| (dp.clone(), serde_json::Value::Null))
+        .collect();
+    build_control_json(device_id, &serde_json::Value::Object(dps))
+}
+
 pub fn build_control_json(device_id: &str, dps: &serde_json::Value) -> Vec<u8> {
     let ts = timestamp_str();
     serde_json::to_vec(&serde_json::json!({
@@ -252,6 +462,14 @@ pub fn build_control_json(device_id: &str, dps: &serde_json::Value) -> Vec<u8> {
     .expect("JSON serialization cannot fail for known-good data")
 }
 
+/// Ask the device to re-read the given DPs and report them, which it
+/// does with status pushes rather than a reply.
+pub fn build_update_dps_json(dps: &[String]) -> Vec<u8> {
+    let dps: Vec<u32> = dps.iter().filter_map(|dp| dp.parse().ok()).collect();
+    serde_json::to_vec(&serde_json::json!({ "dpId": dps }))
+        .expect("JSON serialization cannot fail for known-good data")
+}
+
 pub fn build_heartbeat_json() -> Vec<u8> {
     Vec::new()
 }
@@ -324,16 +542,19 @@ mod tests {
         assert_ne!(&data[HEADER_SIZE..HEADER_SIZE + 3], b"3.3");
     }
 
-    #[test]
-    fn parse_device_response() {
-        // Simulate a device response: [header][retcode][version_header + ciphertext][crc][suffix]
-        let key: [u8; 16] = *b"0123456789abcdef";
-        let json_payload = b"{\"dps\":{\"1\":true,\"6\":55}}";
-        let encrypted = encrypt_payload(json_payload, &key);
+    /// Build a frame shaped like a device response:
+    /// [header][retcode][version_header + ciphertext][crc][suffix]
+    fn device_response_frame(
+        seqno: u32,
+        cmd: u32,
+        retcode: u32,
+        json_payload: &[u8],
+        key: &[u8; 16],
+    ) -> Vec<u8> {
+        let encrypted = encrypt_payload(json_payload, key);
 
-        // Build a fake device response with retcode
         let mut payload_section = Vec::new();
-        payload_section.extend_from_slice(&0u32.to_be_bytes()); // retcode = 0 (success)
+        payload_section.extend_from_slice(&retcode.to_be_bytes());
         payload_section.extend_from_slice(&VERSION_HEADER);
         payload_section.extend_from_slice(&encrypted);
 
@@ -341,14 +562,64 @@ mod tests {
 
         let mut frame = Vec::new();
         frame.extend_from_slice(&PREFIX.to_be_bytes());
-        frame.extend_from_slice(&42u32.to_be_bytes()); // seqno
-        frame.extend_from_slice(&CMD_STATUS.to_be_bytes()); // cmd
+        frame.extend_from_slice(&seqno.to_be_bytes());
+        frame.extend_from_slice(&cmd.to_be_bytes());
         frame.extend_from_slice(&length.to_be_bytes());
         frame.extend_from_slice(&payload_section);
 
         let crc = crc32fast::hash(&frame);
         frame.extend_from_slice(&crc.to_be_bytes());
         frame.extend_from_slice(&SUFFIX.to_be_bytes());
+        frame
+    }
+
+    /// Broadcast frame: [header][retcode][payload][crc][suffix], no version header.
+    fn broadcast_frame(payload: &[u8]) -> Vec<u8> {
+        let length = (RETCODE_SIZE + payload.len() + FOOTER_SIZE) as u32;
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&PREFIX.to_be_bytes());
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(&0x13u32.to_be_bytes());
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&0u32.to_be_bytes());
+        frame.extend_from_slice(payload);
+
+        let crc = crc32fast::hash(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame.extend_from_slice(&SUFFIX.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn parse_broadcast_handles_both_ports() {
+        let json = b"{\"ip\":\"192.168.1.20\",\"gwId\":\"abc\",\"version\":\"3.3\"}";
+
+        let encrypted = broadcast_frame(&encrypt_payload(json, &BROADCAST_KEY));
+        assert_eq!(parse_broadcast(&encrypted).unwrap(), json);
+
+        let plaintext = broadcast_frame(json);
+        assert_eq!(parse_broadcast(&plaintext).unwrap(), json);
+    }
+
+    #[test]
+    fn plaintext_rejections_survive_parsing() {
+        let key: [u8; 16] = *b"0123456789abcdef";
+        let msg = parse_frame(&broadcast_frame(b"data format error"), &key).unwrap();
+        assert_eq!(msg.payload, b"data format error");
+        assert!(is_rejection(&msg));
+
+        let frame = device_response_frame(1, CMD_DP_QUERY, 0, b"{\"dps\":{}}", &key);
+        assert!(!is_rejection(&parse_frame(&frame, &key).unwrap()));
+        let frame = device_response_frame(1, CMD_DP_QUERY, 1, b"{}", &key);
+        assert!(is_rejection(&parse_frame(&frame, &key).unwrap()));
+    }
+
+    #[test]
+    fn parse_device_response() {
+        let key: [u8; 16] = *b"0123456789abcdef";
+        let json_payload = b"{\"dps\":{\"1\":true,\"6\":55}}";
+        let frame = device_response_frame(42, CMD_STATUS, 0, json_payload, &key);
 
         // Parse it
         let msg = parse_frame(&frame, &key).unwrap();
@@ -357,4 +628,191 @@ mod tests {
         assert_eq!(msg.retcode, 0);
         assert_eq!(&msg.payload, json_payload);
     }
+
+    #[test]
+    fn reassemble_joins_fragments_by_seqno() {
+        let msg = |seqno, payload: &[u8]| TuyaMessage {
+            seqno,
+            cmd: CMD_DP_QUERY,
+            retcode: 0,
+            payload: payload.to_vec(),
+        };
+        let mut assembler = PayloadAssembler::default();
+
+        assert!(reassemble(&mut assembler, msg(5, b"{\"dps\":{\"1\":tr")).is_none());
+        // An unrelated complete frame is unaffected by the pending fragment
+        let other = reassemble(&mut assembler, msg(6, b"")).unwrap();
+        assert!(other.payload.is_empty());
+
+        let full = reassemble(&mut assembler, msg(5, b"ue,\"2\":50}}")).unwrap();
+        assert_eq!(full.payload, b"{\"dps\":{\"1\":true,\"2\":50}}");
+        assert!(assembler.partial.is_empty());
+
+        // Non-JSON plaintext isn't mistaken for a fragment
+        let error = reassemble(&mut assembler, msg(7, b"data format error")).unwrap();
+        assert_eq!(error.payload, b"data format error");
+    }
+
+    #[test]
+    fn reassemble_keeps_pushes_with_the_same_seqno_apart() {
+        let msg = |cmd, payload: &[u8]| TuyaMessage {
+            seqno: 0,
+            cmd,
+            retcode: 0,
+            payload: payload.to_vec(),
+        };
+        let mut assembler = PayloadAssembler::default();
+
+        // A push cut short, then a heartbeat ACK on the same seqno
+        assert!(reassemble(&mut assembler, msg(CMD_STATUS, b"{\"dps\":{\"1\":tr")).is_none());
+        let ack = reassemble(&mut assembler, msg(CMD_HEART_BEAT, b"")).unwrap();
+        assert!(ack.payload.is_empty());
+        assert_eq!(assembler.partial.len(), 1);
+
+        // A fresh push isn't glued onto the stale fragment
+        let push = reassemble(&mut assembler, msg(CMD_STATUS, b"{\"dps\":{\"2\":45}}")).unwrap();
+        assert_eq!(push.payload, b"{\"dps\":{\"2\":45}}");
+        assert!(assembler.partial.is_empty());
+
+        // A fragment that's never completed is dropped after a while
+        assert!(reassemble(&mut assembler, msg(CMD_STATUS, b"{\"dps\":")).is_none());
+        for _ in 0..=MAX_PARTIAL_AGE {
+            reassemble(&mut assembler, msg(CMD_HEART_BEAT, b""));
+        }
+        assert!(assembler.partial.is_empty());
+
+        // And only a few are held at once
+        for seqno in 1..=MAX_PARTIALS as u32 + 2 {
+            assert!(reassemble(&mut assembler, TuyaMessage { seqno, ..msg(CMD_DP_QUERY, b"{") }).is_none());
+        }
+        assert_eq!(assembler.partial.len(), MAX_PARTIALS);
+        assert!(!assembler.partial.contains_key(&(1, CMD_DP_QUERY)));
+    }
+
+    #[test]
+    fn describe_frame_decodes_header_fields() {
+        let key: [u8; 16] = *b"0123456789abcdef";
+        let frame = build_frame(9, CMD_HEART_BEAT, b"{}", &key);
+        let summary = describe_frame(&frame.bytes);
+
+        assert!(summary.starts_with("prefix=0x000055aa seqno=9 cmd=0x09(HEART_BEAT)"));
+        assert!(summary.ends_with("suffix=0x0000aa55"));
+        assert_eq!(describe_frame(&frame.bytes[..10]), "truncated frame (10 bytes)");
+    }
+
+    #[test]
+    fn parse_rejects_length_shorter_than_footer() {
+        let key: [u8; 16] = *b"0123456789abcdef";
+        let mut frame = device_response_frame(1, CMD_STATUS, 0, b"{}", &key);
+        frame[12..16].copy_from_slice(&0u32.to_be_bytes());
+
+        assert!(matches!(
+            parse_frame(&frame, &key),
+            Err(ProtocolError::PayloadTooShort)
+        ));
+    }
+
+    // -- Property tests: malformed input off the wire must never panic --
+
+    mod props {
+        use super::*;
+        use proptest::prelude::*;
+
+        const KEY: [u8; 16] = *b"0123456789abcdef";
+
+        proptest! {
+            #[test]
+            fn parse_arbitrary_bytes_never_panics(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+                let _ = parse_frame(&data, &KEY);
+            }
+
+            #[test]
+            fn parse_arbitrary_body_with_valid_prefix_never_panics(
+                seqno: u32,
+                cmd: u32,
+                length in 0u32..128,
+                body in proptest::collection::vec(any::<u8>(), 0..160),
+            ) {
+                let mut data = Vec::new();
+                data.extend_from_slice(&PREFIX.to_be_bytes());
+                data.extend_from_slice(&seqno.to_be_bytes());
+                data.extend_from_slice(&cmd.to_be_bytes());
+                data.extend_from_slice(&length.to_be_bytes());
+                data.extend_from_slice(&body);
+                let _ = parse_frame(&data, &KEY);
+            }
+
+            #[test]
+            fn decrypt_arbitrary_bytes_never_panics(data in proptest::collection::vec(any::<u8>(), 0..128)) {
+                let _ = decrypt_payload(&data, &KEY);
+            }
+
+            #[test]
+            fn encrypt_decrypt_roundtrips(
+                plaintext in proptest::collection::vec(any::<u8>(), 0..256),
+                key: [u8; 16],
+            ) {
+                let encrypted = encrypt_payload(&plaintext, &key);
+                prop_assert_eq!(encrypted.len() % AES_BLOCK_SIZE, 0);
+                prop_assert_eq!(decrypt_payload(&encrypted, &key).unwrap(), plaintext);
+            }
+
+            #[test]
+            fn built_frames_parse_back(
+                seqno: u32,
+                cmd in prop::sample::select(vec![CMD_CONTROL, CMD_STATUS, CMD_HEART_BEAT, CMD_DP_QUERY, CMD_UPDATEDPS]),
+                payload in proptest::collection::vec(any::<u8>(), 0..128),
+            ) {
+                // Outbound frames carry no retcode, so only the envelope roundtrips.
+                let frame = build_frame(seqno, cmd, &payload, &KEY);
+                let length = u32::from_be_bytes([frame.bytes[12], frame.bytes[13], frame.bytes[14], frame.bytes[15]]) as usize;
+                prop_assert_eq!(HEADER_SIZE + length, frame.bytes.len());
+
+                if let Ok(msg) = parse_frame(&frame.bytes, &KEY) {
+                    prop_assert_eq!(msg.seqno, seqno);
+                    prop_assert_eq!(msg.cmd, cmd);
+                }
+            }
+
+            #[test]
+            fn device_responses_roundtrip(
+                seqno: u32,
+                cmd: u32,
+                retcode: u32,
+                payload in proptest::collection::vec(any::<u8>(), 1..256),
+            ) {
+                let frame = device_response_frame(seqno, cmd, retcode, &payload, &KEY);
+                let msg = parse_frame(&frame, &KEY).unwrap();
+                prop_assert_eq!(msg.seqno, seqno);
+                prop_assert_eq!(msg.cmd, cmd);
+                prop_assert_eq!(msg.retcode, retcode);
+                prop_assert_eq!(msg.payload, payload);
+            }
+
+            #[test]
+            fn mutated_frames_never_panic_and_fail_integrity(
+                payload in proptest::collection::vec(any::<u8>(), 1..64),
+                index: prop::sample::Index,
+                flip in 1u8..=255,
+            ) {
+                let mut frame = device_response_frame(7, CMD_STATUS, 0, &payload, &KEY);
+                let i = index.index(frame.len());
+                frame[i] ^= flip;
+
+                // Every byte is covered by prefix, length, CRC or suffix checks,
+                // so a single corrupted byte can never parse successfully.
+                prop_assert!(parse_frame(&frame, &KEY).is_err());
+            }
+
+            #[test]
+            fn truncated_frames_are_rejected(
+                payload in proptest::collection::vec(any::<u8>(), 1..64),
+                cut: prop::sample::Index,
+            ) {
+                let frame = device_response_frame(7, CMD_STATUS, 0, &payload, &KEY);
+                let len = cut.index(frame.len());
+                prop_assert!(parse_frame(&frame[..len], &KEY).is_err());
+            }
+        }
+    }
 }
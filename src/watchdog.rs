@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::config::WatchdogConfig;
+use crate::status;
+use crate::tuya_connection::{self, Deadline, TuyaConnection};
+
+// -- Availability watchdog --
+//
+// Keeps an up-to-date answer to "is the device there?" so a tool call can
+// say "offline since 14:32" at once instead of spending its timeout
+// finding out. Separate from the heartbeat, which exists to keep the
+// socket alive: any frame from the device counts as a sign of life, and
+// the device is only probed when it has been quiet for a whole interval.
+
+#[derive(Debug, Default)]
+pub struct Availability(Mutex<Sighting>);
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Sighting {
+    /// None until the first check.
+    available: Option<bool>,
+    last_seen: Option<SystemTime>,
+    /// When the device was first found missing, as a monotonic instant
+    /// (to compare with frames heard since) and as a clock time.
+    offline_since: Option<(Instant, SystemTime)>,
+}
+
+/// The watchdog's view, for `get_device_info`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AvailabilitySnapshot {
+    pub available: Option<bool>,
+    /// Unix seconds.
+    pub last_seen: Option<u64>,
+    pub offline_since: Option<u64>,
+}
+
+pub fn unix_secs(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Record the outcome of one check. True if it's the one that found the
+/// device gone.
+pub fn mark(availability: &Availability, available: bool) -> bool {
+    let mut sighting = availability.0.lock().expect("availability lock poisoned");
+    let now = SystemTime::now();
+
+    let went_offline = !available && sighting.offline_since.is_none();
+    if available {
+        sighting.last_seen = Some(now);
+        sighting.offline_since = None;
+    } else if went_offline {
+        sighting.offline_since = Some((Instant::now(), now));
+    }
+    sighting.available = Some(available);
+    went_offline
+}
+
+pub fn snapshot(availability: &Availability) -> AvailabilitySnapshot {
+    let sighting = *availability.0.lock().expect("availability lock poisoned");
+    AvailabilitySnapshot {
+        available: sighting.available,
+        last_seen: sighting.last_seen.map(unix_secs),
+        offline_since: sighting.offline_since.map(|(_, at)| unix_secs(at)),
+    }
+}
+
+/// "offline since 14:32 UTC (6 minutes ago)" if the watchdog last found
+/// the device missing and nothing has been heard from it since.
+pub fn offline_notice(availability: &Availability, conn: &TuyaConnection) -> Option<String> {
+    let (since, at) = availability.0.lock().expect("availability lock poisoned").offline_since?;
+    if tuya_connection::last_heard(conn).is_some_and(|heard| heard > since) {
+        return None;
+    }
+    Some(format!(
+        "offline since {} ({})",
+        clock_utc(at),
+        status::format_age(since.elapsed())
+    ))
+}
+
+/// "14:32 UTC". hearth has no timezone database; UTC is unambiguous.
+fn clock_utc(at: SystemTime) -> String {
+    let day_secs = unix_secs(at) % 86_400;
+    format!("{:02}:{:02} UTC", day_secs / 3600, day_secs % 3600 / 60)
+}
+
+/// Check on the device every `interval_secs`, probing it when it has
+/// been quiet that long.
+pub fn spawn_watchdog(
+    conn: Arc<TuyaConnection>,
+    availability: Arc<Availability>,
+    config: &WatchdogConfig,
+) -> tokio::task::JoinHandle<()> {
+    let every = Duration::from_secs(config.interval_secs.max(1));
+    let probe_timeout = Duration::from_millis(config.probe_timeout_ms);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let heard = tuya_connection::last_heard(&conn).is_some_and(|at| at.elapsed() < every);
+            let available = heard || {
+                let deadline = Deadline {
+                    at: Some(Instant::now() + probe_timeout),
+                    ..Deadline::default()
+                };
+                tuya_connection::ping(&conn, &deadline).await.is_ok()
+            };
+            if mark(&availability, available) {
+                tracing::warn!(device = %conn.name, "Device not answering, marking it offline");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_since_is_kept_until_seen_again() {
+        let availability = Availability::default();
+        assert_eq!(snapshot(&availability).available, None);
+
+        mark(&availability, false);
+        let since = snapshot(&availability).offline_since.unwrap();
+        mark(&availability, false);
+        assert_eq!(snapshot(&availability).offline_since, Some(since));
+
+        mark(&availability, true);
+        let seen = snapshot(&availability);
+        assert_eq!(seen.available, Some(true));
+        assert!(seen.offline_since.is_none() && seen.last_seen.is_some());
+
+        let at = UNIX_EPOCH + Duration::from_secs(3 * 86_400 + 14 * 3600 + 32 * 60 + 59);
+        assert_eq!(clock_utc(at), "14:32 UTC");
+    }
+}